@@ -0,0 +1,81 @@
+//! Time the whole pipeline (walk and catalog) over generated mock filesystems
+//! Run with `cargo bench`
+
+#[macro_use]
+extern crate criterion;
+extern crate smllr;
+
+use criterion::Criterion;
+
+use std::path::PathBuf;
+
+use smllr::{deduplicate, Options, TestFileSystem};
+use smllr::catalog::FileCataloger;
+use smllr::hash::Md5Sum;
+use smllr::vfs::{TestFile, TestMD, TreeSpec};
+
+// 1 + 4 + 16 + 64 directories with 20 files each, a fifth of them copies
+fn tree_spec() -> TreeSpec {
+    TreeSpec {
+        fan_out: 4,
+        depth: 3,
+        files_per_dir: 20,
+        min_size: 16,
+        max_size: 8192,
+        dup_ratio: 0.2,
+    }
+}
+
+fn bench_md5(c: &mut Criterion) {
+    c.bench_function("deduplicate 1,700 files", |b| {
+        b.iter_with_setup(
+            || TestFileSystem::generate(42, &tree_spec()).fs,
+            |fs| deduplicate(Options::new(&["/"]), fs).expect("Failed to scan"),
+        )
+    });
+}
+
+fn bench_sha3(c: &mut Criterion) {
+    c.bench_function("deduplicate 1,700 files (paranoid)", |b| {
+        b.iter_with_setup(
+            || TestFileSystem::generate(42, &tree_spec()).fs,
+            |fs| deduplicate(Options::new(&["/"]).paranoid(true), fs).expect("Failed to scan"),
+        )
+    });
+}
+
+// files of the same size, in pairs of copies each told apart by its first bytes
+const SAME_SIZE_FILES: u64 = 100_000;
+
+fn bench_same_size(c: &mut Criterion) {
+    let fs = TestFileSystem::new();
+    fs.borrow_mut().create_dir("/");
+    for i in 0..SAME_SIZE_FILES {
+        let key = i / 2;
+        let byte = move |off: u64| if off < 8 { (key >> (8 * off)) as u8 } else { 0 };
+        let file = TestFile::new(format!("/f{}", i))
+            .with_generated_contents(64, byte)
+            .with_metadata(TestMD::new())
+            .with_inode(i + 1);
+        fs.borrow_mut().add(file);
+    }
+    let paths: Vec<PathBuf> = (0..SAME_SIZE_FILES)
+        .map(|i| PathBuf::from(format!("/f{}", i)))
+        .collect();
+    c.bench_function("catalog 100,000 same-size files", |b| {
+        b.iter(|| {
+            let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs.clone());
+            fc.insert_all(&paths);
+            fc.get_repeats()
+        })
+    });
+}
+
+criterion_group!(benches, bench_md5, bench_sha3);
+criterion_group! {
+    name = large_buckets;
+    // each run reads every file, so take fewer samples
+    config = Criterion::default().sample_size(10);
+    targets = bench_same_size
+}
+criterion_main!(benches, large_buckets);
@@ -1,52 +1,359 @@
 //! Handle duplicates
 
-use vfs::{File, MetaData, VFS};
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::AddAssign;
+use std::path::{Path, PathBuf};
+use std::collections::HashSet;
+
+use vfs::{File, MetaData, OnError, VFS};
 use catalog::proxy::Duplicates;
+use helpers::{path_bytes, prettify_bytes, to_hex, ID};
 
 pub mod selector;
 use self::selector::Selector;
 
+pub mod tree;
+
 mod test; // include unit tests
 
+/// Amount of space taken up by duplicate files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Savings {
+    /// Sum of the lengths of the files
+    pub logical: u64,
+    /// Sum of the space the files occupy on disk; this is what deduplicating frees
+    pub allocated: u64,
+}
+
+impl Savings {
+    /// The space taken up by a single file
+    pub fn of<M: MetaData>(md: &M) -> Self {
+        Savings {
+            logical: md.get_len(),
+            allocated: md.get_allocated_size(),
+        }
+    }
+    /// Whether the logical and allocated sizes differ by more than 10%
+    /// (e.g. because of sparse files or filesystem compression)
+    pub fn differs_significantly(&self) -> bool {
+        let (small, big) = if self.logical < self.allocated {
+            (self.logical, self.allocated)
+        } else {
+            (self.allocated, self.logical)
+        };
+        big - small > big / 10
+    }
+}
+
+// accumulate savings across files
+impl AddAssign for Savings {
+    fn add_assign(&mut self, other: Savings) {
+        self.logical += other.logical;
+        self.allocated += other.allocated;
+    }
+}
+
+// show the reclaimable space, plus the apparent size if it is misleading
+impl fmt::Display for Savings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", prettify_bytes(self.allocated))?;
+        if self.differs_significantly() {
+            write!(f, " ({} apparent size)", prettify_bytes(self.logical))?;
+        }
+        Ok(())
+    }
+}
+
+// the ID of the file at `path` in `dups`: the one it was cataloged with if that's known,
+// otherwise looked up, or None (logged) if it can't be
+fn id_of<V: VFS>(vfs: &V, dups: &Duplicates, path: &Path) -> Option<ID> {
+    if let Some(id) = dups.id_of(path) {
+        return Some(id);
+    }
+    match vfs.get_metadata(path).map(|md| ID::of(&md)) {
+        Ok(Ok(id)) => Some(id),
+        Ok(Err(e)) => {
+            warn!("Cannot identify {:?}: {}", path, e);
+            None
+        }
+        Err(e) => {
+            warn!("{}", e);
+            None
+        }
+    }
+}
+
+// whether the file at `path` is as it was when it was cataloged (or there's no telling),
+// logging why not
+fn unchanged<V: VFS>(vfs: &V, dups: &Duplicates, path: &Path) -> bool {
+    let stamp = match dups.stamp_of(path) {
+        Some(stamp) => stamp,
+        None => return true,
+    };
+    match vfs.get_metadata(path) {
+        Ok(ref md) if stamp.matches(md) => true,
+        Ok(_) => {
+            warn!("{:?} changed since it was cataloged, leaving it alone", path);
+            false
+        }
+        Err(e) => {
+            warn!("{}", e);
+            false
+        }
+    }
+}
+
+// `dups` without the files that changed since they were cataloged if `on`, or None
+// (logged) if fewer than two of them are left to act on
+fn revalidated<V: VFS>(vfs: &V, mut dups: Duplicates, on: bool) -> Option<Duplicates> {
+    if !on {
+        return Some(dups);
+    }
+    let changed: Vec<PathBuf> = dups.iter()
+        .filter(|path| !unchanged(vfs, &dups, path))
+        .map(|path| path.to_path_buf())
+        .collect();
+    if changed.is_empty() {
+        return Some(dups);
+    }
+    dups.retain(|path| !changed.iter().any(|c| c == path));
+    if dups.len() < 2 {
+        warn!("Not acting on the duplicates of {:?}: the others changed", dups.paths().first());
+        return None;
+    }
+    // a file that can't be identified counts as distinct
+    let mut ids = HashSet::new();
+    let files = dups.iter()
+        .filter(|path| dups.stamp_of(path).map_or(true, |stamp| ids.insert(stamp.id)))
+        .count();
+    Some(dups.with_distinct_files(files))
+}
+
+// keeps track of which paths in a group are hard links to the same file,
+// as removing one of them only frees the file along with the rest
+struct Links<'a> {
+    // the paths being kept, and the files they are
+    kept: Vec<(&'a Path, ID)>,
+    // files whose space has been counted already
+    counted: HashSet<ID>,
+}
+
+impl<'a> Links<'a> {
+    // look up the files the `kept` paths of `dups` are
+    fn new<V: VFS>(vfs: &V, dups: &Duplicates, kept: &[&'a Path]) -> Self {
+        Links {
+            kept: kept.iter()
+                .filter_map(|&path| id_of(vfs, dups, path).map(|id| (path, id)))
+                .collect(),
+            counted: HashSet::new(),
+        }
+    }
+    // the kept path that the file `id` is already linked to, if any
+    fn kept_link(&self, id: Option<ID>) -> Option<&'a Path> {
+        let id = id?;
+        self.kept.iter().find(|k| k.1 == id).map(|k| k.0)
+    }
+    // whether getting rid of the file `id` frees space that hasn't been counted yet
+    // (a file that can't be identified is assumed to)
+    fn frees(&mut self, id: Option<ID>) -> bool {
+        match id {
+            Some(id) => self.kept_link(Some(id)).is_none() && self.counted.insert(id),
+            None => true,
+        }
+    }
+}
+
 /// Trait for acting on duplicate files
 pub trait FileActor<V: VFS, S: Selector<V>> {
     /// Use Selector `S` to identify the 'true' file and then perform its action, returning the
     /// amount of duplicate space
-    fn act(&mut self, dups: Duplicates) -> u64;
+    fn act(&mut self, dups: Duplicates) -> Savings;
+    /// Whether acting stopped at an error (see `OnError::Abort`), after which `act`
+    /// does nothing more
+    fn aborted(&self) -> bool {
+        false
+    }
 }
 
 // call FileActor methods on objects on the heap that support it
 impl<V: VFS, S: Selector<V>> FileActor<V, S> for Box<FileActor<V, S>> {
-    fn act(&mut self, dups: Duplicates) -> u64 {
+    fn act(&mut self, dups: Duplicates) -> Savings {
         (**self).act(dups)
     }
+    fn aborted(&self) -> bool {
+        (**self).aborted()
+    }
 }
 
 /// Actor that prints file names but doesn't modify the filesystem
-pub struct FilePrinter<V: VFS, S: Selector<V>> {
+pub struct FilePrinter<V: VFS, S: Selector<V>, W: Write = io::Stdout> {
     selector: S,
     vfs: V,
+    // where the results are written (stdout unless otherwise specified)
+    out: W,
+    // how many of the top-ranked files in each group are left alone
+    keep: usize,
+    // only write the duplicates, each followed by a NUL (for `xargs -0`)
+    print0: bool,
+    // note how many hard links each printed file already has
+    verbose: bool,
+    // start each group with the hash of its contents
+    show_hash: bool,
+    // start each group with its number and the space it wastes
+    numbered: bool,
+    // print paths beneath this directory relative to it
+    relative_to: Option<PathBuf>,
+    // how many groups have been printed
+    groups: usize,
+    // what to do about groups that can't be sized, and whether that stopped everything
+    on_error: OnError,
+    aborted: bool,
 }
 
 /// Actor that deletes all but the selected file
 pub struct FileDeleter<V: VFS, S: Selector<V>> {
     selector: S,
     vfs: V,
+    keep: usize,
+    // leave out the files that changed since they were cataloged
+    revalidate: bool,
+    // what to do about files that can't be deleted, and whether that stopped everything
+    on_error: OnError,
+    aborted: bool,
 }
 
 /// Actor that replaces all but the selected file with links to it
 pub struct FileLinker<V: VFS, S: Selector<V>> {
     selector: S,
     vfs: V,
+    keep: usize,
+    // link files even if their permissions differ from the kept file's
+    ignore_mode: bool,
+    // leave out the files that changed since they were cataloged
+    revalidate: bool,
+    // what to do about files that can't be linked, and whether that stopped everything
+    on_error: OnError,
+    aborted: bool,
+}
+
+/// Actor that makes all but the selected file copy-on-write clones of it (see `VFS::reflink`)
+/// Unlike links, the clones stay separate files with their own metadata and permissions
+pub struct FileReflinker<V: VFS, S: Selector<V>> {
+    selector: S,
+    vfs: V,
+    keep: usize,
+    // leave out the files that changed since they were cataloged
+    revalidate: bool,
+    // what to do about files that can't be cloned onto, and whether that stopped everything
+    on_error: OnError,
+    aborted: bool,
 }
 
 // constructors for FilePrinter: dependency inject a Selector
 impl<V: VFS, S: Selector<V>> FilePrinter<V, S> {
-    /// Create a new `FilePrinter`
+    /// Create a new `FilePrinter` that writes to stdout
     pub fn new(v: V, s: S) -> Self {
+        FilePrinter::with_output(v, s, io::stdout())
+    }
+}
+
+// FilePrinter can also write to any other sink (a file, a buffer, etc.)
+impl<V: VFS, S: Selector<V>, W: Write> FilePrinter<V, S, W> {
+    /// Create a new `FilePrinter` that writes to `w`
+    pub fn with_output(v: V, s: S, w: W) -> Self {
         FilePrinter {
             selector: s,
             vfs: v,
+            out: w,
+            keep: 1,
+            print0: false,
+            verbose: false,
+            show_hash: false,
+            numbered: false,
+            relative_to: None,
+            groups: 0,
+            on_error: OnError::default(),
+            aborted: false,
+        }
+    }
+
+    /// Leave the `n` best-ranked files of each group alone instead of just one
+    pub fn keep_copies(mut self, n: usize) -> Self {
+        self.keep = n.max(1);
+        self
+    }
+
+    /// Write only the paths of the duplicates, each ending in a NUL byte instead of a newline
+    /// Paths are written as raw bytes, so names that aren't UTF-8 survive e.g. `xargs -0`
+    pub fn print0(mut self, on: bool) -> Self {
+        self.print0 = on;
+        self
+    }
+
+    /// Mark each printed file that already has several hard links, e.g. "(already linked x3)"
+    /// Has no effect with `print0`
+    pub fn verbose(mut self, on: bool) -> Self {
+        self.verbose = on;
+        self
+    }
+
+    /// Start each group with the hex digest of its contents, so it can be checked by hand
+    /// (e.g. with `md5sum`); has no effect with `print0`
+    pub fn show_hash(mut self, on: bool) -> Self {
+        self.show_hash = on;
+        self
+    }
+
+    /// Start each group with its number and the space its duplicates waste,
+    /// e.g. "#3 - 1 GB wasted across 4 copies"; has no effect with `print0`
+    pub fn numbered(mut self, on: bool) -> Self {
+        self.numbered = on;
+        self
+    }
+
+    /// Print paths beneath `base` relative to it, e.g. to share a report without the
+    /// home directory's name; paths elsewhere are printed in full
+    pub fn relative_to<P: AsRef<Path>>(mut self, base: P) -> Self {
+        self.relative_to = Some(base.as_ref().to_path_buf());
+        self
+    }
+
+    /// Deal with groups whose size can't be told as `policy` says: by default each is logged
+    /// as a warning and left out, but with `OnError::Abort` nothing more is printed
+    /// after the first (see `FileActor::aborted`)
+    pub fn on_error(mut self, policy: OnError) -> Self {
+        self.on_error = policy;
+        self
+    }
+
+    /// Access the sink the results are written to
+    pub fn get_output(&self) -> &W {
+        &self.out
+    }
+
+    // `path` as it's printed (see `relative_to`)
+    fn shown<'a>(&self, path: &'a Path) -> &'a Path {
+        match self.relative_to {
+            Some(ref base) => path.strip_prefix(base).unwrap_or(path),
+            None => path,
+        }
+    }
+
+    // the annotation (if any) to print after `path`
+    fn links_note(&self, path: &Path) -> String {
+        if !self.verbose {
+            return String::new();
+        }
+        match self.vfs.get_metadata(path) {
+            Ok(ref md) if md.is_multiply_linked() => {
+                format!(" (already linked x{})", md.get_nlink())
+            }
+            Ok(_) => String::new(),
+            Err(e) => {
+                warn!("{}", e);
+                String::new()
+            }
         }
     }
 }
@@ -58,8 +365,34 @@ impl<V: VFS, S: Selector<V>> FileDeleter<V, S> {
         FileDeleter {
             selector: s,
             vfs: v,
+            keep: 1,
+            revalidate: true,
+            on_error: OnError::default(),
+            aborted: false,
         }
     }
+
+    /// Leave the `n` best-ranked files of each group alone instead of just one
+    pub fn keep_copies(mut self, n: usize) -> Self {
+        self.keep = n.max(1);
+        self
+    }
+
+    /// Before deleting any of a group, look up each file again and leave out those whose
+    /// size, mtime, or ID differ from when they were cataloged (see `Duplicates::stamp_of`),
+    /// skipping the group if fewer than two are left; on by default
+    pub fn revalidate(mut self, on: bool) -> Self {
+        self.revalidate = on;
+        self
+    }
+
+    /// Deal with files that can't be deleted as `policy` says: by default each is logged as
+    /// a warning and left as it was, but with `OnError::Abort` nothing more is done
+    /// after the first (see `FileActor::aborted`)
+    pub fn on_error(mut self, policy: OnError) -> Self {
+        self.on_error = policy;
+        self
+    }
 }
 
 // constructors for FileLinker: dependency inject a Selector
@@ -69,74 +402,237 @@ impl<V: VFS, S: Selector<V>> FileLinker<V, S> {
         FileLinker {
             selector: s,
             vfs: v,
+            keep: 1,
+            ignore_mode: false,
+            revalidate: true,
+            on_error: OnError::default(),
+            aborted: false,
         }
     }
+
+    /// Leave the `n` best-ranked files of each group alone instead of just one
+    /// The rest are linked to the best one
+    pub fn keep_copies(mut self, n: usize) -> Self {
+        self.keep = n.max(1);
+        self
+    }
+
+    /// Link duplicates whose permissions differ from the kept file's, which then
+    /// take on its permissions (by default they're warned about and left alone)
+    pub fn ignore_permissions(mut self, on: bool) -> Self {
+        self.ignore_mode = on;
+        self
+    }
+
+    /// Before linking any of a group, look up each file again and leave out those whose
+    /// size, mtime, or ID differ from when they were cataloged (see `Duplicates::stamp_of`),
+    /// skipping the group if fewer than two are left; on by default
+    pub fn revalidate(mut self, on: bool) -> Self {
+        self.revalidate = on;
+        self
+    }
+
+    /// Deal with files that can't be linked as `policy` says: by default each is logged as
+    /// a warning and left as it was, but with `OnError::Abort` nothing more is done
+    /// after the first (see `FileActor::aborted`)
+    pub fn on_error(mut self, policy: OnError) -> Self {
+        self.on_error = policy;
+        self
+    }
+}
+
+// constructors for FileReflinker: dependency inject a Selector
+impl<V: VFS, S: Selector<V>> FileReflinker<V, S> {
+    /// Create a new `FileReflinker`
+    pub fn new(v: V, s: S) -> Self {
+        FileReflinker {
+            selector: s,
+            vfs: v,
+            keep: 1,
+            revalidate: true,
+            on_error: OnError::default(),
+            aborted: false,
+        }
+    }
+
+    /// Leave the `n` best-ranked files of each group alone instead of just one
+    /// The rest are made clones of the best one
+    pub fn keep_copies(mut self, n: usize) -> Self {
+        self.keep = n.max(1);
+        self
+    }
+
+    /// Before cloning onto any of a group, look up each file again and leave out those whose
+    /// size, mtime, or ID differ from when they were cataloged (see `Duplicates::stamp_of`),
+    /// skipping the group if fewer than two are left; on by default
+    pub fn revalidate(mut self, on: bool) -> Self {
+        self.revalidate = on;
+        self
+    }
+
+    /// Deal with files that can't be cloned onto as `policy` says: by default each is logged as
+    /// a warning and left as it was, but with `OnError::Abort` nothing more is done
+    /// after the first (see `FileActor::aborted`)
+    pub fn on_error(mut self, policy: OnError) -> Self {
+        self.on_error = policy;
+        self
+    }
 }
 
 // implement `act()` for a FilePrinter
-impl<V: VFS, S: Selector<V>> FileActor<V, S> for FilePrinter<V, S> {
+impl<V: VFS, S: Selector<V>, W: Write> FileActor<V, S> for FilePrinter<V, S, W> {
     /// Simply print which file in the set is considered the 'true' file and which are
     /// 'duplicates' of it as well as how much space would be saved by
     /// deleting them
-    fn act(&mut self, dups: Duplicates) -> u64 {
-        // identify true file with selector S
-        let real = self.selector.select(&dups);
+    fn act(&mut self, dups: Duplicates) -> Savings {
+        if self.aborted {
+            return Savings::default();
+        }
+        // identify true file (and any extra copies to keep) with selector S
+        let ranked = self.selector.rank(&dups);
+        let real = ranked[0];
         // get the size; need to know how much space we're freeing
-        let size = self.vfs
-            .get_file(real)
-            .expect("Failed to get file from path")
-            .get_metadata()
-            .expect("Failed to get file metadata")
-            .get_len();
-        let mut save_size = 0;
+        // (the group knows it too, if the file isn't there, e.g. in an imported catalog)
+        let size = match (self.vfs.get_metadata(real), dups.size()) {
+            (Ok(md), _) => Savings::of(&md),
+            (Err(_), Some(len)) => Savings {
+                logical: len,
+                allocated: len,
+            },
+            (Err(e), None) => {
+                self.aborted = !self.on_error.carry_on(&e);
+                return Savings::default();
+            }
+        };
+        let mut save_size = Savings::default();
         // log the selection
         info!("{:?} is the true file", real);
-        // print the file that is considered 'true'
-        println!("{:?} is the true file", real);
+        self.groups += 1;
+        if self.numbered && !self.print0 {
+            writeln!(
+                self.out,
+                "#{} - {} wasted across {} copies",
+                self.groups,
+                prettify_bytes(dups.redundant_bytes()),
+                dups.distinct_files()
+            ).expect("Failed to write output");
+        }
+        // print the group's hash and the file that is considered 'true'
+        if self.show_hash && !self.print0 {
+            let hash = dups.hash().map_or_else(|| String::from("(not hashed)"), to_hex);
+            writeln!(self.out, "Hash {}", hash).expect("Failed to write output");
+        }
+        if !self.print0 {
+            let (shown, note) = (self.shown(real), self.links_note(real));
+            writeln!(self.out, "{} is the true file{}", shown.display(), note)
+                .expect("Failed to write output");
+        }
+        // print the other files that would be kept
+        for f in ranked.iter().take(self.keep).skip(1) {
+            info!("\t{:?} is kept as a copy", f);
+            if !self.print0 {
+                let (shown, note) = (self.shown(f), self.links_note(f));
+                writeln!(self.out, "\t{} is kept as a copy{}", shown.display(), note)
+                    .expect("Failed to write output");
+            }
+        }
         // iterate over all other duplicates
-        for f in dups.0.iter().filter(|&f| f.as_path() != real) {
-            info!("\t{:?} is a duplicate", f);
-            println!("\t{:?} is a duplicate", f);
+        // hard links to a kept file free nothing, and are flagged as such
+        let mut links = Links::new(&self.vfs, &dups, &ranked[..self.keep.min(ranked.len())]);
+        for f in ranked.iter().skip(self.keep) {
+            let id = id_of(&self.vfs, &dups, f);
+            let linked_to = links.kept_link(id);
+            match linked_to {
+                Some(kept) => info!("\t{:?} is already linked to {:?}", f, kept),
+                None => info!("\t{:?} is a duplicate", f),
+            }
+            let shown = self.shown(f);
+            if self.print0 {
+                self.out
+                    .write_all(&path_bytes(shown))
+                    .and_then(|_| self.out.write_all(b"\0"))
+                    .expect("Failed to write output");
+            } else if let Some(kept) = linked_to {
+                let kept = self.shown(kept);
+                writeln!(self.out, "\t{} is already linked to {}", shown.display(), kept.display())
+                    .expect("Failed to write output");
+            } else {
+                let note = self.links_note(f);
+                writeln!(self.out, "\t{} is a duplicate{}", shown.display(), note)
+                    .expect("Failed to write output");
+            }
             // keep track of how much space we could save (in bytes)
-            save_size += size;
+            if links.frees(id) {
+                save_size += size;
+            }
         }
         //log the amount of space that could be saved
-        info!(
-            "You can save {} bytes by deduplicating this file",
-            save_size
-        );
+        info!("You can save {} by deduplicating this file", save_size);
         save_size
     }
+
+    fn aborted(&self) -> bool {
+        self.aborted
+    }
 }
 
 // implement `act()` for a FileDeleter
 impl<V: VFS, S: Selector<V>> FileActor<V, S> for FileDeleter<V, S> {
     /// Print what files are duplicated and have been deleted, which one is considered
     /// the 'true', and how much space has been freed
-    fn act(&mut self, dups: Duplicates) -> u64 {
-        //Get the file we arn't deleteing from the selector
-        let real = self.selector.select(&dups);
-        let size = self.vfs
-            .get_file(real)
-            .expect("Failed to get file from path")
-            .get_metadata()
-            .expect("Failed to get file metadata")
-            .get_len(); //get the size from the filesystem
-        let mut save_size = 0;
-        //Log which file we are not deleting
+    fn act(&mut self, dups: Duplicates) -> Savings {
+        if self.aborted {
+            return Savings::default();
+        }
+        // hours may have passed since the files were cataloged
+        let dups = match revalidated(&self.vfs, dups, self.revalidate) {
+            Some(dups) => dups,
+            None => return Savings::default(),
+        };
+        //Get the files we arn't deleteing from the selector
+        let ranked = self.selector.rank(&dups);
+        let real = ranked[0];
+        //get the size from the filesystem (the true file may have gone since)
+        let size = match self.vfs.get_file(real).and_then(|f| f.get_metadata()) {
+            Ok(md) => Savings::of(&md),
+            Err(e) => {
+                self.aborted = !self.on_error.carry_on(&e);
+                return Savings::default();
+            }
+        };
+        let mut save_size = Savings::default();
+        //Log which files we are not deleting
         info!("{:?} is the true file", real);
+        for f in ranked.iter().take(self.keep).skip(1) {
+            info!("\t{:?} is kept as a copy", f);
+        }
         // iterate over all other duplicates
-        for f in dups.0.iter().filter(|&f| f.as_path() != real) {
+        let mut links = Links::new(&self.vfs, &dups, &ranked[..self.keep.min(ranked.len())]);
+        for f in ranked.iter().skip(self.keep) {
             // log that we will delete them
             info!("\tDeleting {:?}...", f);
-            self.vfs.rm_file(f).expect("Couldn't delete file");
-            // delete vfs handles logging and error printing in the case of errors
-            save_size += size; //and increment the amount of space freed
+            let id = id_of(&self.vfs, &dups, f);
+            // a file we failed to delete frees nothing; report it and move on (or stop)
+            if let Err(e) = self.vfs.rm_file(f) {
+                if !self.on_error.carry_on(&e) {
+                    self.aborted = true;
+                    break;
+                }
+                continue;
+            }
+            // and increment the amount of space freed (unless it lives on as another link)
+            if links.frees(id) {
+                save_size += size;
+            }
         }
         //log the amount of space freed
-        info!("You saved {} bytes by deduplicating this file", save_size);
+        info!("You saved {} by deduplicating this file", save_size);
         save_size
     }
+
+    fn aborted(&self) -> bool {
+        self.aborted
+    }
 }
 
 // implement `act()` for a FileLinker
@@ -144,60 +640,178 @@ impl<V: VFS, S: Selector<V>> FileActor<V, S> for FileLinker<V, S> {
     /// Print which file is the 'true' and which have been replaced with hardlinks to
     /// the that file (and are thus effectively that file), along with
     /// how much space has been freed
-    fn act(&mut self, dups: Duplicates) -> u64 {
+    fn act(&mut self, dups: Duplicates) -> Savings {
+        if self.aborted {
+            return Savings::default();
+        }
+        // hours may have passed since the files were cataloged
+        let dups = match revalidated(&self.vfs, dups, self.revalidate) {
+            Some(dups) => dups,
+            None => return Savings::default(),
+        };
+        // a group of links to one file can't be linked any further: leave it be
+        // (rather than churning its links and their timestamps)
+        let ids: HashSet<Option<ID>> = dups.iter().map(|f| id_of(&self.vfs, &dups, f)).collect();
+        if ids.len() == 1 && !ids.contains(&None) {
+            info!("{:?} and its duplicates are already linked", dups.paths()[0]);
+            return Savings::default();
+        }
         // Select the File:
         // get the file, metadata, size, and device from the vfs
-        let real = self.selector.select(&dups);
-        let real_file = self.vfs.get_file(real).expect("Couldn't find link dst");
-        let real_md = real_file.get_metadata().expect("Couldn't get link dst md");
-        let real_dev = real_md.get_device().expect("Couldn't get link dst device");
-        let size = real_md.get_len();
-        let mut save_size = 0;
+        let ranked = self.selector.rank(&dups);
+        let real = ranked[0];
+        let real_md = match self.vfs.get_file(real).and_then(|f| f.get_metadata()) {
+            Ok(md) => md,
+            Err(e) => {
+                self.aborted = !self.on_error.carry_on(&e);
+                return Savings::default();
+            }
+        };
+        let size = Savings::of(&real_md);
+        let mut save_size = Savings::default();
         //log the 'real' file
         info!("{:?} is the true file", real);
+        for f in ranked.iter().take(self.keep).skip(1) {
+            info!("\t{:?} is kept as a copy", f);
+        }
         // iterate over all other duplicates
-        for f in dups.0.iter().filter(|&f| f.as_path() != real) {
+        let links = Links::new(&self.vfs, &dups, &ranked[..1]);
+        for f in ranked.iter().skip(self.keep) {
+            // nothing to do for a link to the true file already
+            if links.kept_link(id_of(&self.vfs, &dups, f)).is_some() {
+                info!("\t{:?} is already linked to {:?}", f, real);
+                continue;
+            }
             // Check that we can create a hardlink
             let f_dir = f.parent().unwrap(); // can't be a dir so can't be "/"
-            let f_dir_file = self.vfs
-                .get_file(f_dir)
-                .expect("Couldn't find link src parent");
-            let f_dir_md = f_dir_file
-                .get_metadata()
-                .expect("Couldn't get link src parent md");
-            let f_dir_dev = f_dir_md
-                .get_device()
-                .expect("Couldn't get link src parent device");
+            let same_fs = match self.vfs.same_filesystem(real, f_dir) {
+                Ok(same) => same,
+                Err(e) => {
+                    if !self.on_error.carry_on(&e) {
+                        self.aborted = true;
+                        break;
+                    }
+                    continue;
+                }
+            };
             // If not, inform the user.
-            if real_dev != f_dir_dev {
+            if !same_fs {
                 warn!(
-                    "You tried to create a link from directory `{:?}` on device {:?} \
-                     to the file `{:?}` on device {:?}.\n\
+                    "You tried to create a link from directory `{:?}` \
+                     to the file `{:?}` on a different filesystem.\n\
                      Hard-linking across devices is generally an error. \
                      Skipping...",
                     f_dir,
-                    f_dir_dev,
-                    real,
-                    real_dev
+                    real
                 );
             } else {
-                //If we can, log and print that we are deleting of the file
-                info!("\tDeleting {:?}...", f);
-                //println!("\tDeleting `{:?}`...", f);
-                //And deleting it.
-                self.vfs.rm_file(f).expect("Couldn't delete file");
-                //log and print that we are replacing it with a link
-                info!("\t\tand replacing it with a link to {:?}...", real);
-                //println!("\t\tand replacing it with a link to `{:?}`...", real);
-                //and link.
-                self.vfs.make_link(f, real).expect("Couldn't create link");
+                let md = match self.vfs.get_metadata(f) {
+                    Ok(md) => md,
+                    Err(e) => {
+                        if !self.on_error.carry_on(&e) {
+                            self.aborted = true;
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                // a link can only have the permissions of the file it links to
+                if !self.ignore_mode && md.get_mode() != real_md.get_mode() {
+                    warn!(
+                        "{:?} has permissions {:o} but {:?} has {:o}; \
+                         not linking it (see --ignore-permissions-diff)",
+                        f,
+                        md.get_mode(),
+                        real,
+                        real_md.get_mode()
+                    );
+                    continue;
+                }
+                // the duplicate's space is only freed along with its last link
+                // (e.g. it may already be a link to the true file)
+                let last_link = md.get_nlink() <= 1;
+                //If we can, log that we are replacing the file with a link
+                info!("\tReplacing {:?} with a link to {:?}...", f, real);
+                // the duplicate is only swapped out once the link exists,
+                // so a failure leaves it as it was
+                if let Err(e) = self.vfs.replace_with_link(real, f) {
+                    if !self.on_error.carry_on(&e) {
+                        self.aborted = true;
+                        break;
+                    }
+                    continue;
+                }
                 //and increment the amount of space we save
-                save_size += size;
+                if last_link {
+                    save_size += size;
+                }
             }
         }
         // and log and print how much space was saved
-        info!("You saved {} bytes by deduplicating this file", save_size);
+        info!("You saved {} by deduplicating this file", save_size);
         //println!("You saved {} bytes by deduplicating this file", save_size);
         save_size
     }
+
+    fn aborted(&self) -> bool {
+        self.aborted
+    }
+}
+
+// implement `act()` for a FileReflinker
+impl<V: VFS, S: Selector<V>> FileActor<V, S> for FileReflinker<V, S> {
+    /// Make every duplicate a clone of the 'true' file, sharing its storage,
+    /// and return how much space that freed
+    fn act(&mut self, dups: Duplicates) -> Savings {
+        if self.aborted {
+            return Savings::default();
+        }
+        // hours may have passed since the files were cataloged
+        let dups = match revalidated(&self.vfs, dups, self.revalidate) {
+            Some(dups) => dups,
+            None => return Savings::default(),
+        };
+        let ranked = self.selector.rank(&dups);
+        let real = ranked[0];
+        let size = match self.vfs.get_metadata(real) {
+            Ok(md) => Savings::of(&md),
+            Err(e) => {
+                self.aborted = !self.on_error.carry_on(&e);
+                return Savings::default();
+            }
+        };
+        let mut save_size = Savings::default();
+        info!("{:?} is the true file", real);
+        for f in ranked.iter().take(self.keep).skip(1) {
+            info!("\t{:?} is kept as a copy", f);
+        }
+        let mut links = Links::new(&self.vfs, &dups, &ranked[..1]);
+        for f in ranked.iter().skip(self.keep) {
+            // a hard link to the true file already shares its storage
+            let id = id_of(&self.vfs, &dups, f);
+            if links.kept_link(id).is_some() {
+                info!("\t{:?} is already linked to {:?}", f, real);
+                continue;
+            }
+            info!("\tCloning {:?} onto {:?}...", real, f);
+            // unsupported filesystems (and devices apart) fail here, leaving the file as it was
+            if let Err(e) = self.vfs.reflink(real, f) {
+                if !self.on_error.carry_on(&e) {
+                    self.aborted = true;
+                    break;
+                }
+                continue;
+            }
+            // hard links to one duplicate are all cloned at once
+            if links.frees(id) {
+                save_size += size;
+            }
+        }
+        info!("You saved {} by deduplicating this file", save_size);
+        save_size
+    }
+
+    fn aborted(&self) -> bool {
+        self.aborted
+    }
 }
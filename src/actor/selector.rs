@@ -1,22 +1,55 @@
 //! Determine which of the duplicate files shouldn't be touched
 
-use std::cmp::Ordering;
-use std::path::Path;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::marker::PhantomData;
+use std::time::SystemTime;
 
-use vfs::{File, MetaData, VFS};
+use vfs::{DeviceId, MetaData, VFS};
+use helpers::path_bytes;
 use catalog::proxy::Duplicates;
 
 /// Interface for choosing between files
+/// Groups from the catalog have their paths sorted (see `Duplicates::sort`), so a
+/// selector taking the first or last of several equally good files is stable
 pub trait Selector<V: VFS> {
-    /// Indicate that you want the max instead of the min or vice versa
+    /// Prefer files the other way round (reversing twice goes back to the original order)
     fn reverse(&mut self);
     /// Choose which of the Paths in Duplicates is the "true" (unchanged) one
-    fn select<'b>(&self, dups: &'b Duplicates) -> &'b Path;
-    /// Helper to be called by `select`: identify the minimum
-    fn min<'b>(&self, dups: &'b Duplicates) -> &'b Path;
-    /// Helper to be called by `select`: identify the maximum
-    fn max<'b>(&self, dups: &'b Duplicates) -> &'b Path;
+    /// Files the selector can't tell apart go to whichever path's bytes sort first,
+    /// so the choice never depends on the order of `dups`
+    fn select<'b>(&self, dups: &'b Duplicates) -> &'b Path {
+        let candidates: Vec<&Path> = dups.iter().map(|p| &**p).collect();
+        let mut tied = self.narrow(&candidates);
+        tied.sort_by(|a, b| path_bytes(a).cmp(&path_bytes(b)));
+        tied.first().cloned().unwrap_or(&dups.paths()[0])
+    }
+    /// Keep every candidate tied for what `select` would choose
+    /// Lets selectors be chained, each breaking the ties of the last
+    fn narrow<'b>(&self, candidates: &[&'b Path]) -> Vec<&'b Path>;
+    /// Order every path in `dups` from most to least preferred
+    /// The first is always the one `select` chooses; the rest are ranked by
+    /// repeatedly taking the candidates `narrow` would keep, in path order
+    fn rank<'b>(&self, dups: &'b Duplicates) -> Vec<&'b Path> {
+        let first = self.select(dups);
+        let mut rest: Vec<&Path> = dups.iter()
+            .map(|p| &**p)
+            .filter(|&p| p != first)
+            .collect();
+        let mut ranked = vec![first];
+        while !rest.is_empty() {
+            let mut tier = self.narrow(&rest);
+            tier.sort_by(|a, b| path_bytes(a).cmp(&path_bytes(b)));
+            if tier.is_empty() {
+                // shouldn't happen, but don't loop forever
+                ranked.append(&mut rest);
+                break;
+            }
+            rest.retain(|p| !tier.contains(p));
+            ranked.extend(tier);
+        }
+        ranked
+    }
 }
 
 /// Choose between files based on their path
@@ -31,6 +64,33 @@ pub struct DateSelect<V: VFS> {
     vfs: V,
 }
 
+/// Choose files located under a preferred directory
+pub struct PrefixSelect<V: VFS> {
+    reverse: bool,
+    prefix: PathBuf,
+    vfs: PhantomData<V>,
+}
+
+/// Choose files on the most preferred device, e.g. an SSD over a spinning disk
+/// Files on devices not listed (or whose device can't be told) come last
+pub struct DeviceSelect<V: VFS> {
+    reverse: bool,
+    devices: Vec<DeviceId>,
+    vfs: V,
+}
+
+/// Choose files other than those to avoid (e.g. `Report::excluded`), whichever way round
+/// Reversing it does nothing, so it can lead a chain of selectors that is reversed
+pub struct AvoidSelect<V: VFS> {
+    avoid: HashSet<PathBuf>,
+    vfs: PhantomData<V>,
+}
+
+/// Try several selectors in order, only consulting the next one to break a tie
+pub struct ChainSelect<V: VFS> {
+    links: Vec<Box<dyn Selector<V>>>,
+}
+
 // constructor for PathSelect
 impl<V: VFS> PathSelect<V> {
     /// Construct an empty `PathSelect`
@@ -51,6 +111,114 @@ impl<V: VFS> DateSelect<V> {
             vfs: v,
         }
     }
+    // when the file at `path` was last modified, or None (with a warning) if that can't be told
+    fn mod_time(&self, path: &Path) -> Option<SystemTime> {
+        let md = match self.vfs.get_metadata(path) {
+            Ok(md) => md,
+            Err(e) => {
+                warn!("{}", e);
+                return None;
+            }
+        };
+        match md.get_mod_time() {
+            Ok(time) => Some(time),
+            Err(e) => {
+                warn!("Cannot tell when {:?} was modified: {}", path, e);
+                None
+            }
+        }
+    }
+    // sorts files from oldest to newest, then those whose time can't be told
+    fn age(&self, path: &Path) -> (bool, Option<SystemTime>) {
+        let time = self.mod_time(path);
+        (time.is_none(), time)
+    }
+}
+
+// constructor for PrefixSelect
+impl<V: VFS> PrefixSelect<V> {
+    /// Construct a `PrefixSelect` preferring files beneath `prefix`
+    pub fn new<P: AsRef<Path>>(_: V, prefix: P) -> Self {
+        PrefixSelect {
+            reverse: false,
+            prefix: prefix.as_ref().to_path_buf(),
+            vfs: PhantomData,
+        }
+    }
+}
+
+// constructor for DeviceSelect
+impl<V: VFS> DeviceSelect<V> {
+    /// Construct a `DeviceSelect` preferring `devices` in order
+    pub fn new(v: V, devices: Vec<DeviceId>) -> Self {
+        DeviceSelect {
+            reverse: false,
+            devices,
+            vfs: v,
+        }
+    }
+    // where the device of `path` is in the order of preference (lower is better)
+    fn priority(&self, path: &Path) -> usize {
+        let unlisted = self.devices.len();
+        let md = match self.vfs.get_metadata(path) {
+            Ok(md) => md,
+            Err(e) => {
+                warn!("{}", e);
+                return unlisted;
+            }
+        };
+        match md.get_device() {
+            Ok(dev) => self.devices.iter().position(|&d| d == dev).unwrap_or(unlisted),
+            Err(e) => {
+                warn!("Cannot tell the device of {:?}: {}", path, e);
+                unlisted
+            }
+        }
+    }
+}
+
+// constructor for AvoidSelect
+impl<V: VFS> AvoidSelect<V> {
+    /// Construct an `AvoidSelect` that passes over each of `avoid`
+    pub fn new<I: IntoIterator<Item = PathBuf>>(_: V, avoid: I) -> Self {
+        AvoidSelect {
+            avoid: avoid.into_iter().collect(),
+            vfs: PhantomData,
+        }
+    }
+    /// Whether every path in `dups` is to be avoided, so none of them should be kept
+    pub fn avoids_all(&self, dups: &Duplicates) -> bool {
+        dups.iter().all(|p| self.avoid.contains(&**p))
+    }
+}
+
+// constructor for ChainSelect
+impl<V: VFS> ChainSelect<V> {
+    /// Construct a `ChainSelect` from selectors in order of precedence
+    pub fn new(links: Vec<Box<dyn Selector<V>>>) -> Self {
+        ChainSelect { links }
+    }
+}
+
+// helper function for narrowing: keep the candidates with the smallest
+// (or largest, if `max` is set) key
+fn ties<'b, K, F>(candidates: &[&'b Path], key: F, max: bool) -> Vec<&'b Path>
+where
+    K: Ord,
+    F: Fn(&Path) -> K,
+{
+    let keys: Vec<K> = candidates.iter().map(|p| key(p)).collect();
+    let best = if max {
+        keys.iter().max()
+    } else {
+        keys.iter().min()
+    };
+    candidates
+        .iter()
+        .zip(&keys)
+        .filter(|&(_, k)| Some(k) == best)
+        .map(|(&p, _)| p)
+        .collect()
 }
 
 // implement Selector for heap/trait objects
@@ -61,91 +229,85 @@ impl<V: VFS> Selector<V> for Box<Selector<V>> {
     fn select<'b>(&self, dups: &'b Duplicates) -> &'b Path {
         (**self).select(dups)
     }
-    fn min<'b>(&self, dups: &'b Duplicates) -> &'b Path {
-        (**self).min(dups)
-    }
-    fn max<'b>(&self, dups: &'b Duplicates) -> &'b Path {
-        (**self).max(dups)
+    fn narrow<'b>(&self, candidates: &[&'b Path]) -> Vec<&'b Path> {
+        (**self).narrow(candidates)
     }
 }
 
 // implement Selector based on filepaths
 impl<V: VFS> Selector<V> for PathSelect<V> {
     fn reverse(&mut self) {
-        self.reverse = true;
+        self.reverse = !self.reverse;
     }
-    fn select<'b>(&self, dups: &'b Duplicates) -> &'b Path {
-        // select the shallowest element (the path is the shortest)
-        if self.reverse {
-            self.max(dups)
-        } else {
-            self.min(dups)
-        }
+    // keep all files as close to (or as far from) the root as the selection
+    fn narrow<'b>(&self, candidates: &[&'b Path]) -> Vec<&'b Path> {
+        ties(candidates, |p| p.components().count(), self.reverse)
     }
-    // select the file closest to the root
-    fn min<'b>(&self, dups: &'b Duplicates) -> &'b Path {
-        dups.0
-            .iter()
-            .min_by(|&a_path, &b_path| {
-                let a_score = a_path.components().count();
-                let b_score = b_path.components().count();
-                a_score.cmp(&b_score)
-            })
-            .unwrap() // is only None if `dups` is empty
-    }
-    // select the file farthest from the root
-    fn max<'b>(&self, dups: &'b Duplicates) -> &'b Path {
-        dups.0
-            .iter()
-            .max_by(|&a_path, &b_path| {
-                let a_score = a_path.components().count();
-                let b_score = b_path.components().count();
-                a_score.cmp(&b_score)
-            })
-            .unwrap()
-    }
-}
-
-// helper function for comparing two Files based on their date
-fn date_cmp<'a, T: File>(a: &'a T, b: &'a T) -> Ordering {
-    let md_a = a.get_metadata().expect("Failed to get metadata");
-    let md_b = b.get_metadata().expect("Failed to get metadata");
-    let date_a = md_a.get_mod_time()
-        .expect("Failed to get modification time");
-    let date_b = md_b.get_mod_time()
-        .expect("Failed to get modification time");
-    date_a.cmp(&date_b)
 }
 
 // implement Selector based on modification date
 impl<V: VFS> Selector<V> for DateSelect<V> {
     fn reverse(&mut self) {
-        self.reverse = true;
-    }
-    // select the file modified most recently
-    fn min<'b>(&self, dups: &'b Duplicates) -> &'b Path {
-        dups.0
-            .iter()
-            .map(|path| (path, self.vfs.get_file(path).expect("Failed to get file")))
-            .min_by(|&(_, ref a), &(_, ref b)| date_cmp(a, b))
-            .unwrap() // safe to assume >0 files
-            .0
-    }
-    // select the file modified first
-    fn max<'b>(&self, dups: &'b Duplicates) -> &'b Path {
-        dups.0
-            .iter()
-            .map(|path| (path, self.vfs.get_file(path).expect("Failed to get file")))
-            .max_by(|&(_, ref a), &(_, ref b)| date_cmp(a, b))
-            .unwrap() // safe to assume >0 files
-            .0
+        self.reverse = !self.reverse;
     }
-    fn select<'b>(&self, dups: &'b Duplicates) -> &'b Path {
-        // select the newest element (the SystemTime is the largest)
+    // keep all files modified at the same time as the selection
+    fn narrow<'b>(&self, candidates: &[&'b Path]) -> Vec<&'b Path> {
         if self.reverse {
-            self.min(dups)
+            ties(candidates, |p| self.age(p), false)
         } else {
-            self.max(dups)
+            ties(candidates, |p| self.mod_time(p), true)
+        }
+    }
+}
+
+// implement Selector based on whether a file is beneath a directory
+impl<V: VFS> Selector<V> for PrefixSelect<V> {
+    fn reverse(&mut self) {
+        self.reverse = !self.reverse;
+    }
+    // keep all files under the prefix (or all outside it if reversed)
+    fn narrow<'b>(&self, candidates: &[&'b Path]) -> Vec<&'b Path> {
+        ties(candidates, |p| p.starts_with(&self.prefix), !self.reverse)
+    }
+}
+
+// implement Selector based on the device a file is on
+impl<V: VFS> Selector<V> for DeviceSelect<V> {
+    fn reverse(&mut self) {
+        self.reverse = !self.reverse;
+    }
+    // keep all files on the same device as the selection (or equally unlisted ones)
+    fn narrow<'b>(&self, candidates: &[&'b Path]) -> Vec<&'b Path> {
+        ties(candidates, |p| self.priority(p), self.reverse)
+    }
+}
+
+// implement Selector based on whether a file is to be avoided
+impl<V: VFS> Selector<V> for AvoidSelect<V> {
+    // an avoided file is never preferred, so there's nothing to reverse
+    fn reverse(&mut self) {}
+    // keep all files not to be avoided (or all of them, if every one is)
+    fn narrow<'b>(&self, candidates: &[&'b Path]) -> Vec<&'b Path> {
+        ties(candidates, |p| self.avoid.contains(p), false)
+    }
+}
+
+// implement Selector by deferring to each link until there's a single candidate
+impl<V: VFS> Selector<V> for ChainSelect<V> {
+    // reverse the criterion of every link
+    fn reverse(&mut self) {
+        for link in &mut self.links {
+            link.reverse();
+        }
+    }
+    fn narrow<'b>(&self, candidates: &[&'b Path]) -> Vec<&'b Path> {
+        let mut candidates = candidates.to_vec();
+        for link in &self.links {
+            if candidates.len() <= 1 {
+                break;
+            }
+            candidates = link.narrow(&candidates);
         }
+        candidates
     }
 }
@@ -2,11 +2,15 @@
 mod test {
 
     use helpers::ID;
-    use actor::{FileActor, FileDeleter, FileLinker, FilePrinter};
-    use actor::selector::{DateSelect, PathSelect, Selector};
-    use vfs::{FileType, TestFile, TestFileSystem, TestMD};
-    use catalog::proxy::Duplicates;
+    use actor::{FileActor, FileDeleter, FileLinker, FilePrinter, FileReflinker, Savings};
+    use actor::tree::{self, Action};
+    use actor::selector::{ChainSelect, DateSelect, DeviceSelect, PathSelect, PrefixSelect,
+                          Selector};
+    use vfs::{staging_path, DeviceId, File, FileType, FsOp, MetaData, OnError, Operation,
+              OverlayFileSystem, ReadOnlyVfs, TestFile, TestFileSystem, TestMD, VFS};
+    use catalog::proxy::{Duplicates, Stamp};
 
+    use std::io;
     use std::path::{Path, PathBuf};
     use std::time::{Duration, UNIX_EPOCH};
 
@@ -28,7 +32,7 @@ mod test {
             fs.add(TestFile::new("/x/y/z/d"));
         }
         let paths = vec!["/a", "/x/b", "/x/y/c", "/x/y/z/d"];
-        let files = Duplicates(paths.iter().map(PathBuf::from).collect());
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
         let shortest = PathSelect::new(fs).select(&files);
         assert_eq!(shortest, Path::new("/a"));
     }
@@ -49,7 +53,7 @@ mod test {
             fs.add(TestFile::new("/x/y/z/d"));
         }
         let paths = vec!["/a", "/x/b", "/x/y/c", "/x/y/z/d"];
-        let files = Duplicates(paths.iter().map(PathBuf::from).collect());
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
         let mut selector = PathSelect::new(fs);
         selector.reverse();
         let longest = selector.select(&files);
@@ -80,7 +84,7 @@ mod test {
             fs.add(TestFile::new("/x/y/z/d").with_metadata(md_d));
         }
         let paths = vec!["/a", "/x/b", "/x/y/c", "/x/y/z/d"];
-        let files = Duplicates(paths.iter().map(PathBuf::from).collect());
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
         let newest = DateSelect::new(fs).select(&files);
         assert_eq!(newest, Path::new("/x/y/z/d"));
     }
@@ -109,7 +113,7 @@ mod test {
             fs.add(TestFile::new("/x/y/z/d").with_metadata(md_d));
         }
         let paths = vec!["/a", "/x/b", "/x/y/c", "/x/y/z/d"];
-        let files = Duplicates(paths.iter().map(PathBuf::from).collect());
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
 
         let mut selector = DateSelect::new(fs.clone());
         selector.reverse();
@@ -117,12 +121,159 @@ mod test {
         assert_eq!(oldest, Path::new("/a"));
     }
 
+    #[test]
+    fn select_chain() {
+        // prefer files under /master, then the newest, then the shallowest
+        let fs = TestFileSystem::new();
+        let time_a = UNIX_EPOCH + Duration::new(1, 0); // + 1 second
+        let time_b = UNIX_EPOCH + Duration::new(2, 0); // + 2 seconds
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.create_dir("/master");
+            fs.create_dir("/master/x");
+            fs.create_dir("/other");
+            let md = |t| TestMD::new().with_mod_time(t);
+            fs.add(TestFile::new("/master/a").with_metadata(md(time_a)));
+            fs.add(TestFile::new("/master/x/b").with_metadata(md(time_b)));
+            fs.add(TestFile::new("/master/c").with_metadata(md(time_b)));
+            fs.add(TestFile::new("/other/d").with_metadata(md(time_b)));
+            fs.add(TestFile::new("/other/x/e").with_metadata(md(time_b)));
+            fs.add(TestFile::new("/f").with_metadata(md(time_a)));
+        }
+        let chain = ChainSelect::new(vec![
+            Box::new(PrefixSelect::new(fs.clone(), "/master")),
+            Box::new(DateSelect::new(fs.clone())),
+            Box::new(PathSelect::new(fs.clone())),
+        ]);
+
+        // prefix leaves a, b, c; date leaves b, c; path leaves c
+        let paths = ["/master/x/b", "/other/d", "/master/a", "/master/c"];
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+        assert_eq!(chain.select(&files), Path::new("/master/c"));
+
+        // no file under the prefix: date leaves d and e; path leaves d
+        let paths = ["/other/x/e", "/f", "/other/d"];
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+        assert_eq!(chain.select(&files), Path::new("/other/d"));
+
+        // a single criterion decides without consulting the rest
+        let paths = ["/f", "/master/a"];
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+        assert_eq!(chain.select(&files), Path::new("/master/a"));
+    }
+
+    #[test]
+    fn select_preferred_device() {
+        // keep the file on the most preferred device, wherever it is in the tree
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.create_dir("/ssd");
+            fs.create_dir("/ssd/deep");
+            for &(path, dev) in &[("/hdd", 1), ("/ssd/deep/a", 2), ("/usb", 3), ("/nvme", 4)] {
+                let md = TestMD::new().with_id(ID { dev, inode: dev });
+                fs.add(TestFile::new(path).with_metadata(md));
+            }
+        }
+        let paths = ["/hdd", "/ssd/deep/a", "/usb"];
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+        let mut selector = DeviceSelect::new(fs.clone(), vec![DeviceId(2), DeviceId(1)]);
+        assert_eq!(selector.select(&files), Path::new("/ssd/deep/a"));
+        let ranked: Vec<&Path> = ["/ssd/deep/a", "/hdd", "/usb"].iter().map(Path::new).collect();
+        assert_eq!(ranked, selector.rank(&files));
+
+        // a file on an unlisted device is only kept if nothing's on a listed one
+        // (and between unlisted ones, the path sorting first is)
+        let paths = ["/usb", "/nvme"];
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+        assert_eq!(selector.select(&files), Path::new("/nvme"));
+
+        selector.reverse();
+        let paths = ["/hdd", "/ssd/deep/a"];
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+        assert_eq!(selector.select(&files), Path::new("/hdd"));
+    }
+
+    #[test]
+    fn select_rank() {
+        // ranking starts with the selection and orders the rest by preference
+        let fs = TestFileSystem::new();
+        let paths = ["/x/y/c", "/a", "/x/y/z/d", "/x/b"];
+        let dups = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+        let mut selector = PathSelect::new(fs.clone());
+        assert_eq!(selector.select(&dups), selector.rank(&dups)[0]);
+        let expected: Vec<&Path> = ["/a", "/x/b", "/x/y/c", "/x/y/z/d"]
+            .iter()
+            .map(Path::new)
+            .collect();
+        assert_eq!(expected, selector.rank(&dups));
+        selector.reverse();
+        let reversed: Vec<&Path> = expected.iter().rev().cloned().collect();
+        assert_eq!(reversed, selector.rank(&dups));
+        // and reversing it again puts it back
+        selector.reverse();
+        assert_eq!(expected, selector.rank(&dups));
+
+        // as does reversing a chain whose first link was reversed already
+        let mut deepest = PathSelect::new(fs.clone());
+        deepest.reverse();
+        let mut chain = ChainSelect::new(vec![Box::new(deepest) as Box<dyn Selector<_>>]);
+        assert_eq!(Path::new("/x/y/z/d"), chain.select(&dups));
+        chain.reverse();
+        assert_eq!(Path::new("/a"), chain.select(&dups));
+    }
+
+    #[test]
+    fn select_ties_by_path() {
+        // when every selector ties, the path sorting first is kept, whatever the order
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.create_dir("/x");
+            fs.create_dir("/y");
+            for path in &["/x/b", "/x/a", "/y/a"] {
+                let md = TestMD::new().with_mod_time(UNIX_EPOCH).with_id(ID { dev: 1, inode: 1 });
+                fs.add(TestFile::new(path).with_metadata(md));
+            }
+        }
+        let selectors: Vec<Box<dyn Selector<_>>> = vec![
+            Box::new(PathSelect::new(fs.clone())),
+            Box::new(DateSelect::new(fs.clone())),
+            Box::new(PrefixSelect::new(fs.clone(), "/z")),
+            Box::new(DeviceSelect::new(fs.clone(), vec![DeviceId(1)])),
+            Box::new(ChainSelect::new(vec![
+                Box::new(DateSelect::new(fs.clone())),
+                Box::new(PathSelect::new(fs.clone())),
+            ])),
+        ];
+        let orders = [
+            ["/x/b", "/y/a", "/x/a"],
+            ["/y/a", "/x/a", "/x/b"],
+            ["/x/a", "/x/b", "/y/a"],
+        ];
+        let expected: Vec<&Path> = ["/x/a", "/x/b", "/y/a"].iter().map(Path::new).collect();
+        for mut selector in selectors {
+            for order in &orders {
+                let dups = Duplicates::new(order.iter().map(PathBuf::from).collect());
+                assert_eq!(Path::new("/x/a"), selector.select(&dups));
+                assert_eq!(expected, selector.rank(&dups));
+            }
+            // reversing a tie is still a tie
+            selector.reverse();
+            let dups = Duplicates::new(orders[0].iter().map(PathBuf::from).collect());
+            assert_eq!(Path::new("/x/a"), selector.select(&dups));
+        }
+    }
+
     // actor tests
 
     #[test]
     fn actor_print() {
         // run `FilePrinter::act()` on a set of duplicates
-        // verify the filesystem doesn't change
+        // verify the filesystem doesn't change (it couldn't if it tried)
 
         let fs = TestFileSystem::new();
         {
@@ -134,12 +285,235 @@ mod test {
             fs.add(TestFile::new("/x/c").with_metadata(TestMD::new()));
         };
         let paths = vec!["/a", "/x/b", "/x/c"];
-        let files = Duplicates(paths.iter().map(PathBuf::from).collect());
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
 
-        let selector = PathSelect::new(fs.clone());
-        let mut actor = FilePrinter::new(fs.clone(), selector);
+        let guarded = ReadOnlyVfs::new(fs.clone());
+        let selector = PathSelect::new(guarded.clone());
+        let mut actor = FilePrinter::new(guarded, selector);
         actor.act(files);
         assert_eq!(5, fs.borrow().num_elements());
+        assert!(fs.borrow().ops().is_empty());
+    }
+
+    #[test]
+    fn actor_read_only_guard() {
+        // an actor that tries to change a read-only filesystem gets errors instead
+
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'A' inode=1
+             file /b 'A' inode=2
+             file /c 'A' inode=3",
+        ).unwrap();
+        let paths = ["/a", "/b", "/c"];
+        let files = || Duplicates::new(paths.iter().map(PathBuf::from).collect());
+
+        let guarded = ReadOnlyVfs::new(fs.clone());
+        let mut deleter = FileDeleter::new(guarded.clone(), PathSelect::new(guarded.clone()));
+        assert_eq!(Savings::default(), deleter.act(files()));
+        let mut linker = FileLinker::new(guarded.clone(), PathSelect::new(guarded.clone()));
+        assert_eq!(Savings::default(), linker.act(files()));
+
+        let err = guarded.clone().rm_file(&Path::new("/b")).unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+        assert_eq!(Operation::Delete, err.op());
+        // nothing was touched, but everything can still be read
+        assert!(fs.borrow().ops().is_empty());
+        assert_eq!(4, fs.borrow().num_elements());
+        assert_eq!(3, guarded.get_metadata("/c").unwrap().get_inode().0);
+    }
+
+    #[test]
+    fn actor_print_to_buffer() {
+        // run `FilePrinter::act()` with an in-memory sink
+        // verify every path ends up in the output and the keeper is listed first
+
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.add(TestFile::new("/a").with_metadata(TestMD::new().with_len(10)).with_inode(1));
+            fs.create_dir("/x");
+            fs.add(TestFile::new("/x/b").with_metadata(TestMD::new().with_len(10)).with_inode(2));
+            fs.add(TestFile::new("/x/c").with_metadata(TestMD::new().with_len(10)).with_inode(3));
+        };
+        let paths = ["/x/b", "/a", "/x/c"];
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+
+        let selector = PathSelect::new(fs.clone());
+        let mut actor = FilePrinter::with_output(fs.clone(), selector, Vec::<u8>::new());
+        let saved = actor.act(files);
+        assert_eq!(20, saved.logical);
+        assert_eq!(20, saved.allocated);
+        assert!(!saved.differs_significantly());
+
+        let out = String::from_utf8(actor.get_output().clone()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(3, lines.len());
+        assert_eq!("/a is the true file", lines[0]);
+        assert!(lines.contains(&"\t/x/b is a duplicate"));
+        assert!(lines.contains(&"\t/x/c is a duplicate"));
+        assert_eq!(5, fs.borrow().num_elements());
+    }
+
+    #[test]
+    fn actor_print_verbose_links() {
+        // in verbose mode, files that already have several hard links are annotated
+
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.add(TestFile::new("/a").with_metadata(TestMD::new().with_len(10)).with_inode(1));
+            let md = TestMD::new().with_len(10).with_nlink(3);
+            fs.add(TestFile::new("/b").with_metadata(md).with_inode(2));
+        };
+        let group = || Duplicates::new(vec![PathBuf::from("/a"), PathBuf::from("/b")]);
+
+        let selector = PathSelect::new(fs.clone());
+        let mut actor =
+            FilePrinter::with_output(fs.clone(), selector, Vec::<u8>::new()).verbose(true);
+        actor.act(group());
+        let out = String::from_utf8(actor.get_output().clone()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(vec!["/a is the true file", "\t/b is a duplicate (already linked x3)"], lines);
+
+        // without it the output is unchanged
+        let selector = PathSelect::new(fs.clone());
+        let mut actor = FilePrinter::with_output(fs.clone(), selector, Vec::<u8>::new());
+        actor.act(group());
+        let out = String::from_utf8(actor.get_output().clone()).unwrap();
+        assert!(!out.contains("already linked"));
+    }
+
+    #[test]
+    fn actor_print_relative_to() {
+        // paths beneath the base are printed relative to it, others in full
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             dir /home
+             dir /home/me
+             dir /home/me/x
+             dir /mnt
+             file /home/me/a 'AAAA'
+             file /home/me/x/b 'AAAA'
+             file /mnt/c 'AAAA'",
+        ).unwrap();
+        let paths = ["/home/me/x/b", "/mnt/c", "/home/me/a"];
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+
+        let selector = PathSelect::new(fs.clone());
+        let mut actor = FilePrinter::with_output(fs.clone(), selector, Vec::<u8>::new())
+            .relative_to("/home/me");
+        actor.act(files);
+        let out = String::from_utf8(actor.get_output().clone()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        let expected = vec![
+            "/mnt/c is the true file",
+            "\ta is a duplicate",
+            "\tx/b is a duplicate",
+        ];
+        assert_eq!(expected, lines);
+    }
+
+    #[test]
+    fn actor_print_already_linked() {
+        // hard links to the true file are flagged and free nothing,
+        // and two links to one duplicate free it only once
+
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            for &(path, inode) in &[("/a", 1), ("/b", 1), ("/c", 2), ("/d", 2)] {
+                let md = TestMD::new().with_len(10);
+                fs.add(TestFile::new(path).with_metadata(md).with_inode(inode));
+            }
+        };
+        let paths = ["/a", "/b", "/c", "/d"];
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+
+        let selector = PathSelect::new(fs.clone());
+        let mut actor = FilePrinter::with_output(fs.clone(), selector, Vec::<u8>::new());
+        let saved = actor.act(files);
+        assert_eq!(10, saved.logical);
+
+        let out = String::from_utf8(actor.get_output().clone()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        let expected = [
+            "/a is the true file",
+            "\t/b is already linked to /a",
+            "\t/c is a duplicate",
+            "\t/d is a duplicate",
+        ];
+        assert_eq!(&expected[..], &lines[..]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn actor_print0_non_utf8() {
+        // with `print0` only the duplicates are written, as raw NUL-terminated bytes
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let odd = Path::new(OsStr::from_bytes(b"/x/\xff\xfe.txt"));
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.create_dir("/x");
+            fs.add(TestFile::new("/a").with_metadata(TestMD::new().with_len(10)));
+            fs.add(TestFile::new(odd).with_metadata(TestMD::new().with_len(10)));
+            fs.add(TestFile::new("/x/c").with_metadata(TestMD::new().with_len(10)));
+        };
+        let files =
+            Duplicates::new(vec![odd.to_path_buf(), PathBuf::from("/a"), PathBuf::from("/x/c")]);
+
+        let selector = PathSelect::new(fs.clone());
+        let mut actor =
+            FilePrinter::with_output(fs.clone(), selector, Vec::<u8>::new()).print0(true);
+        actor.act(files);
+
+        let out = actor.get_output();
+        let mut printed: Vec<&[u8]> = out.split(|&b| b == 0).collect();
+        assert_eq!(Some(&&b""[..]), printed.last());
+        printed.pop();
+        printed.sort();
+        assert_eq!(vec![&b"/x/c"[..], &b"/x/\xff\xfe.txt"[..]], printed);
+    }
+
+    #[test]
+    fn actor_sparse_savings() {
+        // sparse duplicates should report the space they occupy, not their length
+
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            let md = TestMD::new().with_len(1 << 30).with_allocated_size(4096);
+            fs.add(TestFile::new("/a").with_metadata(md).with_inode(1));
+            fs.add(TestFile::new("/b").with_metadata(md).with_inode(2));
+            fs.add(TestFile::new("/c").with_metadata(md).with_inode(3));
+        };
+        let paths = ["/a", "/b", "/c"];
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+
+        let selector = PathSelect::new(fs.clone());
+        let mut actor = FilePrinter::with_output(fs.clone(), selector, Vec::<u8>::new());
+        let saved = actor.act(files);
+        assert_eq!(2 << 30, saved.logical);
+        assert_eq!(2 * 4096, saved.allocated);
+        assert!(saved.differs_significantly());
+
+        let mut total = Savings::default();
+        total += saved;
+        total += Savings {
+            logical: 100,
+            allocated: 100,
+        };
+        assert_eq!((2 << 30) + 100, total.logical);
+        assert_eq!(2 * 4096 + 100, total.allocated);
+        assert!(format!("{}", total).contains("apparent size"));
     }
 
     #[test]
@@ -157,12 +531,322 @@ mod test {
             fs.add(TestFile::new("/x/c").with_metadata(TestMD::new()));
         };
         let paths = vec!["/a", "/x/b", "/x/c"];
-        let files = Duplicates(paths.iter().map(PathBuf::from).collect());
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
 
         let selector = PathSelect::new(fs.clone());
         let mut actor = FileDeleter::new(fs.clone(), selector);
         actor.act(files);
         assert_eq!(3, fs.borrow().num_elements());
+
+        // exactly the non-selected files were deleted, in order
+        let expected = [
+            FsOp::Delete(PathBuf::from("/x/b")),
+            FsOp::Delete(PathBuf::from("/x/c")),
+        ];
+        assert_eq!(&expected[..], fs.borrow().ops());
+        assert!(!fs.borrow().ops().contains(&FsOp::Delete(PathBuf::from("/a"))));
+    }
+
+    #[test]
+    fn actor_uses_cataloged_ids() {
+        // files a group knows the identity of aren't looked up again to act on them
+        let spec = "dir /
+             file /a 'AAAA' inode=1
+             file /b 'AAAA' inode=1
+             file /c 'AAAA' inode=3
+             file /d 'AAAA' inode=4";
+        let paths = ["/a", "/b", "/c", "/d"];
+        // a fresh filesystem, and the group as the catalog would stamp it
+        let stamped = || {
+            let fs = TestFileSystem::from_spec(spec).unwrap();
+            let dups = paths.iter().fold(
+                Duplicates::new(paths.iter().map(PathBuf::from).collect()),
+                |dups, path| {
+                    let md = fs.get_metadata(path).unwrap();
+                    let stamp = Stamp {
+                        size: md.get_len(),
+                        modified: md.get_mod_time().ok(),
+                        id: ID::of(&md).unwrap(),
+                    };
+                    dups.with_stamp(path, stamp)
+                },
+            );
+            let before = fs.borrow().num_metadata_reads();
+            (fs, dups.with_distinct_files(3), before)
+        };
+
+        // only the true file's size is looked up, by printing and deleting alike
+        let (fs, files, before) = stamped();
+        let mut printer = FilePrinter::with_output(fs.clone(), PathSelect::new(fs.clone()), vec![]);
+        assert_eq!(8, printer.act(files).logical);
+        assert_eq!(before + 1, fs.borrow().num_metadata_reads());
+
+        let (fs, files, before) = stamped();
+        let mut actor = FileDeleter::new(fs.clone(), PathSelect::new(fs.clone())).revalidate(false);
+        // `/b` is a link to the file kept, so deleting it frees nothing
+        assert_eq!(8, actor.act(files).logical);
+        assert_eq!(before + 1, fs.borrow().num_metadata_reads());
+        assert_eq!(3, fs.borrow().ops().len());
+
+        // revalidating (as by default) checks each file once more, and no more than that
+        let (fs, files, before) = stamped();
+        let mut actor = FileDeleter::new(fs.clone(), PathSelect::new(fs.clone()));
+        assert_eq!(8, actor.act(files).logical);
+        assert_eq!(before + 4 + 1, fs.borrow().num_metadata_reads());
+    }
+
+    #[test]
+    fn actor_delete_failure() {
+        // a file that can't be deleted is reported and skipped
+        // the rest of the group is still deleted
+
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.add(TestFile::new("/a").with_metadata(TestMD::new().with_len(10)).with_inode(1));
+            fs.add(TestFile::new("/b").with_metadata(TestMD::new().with_len(10)).with_inode(2));
+            fs.add(TestFile::new("/c").with_metadata(TestMD::new().with_len(10)).with_inode(3));
+            fs.fail_next(Operation::Delete, "/b", io::ErrorKind::PermissionDenied);
+        };
+        let paths = ["/a", "/b", "/c"];
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+
+        let selector = PathSelect::new(fs.clone());
+        let mut actor = FileDeleter::new(fs.clone(), selector);
+        let saved = actor.act(files);
+        assert_eq!(10, saved.logical);
+        assert_eq!(3, fs.borrow().num_elements());
+        assert_eq!(&[FsOp::Delete(PathBuf::from("/c"))][..], fs.borrow().ops());
+    }
+
+    #[test]
+    fn actor_delete_abort() {
+        // with `Abort`, nothing more is deleted after a file that can't be
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1
+             file /b 'AAAA' inode=2
+             file /c 'AAAA' inode=3
+             file /d 'DD' inode=4
+             file /e 'DD' inode=5",
+        ).unwrap();
+        fs.borrow_mut().fail_always(Operation::Delete, "/b", io::ErrorKind::PermissionDenied);
+        let group = |paths: &[&str]| Duplicates::new(paths.iter().map(PathBuf::from).collect());
+
+        let mut actor = FileDeleter::new(fs.clone(), PathSelect::new(fs.clone()))
+            .on_error(OnError::Abort);
+        assert!(!actor.aborted());
+        assert_eq!(0, actor.act(group(&["/a", "/b", "/c"])).logical);
+        assert!(actor.aborted());
+        assert_eq!(0, actor.act(group(&["/d", "/e"])).logical);
+        assert!(fs.borrow().ops().is_empty());
+
+        // skipping it instead carries on with the rest
+        let mut actor = FileDeleter::new(fs.clone(), PathSelect::new(fs.clone()))
+            .on_error(OnError::Skip);
+        assert_eq!(4, actor.act(group(&["/a", "/b", "/c"])).logical);
+        assert_eq!(2, actor.act(group(&["/d", "/e"])).logical);
+        assert!(!actor.aborted());
+        assert_eq!(2, fs.borrow().ops().len());
+    }
+
+    #[test]
+    fn actor_file_vanished() {
+        // a file that's gone by the time its group is acted on is dealt with as `on_error`
+        // says, rather than panicking; here it's the true file, so nothing can be sized
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1
+             file /b 'AAAA' inode=2
+             file /c 'AAAA' inode=3
+             file /d 'DD' inode=4
+             file /e 'DD' inode=5",
+        ).unwrap();
+        fs.clone().rm_file(&"/a").unwrap();
+        fs.borrow_mut().clear_ops();
+        let group = |paths: &[&str]| Duplicates::new(paths.iter().map(PathBuf::from).collect());
+
+        let mut deleter = FileDeleter::new(fs.clone(), PathSelect::new(fs.clone()))
+            .revalidate(false)
+            .on_error(OnError::Abort);
+        assert_eq!(0, deleter.act(group(&["/a", "/b", "/c"])).logical);
+        assert!(deleter.aborted());
+        let mut linker = FileLinker::new(fs.clone(), PathSelect::new(fs.clone()))
+            .revalidate(false)
+            .on_error(OnError::Abort);
+        assert_eq!(0, linker.act(group(&["/a", "/b", "/c"])).logical);
+        assert!(linker.aborted());
+        let selector = PathSelect::new(fs.clone());
+        let mut printer =
+            FilePrinter::with_output(fs.clone(), selector, Vec::new()).on_error(OnError::Abort);
+        assert_eq!(0, printer.act(group(&["/a", "/b", "/c"])).logical);
+        assert!(printer.aborted());
+        assert!(printer.get_output().is_empty());
+        assert!(fs.borrow().ops().is_empty());
+
+        // warning about it instead leaves that group alone and carries on with the rest
+        let mut deleter = FileDeleter::new(fs.clone(), PathSelect::new(fs.clone()))
+            .revalidate(false)
+            .on_error(OnError::Warn);
+        assert_eq!(0, deleter.act(group(&["/a", "/b", "/c"])).logical);
+        assert_eq!(2, deleter.act(group(&["/d", "/e"])).logical);
+        assert!(!deleter.aborted());
+        assert_eq!(&[FsOp::Delete(PathBuf::from("/e"))][..], fs.borrow().ops());
+
+        // and choosing by date ranks it last either way
+        let files = group(&["/a", "/b", "/c"]);
+        let mut selector = DateSelect::new(fs.clone());
+        assert_eq!(Path::new("/a"), *selector.rank(&files).last().unwrap());
+        assert_ne!(Path::new("/a"), selector.select(&files));
+        selector.reverse();
+        assert_eq!(Path::new("/a"), *selector.rank(&files).last().unwrap());
+        assert_ne!(Path::new("/a"), selector.select(&files));
+    }
+
+    #[test]
+    fn actor_delete_overlay() {
+        // run `FileDeleter::act()` through an overlay
+        // the deletes are recorded in order but the mock itself is untouched
+
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.add(TestFile::new("/a").with_metadata(TestMD::new()));
+            fs.create_dir("/x");
+            fs.add(TestFile::new("/x/b").with_metadata(TestMD::new()));
+            fs.add(TestFile::new("/x/c").with_metadata(TestMD::new()));
+        };
+        let paths = ["/x/c", "/a", "/x/b"];
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+
+        let overlay = OverlayFileSystem::new(fs.clone());
+        let selector = PathSelect::new(overlay.clone());
+        let mut actor = FileDeleter::new(overlay.clone(), selector);
+        actor.act(files);
+
+        let expected = [
+            FsOp::Delete(PathBuf::from("/x/b")),
+            FsOp::Delete(PathBuf::from("/x/c")),
+        ];
+        assert_eq!(&expected[..], &overlay.ops()[..]);
+        assert!(overlay.get_file(Path::new("/x/b")).is_err());
+        assert!(overlay.get_file(Path::new("/a")).is_ok());
+        assert_eq!(5, fs.borrow().num_elements());
+        assert!(fs.borrow().ops().is_empty());
+
+        // deleting again fails since the overlay remembers
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+        let selector = PathSelect::new(overlay.clone());
+        let mut actor = FileDeleter::new(overlay.clone(), selector);
+        actor.act(files);
+        assert_eq!(2, overlay.ops().len());
+    }
+
+    #[test]
+    fn actor_link_overlay() {
+        // run `FileLinker::act()` through an overlay
+        // each duplicate is linked at a staging name and then renamed over, so its path
+        // never stops existing; the mock itself is untouched
+
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.add(TestFile::new("/a").with_metadata(TestMD::new()).with_inode(1));
+            fs.add(TestFile::new("/b").with_metadata(TestMD::new()).with_inode(2));
+        };
+        let files = Duplicates::new(vec![PathBuf::from("/a"), PathBuf::from("/b")]);
+
+        let overlay = OverlayFileSystem::new(fs.clone());
+        let selector = PathSelect::new(overlay.clone());
+        let mut actor = FileLinker::new(overlay.clone(), selector);
+        actor.act(files);
+
+        let staged = staging_path(Path::new("/b"));
+        let expected = [
+            FsOp::HardLink {
+                src: PathBuf::from("/a"),
+                dst: staged.clone(),
+            },
+            FsOp::Rename {
+                from: staged.clone(),
+                to: PathBuf::from("/b"),
+            },
+        ];
+        assert_eq!(&expected[..], &overlay.ops()[..]);
+        assert_eq!(1, overlay.get_file(Path::new("/b")).unwrap().get_inode().unwrap().0);
+        assert!(overlay.get_file(&staged).is_err());
+        assert!(fs.borrow().ops().is_empty());
+    }
+
+    #[test]
+    fn actor_reflink() {
+        // every duplicate is cloned from the kept file: it shares its storage, but keeps
+        // its own inode and permissions; one the filesystem can't clone is left alone
+
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            for (i, name) in ["/a", "/bb", "/ccc", "/dddd"].iter().enumerate() {
+                let md = TestMD::new().with_mode(0o600 + i as u32);
+                let file = TestFile::new(name)
+                    .with_contents(String::from("same"))
+                    .with_metadata(md)
+                    .with_inode(i as u64 + 1);
+                fs.add(file);
+            }
+            fs.fail_next(Operation::Reflink, "/dddd", io::ErrorKind::Other);
+        };
+        let paths = vec!["/a", "/bb", "/ccc", "/dddd"].into_iter().map(PathBuf::from).collect();
+        let files = Duplicates::new(paths);
+
+        let mut actor = FileReflinker::new(fs.clone(), PathSelect::new(fs.clone()));
+        let saved = actor.act(files);
+        assert_eq!(8, saved.logical);
+
+        let cloned = |dst: &str| FsOp::Reflink {
+            src: PathBuf::from("/a"),
+            dst: PathBuf::from(dst),
+        };
+        assert_eq!(&[cloned("/bb"), cloned("/ccc")][..], fs.borrow().ops());
+        for (i, name) in ["/bb", "/ccc"].iter().enumerate() {
+            let md = fs.get_metadata(name).unwrap();
+            assert_eq!((i as u64 + 2, 0o601 + i as u32), (md.get_inode().0, md.get_mode()));
+            assert!(fs.borrow().shares_storage("/a", name));
+        }
+        assert!(!fs.borrow().shares_storage("/a", "/dddd"));
+    }
+
+    #[test]
+    fn actor_delete_keep_copies() {
+        // keeping 2 copies of a 5-file group deletes the 3 lowest-ranked files
+
+        let fs = TestFileSystem::new();
+        let paths = ["/x/y/z/w/e", "/x/b", "/x/y/z/d", "/a", "/x/y/c"];
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            for (i, path) in paths.iter().enumerate() {
+                let md = TestMD::new().with_len(10);
+                fs.add(TestFile::new(path).with_metadata(md).with_inode(i as u64 + 1));
+            }
+        };
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+
+        let selector = PathSelect::new(fs.clone());
+        let mut actor = FileDeleter::new(fs.clone(), selector).keep_copies(2);
+        let saved = actor.act(files);
+        assert_eq!(30, saved.logical);
+
+        assert_eq!(3, fs.borrow().ops().len());
+        assert!(fs.get_file(Path::new("/a")).is_ok());
+        assert!(fs.get_file(Path::new("/x/b")).is_ok());
+        for path in &["/x/y/c", "/x/y/z/d", "/x/y/z/w/e"] {
+            assert!(fs.get_file(Path::new(path)).is_err());
+        }
     }
 
     #[test]
@@ -191,7 +875,7 @@ mod test {
             );
         };
         let paths = vec!["/a", "/b", "/c"];
-        let files = Duplicates(paths.iter().map(PathBuf::from).collect());
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
 
         // currently all files are identical and distinct
         // remember that the root dir counts and has an inode
@@ -205,6 +889,132 @@ mod test {
         // after acting, all files should have the same inode
         assert_eq!(4, fs.borrow().num_elements());
         assert_eq!(2, fs.borrow().num_inodes());
+
+        // each duplicate is replaced by a link staged next to it
+        let keeper = PathBuf::from("/a");
+        let expected = [
+            FsOp::HardLink {
+                src: keeper.clone(),
+                dst: PathBuf::from("/.b.smllr-link"),
+            },
+            FsOp::Rename {
+                from: PathBuf::from("/.b.smllr-link"),
+                to: PathBuf::from("/b"),
+            },
+            FsOp::HardLink {
+                src: keeper.clone(),
+                dst: PathBuf::from("/.c.smllr-link"),
+            },
+            FsOp::Rename {
+                from: PathBuf::from("/.c.smllr-link"),
+                to: PathBuf::from("/c"),
+            },
+        ];
+        assert_eq!(&expected[..], fs.borrow().ops());
+        assert!(!fs.borrow().ops().contains(&FsOp::Delete(keeper)));
+    }
+
+    #[test]
+    fn actor_link_failure() {
+        // a duplicate whose replacement fails at either step is left untouched
+
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            for (i, name) in ["/a", "/b", "/c", "/d"].iter().enumerate() {
+                fs.add(
+                    TestFile::new(name)
+                        .with_inode(i as u64 + 1)
+                        .with_metadata(TestMD::new().with_len(10)),
+                );
+            }
+            // /b can't be linked and /c can't be replaced
+            let staged = staging_path(Path::new("/b"));
+            fs.fail_next(Operation::HardLink, staged, io::ErrorKind::Other);
+            fs.fail_next(Operation::Rename, PathBuf::from("/c"), io::ErrorKind::PermissionDenied);
+        };
+        let paths = ["/a", "/b", "/c", "/d"];
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+
+        let selector = PathSelect::new(fs.clone());
+        let mut actor = FileLinker::new(fs.clone(), selector);
+        let saved = actor.act(files);
+        assert_eq!(10, saved.logical);
+
+        // only /d was replaced; the failed link to /c was cleaned up
+        assert_eq!(5, fs.borrow().num_elements());
+        assert_eq!(4, fs.borrow().num_inodes());
+        let inode_of = |p| fs.get_file(Path::new(p)).unwrap().get_inode().unwrap().0;
+        assert_eq!(2, inode_of("/b"));
+        assert_eq!(3, inode_of("/c"));
+        assert_eq!(1, inode_of("/d"));
+        let staged = staging_path(Path::new("/c"));
+        assert_eq!(Some(&FsOp::Delete(staged.clone())), fs.borrow().ops().get(1));
+        assert!(fs.get_file(&staged).is_err());
+    }
+
+    #[test]
+    fn actor_link_permissions_differ() {
+        // a duplicate with other permissions than the kept file is skipped unless asked
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            for (i, &(name, mode)) in [("/a", 0o644), ("/b", 0o755), ("/c", 0o644)]
+                .iter()
+                .enumerate()
+            {
+                let md = TestMD::new().with_len(10).with_mode(mode);
+                fs.add(TestFile::new(name).with_inode(i as u64 + 1).with_metadata(md));
+            }
+        };
+        let paths = ["/a", "/b", "/c"];
+        let inode_of = |p| fs.get_file(Path::new(p)).unwrap().get_inode().unwrap().0;
+
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+        let mut actor = FileLinker::new(fs.clone(), PathSelect::new(fs.clone()));
+        let saved = actor.act(files);
+        assert_eq!(10, saved.logical);
+        assert_eq!(2, inode_of("/b"));
+        assert_eq!(1, inode_of("/c"));
+
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+        let mut actor =
+            FileLinker::new(fs.clone(), PathSelect::new(fs.clone())).ignore_permissions(true);
+        let saved = actor.act(files);
+        assert_eq!(10, saved.logical);
+        assert_eq!(1, inode_of("/b"));
+    }
+
+    #[test]
+    fn actor_link_savings_nlink() {
+        // replacing a file that has other links frees nothing until its last link goes
+
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1
+             file /b 'AAAA' inode=2
+             file /c 'AAAA' inode=3
+             file /d 'AAAA' inode=4",
+        ).unwrap();
+        {
+            // /a and /b are already the same file; /c is also linked outside the group
+            let mut fs = fs.borrow_mut();
+            fs.create_hardlink("/a", "/b2").unwrap();
+            fs.rename_file("/b2", "/b").unwrap();
+            fs.create_hardlink("/c", "/c2").unwrap();
+        }
+        let paths = ["/a", "/b", "/c", "/d"];
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+
+        let selector = PathSelect::new(fs.clone());
+        let mut actor = FileLinker::new(fs.clone(), selector);
+        let saved = actor.act(files);
+        // only /d's space was freed
+        assert_eq!(4, saved.logical);
+        assert_eq!(4, fs.get_metadata("/a").unwrap().get_nlink());
+        assert_eq!(1, fs.get_metadata("/c2").unwrap().get_nlink());
     }
 
     #[test]
@@ -231,7 +1041,7 @@ mod test {
             );
         };
         let paths = vec!["/a", "/b", "/c"];
-        let files = Duplicates(paths.iter().map(PathBuf::from).collect());
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
 
         // currently all files are identical and distinct
         // remember that the root dir counts and has an inode
@@ -245,5 +1055,65 @@ mod test {
         // after acting, nothing should have happened (except a warning message)
         assert_eq!(4, fs.borrow().num_elements());
         assert_eq!(4, fs.borrow().num_inodes());
+        assert!(fs.borrow().ops().is_empty());
+    }
+
+    #[test]
+    fn actor_link_already_linked() {
+        // a group whose paths are all links to one file is left exactly as it is
+
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1",
+        ).unwrap();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_hardlink("/a", "/b").unwrap();
+            fs.create_hardlink("/a", "/c").unwrap();
+        }
+        let before = fs.borrow().ops().len();
+        let paths = ["/a", "/b", "/c"];
+        let files = Duplicates::new(paths.iter().map(PathBuf::from).collect());
+
+        let selector = PathSelect::new(fs.clone());
+        let mut actor = FileLinker::new(fs.clone(), selector);
+        let saved = actor.act(files);
+        assert_eq!(Savings::default(), saved);
+        assert_eq!(before, fs.borrow().ops().len());
+        assert_eq!(3, fs.get_metadata("/a").unwrap().get_nlink());
+    }
+
+    #[test]
+    fn preview_tree_marks() {
+        // each group has exactly one keeper; the rest carry the action's marker
+        let fs = TestFileSystem::new();
+        let groups = vec![
+            Duplicates::new(["/x/y/a", "/x/a", "/z/a"].iter().map(PathBuf::from).collect()),
+            Duplicates::new(["/x/y/b", "/b"].iter().map(PathBuf::from).collect()),
+        ];
+        let selector = PathSelect::new(fs.clone());
+        let drawn = tree::preview(&groups, &selector, 1, Action::Link);
+        let expected = "/
+  x/
+    y/
+      [L1] a
+      [L2] b
+    [K1] a
+  z/
+    [L1] a
+  [K2] b
+";
+        assert_eq!(expected, drawn);
+        for group in 1..3 {
+            let marked = |m: char| drawn.matches(&format!("[{}{}]", m, group)).count();
+            assert_eq!(1, marked('K'));
+            assert_eq!(groups[group - 1].len() - 1, marked('L'));
+            assert_eq!(0, marked('D'));
+        }
+
+        // extra copies kept are marked as such
+        let drawn = tree::preview(&groups, &selector, 2, Action::Delete);
+        assert_eq!(4, drawn.matches("[K").count());
+        assert_eq!(1, drawn.matches("[D1] a").count());
     }
 }
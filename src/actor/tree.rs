@@ -0,0 +1,100 @@
+//! Preview what acting on groups of duplicates would do, as a tree of the files affected
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::path::{Component, Path};
+
+use vfs::VFS;
+use catalog::proxy::Duplicates;
+use super::selector::Selector;
+
+/// What happens to the files of a group that aren't kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// They're deleted (`--delete`)
+    Delete,
+    /// They're replaced by hard links to the kept file (`--link`)
+    Link,
+    /// They're made copy-on-write clones of the kept file (`--reflink`)
+    Reflink,
+}
+
+impl Action {
+    /// The letter a file is marked with in the tree
+    pub fn marker(&self) -> char {
+        match *self {
+            Action::Delete => 'D',
+            Action::Link => 'L',
+            Action::Reflink => 'R',
+        }
+    }
+}
+
+/// The letter a kept file is marked with in the tree
+pub const KEEP: char = 'K';
+
+// a directory in the tree: its subdirectories, and its files with their marker and group
+#[derive(Debug, Default)]
+struct Dir {
+    dirs: BTreeMap<OsString, Dir>,
+    files: BTreeMap<OsString, (char, usize)>,
+}
+
+impl Dir {
+    // file `path` under this directory, creating the directories on the way
+    fn add(&mut self, path: &Path, marker: char, group: usize) {
+        let mut names: Vec<OsString> = path.components()
+            .map(|c| match c {
+                Component::RootDir => OsString::from("/"),
+                c => c.as_os_str().to_os_string(),
+            })
+            .collect();
+        let file = match names.pop() {
+            Some(file) => file,
+            None => return,
+        };
+        let mut dir = self;
+        for name in names {
+            dir = dir.dirs.entry(name).or_default();
+        }
+        dir.files.insert(file, (marker, group));
+    }
+
+    // write the contents of this directory, indented `depth` levels
+    fn render(&self, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        for (name, dir) in &self.dirs {
+            let name = name.to_string_lossy();
+            // the root is already a `/`
+            let slash = if name.ends_with('/') { "" } else { "/" };
+            writeln!(out, "{}{}{}", indent, name, slash).unwrap();
+            dir.render(depth + 1, out);
+        }
+        for (name, &(marker, group)) in &self.files {
+            writeln!(out, "{}[{}{}] {}", indent, marker, group, name.to_string_lossy()).unwrap();
+        }
+    }
+}
+
+/// Draw the files of `groups` as an indented tree of the directories they're in, each
+/// marked with what acting on them would do and the number of its group (from 1), e.g.
+/// `[K1] a.txt` for the file kept in the first group and `[D1] b.txt` for its duplicate
+/// The `keep` files `selector` ranks highest in each group are kept (see `Selector::rank`);
+/// nothing is touched
+pub fn preview<V, S>(groups: &[Duplicates], selector: &S, keep: usize, action: Action) -> String
+where
+    V: VFS,
+    S: Selector<V>,
+{
+    let mut root = Dir::default();
+    for (i, dups) in groups.iter().enumerate() {
+        for (rank, path) in selector.rank(dups).into_iter().enumerate() {
+            let marker = if rank < keep { KEEP } else { action.marker() };
+            root.add(path, marker, i + 1);
+        }
+    }
+    let mut out = String::new();
+    root.render(0, &mut out);
+    out
+}
@@ -0,0 +1,144 @@
+//! Errors from adding files to the catalog
+
+use std::{error, fmt, io};
+use std::path::{Path, PathBuf};
+
+use vfs::VfsError;
+
+/// Why a file was left out of the catalog
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// It was gone by the time it was looked at (deleted or moved after the walk)
+    Vanished,
+    /// It couldn't be read (permissions, I/O errors, ...)
+    Unreadable,
+    /// Its size or mtime changed between reading its first bytes and hashing it
+    /// (only noticed with `FileCataloger::rehash_on_mismatch`)
+    Changed,
+}
+
+/// A file that couldn't be added to the catalog (e.g. it was deleted after the walk)
+pub struct CatalogError {
+    path: PathBuf,
+    cause: Option<VfsError>,
+    failure: Failure,
+}
+
+impl CatalogError {
+    /// Record that `path` couldn't be cataloged because of `cause`
+    pub fn new<P: AsRef<Path>>(path: P, cause: VfsError) -> Self {
+        let failure = match cause.kind() {
+            io::ErrorKind::NotFound => Failure::Vanished,
+            _ => Failure::Unreadable,
+        };
+        CatalogError {
+            path: path.as_ref().to_path_buf(),
+            cause: Some(cause),
+            failure,
+        }
+    }
+    /// Record that `path` was left out for changing while it was being cataloged
+    pub fn changed<P: AsRef<Path>>(path: P) -> Self {
+        CatalogError {
+            path: path.as_ref().to_path_buf(),
+            cause: None,
+            failure: Failure::Changed,
+        }
+    }
+    /// The path that was left out
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// The filesystem error behind it, if there was one (a file that changed had none)
+    pub fn cause(&self) -> Option<&VfsError> {
+        self.cause.as_ref()
+    }
+    /// What kind of problem it was
+    pub fn failure(&self) -> Failure {
+        self.failure
+    }
+}
+
+// e.g. "couldn't catalog /data/x.bin: failed to read metadata for /data/x.bin: No such file"
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.cause {
+            Some(ref cause) => write!(f, "couldn't catalog {}: {}", self.path.display(), cause),
+            None => {
+                write!(f, "couldn't catalog {}: it changed while being read", self.path.display())
+            }
+        }
+    }
+}
+
+// Debug is what `unwrap()` prints, so make it just as readable
+impl fmt::Debug for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+// lose the structure but keep the kind (a file that changed has bad data) and the message
+impl From<CatalogError> for io::Error {
+    fn from(e: CatalogError) -> io::Error {
+        let kind = e.cause().map_or(io::ErrorKind::InvalidData, VfsError::kind);
+        io::Error::new(kind, e)
+    }
+}
+
+impl error::Error for CatalogError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.cause.as_ref().map(|cause| cause as _)
+    }
+}
+
+/// How many files were left out of the catalog, by `Failure`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ErrorTally {
+    /// Files gone by the time they were looked at
+    pub vanished: usize,
+    /// Files that couldn't be read
+    pub unreadable: usize,
+    /// Files that changed while being read
+    pub changed: usize,
+}
+
+impl ErrorTally {
+    /// Count one more file left out for `failure`
+    pub fn add(&mut self, failure: Failure) {
+        match failure {
+            Failure::Vanished => self.vanished += 1,
+            Failure::Unreadable => self.unreadable += 1,
+            Failure::Changed => self.changed += 1,
+        }
+    }
+    /// Number of files left out in all
+    pub fn total(&self) -> usize {
+        self.vanished + self.unreadable + self.changed
+    }
+}
+
+// e.g. "37 files unreadable, 2 vanished during scan", leaving out the kinds that didn't occur
+impl fmt::Display for ErrorTally {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts = [
+            (self.unreadable, "unreadable"),
+            (self.vanished, "vanished during scan"),
+            (self.changed, "changed during scan"),
+        ];
+        let mut first = true;
+        for &(n, what) in parts.iter().filter(|&&(n, _)| n > 0) {
+            if first {
+                let files = if n == 1 { "file" } else { "files" };
+                write!(f, "{} {} {}", n, files, what)?;
+            } else {
+                write!(f, ", {} {}", n, what)?;
+            }
+            first = false;
+        }
+        if first {
+            write!(f, "no files left out")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+//! Summarize the composition of a scan by file size
+
+use std::fmt;
+use std::collections::BTreeMap;
+
+use helpers::prettify_bytes;
+
+/// Distribution of cataloged files by size
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeHistogram {
+    /// Number of distinct file sizes (i.e. entries in the catalog)
+    pub num_buckets: usize,
+    /// Total number of files cataloged
+    pub num_files: usize,
+    /// Number of files in each power-of-two size range
+    /// Key 0 holds empty files; key `k` holds sizes in `[2^(k-1), 2^k)`
+    pub ranges: BTreeMap<u32, usize>,
+    /// The `(size, number of files)` pairs shared by the most files, largest first
+    pub largest: Vec<(u64, usize)>,
+    // how many entries to keep in `largest`
+    top: usize,
+}
+
+impl SizeHistogram {
+    /// Construct an empty histogram that remembers the `top` biggest buckets
+    pub fn new(top: usize) -> Self {
+        SizeHistogram {
+            num_buckets: 0,
+            num_files: 0,
+            ranges: BTreeMap::new(),
+            largest: vec![],
+            top,
+        }
+    }
+
+    /// Identify which power-of-two range a file size falls in
+    pub fn range_of(size: u64) -> u32 {
+        64 - size.leading_zeros()
+    }
+
+    /// Record a catalog bucket of `count` files which are all `size` bytes long
+    pub fn add_bucket(&mut self, size: u64, count: usize) {
+        self.num_buckets += 1;
+        self.num_files += count;
+        *self.ranges.entry(Self::range_of(size)).or_insert(0) += count;
+        // keep `largest` sorted by count (ties broken by size) and truncated
+        let pos = self.largest
+            .iter()
+            .position(|&(s, c)| (c, s) < (count, size))
+            .unwrap_or(self.largest.len());
+        if pos < self.top {
+            self.largest.insert(pos, (size, count));
+            self.largest.truncate(self.top);
+        }
+    }
+}
+
+// print a human readable report
+impl fmt::Display for SizeHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} files in {} size buckets",
+            self.num_files,
+            self.num_buckets
+        )?;
+        writeln!(f, "Files by size:")?;
+        for (&range, &count) in &self.ranges {
+            if range == 0 {
+                writeln!(f, "\tempty: {}", count)?;
+            } else {
+                let low = 1u64 << (range - 1);
+                writeln!(f, "\t>= {}: {}", prettify_bytes(low), count)?;
+            }
+        }
+        writeln!(f, "Largest buckets:")?;
+        for &(size, count) in &self.largest {
+            writeln!(f, "\t{}: {} files", prettify_bytes(size), count)?;
+        }
+        Ok(())
+    }
+}
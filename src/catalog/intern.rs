@@ -0,0 +1,36 @@
+//! Share a single allocation of each path between the catalog and the groups it reports
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Hands out one shared copy of each distinct path, so a path held by the catalog,
+/// its proxies, and every group of duplicates reported is only allocated once
+#[derive(Debug, Default)]
+pub struct PathInterner {
+    paths: HashSet<Arc<Path>>,
+}
+
+impl PathInterner {
+    /// The shared copy of `path`, allocating it the first time it's seen
+    pub fn intern(&mut self, path: &Path) -> Arc<Path> {
+        if let Some(shared) = self.paths.get(path) {
+            return shared.clone();
+        }
+        let shared: Arc<Path> = Arc::from(path);
+        self.paths.insert(shared.clone());
+        shared
+    }
+    /// Stop sharing `path`; copies already handed out stay valid
+    pub fn forget(&mut self, path: &Path) {
+        self.paths.remove(path);
+    }
+    /// Number of distinct paths allocated
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+    /// Whether no path has been interned
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+}
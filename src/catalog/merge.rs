@@ -0,0 +1,73 @@
+//! Merge the catalog of another scan (e.g. of another machine) into this one
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use helpers::ID;
+use hash::FileHash;
+use vfs::VFS;
+use super::{FileCataloger, Located};
+use super::proxy::Duplicates;
+
+impl<T: VFS, H: FileHash> FileCataloger<T, H> {
+    /// Add every path of `other` to this catalog, along with the hashes `other` computed,
+    /// and group them with the files here; returns how many paths were added
+    /// Files of a size both catalogs hold are compared as usual: hashes either side
+    /// computed are reused, and any still needed are read through this catalog's
+    /// filesystem (a path of `other` that can't be read there is left out; with a weak
+    /// hash, that includes any whose match has to be confirmed, see `verify`)
+    /// A path already in this catalog is kept as it is. `other`'s devices are renumbered,
+    /// so its files are never taken for hard links to files here
+    /// With `hash_threads`, call `finalize` on `other` first
+    pub fn merge<U: VFS>(&mut self, other: &FileCataloger<U, H>) -> usize {
+        // `other`'s devices get numbers from the top down, skipping any in use here
+        let used: HashSet<u64> = self.located.values().map(|loc| loc.id.dev).collect();
+        let mut devices: HashMap<u64, u64> = HashMap::new();
+        let mut next = u64::MAX;
+        let mut entries: Vec<(&Arc<Path>, &Located)> = other.located.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        let mut added = 0;
+        for (path, located) in entries {
+            if self.located.contains_key(&**path) {
+                warn!("{:?} is in both catalogs, keeping the one already here", path);
+                continue;
+            }
+            let dev = *devices.entry(located.id.dev).or_insert_with(|| {
+                while used.contains(&next) {
+                    next -= 1;
+                }
+                let dev = next;
+                next -= 1;
+                dev
+            });
+            let recorded = other.known(path, located);
+            let located = Located {
+                id: ID {
+                    dev,
+                    inode: located.id.inode,
+                },
+                ..*located
+            };
+            self.recorded.insert(path.to_path_buf(), recorded);
+            self.merged.insert(path.to_path_buf());
+            self.file(path, located);
+            self.report_group_of(path, located.size);
+            added += 1;
+        }
+        added
+    }
+
+    /// Whether `path` was added by `merge` rather than found by this scan, so that
+    /// acting on it from here may not make sense
+    pub fn is_merged(&self, path: &Path) -> bool {
+        self.merged.contains(path)
+    }
+
+    /// Whether `dups` holds paths from this scan as well as paths added by `merge`;
+    /// such a group can't be acted on from this machine alone
+    pub fn is_cross_snapshot(&self, dups: &Duplicates) -> bool {
+        let merged = dups.iter().filter(|path| self.is_merged(path)).count();
+        merged > 0 && merged < dups.len()
+    }
+}
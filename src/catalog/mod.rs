@@ -1,30 +1,434 @@
 //! Identify duplicates in a collection of files
 
-use std::path::Path;
-use std::collections::HashMap;
+use std::{fmt, mem};
+use std::cmp::Reverse;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::Entry;
 
 pub use helpers::ID;
-use vfs::{File, MetaData, VFS};
+use helpers::{prettify_bytes, with_commas};
+use vfs::{File, MetaData, OnError, Operation, VfsError, VFS};
 use hash::FileHash;
 
 pub mod proxy;
-use self::proxy::{Duplicates, FirstKBytesProxy};
+use self::proxy::{Context, Duplicates, FirstKBytesProxy, Recorded, Stamp};
+
+pub mod histogram;
+use self::histogram::SizeHistogram;
+
+mod pool;
+use self::pool::HashPool;
+
+mod error;
+pub use self::error::{CatalogError, ErrorTally, Failure};
+
+mod save;
+
+mod merge;
+
+mod intern;
+pub use self::intern::PathInterner;
 
 mod print; // include debug printing info
 
 mod test; // include unit tests
 
 
+/// Drop every group of duplicates with fewer than `n` paths
+pub fn filter_min_copies(repeats: Vec<Duplicates>, n: usize) -> Vec<Duplicates> {
+    repeats.into_iter().filter(|dups| dups.len() >= n).collect()
+}
+
+/// Drop every group of duplicates whose redundant copies take up fewer than `bytes`
+pub fn filter_min_waste(repeats: Vec<Duplicates>, bytes: u64) -> Vec<Duplicates> {
+    repeats
+        .into_iter()
+        .filter(|dups| dups.redundant_bytes() >= bytes)
+        .collect()
+}
+
+/// Sort the paths within each group, then the groups by size and first path, so the
+/// same files are always reported in the same order (catalog lookups are unordered)
+pub fn sort_groups(mut repeats: Vec<Duplicates>) -> Vec<Duplicates> {
+    for dups in &mut repeats {
+        dups.sort();
+    }
+    repeats.sort_by(|a, b| {
+        a.size()
+            .cmp(&b.size())
+            .then_with(|| a.paths().first().cmp(&b.paths().first()))
+    });
+    repeats
+}
+
+/// Order groups of duplicates by the space their redundant copies waste, most first
+/// (groups wasting the same are ordered by their first path)
+pub fn most_wasteful_first(mut repeats: Vec<Duplicates>) -> Vec<Duplicates> {
+    repeats.sort_by(|a, b| {
+        b.redundant_bytes()
+            .cmp(&a.redundant_bytes())
+            .then_with(|| a.paths().first().cmp(&b.paths().first()))
+    });
+    repeats
+}
+
+/// Keep only the `n` groups of duplicates with the largest files, largest first
+pub fn largest_first(mut repeats: Vec<Duplicates>, n: usize) -> Vec<Duplicates> {
+    repeats.sort_by_key(|dups| Reverse(dups.size()));
+    repeats.truncate(n);
+    repeats
+}
+
+// the paths in a group that share a key (e.g. one user owns)
+struct Keyed<K> {
+    key: K,
+    paths: Vec<PathBuf>,
+    // the files they are, to count hard links once
+    ids: HashSet<ID>,
+    // how many distinct files the paths are
+    files: usize,
+}
+
+/// Split every group of duplicates into one group per owner (user ID), so files of
+/// different users are never collapsed into one
+/// Files whose owner can't be read are left out, as are owners with a single file
+pub fn split_by_owner<V: VFS>(vfs: &V, repeats: Vec<Duplicates>) -> Vec<Duplicates> {
+    split_by_key(vfs, repeats, |_, md| Some(md.get_uid())).0
+}
+
+/// Split every group of duplicates into one group per device, as only files on the same
+/// device can be hard linked to each other
+/// Also returns the paths alone on their device, which can't be linked to any of their
+/// duplicates; files whose device can't be read are left out
+pub fn split_by_device<V: VFS>(
+    vfs: &V,
+    repeats: Vec<Duplicates>,
+) -> (Vec<Duplicates>, Vec<PathBuf>) {
+    split_by_key(vfs, repeats, |path, md| match md.get_device() {
+        Ok(dev) => Some(dev.0),
+        Err(e) => {
+            warn!("Cannot tell the device of {:?}: {}", path, e);
+            None
+        }
+    })
+}
+
+// split every group into one group per `key` of its files' metadata, keys in the order
+// their first file appears; returns the groups, and the paths alone under their key
+// files without a key are left out
+fn split_by_key<V, K, F>(
+    vfs: &V,
+    repeats: Vec<Duplicates>,
+    key: F,
+) -> (Vec<Duplicates>, Vec<PathBuf>)
+where
+    V: VFS,
+    K: PartialEq,
+    F: Fn(&Path, &<V::FileIter as File>::MD) -> Option<K>,
+{
+    let mut split = vec![];
+    let mut alone = vec![];
+    for dups in repeats {
+        let mut keyed: Vec<Keyed<K>> = vec![];
+        let size = dups.size();
+        let hash = dups.hash().map(<[u8]>::to_vec);
+        for path in dups.iter().map(|path| path.to_path_buf()) {
+            let md = match vfs.get_metadata(&path) {
+                Ok(md) => md,
+                Err(e) => {
+                    warn!("{}", e);
+                    continue;
+                }
+            };
+            let k = match key(&path, &md) {
+                Some(k) => k,
+                None => continue,
+            };
+            let i = match keyed.iter().position(|o| o.key == k) {
+                Some(i) => i,
+                None => {
+                    keyed.push(Keyed {
+                        key: k,
+                        paths: vec![],
+                        ids: HashSet::new(),
+                        files: 0,
+                    });
+                    keyed.len() - 1
+                }
+            };
+            let same = &mut keyed[i];
+            // a file that can't be identified counts as distinct
+            if ID::of(&md).map(|id| same.ids.insert(id)).unwrap_or(true) {
+                same.files += 1;
+            }
+            same.paths.push(path);
+        }
+        for same in keyed {
+            if same.paths.len() < 2 {
+                alone.extend(same.paths);
+                continue;
+            }
+            let mut group = Duplicates::new(same.paths)
+                .with_distinct_files(same.files)
+                .with_stamps_from(&dups);
+            if let Some(size) = size {
+                group = group.with_size(size);
+            }
+            if let Some(ref hash) = hash {
+                group = group.with_hash(hash.clone());
+            }
+            split.push(group);
+        }
+    }
+    (split, alone)
+}
+
+/// Keep only the groups that have a symlink in them, i.e. a link whose target matches
+/// other files (for auditing links that could be consolidated, see `DirWalker::keep_symlinks`)
+pub fn filter_symlinked<V: VFS>(vfs: &V, repeats: Vec<Duplicates>) -> Vec<Duplicates> {
+    repeats
+        .into_iter()
+        .filter(|dups| dups.iter().any(|path| vfs.read_link(path).is_ok()))
+        .collect()
+}
+
+/// Files that share a size but turned out to have different contents
+/// These are near-misses found while looking for duplicates, not duplicates
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collision {
+    /// The size shared by every file
+    pub size: u64,
+    /// One path for each distinct content of that size
+    pub paths: Vec<PathBuf>,
+}
+
+// e.g. "4 KB shared by 2 distinct files" followed by the indented paths
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} shared by {} distinct files",
+            prettify_bytes(self.size),
+            self.paths.len()
+        )?;
+        for path in &self.paths {
+            writeln!(f, "\t{}", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// How far the cataloged files got along the lazy comparison path, and the work done
+/// along the way
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CatalogStats {
+    /// Number of paths in the catalog
+    pub files: usize,
+    /// Number of distinct file sizes
+    pub buckets: usize,
+    /// Size buckets promoted to comparing first bytes (i.e. shared by 2+ files)
+    pub contested: usize,
+    /// Groups of matching first bytes promoted to whole-file hashing
+    pub hashed: usize,
+    /// Number of files whose first bytes were read
+    pub first_bytes_read: usize,
+    /// Number of files whose last bytes were read (see `FileCataloger::last_bytes`)
+    pub last_bytes_read: usize,
+    /// Number of files hashed whole (not counting files small enough to be hashed
+    /// whole with their first bytes)
+    pub full_hashes: usize,
+    /// Bytes read and hashed, first bytes, last bytes, and whole files alike
+    pub bytes_hashed: u64,
+    /// Number of times a file was read again to check that its contents match those of
+    /// a file with the same hash (see `Verify`)
+    pub verify_reads: usize,
+}
+
+// e.g. "1,204 buckets, 88 contested, 31 fully hashed"
+impl fmt::Display for CatalogStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} buckets, {} contested, {} fully hashed",
+            with_commas(self.buckets as u64),
+            with_commas(self.contested as u64),
+            with_commas(self.hashed as u64)
+        )
+    }
+}
+
+/// What `FileCataloger::insert_all` did with the paths it was given
+#[derive(Debug, Default)]
+pub struct CatalogReport {
+    /// Number of paths added to the catalog
+    pub inserted: usize,
+    /// The paths that couldn't be added or read, and why, up to `FileCataloger::max_errors`
+    pub errors: Vec<CatalogError>,
+    /// How many paths couldn't be added or read, including any past the cap on `errors`
+    pub failures: ErrorTally,
+    /// Whether insertion was cancelled before the paths ran out
+    pub cancelled: bool,
+    /// Whether insertion stopped at the first error (see `FileCataloger::on_error`),
+    /// which is the last of `errors`
+    pub aborted: bool,
+}
+
+impl CatalogReport {
+    /// Number of paths left out because of errors
+    pub fn skipped(&self) -> usize {
+        self.failures.total()
+    }
+    // count `e` and deal with it as `policy` says, keeping it if fewer than `max` have
+    // been kept (or if it's what stopped insertion)
+    fn record(&mut self, e: CatalogError, max: usize, policy: OnError) {
+        if self.aborted {
+            return;
+        }
+        self.failures.add(e.failure());
+        self.aborted = !policy.carry_on(&e);
+        if self.errors.len() < max || self.aborted {
+            self.errors.push(e);
+        }
+    }
+}
+
+/// How many errors `FileCataloger::insert_all` keeps by default
+pub const MAX_ERRORS: usize = 1_000;
+
+/// How closely files with the same hash are compared before they're grouped, in case the
+/// hashes collided (see `FileCataloger::verify`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verify {
+    /// Trust the hash
+    Off,
+    /// Compare the first bytes of the files themselves (the default for weak hashes like MD5)
+    Prefix,
+    /// Compare the files byte by byte, reading both in full
+    Full,
+}
+
+impl Verify {
+    // what files hashed with `H` get unless told otherwise
+    fn default_for<H: FileHash>() -> Self {
+        if H::is_weak() {
+            Verify::Prefix
+        } else {
+            Verify::Off
+        }
+    }
+}
+
+/// How often `FileCataloger::insert_all` logs its progress: whenever `files` more paths
+/// have been inserted or `time` has passed since it last did, whichever comes first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LogInterval {
+    /// Number of paths inserted between two log lines
+    pub files: usize,
+    /// Longest time between two log lines
+    pub time: Duration,
+}
+
+// every 10,000 files or 30 seconds
+impl Default for LogInterval {
+    fn default() -> Self {
+        LogInterval {
+            files: 10_000,
+            time: Duration::from_secs(30),
+        }
+    }
+}
+
+// how many paths `insert_all` had gone through when it last logged its progress, and when
+#[derive(Debug, Clone, Copy)]
+struct LastLogged {
+    done: usize,
+    at: Instant,
+}
+
 /// Catalog files, determining lazily if files are identical
 ///  by checking filesize, the first K bytes, and then the whole file hash
 ///  but only when necessary to check
 pub struct FileCataloger<T: VFS, H: FileHash> {
     catalog: HashMap<u64, FirstKBytesProxy<H>>,
     vfs: T,
-    // In the future, it would also be helpful to include a shortcut to know
-    // which FirstKBytesProxies contain duplicates to avoid a full search when
-    // get_repeats() is called.
+    // promotion counters, updated as files are inserted
+    stats: CatalogStats,
+    // re-check metadata before hashing a whole file
+    rehash_on_mismatch: bool,
+    // sizes above this are matched without reading contents
+    hash_size_limit: Option<u64>,
+    // compare the last bytes of files whose first bytes match before hashing them
+    last_bytes: bool,
+    // report empty files as duplicates of each other
+    dedupe_empty: bool,
+    // catalog symlinks under their own path rather than as their target
+    keep_symlinks: bool,
+    // how to check files with the same hash really match (by default, depends on `H`)
+    verify: Option<Verify>,
+    // number of paths inserted so far
+    inserted: usize,
+    // sizes whose FirstKBytesProxy holds more than one path, the only ones that
+    // can contain duplicates, so get_repeats() doesn't visit every unique file
+    contested: HashSet<u64>,
+    // workers hashing whole files, if hashing is done in parallel
+    pool: Option<HashPool<H>>,
+    // the one copy of each inserted path, shared with the proxies and reported groups
+    paths: PathInterner,
+    // where each inserted path was filed, so `remove` can find it
+    located: HashMap<Arc<Path>, Located>,
+    // how many inserted paths are each file (more than one for hard links)
+    link_counts: HashMap<ID, usize>,
+    // hashes already known for some paths, from a saved catalog
+    recorded: HashMap<PathBuf, Recorded<<H as FileHash>::Output>>,
+    // paths loaded from a saved catalog (see `load`), re-checked before they are reported
+    unverified: HashSet<PathBuf>,
+    // paths added from another catalog (see `merge`)
+    merged: HashSet<PathBuf>,
+    // what a previous run saved about each path (see `incremental`)
+    cache: HashMap<PathBuf, (Located, Recorded<<H as FileHash>::Output>)>,
+    // called with each group of duplicates as it's confirmed or grows
+    on_duplicate: Option<DuplicateHook>,
+    // called as each file is hashed whole
+    hash_progress: Option<ProgressHook>,
+    // paths inserted while the pool was hashing, whose groups `finalize` reports
+    unreported: HashSet<PathBuf>,
+    // how often `insert_all` logs its progress, if at all
+    log_interval: Option<LogInterval>,
+    // files left out after being inserted (e.g. unreadable when their first bytes were needed)
+    failed: Vec<CatalogError>,
+    // how many errors `insert_all` keeps in its report
+    max_errors: usize,
+    // what `insert_all` does about files that can't be cataloged
+    on_error: OnError,
+}
+
+// a callback for groups of duplicates (see `FileCataloger::on_duplicate`)
+type DuplicateHook = Box<dyn FnMut(&Duplicates)>;
+
+// a callback for the progress of hashing a file (see `FileCataloger::hash_progress`)
+type ProgressHook = Box<dyn FnMut(&Path, u64, u64)>;
+
+// what a path's metadata was when it was inserted
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Located {
+    size: u64,
+    id: ID,
+    modified: Option<SystemTime>,
+}
+
+impl Located {
+    // the same, as reported with the groups the path is in
+    fn stamp(&self) -> Stamp {
+        Stamp {
+            size: self.size,
+            modified: self.modified,
+            id: self.id,
+        }
+    }
 }
 
 impl<T: VFS, H: FileHash> FileCataloger<T, H> {
@@ -33,6 +437,194 @@ impl<T: VFS, H: FileHash> FileCataloger<T, H> {
         FileCataloger {
             catalog: HashMap::new(),
             vfs: vfs,
+            stats: CatalogStats::default(),
+            rehash_on_mismatch: false,
+            hash_size_limit: None,
+            last_bytes: false,
+            dedupe_empty: false,
+            keep_symlinks: false,
+            verify: None,
+            inserted: 0,
+            contested: HashSet::new(),
+            pool: None,
+            paths: PathInterner::default(),
+            located: HashMap::new(),
+            link_counts: HashMap::new(),
+            recorded: HashMap::new(),
+            unverified: HashSet::new(),
+            merged: HashSet::new(),
+            cache: HashMap::new(),
+            on_duplicate: None,
+            hash_progress: None,
+            unreported: HashSet::new(),
+            log_interval: Some(LogInterval::default()),
+            failed: vec![],
+            max_errors: MAX_ERRORS,
+            on_error: OnError::default(),
+        }
+    }
+
+    /// Before hashing a whole file, make sure its size and mtime haven't changed
+    /// since its first bytes were read; files that changed are left out
+    pub fn rehash_on_mismatch(mut self, on: bool) -> Self {
+        self.rehash_on_mismatch = on;
+        self
+    }
+
+    /// Never read files larger than `limit` bytes; files that share such a size
+    /// are reported by `get_size_only()` as probable duplicates instead
+    pub fn hash_size_limit(mut self, limit: u64) -> Self {
+        self.hash_size_limit = Some(limit);
+        self
+    }
+
+    /// Catalog a symlink to a file under its own path, as a file of its own that matches
+    /// its target (see `DirWalker::keep_symlinks`)
+    /// By default a symlink is cataloged as its target's canonical path, so a link is never
+    /// a duplicate of the file it points to, and inserting it is a no-op once that path is in
+    pub fn keep_symlinks(mut self, on: bool) -> Self {
+        self.keep_symlinks = on;
+        self
+    }
+
+    /// Before a file joins the group of files with the same hash, compare it with the group's
+    /// first file this closely; if they differ after all, it's left out of the group
+    /// By default weak hashes (see `FileHash::is_weak`) get `Verify::Prefix` and others
+    /// `Verify::Off`
+    pub fn verify(mut self, level: Verify) -> Self {
+        self.verify = Some(level);
+        self
+    }
+
+    /// When the first bytes of files match, compare their last bytes before hashing them
+    /// whole, which saves reading large files that share a header (e.g. media files)
+    /// Files smaller than twice the bytes compared are hashed whole as usual
+    pub fn last_bytes(mut self, on: bool) -> Self {
+        self.last_bytes = on;
+        self
+    }
+
+    /// Call `f` with each group of duplicates the moment it's confirmed, e.g. to stream
+    /// results while the scan goes on; the group has its size and hash, as from `get_repeats`
+    /// A group is passed again, whole, each time a path joins it, so a report replaces any
+    /// earlier one of the same group: act on groups only once the scan is done
+    /// With `hash_threads`, groups are only confirmed when `finalize` sorts in the hashes
+    pub fn on_duplicate<F: FnMut(&Duplicates) + 'static>(mut self, f: F) -> Self {
+        self.on_duplicate = Some(Box::new(f));
+        self
+    }
+
+    /// While a file is hashed whole, call `f` with its path, the bytes hashed so far, and
+    /// its length after each chunk, e.g. to show "hashing bigfile.iso: 40%"
+    /// Files hashed by `hash_threads` workers aren't reported
+    pub fn hash_progress<F: FnMut(&Path, u64, u64) + 'static>(mut self, f: F) -> Self {
+        self.hash_progress = Some(Box::new(f));
+        self
+    }
+
+    /// Log how far `insert_all` has got (files, groups of duplicates, and bytes hashed so
+    /// far) at the info level this often, or never if `None`
+    /// By default it logs every 10,000 files or 30 seconds (see `LogInterval`)
+    pub fn log_progress(mut self, interval: Option<LogInterval>) -> Self {
+        self.log_interval = interval;
+        self
+    }
+
+    /// Report empty files as duplicates of each other, like any others
+    /// By default they're left out of `get_repeats()` and listed by `get_empty()` instead,
+    /// as one huge group of unrelated placeholder files is rarely worth acting on
+    pub fn dedupe_empty(mut self, on: bool) -> Self {
+        self.dedupe_empty = on;
+        self
+    }
+
+    /// Keep at most `n` of the errors `insert_all` runs into in its report; the rest are
+    /// only counted (see `CatalogReport::failures`)
+    pub fn max_errors(mut self, n: usize) -> Self {
+        self.max_errors = n;
+        self
+    }
+
+    /// Have `insert_all` deal with files that can't be cataloged as `policy` says: by
+    /// default each is logged as a warning and left out, but with `OnError::Abort`
+    /// insertion stops at the first (see `CatalogReport::aborted`)
+    pub fn on_error(mut self, policy: OnError) -> Self {
+        self.on_error = policy;
+        self
+    }
+
+    /// The files left out since this was last called, after `insert` had accepted them
+    /// (e.g. one that vanished before its contents were compared with another's)
+    /// `insert_all` collects these into its report
+    pub fn take_errors(&mut self) -> Vec<CatalogError> {
+        mem::take(&mut self.failed)
+    }
+
+    /// Wait for files being hashed in parallel (see `hash_threads`) and sort them in
+    /// Must be called before looking at the results; `insert_all` calls it when done
+    pub fn finalize(&mut self) {
+        let pool = match self.pool {
+            Some(ref mut pool) if pool.is_open() => pool,
+            _ => return,
+        };
+        pool.finish();
+        for size in &self.contested {
+            let mut ctx = Context {
+                vfs: &self.vfs,
+                stats: &mut self.stats,
+                rehash_on_mismatch: self.rehash_on_mismatch,
+                size_only: false,
+                last_bytes: self.last_bytes,
+                pool: Some(&mut *pool),
+                progress: self.hash_progress.as_mut().map(|f| &mut **f as _),
+                recorded: &self.recorded,
+                size: *size,
+                first_bytes: None,
+                verify: self.verify.unwrap_or_else(Verify::default_for::<H>),
+                errors: &mut self.failed,
+            };
+            self.catalog
+                .get_mut(size)
+                .expect("contested size missing from catalog")
+                .resolve(&mut ctx);
+        }
+        pool.reopen();
+        // report the groups the queued paths joined
+        let unreported = mem::take(&mut self.unreported);
+        if self.on_duplicate.is_some() && !unreported.is_empty() {
+            let joined: Vec<Duplicates> = self.contested
+                .iter()
+                .flat_map(|&size| self.repeats_of(size))
+                .filter(|dups| dups.iter().any(|path| unreported.contains(&**path)))
+                .collect();
+            for dups in &joined {
+                self.report(dups);
+            }
+        }
+    }
+
+    // pass a group that was just confirmed or grew to `on_duplicate`
+    fn report(&mut self, dups: &Duplicates) {
+        if let Some(ref mut f) = self.on_duplicate {
+            f(dups);
+        }
+    }
+
+    // report the group `path` (just inserted with `size`) is in, if any
+    // a path whose hash is still being computed is reported by `finalize` instead
+    fn report_group_of(&mut self, path: &Path, size: u64) {
+        if self.on_duplicate.is_none() {
+            return;
+        }
+        if self.pool.as_ref().is_some_and(HashPool::is_open) {
+            self.unreported.insert(path.to_path_buf());
+            return;
+        }
+        let group = self.repeats_of(size)
+            .into_iter()
+            .find(|dups| dups.iter().any(|p| **p == *path));
+        if let Some(dups) = group {
+            self.report(&dups);
         }
     }
 
@@ -40,34 +632,487 @@ impl<T: VFS, H: FileHash> FileCataloger<T, H> {
     // Each Duplicate is a vector of links that point to one inode
     /// Check all included Proxies for duplicates
     pub fn get_repeats(&self) -> Vec<Duplicates> {
+        // a lone file can't be a duplicate of anything
+        if self.inserted < 2 {
+            debug!("Nothing to compare");
+            return vec![];
+        }
         let mut all = vec![];
-        // for each subgrouping (done by size), get all the list of duplicates and
-        // add them to are return variable.
-        for fkbp in self.catalog.values() {
-            //for (_size, ref fkbp) in &self.catalog {
-            all.append(&mut fkbp.get_repeats());
+        // for each subgrouping (done by size) holding more than one file, get all the
+        // list of duplicates and add them to are return variable.
+        for &size in &self.contested {
+            all.append(&mut self.repeats_of(size));
+        }
+        all
+    }
+
+    /// The same groups as `get_repeats()`, moved out of the catalog rather than cloned,
+    /// for when nothing more is asked of it (e.g. at the end of a scan)
+    /// Hashes queued for `hash_threads` are waited for first
+    pub fn into_repeats(mut self) -> Vec<Duplicates> {
+        self.finalize();
+        if self.inserted < 2 {
+            debug!("Nothing to compare");
+            return vec![];
+        }
+        let mut catalog = mem::take(&mut self.catalog);
+        let mut all = vec![];
+        for &size in &self.contested {
+            if size == 0 && !self.dedupe_empty {
+                continue;
+            }
+            let fkbp = match catalog.remove(&size) {
+                Some(fkbp) => fkbp,
+                None => continue,
+            };
+            all.extend(
+                fkbp.into_repeats()
+                    .into_iter()
+                    .filter_map(|dups| self.drop_stale(dups))
+                    .map(|dups| self.stamped(dups).with_size(size)),
+            );
+        }
+        all
+    }
+
+    /// Find the `k` largest groups of duplicates, largest files first
+    /// Only sizes shared by several files are looked at, biggest first, so this stops
+    /// as soon as it has found enough
+    pub fn largest_duplicates(&self, k: usize) -> Vec<Duplicates> {
+        let mut sizes: Vec<u64> = self.contested.iter().cloned().collect();
+        sizes.sort_by_key(|&size| Reverse(size));
+        let mut all = vec![];
+        for size in sizes {
+            if all.len() >= k {
+                break;
+            }
+            let mut repeats = self.repeats_of(size);
+            // of groups the same size, the one wasting the most space comes first
+            repeats.sort_by_key(|dups| Reverse(dups.distinct_files()));
+            all.append(&mut repeats);
+        }
+        all.truncate(k);
+        all
+    }
+
+    /// Find the group of duplicates with the largest files, and their size
+    pub fn largest_duplicate(&self) -> Option<(u64, Duplicates)> {
+        self.largest_duplicates(1)
+            .pop()
+            .and_then(|dups| dups.size().map(|size| (size, dups)))
+    }
+
+    /// The group of duplicates `path` is in, if it was inserted and has any, without
+    /// looking at the rest of the catalog
+    /// Comparisons still outstanding for `path` are done now: hashes queued for
+    /// `hash_threads` are waited for, and if its size was only matched by size (see
+    /// `hash_size_limit`), the files of that size are read and compared after all
+    /// (once: from then on they're reported by `get_repeats()`, not `get_size_only()`)
+    pub fn duplicates_of(&mut self, path: &Path) -> Option<Duplicates> {
+        let size = self.located.get(path)?.size;
+        self.finalize();
+        if self.catalog[&size].get_size_only().is_some() {
+            self.compare_size_only(size);
+        }
+        self.repeats_of(size)
+            .into_iter()
+            .find(|dups| dups.iter().any(|p| **p == *path))
+    }
+
+    // read and compare the files of `size` after they were matched by size alone
+    fn compare_size_only(&mut self, size: u64) {
+        let dups = match self.catalog.remove(&size).and_then(|fkbp| fkbp.get_size_only()) {
+            Some(dups) => dups,
+            None => return,
+        };
+        debug!("Comparing the {} files of size {} after all", dups.len(), size);
+        let mut fkbp: Option<FirstKBytesProxy<H>> = None;
+        for path in dups.iter() {
+            let id = self.located[&**path].id;
+            match fkbp {
+                Some(ref mut fkbp) => {
+                    let mut ctx = Context {
+                        vfs: &self.vfs,
+                        stats: &mut self.stats,
+                        rehash_on_mismatch: self.rehash_on_mismatch,
+                        size_only: false,
+                        last_bytes: self.last_bytes,
+                        pool: None,
+                        progress: self.hash_progress.as_mut().map(|f| &mut **f as _),
+                        recorded: &self.recorded,
+                        size,
+                        first_bytes: None,
+                        verify: self.verify.unwrap_or_else(Verify::default_for::<H>),
+                        errors: &mut self.failed,
+                    };
+                    fkbp.insert(&mut ctx, id, path);
+                }
+                None => fkbp = Some(FirstKBytesProxy::new(id, path)),
+            }
+        }
+        if let Some(fkbp) = fkbp {
+            self.catalog.insert(size, fkbp);
+        }
+    }
+
+    // the duplicates among the files of one size
+    fn repeats_of(&self, size: u64) -> Vec<Duplicates> {
+        if size == 0 && !self.dedupe_empty {
+            return vec![];
+        }
+        self.catalog[&size]
+            .get_repeats()
+            .into_iter()
+            .filter_map(|dups| self.drop_stale(dups))
+            .map(|dups| self.stamped(dups).with_size(size))
+            .collect()
+    }
+
+    // `dups` with what each of its files was like when it was inserted
+    fn stamped(&self, dups: Duplicates) -> Duplicates {
+        dups.with_stamps(|path| self.located.get(path).map(Located::stamp))
+    }
+
+    // leave out the paths loaded from a saved catalog whose files have changed since,
+    // and the group if that leaves it with a single path
+    fn drop_stale(&self, mut dups: Duplicates) -> Option<Duplicates> {
+        if self.unverified.is_empty() {
+            return Some(dups);
+        }
+        let before = dups.len();
+        dups.retain(|path| !self.unverified.contains(path) || !self.is_stale(path));
+        if dups.len() == before {
+            Some(dups)
+        } else if dups.len() < 2 {
+            None
+        } else {
+            let ids: HashSet<ID> = dups.iter()
+                .filter_map(|path| self.located.get(path))
+                .map(|loc| loc.id)
+                .collect();
+            let files = ids.len();
+            Some(dups.with_distinct_files(files))
+        }
+    }
+
+    // whether the file at `path` no longer has the size and mtime it was cataloged with
+    fn is_stale(&self, path: &Path) -> bool {
+        let located = match self.located.get(path) {
+            Some(located) => located,
+            None => return true,
+        };
+        match self.vfs.get_metadata(path) {
+            Ok(ref md) if md.get_len() == located.size
+                && md.get_mod_time().ok() == located.modified =>
+            {
+                false
+            }
+            Ok(_) => {
+                warn!("{:?} changed since the catalog was saved, skipping", path);
+                true
+            }
+            Err(e) => {
+                warn!("{}", e);
+                true
+            }
+        }
+    }
+
+    /// Find every group of files matched by size alone, smallest size first
+    /// These are only probable duplicates, so they are not in `get_repeats()`
+    pub fn get_size_only(&self) -> Vec<Duplicates> {
+        let mut all: Vec<(u64, Duplicates)> = self.catalog
+            .iter()
+            .filter_map(|(&size, fkbp)| fkbp.get_size_only().map(|dups| (size, self.stamped(dups))))
+            .collect();
+        all.sort_by_key(|&(size, _)| size);
+        all.into_iter().map(|(_, dups)| dups).collect()
+    }
+
+    /// The identifier (device and inode) `path` was cataloged with, if it's in the catalog
+    pub fn id_of(&self, path: &Path) -> Option<ID> {
+        self.located.get(path).map(|located| located.id)
+    }
+
+    /// Every empty file in the catalog, sorted, unless `dedupe_empty` is set
+    /// (in which case they're reported by `get_repeats()` instead)
+    pub fn get_empty(&self) -> Vec<PathBuf> {
+        if self.dedupe_empty {
+            return vec![];
         }
+        let mut empty: Vec<PathBuf> = self.located
+            .iter()
+            .filter(|&(_, located)| located.size == 0)
+            .map(|(path, _)| path.to_path_buf())
+            .collect();
+        empty.sort();
+        empty
+    }
+
+    /// Find every size shared by files with different contents, smallest first
+    /// Hard links and duplicates of one file count as a single distinct file
+    pub fn get_collisions(&self) -> Vec<Collision> {
+        let mut all: Vec<Collision> = self.catalog
+            .iter()
+            .map(|(&size, fkbp)| Collision {
+                size,
+                paths: fkbp.get_distinct(),
+            })
+            .filter(|c| c.paths.len() >= 2)
+            .collect();
+        all.sort_by_key(|c| c.size);
         all
     }
 
-    /// Inserts path into the catalog
-    pub fn insert(&mut self, path: &Path) {
+    /// The number of paths inserted so far
+    pub fn num_inserted(&self) -> usize {
+        self.inserted
+    }
+
+    /// The number of threads hashing whole files (see `hash_threads`)
+    pub fn num_hash_threads(&self) -> usize {
+        self.pool.as_ref().map_or(1, HashPool::threads)
+    }
+
+    /// Count how many buckets needed their first bytes or whole contents compared,
+    /// and how much reading that took
+    pub fn stats(&self) -> CatalogStats {
+        CatalogStats {
+            files: self.inserted,
+            buckets: self.catalog.len(),
+            ..self.stats
+        }
+    }
+
+    /// Summarize how the cataloged files are distributed by size,
+    /// listing the `top` sizes shared by the most files
+    pub fn get_histogram(&self, top: usize) -> SizeHistogram {
+        let mut hist = SizeHistogram::new(top);
+        for (&size, fkbp) in &self.catalog {
+            hist.add_bucket(size, fkbp.num_files());
+        }
+        hist
+    }
+
+    /// Insert every path in `paths`, carrying on past the ones that fail
+    pub fn insert_all<I>(&mut self, paths: I) -> CatalogReport
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        self.insert_all_with(paths, |_| (), &AtomicBool::new(false))
+    }
+
+    /// Like `insert_all`, but call `progress` with the running report after each path,
+    /// and stop early once `cancel` is set (e.g. from a signal handler)
+    pub fn insert_all_with<I, F>(&mut self, paths: I, mut progress: F, cancel: &AtomicBool)
+        -> CatalogReport
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+        F: FnMut(&CatalogReport),
+    {
+        let mut report = CatalogReport::default();
+        let mut last = LastLogged {
+            done: 0,
+            at: Instant::now(),
+        };
+        for (done, path) in paths.into_iter().enumerate() {
+            if cancel.load(Ordering::Relaxed) {
+                report.cancelled = true;
+                break;
+            }
+            match self.insert(path.as_ref()) {
+                Ok(()) => report.inserted += 1,
+                Err(e) => report.record(e, self.max_errors, self.on_error),
+            }
+            for e in self.take_errors() {
+                report.record(e, self.max_errors, self.on_error);
+            }
+            if report.aborted {
+                break;
+            }
+            progress(&report);
+            if let Some(line) = self.progress_line(done + 1, &mut last, Instant::now()) {
+                info!("{}", line);
+            }
+        }
+        self.finalize();
+        for e in self.take_errors() {
+            report.record(e, self.max_errors, self.on_error);
+        }
+        report
+    }
+
+    // what to log once `done` paths have gone through `insert_all` at `now`, if it's time
+    fn progress_line(&self, done: usize, last: &mut LastLogged, now: Instant) -> Option<String> {
+        let interval = self.log_interval?;
+        let due = done - last.done >= interval.files
+            || now.saturating_duration_since(last.at) >= interval.time;
+        if !due {
+            return None;
+        }
+        *last = LastLogged { done, at: now };
+        // groups can't be counted while their hashes are still being computed
+        let groups = if self.pool.as_ref().is_some_and(HashPool::is_open) {
+            String::new()
+        } else {
+            format!("{} groups of duplicates, ", with_commas(self.get_repeats().len() as u64))
+        };
+        Some(format!(
+            "Cataloged {} files so far: {}{} hashed",
+            with_commas(done as u64),
+            groups,
+            prettify_bytes(self.stats.bytes_hashed)
+        ))
+    }
+
+    /// Inserts path into the catalog; inserting a path that's already in does nothing
+    /// A file that can't be looked up is left out, and the catalog is unaffected
+    /// With `hash_threads`, call `finalize` before looking at the results
+    pub fn insert(&mut self, path: &Path) -> Result<(), CatalogError> {
+        // a link stands for its target (see `keep_symlinks`)
+        // (a dangling link has no target, and is an error)
+        let target;
+        let path = if !self.keep_symlinks && self.vfs.read_link(path).is_ok() {
+            target = self.vfs
+                .canonicalize(path)
+                .map_err(|e| CatalogError::new(path, e))?;
+            target.as_path()
+        } else {
+            path
+        };
+        // each path is only filed once (though one loaded by `load` is looked at afresh)
+        if self.located.contains_key(path) && !self.unverified.contains(path) {
+            return Ok(());
+        }
         // get the metadata (needed for preliminary comparision and storage)
-        let file = self.vfs.get_file(path).expect("No such file");
-        let md = file.get_metadata().expect("IO Error getting Metadata");
-        let size: u64 = md.get_len();
-        let id = ID {
-            dev: md.get_device().expect("Failed to read device info").0,
-            inode: md.get_inode().0,
+        // symlinks are followed, so a link is measured (and identified) by its target
+        let md = self.vfs
+            .get_metadata(path)
+            .map_err(|e| CatalogError::new(path, e))?;
+        let id = ID::of(&md).map_err(|e| {
+            CatalogError::new(path, VfsError::new(Operation::ReadMetadata, path, e))
+        })?;
+        let located = Located {
+            size: md.get_len(),
+            id,
+            modified: md.get_mod_time().ok(),
         };
+        // a loaded path is already filed, and comes out before it's filed again
+        if self.unverified.contains(path) {
+            self.remove(path);
+        }
+        // a path inserted afresh is read afresh, unless it's unchanged since the last run
+        self.recorded.remove(path);
+        self.unverified.remove(path);
+        self.merged.remove(path);
+        if let Some((cached, recorded)) = self.cache.remove(path) {
+            if cached == located {
+                self.recorded.insert(path.to_path_buf(), recorded);
+            }
+        }
+        self.file(path, located);
+        self.report_group_of(path, located.size);
+        Ok(())
+    }
+
+    // sort a path whose metadata has been looked up into the appropriate proxy
+    fn file(&mut self, path: &Path, located: Located) {
+        let Located { size, id, .. } = located;
+        let path = &self.paths.intern(path);
+        self.inserted += 1;
+        self.located.insert(path.clone(), located);
+        *self.link_counts.entry(id).or_insert(0) += 1;
         // sort by size into the appropriate proxy
         match self.catalog.entry(size) {
             // If another file of that size has been included, insert into that proxy
-            Entry::Occupied(mut occ_entry) => occ_entry.get_mut().insert(&self.vfs, id, path),
+            Entry::Occupied(mut occ_entry) => {
+                self.contested.insert(size);
+                let mut ctx = Context {
+                    vfs: &self.vfs,
+                    stats: &mut self.stats,
+                    rehash_on_mismatch: self.rehash_on_mismatch,
+                    size_only: match self.hash_size_limit {
+                        Some(limit) => size > limit,
+                        None => false,
+                    },
+                    last_bytes: self.last_bytes,
+                    pool: self.pool.as_mut(),
+                    progress: self.hash_progress.as_mut().map(|f| &mut **f as _),
+                    recorded: &self.recorded,
+                    size,
+                    first_bytes: None,
+                    verify: self.verify.unwrap_or_else(Verify::default_for::<H>),
+                    errors: &mut self.failed,
+                };
+                occ_entry.get_mut().insert(&mut ctx, id, path)
+            }
             // otherwise create a new firstkbytesproxy with path as the delayed insert.
             Entry::Vacant(vac_entry) => {
                 vac_entry.insert(FirstKBytesProxy::new(id, path));
             }
         }
     }
+
+    /// Take `path` back out of the catalog, e.g. once it's been deleted or modified, so
+    /// it's no longer reported with its duplicates (a group left with a single path isn't
+    /// reported at all); returns whether `path` was in the catalog
+    pub fn remove(&mut self, path: &Path) -> bool {
+        let Located { size, id, .. } = match self.located.remove(path) {
+            Some(located) => located,
+            None => return false,
+        };
+        self.paths.forget(path);
+        self.recorded.remove(path);
+        self.unverified.remove(path);
+        self.merged.remove(path);
+        self.inserted -= 1;
+        // whether this was the last path of its file
+        let last = match self.link_counts.entry(id) {
+            Entry::Occupied(mut count) => {
+                *count.get_mut() -= 1;
+                if *count.get() == 0 {
+                    count.remove();
+                    true
+                } else {
+                    false
+                }
+            }
+            Entry::Vacant(_) => true,
+        };
+        // queued hashes have to be sorted in before anything can be taken out
+        self.finalize();
+        let (removed, left) = match self.catalog.get_mut(&size) {
+            Some(fkbp) => (fkbp.remove(id, path, last), fkbp.num_files()),
+            None => return false,
+        };
+        if left == 0 {
+            self.catalog.remove(&size);
+        }
+        if left < 2 {
+            self.contested.remove(&size);
+        }
+        removed
+    }
+}
+
+// hashing in parallel needs a filesystem and hashes that can be sent to other threads
+impl<T, H> FileCataloger<T, H>
+where
+    T: VFS + Send + 'static,
+    H: FileHash + 'static,
+    H::Output: Send + 'static,
+{
+    /// Hash whole files on `n` worker threads while the catalog carries on with sizes and
+    /// first bytes; the duplicates found are exactly the ones found without it
+    /// `n` below 2 hashes on the calling thread, as by default
+    pub fn hash_threads(mut self, n: usize) -> Self {
+        self.pool = if n >= 2 {
+            Some(HashPool::new(&self.vfs, n))
+        } else {
+            None
+        };
+        self
+    }
 }
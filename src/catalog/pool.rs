@@ -0,0 +1,133 @@
+//! Worker threads that hash whole files while the catalog carries on sorting others
+
+use std::thread::{self, JoinHandle};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::collections::{HashMap, HashSet};
+
+use vfs::VFS;
+use hash::FileHash;
+use super::proxy::{hash_file, Hashed, Snapshot};
+
+// a file to hash, and its metadata to re-check first (if any)
+type Job = (PathBuf, Option<Snapshot>);
+
+/// A fixed set of threads hashing the files submitted to it
+/// While open it takes work; `finish` waits for the hashes and closes it so they can be
+/// looked up, and `reopen` forgets them to take more work
+pub struct HashPool<H: FileHash> {
+    // `None` only while being dropped
+    jobs: Option<Sender<Job>>,
+    results: Receiver<(PathBuf, Hashed<<H as FileHash>::Output>)>,
+    workers: Vec<JoinHandle<()>>,
+    // every path submitted since the pool (re)opened
+    submitted: HashSet<PathBuf>,
+    // number of submitted paths whose hashes haven't come back
+    outstanding: usize,
+    // the hashes that came back, once the pool is closed
+    done: HashMap<PathBuf, Hashed<<H as FileHash>::Output>>,
+    open: bool,
+}
+
+impl<H: FileHash> HashPool<H> {
+    /// Hash files on `vfs` with `threads` workers (at least one)
+    pub fn new<T>(vfs: &T, threads: usize) -> Self
+    where
+        T: VFS + Send + 'static,
+        H: 'static,
+        H::Output: Send + 'static,
+    {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (report, results) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let vfs = vfs.clone();
+                let queue = Arc::clone(&queue);
+                let report = report.clone();
+                let setup = H::carry_to_thread();
+                thread::spawn(move || {
+                    setup();
+                    loop {
+                        // the lock is only held while waiting for the next job
+                        let job = queue.lock().expect("Hash worker panicked").recv();
+                        let (path, snapshot) = match job {
+                            Ok(job) => job,
+                            Err(_) => break,
+                        };
+                        let outcome = hash_file::<T, H>(&vfs, &path, snapshot, None);
+                        if report.send((path, outcome)).is_err() {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+        HashPool {
+            jobs: Some(jobs),
+            results,
+            workers,
+            submitted: HashSet::new(),
+            outstanding: 0,
+            done: HashMap::new(),
+            open: true,
+        }
+    }
+
+    /// Number of worker threads
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Whether the pool is taking work (rather than holding finished hashes)
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Queue `path` to be hashed, unless it already has been since the pool opened
+    pub fn submit(&mut self, path: &Path, snapshot: Option<Snapshot>) {
+        assert!(self.open, "submitted to a closed HashPool");
+        if self.submitted.insert(path.to_path_buf()) {
+            self.jobs
+                .as_ref()
+                .expect("HashPool is being dropped")
+                .send((path.to_path_buf(), snapshot))
+                .expect("Hash workers exited early");
+            self.outstanding += 1;
+        }
+    }
+
+    /// Wait for every submitted hash, then close the pool so they can be looked up
+    pub fn finish(&mut self) {
+        while self.outstanding > 0 {
+            let (path, outcome) = self.results.recv().expect("Hash workers exited early");
+            self.done.insert(path, outcome);
+            self.outstanding -= 1;
+        }
+        self.open = false;
+    }
+
+    /// What came of hashing `path`, if it was submitted and the pool has closed
+    pub(super) fn get(&self, path: &Path) -> Option<&Hashed<<H as FileHash>::Output>> {
+        self.done.get(path)
+    }
+
+    /// Forget the finished hashes and take work again
+    pub fn reopen(&mut self) {
+        assert_eq!(0, self.outstanding);
+        self.submitted.clear();
+        self.done.clear();
+        self.open = true;
+    }
+}
+
+// hang up on the workers, then wait for them to notice
+impl<H: FileHash> Drop for HashPool<H> {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -11,7 +11,7 @@ use catalog::FileCataloger;
 use hash::FileHash;
 
 use vfs::VFS;
-use catalog::proxy::{Duplicates, FirstKBytesProxy, HashProxy};
+use catalog::proxy::{Duplicates, FirstKBytesProxy, HashProxy, LastKBytesProxy};
 
 // print debug info for ID
 impl Debug for ID {
@@ -24,15 +24,15 @@ impl Debug for ID {
 impl Debug for Duplicates {
     fn fmt(&self, f: &mut Formatter) -> Result {
         write!(f, "[")?;
-        if let Some(i) = self.0.get(0) {
-            write!(f, "{:?}", i)?;
+        if let Some(i) = self.paths().first() {
+            write!(f, "{}", i.display())?;
         } else {
             // something's probably wrong as ATM this object only
             // should be created if 2+ entries are to be added
             write!(f, "~EMPTY~")?;
         }
-        for i in self.0.iter().skip(1) {
-            write!(f, ", {:?}", i)?;
+        for i in self.iter().skip(1) {
+            write!(f, ", {}", i.display())?;
         }
         write!(f, "]")
     }
@@ -74,11 +74,29 @@ impl<H: FileHash> Debug for FirstKBytesProxy<H> {
                     write!(f, "'':  {:?}", hp)?;
                 }
             }
+            FirstKBytesProxy::SizeOnly { ref dups } => {
+                write!(f, "SizeOnly: {:?}", dups)?;
+            }
         }
         Ok(())
     }
 }
 
+// print debug info for LastKBytesProxy
+impl<H: FileHash> Debug for LastKBytesProxy<H> {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match *self {
+            LastKBytesProxy::Off(ref hp) => write!(f, "{:?}", hp),
+            LastKBytesProxy::Delay {
+                ref id, ref dups, ..
+            } => write!(f, "LKBProxy::Delay: ({:?})  {:?}", id, dups),
+            LastKBytesProxy::Thunk { ref thunk, .. } => {
+                write!(f, "LKBProxy::Thunk: {:?}", thunk.values().collect::<Vec<_>>())
+            }
+        }
+    }
+}
+
 // print debug info for HashProxy
 impl<T: FileHash> Debug for HashProxy<T> {
     fn fmt(&self, f: &mut Formatter) -> Result {
@@ -106,6 +124,9 @@ impl<T: FileHash> Debug for HashProxy<T> {
                     write!(f, "{:?}, ", repeats)?;
                 }
                 */            }
+            HashProxy::Queued { ref base, ref log } => {
+                write!(f, "Queued: {:?} + {} to hash", base, log.len())?;
+            }
         }
         Ok(())
     }
@@ -1,39 +1,466 @@
 //! Internals of the Cataloge data structure: identifying files by their size, hash, or first bytes
 
+use std::{io, mem, slice};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::collections::hash_map::Entry;
+use std::time::SystemTime;
 
-use vfs::{File, VFS};
-use helpers::ID;
-use hash::FileHash;
+use vfs::{File, MetaData, VfsError, VFS};
+use helpers::{path_bytes, FIRST_K_BYTES, ID, LAST_K_BYTES};
+use hash::{FileHash, Identity};
+use super::{CatalogError, CatalogStats, Verify};
+use super::pool::HashPool;
+
+/// What a file was like when it was cataloged, to tell whether it has changed since
+/// (see `Duplicates::stamp_of`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stamp {
+    /// Its length
+    pub size: u64,
+    /// When it was last modified, if that could be read
+    pub modified: Option<SystemTime>,
+    /// The file it was
+    pub id: ID,
+}
+
+impl Stamp {
+    /// Whether a file with metadata `md` is still the one stamped, unmodified
+    pub fn matches<M: MetaData>(&self, md: &M) -> bool {
+        md.get_len() == self.size
+            && md.get_mod_time().ok() == self.modified
+            && ID::of(md).ok() == Some(self.id)
+    }
+}
 
 // Duplicates is a decorator for a vector of pathbufs which represents
 // a set of files. In code, it is an invariant that any 2 files in a
 // duplicates are identicle.
 #[derive(Clone)]
-/// Collection of `PathBuf`s that point to identical files
-pub struct Duplicates(pub(crate) Vec<PathBuf>);
+/// Collection of paths that point to identical files
+/// The paths are shared with the catalog that found them (see `PathInterner`), so
+/// cloning a group doesn't copy them
+pub struct Duplicates {
+    paths: Vec<Arc<Path>>,
+    // the length of each file, if known
+    size: Option<u64>,
+    // the digest of the contents, if they were hashed
+    hash: Option<Vec<u8>>,
+    // how many distinct files the paths are (fewer than the paths if some are hard links)
+    files: usize,
+    // what the files were like when they were cataloged, by path (if known)
+    stamps: HashMap<Arc<Path>, Stamp>,
+}
 
 impl Duplicates {
+    /// Group `paths`, which the caller vouches are identical (and all distinct files)
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let files = paths.len();
+        Duplicates {
+            paths: paths.into_iter().map(Arc::from).collect(),
+            size: None,
+            hash: None,
+            files,
+            stamps: HashMap::new(),
+        }
+    }
+    /// Record the length of each file in the group
+    pub fn with_size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+    /// Record the digest of the group's contents
+    pub fn with_hash(mut self, hash: Vec<u8>) -> Self {
+        self.hash = Some(hash);
+        self
+    }
+    /// Record that the paths are only `n` distinct files, the rest being hard links
+    pub fn with_distinct_files(mut self, n: usize) -> Self {
+        self.files = n;
+        self
+    }
+    /// Record what the file at `path` was like when it was cataloged
+    pub fn with_stamp<P: AsRef<Path>>(mut self, path: P, stamp: Stamp) -> Self {
+        self.stamps.insert(Arc::from(path.as_ref()), stamp);
+        self
+    }
+    // record the stamp `stamp_of` gives each path, sharing the paths already in the group
+    pub(super) fn with_stamps<F: Fn(&Path) -> Option<Stamp>>(mut self, stamp_of: F) -> Self {
+        for path in &self.paths {
+            if let Some(stamp) = stamp_of(path) {
+                self.stamps.insert(path.clone(), stamp);
+            }
+        }
+        self
+    }
+    /// Take on the stamps `other` has for the paths in this group
+    pub fn with_stamps_from(mut self, other: &Duplicates) -> Self {
+        for path in &self.paths {
+            if let Some(&stamp) = other.stamps.get(path) {
+                self.stamps.insert(path.clone(), stamp);
+            }
+        }
+        self
+    }
+    /// Number of paths in the group (hard links to the same file each count)
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+    /// Whether the group has no paths at all
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+    /// Every path in the group
+    pub fn paths(&self) -> &[Arc<Path>] {
+        &self.paths
+    }
+    /// Iterate over the paths in the group
+    pub fn iter(&self) -> slice::Iter<'_, Arc<Path>> {
+        self.paths.iter()
+    }
+    /// Put the paths in order of their bytes (see `path_bytes`)
+    pub fn sort(&mut self) {
+        self.paths.sort_by(|a, b| path_bytes(a).cmp(&path_bytes(b)));
+    }
+    /// Copy the paths out of the group
+    pub fn into_paths(self) -> Vec<PathBuf> {
+        self.paths.iter().map(|p| p.to_path_buf()).collect()
+    }
+    /// The length of each file in the group, if known
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+    /// Number of distinct files in the group (hard links to one file count once)
+    pub fn distinct_files(&self) -> usize {
+        self.files
+    }
+    /// Space the group's files take up (0 if their size isn't known)
+    pub fn total_bytes(&self) -> u64 {
+        self.size.unwrap_or(0) * self.files as u64
+    }
+    /// Space that keeping just one of the files would free
+    pub fn redundant_bytes(&self) -> u64 {
+        self.size.unwrap_or(0) * self.files.saturating_sub(1) as u64
+    }
+    /// The digest of the group's contents, if the hash in use produces one
+    /// Groups made only of hard links to one file are never hashed, so have none
+    pub fn hash(&self) -> Option<&[u8]> {
+        self.hash.as_deref()
+    }
+    /// What the file at `path` was like when it was cataloged, if known
+    /// (groups from a `FileCataloger` know it for each of their paths)
+    pub fn stamp_of(&self, path: &Path) -> Option<Stamp> {
+        self.stamps.get(path).cloned()
+    }
+    /// The file (device and inode) `path` was cataloged as, if it's known,
+    /// so acting on the group needn't look it up again
+    pub fn id_of(&self, path: &Path) -> Option<ID> {
+        self.stamps.get(path).map(|stamp| stamp.id)
+    }
+    /// Keep only the paths for which `keep` returns true
+    /// The count of distinct files is left as it was (see `with_distinct_files`)
+    pub fn retain<F: FnMut(&Path) -> bool>(&mut self, mut keep: F) {
+        self.paths.retain(|p| keep(p));
+        let paths = &self.paths;
+        self.stamps.retain(|p, _| paths.contains(p));
+    }
     // Convert a path to a vector of length 1 containing that path
-    fn from(path: &Path) -> Self {
-        Duplicates(vec![path.to_path_buf()])
+    fn from(path: &Arc<Path>) -> Self {
+        Duplicates {
+            paths: vec![path.clone()],
+            size: None,
+            hash: None,
+            files: 1,
+            stamps: HashMap::new(),
+        }
     }
     // Convert the first element to a path
     fn get_path(&self) -> &Path {
-        &self.0[0]
+        &self.paths[0]
     }
     // Add path to this Duplicates
-    fn push(&mut self, path: &Path) {
-        self.0.push(path.to_path_buf());
+    fn push(&mut self, path: &Arc<Path>) {
+        self.paths.push(path.clone());
+    }
+    // Take path out of this Duplicates, returning whether it was there
+    fn remove(&mut self, path: &Path) -> bool {
+        match self.paths.iter().position(|p| **p == *path) {
+            Some(i) => {
+                self.paths.remove(i);
+                self.stamps.remove(path);
+                true
+            }
+            None => false,
+        }
     }
     // Add all elements in another object othr to this Duplicates
     fn append(&mut self, mut othr: Duplicates) {
-        self.0.append(&mut othr.0);
+        self.paths.append(&mut othr.paths);
+        self.stamps.extend(othr.stamps);
+    }
+}
+
+// Read a file's contents through `read`, e.g. hashing its first K bytes
+// A file that can be listed but not read (ACLs, selinux, ...) can't be compared to anything:
+// record why and return None so the caller leaves it out of every group
+fn read_contents<T, H, R, F>(ctx: &mut Context<T, H>, path: &Path, read: F) -> Option<R>
+where
+    T: VFS,
+    H: FileHash,
+    F: FnOnce(&T::FileIter) -> ::vfs::Result<R>,
+{
+    match ctx.vfs.get_file(path).and_then(|file| read(&file)) {
+        Ok(r) => Some(r),
+        Err(e) => {
+            ctx.errors.push(CatalogError::new(path, e));
+            None
+        }
+    }
+}
+
+/// Files whose whole contents hashed the same and, as far as `Verify` asks to check,
+/// are the same
+#[derive(Debug)]
+pub struct HashGroup {
+    dups: Duplicates,
+    // how many distinct files (identifiers) the paths in `dups` belong to
+    files: usize,
+    // the first bytes of the first file, once they've been read to check another against
+    prefix: Option<Vec<u8>>,
+}
+
+impl HashGroup {
+    fn new(dups: Duplicates, prefix: Option<Vec<u8>>) -> Self {
+        HashGroup {
+            dups,
+            files: 1,
+            prefix,
+        }
+    }
+
+    // add the paths of a file that isn't in the group yet
+    fn add(&mut self, dups: Duplicates) {
+        self.dups.append(dups);
+        self.files += 1;
+    }
+}
+
+// Sort `dups`, the paths of a new file, in with the groups of files that share its hash: into
+// the first whose contents
+// match its own as far as `ctx.verify` asks to check, or into a new one if none does (the hashes
+// collided); returns where it went, or None if it can't be read to tell (and was left out)
+fn sort_in<T: VFS, H: FileHash>(
+    ctx: &mut Context<T, H>,
+    groups: &mut Vec<HashGroup>,
+    dups: Duplicates,
+) -> Option<usize> {
+    // empty files can't differ, and files from a saved catalog were compared when it was made
+    let recorded = |p: &Path| ctx.recorded.get(p).is_some_and(|r| r.hash.is_some());
+    let trusted = if ctx.size == 0 || ctx.verify == Verify::Off {
+        groups.iter().position(|g| !g.dups.is_empty())
+    } else if recorded(dups.get_path()) {
+        groups.iter().position(|g| !g.dups.is_empty() && recorded(g.dups.get_path()))
+    } else {
+        None
+    };
+    if let Some(i) = trusted {
+        groups[i].add(dups);
+        return Some(i);
+    }
+    if groups.iter().all(|g| g.dups.is_empty()) {
+        groups.push(HashGroup::new(dups, None));
+        return Some(groups.len() - 1);
+    }
+    let read: fn(&T::FileIter) -> ::vfs::Result<Vec<u8>> = match ctx.verify {
+        Verify::Full => File::get_hash::<Identity>,
+        _ => File::get_first_bytes::<Identity>,
+    };
+    let path = dups.get_path().to_path_buf();
+    let mine = read_contents(ctx, &path, read)?;
+    ctx.stats.verify_reads += 1;
+    for (i, group) in groups.iter_mut().enumerate() {
+        if group.dups.is_empty() {
+            continue;
+        }
+        // the first bytes of each group are only read once; whole files every time
+        let same = match group.prefix {
+            Some(ref prefix) if ctx.verify == Verify::Prefix => *prefix == mine,
+            _ => {
+                let theirs = match read_contents(ctx, group.dups.get_path(), read) {
+                    Some(theirs) => theirs,
+                    None => continue,
+                };
+                ctx.stats.verify_reads += 1;
+                let same = theirs == mine;
+                if ctx.verify == Verify::Prefix {
+                    group.prefix = Some(theirs);
+                }
+                same
+            }
+        };
+        if same {
+            group.add(dups);
+            return Some(i);
+        }
+        error!(
+            "{:?} has the same hash as {:?} but different contents",
+            path,
+            group.dups.get_path()
+        );
+    }
+    let prefix = if ctx.verify == Verify::Prefix { Some(mine) } else { None };
+    groups.push(HashGroup::new(dups, prefix));
+    Some(groups.len() - 1)
+}
+
+/// Told how far hashing a file has got: its path, the bytes hashed so far, and its length
+pub type HashProgress<'a> = &'a mut dyn FnMut(&Path, u64, u64);
+
+/// Everything a proxy needs while a file is being inserted
+pub struct Context<'a, T: 'a + VFS, H: 'a + FileHash> {
+    /// The filesystem the files live on
+    pub vfs: &'a T,
+    /// Promotion counters to update
+    pub stats: &'a mut CatalogStats,
+    /// Re-check a file's size and mtime before hashing it in full
+    pub rehash_on_mismatch: bool,
+    /// The file is too large to hash: match it by size alone
+    pub size_only: bool,
+    /// Compare the last bytes of files whose first bytes match (see `LastKBytesProxy`)
+    pub last_bytes: bool,
+    /// Workers to hand whole-file hashes to, instead of hashing on this thread
+    pub pool: Option<&'a mut HashPool<H>>,
+    /// Called as files are hashed whole on this thread (see `FileCataloger::hash_progress`)
+    pub progress: Option<HashProgress<'a>>,
+    /// Hashes already known from a saved catalog, by path, so the files needn't be read
+    pub recorded: &'a HashMap<PathBuf, Recorded<<H as FileHash>::Output>>,
+    /// The size of the files being compared
+    pub size: u64,
+    /// If the files are no bigger than the first bytes read of them, the hash of the
+    /// first bytes they share, which is also the hash of each (set by `FirstKBytesProxy`)
+    pub first_bytes: Option<<H as FileHash>::Output>,
+    /// How to check that a file really matches the files with the same hash
+    pub verify: Verify,
+    /// Where to record files that have to be left out (see `FileCataloger::take_errors`)
+    pub errors: &'a mut Vec<CatalogError>,
+}
+
+impl<'a, T: VFS, H: FileHash> Context<'a, T, H> {
+    // whether hashing the first bytes of the files hashes all of them
+    fn small(&self) -> bool {
+        self.size <= FIRST_K_BYTES as u64
+    }
+    // whether files whose first bytes match are compared by their last bytes next
+    // (pointless unless the first and last bytes are far enough apart)
+    fn compare_last_bytes(&self) -> bool {
+        self.last_bytes && self.size >= 2 * LAST_K_BYTES as u64
+    }
+    // about to compare files whose first bytes hashed to `first_bytes`
+    fn comparing(&mut self, first_bytes: &<H as FileHash>::Output) {
+        self.first_bytes = if self.small() {
+            Some(first_bytes.clone())
+        } else {
+            None
+        };
     }
 }
 
+/// What a catalog knew about a file's contents: the hashes of its first bytes and of
+/// the whole file, whichever were computed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recorded<O> {
+    /// The hash of the first bytes
+    pub first_bytes: Option<O>,
+    /// The hash of the whole file
+    pub hash: Option<O>,
+}
+
+impl<O> Default for Recorded<O> {
+    fn default() -> Self {
+        Recorded {
+            first_bytes: None,
+            hash: None,
+        }
+    }
+}
+
+/// The size and modification time of a file when its first bytes were read
+/// Only recorded with `rehash_on_mismatch`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapshot {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Snapshot {
+    fn take<T: VFS>(vfs: &T, path: &Path) -> ::vfs::Result<Self> {
+        let md = vfs.get_metadata(path)?;
+        Ok(Snapshot {
+            len: md.get_len(),
+            modified: md.get_mod_time().ok(),
+        })
+    }
+}
+
+// What came of hashing a file in full
+pub(super) enum Hashed<O> {
+    // the hash of its contents
+    Done(O),
+    // its size or mtime changed since its snapshot was taken
+    Changed,
+    // it couldn't be read
+    Failed(VfsError),
+}
+
+// What to make of a file that has been modified since its snapshot was taken, if it has
+fn changed<T: VFS, O>(vfs: &T, path: &Path, snapshot: Option<Snapshot>) -> Option<Hashed<O>> {
+    let before = snapshot?;
+    match Snapshot::take(vfs, path) {
+        Ok(ref now) if *now == before => None,
+        Ok(_) => Some(Hashed::Changed),
+        Err(e) => Some(Hashed::Failed(e)),
+    }
+}
+
+// Hash a file in full, first making sure it hasn't been modified since its snapshot was taken
+// (if it has, its first bytes and its hash may disagree)
+// Nothing is logged, so this can run on any thread
+// `progress` is passed the bytes hashed so far and the file's length as it goes
+pub(super) fn hash_file<T: VFS, H: FileHash>(
+    vfs: &T,
+    path: &Path,
+    snapshot: Option<Snapshot>,
+    progress: Option<&mut dyn FnMut(u64, u64)>,
+) -> Hashed<<H as FileHash>::Output> {
+    if let Some(outcome) = changed(vfs, path, snapshot) {
+        return outcome;
+    }
+    let hash = vfs.get_file(path).and_then(|file| match progress {
+        Some(progress) => file.get_hash_with_progress::<H>(progress),
+        None => file.get_hash::<H>(),
+    });
+    match hash {
+        Ok(hash) => Hashed::Done(hash),
+        Err(e) => Hashed::Failed(e),
+    }
+}
+
+// The hash in `outcome`, or None (recorded in `errors`) if the file has to be left out
+fn accept<O: Clone>(errors: &mut Vec<CatalogError>, path: &Path, outcome: &Hashed<O>) -> Option<O> {
+    let e = match *outcome {
+        Hashed::Done(ref hash) => return Some(hash.clone()),
+        Hashed::Changed => CatalogError::changed(path),
+        // the outcome may be the pool's to keep: copy its error's kind and message
+        Hashed::Failed(ref e) => {
+            let cause = io::Error::new(e.kind(), e.cause().to_string());
+            CatalogError::new(path, VfsError::new(e.op(), e.path(), cause))
+        }
+    };
+    errors.push(e);
+    None
+}
+
 // Begin FirstKBytesProxy
 
 /// Proxy of firstbytes: until two elements have been added, there is no
@@ -51,46 +478,144 @@ pub enum FirstKBytesProxy<H: FileHash> {
     // also maintain a shortcut for looking up their values by their id
     // for hardlink detection.
     Thunk {
-        /// Identify a `HashProxy` by the hash of the first K bytes
-        thunk: HashMap<<H as FileHash>::Output, HashProxy<H>>,
+        /// Identify a `LastKBytesProxy` by the hash of the first K bytes
+        thunk: HashMap<<H as FileHash>::Output, LastKBytesProxy<H>>,
         /// Map the unique identifier to a first k bytes hash to enable registering links later
         shortcut: HashMap<ID, <H as FileHash>::Output>,
     },
+    // files too large to hash that share a size are probable duplicates
+    // their contents are never read, so they are never acted on
+    SizeOnly {
+        /// Every path of this size, including hard links
+        dups: Duplicates,
+    },
 }
 
 
 impl<H: FileHash> FirstKBytesProxy<H> {
     /// Construct a new FirstKBytesProxy with delay of path
-    pub fn new(id: ID, path: &Path) -> Self {
+    pub fn new(id: ID, path: &Arc<Path>) -> Self {
         FirstKBytesProxy::Delay {
             id,
             dups: Duplicates::from(path),
         }
     }
 
-    /// Traverse contained `HashProxy`s and identify contents with more than one
+    /// Count every path stored in this proxy (including hard links)
+    pub fn num_files(&self) -> usize {
+        match *self {
+            FirstKBytesProxy::Delay { ref dups, .. } => dups.len(),
+            FirstKBytesProxy::Thunk { ref thunk, .. } => {
+                thunk.values().map(LastKBytesProxy::num_files).sum()
+            }
+            FirstKBytesProxy::SizeOnly { ref dups } => dups.len(),
+        }
+    }
+
+    /// Traverse contained `LastKBytesProxy`s and identify contents with more than one
     /// path associated with it
+    /// The paths of each group are sorted, as they're stored in no particular order
     pub(super) fn get_repeats(&self) -> Vec<Duplicates> {
-        match *self {
+        let mut repeats = match *self {
             // in the Delay state, return `dups` if it contains multiple paths
-            FirstKBytesProxy::Delay { ref dups, .. } => if dups.0.len() >= 2 {
-                vec![dups.clone()]
+            FirstKBytesProxy::Delay { ref dups, .. } => if dups.len() >= 2 {
+                vec![dups.clone().with_distinct_files(1)]
             } else {
                 vec![]
             },
-            // in the Thunk state, traverse all `HashProx`s
+            // in the Thunk state, traverse all `LastKBytesProxy`s
             FirstKBytesProxy::Thunk { ref thunk, .. } => {
                 thunk.iter().fold(vec![], |mut acc, (_fb, hp)| {
                     acc.append(&mut hp.get_repeats());
                     acc
                 })
             }
+            // matching sizes alone aren't enough to act on
+            FirstKBytesProxy::SizeOnly { .. } => vec![],
+        };
+        for dups in &mut repeats {
+            dups.sort();
         }
+        repeats
+    }
+
+    /// The same as `get_repeats`, moving the groups out of the proxy rather than
+    /// cloning them
+    pub(super) fn into_repeats(self) -> Vec<Duplicates> {
+        let mut repeats = match self {
+            FirstKBytesProxy::Delay { dups, .. } => if dups.len() >= 2 {
+                vec![dups.with_distinct_files(1)]
+            } else {
+                vec![]
+            },
+            FirstKBytesProxy::Thunk { thunk, .. } => {
+                thunk.into_values().flat_map(LastKBytesProxy::into_repeats).collect()
+            }
+            FirstKBytesProxy::SizeOnly { .. } => vec![],
+        };
+        for dups in &mut repeats {
+            dups.sort();
+        }
+        repeats
+    }
+
+    /// Get the paths that were matched by size alone (if any)
+    pub(super) fn get_size_only(&self) -> Option<Duplicates> {
+        match *self {
+            // (one path is left if the others were removed)
+            FirstKBytesProxy::SizeOnly { ref dups } if dups.len() >= 2 => Some(dups.clone()),
+            _ => None,
+        }
+    }
+
+    /// Pick one path for each distinct content stored in this proxy
+    /// More than one means same-size files that turned out to differ
+    pub(super) fn get_distinct(&self) -> Vec<PathBuf> {
+        match *self {
+            // in the Delay state, everything is a link to the same file
+            FirstKBytesProxy::Delay { ref dups, .. } => vec![dups.get_path().to_path_buf()],
+            FirstKBytesProxy::Thunk { ref thunk, .. } => {
+                thunk.values().flat_map(LastKBytesProxy::get_distinct).collect()
+            }
+            // contents were never compared, so nothing is known to differ
+            FirstKBytesProxy::SizeOnly { .. } => vec![],
+        }
+    }
+
+    // Hash the first K bytes of a file
+    // also snapshot its metadata if it needs to be re-checked before hashing the rest
+    fn preview<T: VFS>(
+        ctx: &mut Context<T, H>,
+        path: &Path,
+    ) -> Option<(<H as FileHash>::Output, Option<Snapshot>)> {
+        if let Some(first_bytes) = ctx.recorded.get(path).and_then(|r| r.first_bytes.clone()) {
+            return Some((first_bytes, None));
+        }
+        // every empty file hashes the same: no need to open it
+        if ctx.size == 0 {
+            return Some((H::hash(&[]), None));
+        }
+        let snapshot = if ctx.rehash_on_mismatch {
+            match Snapshot::take(ctx.vfs, path) {
+                Ok(s) => Some(s),
+                Err(e) => {
+                    ctx.errors.push(CatalogError::new(path, e));
+                    return None;
+                }
+            }
+        } else {
+            None
+        };
+        let first_bytes = read_contents(ctx, path, File::get_first_bytes::<H>)?;
+        ctx.stats.first_bytes_read += 1;
+        ctx.stats.bytes_hashed += ctx.size.min(FIRST_K_BYTES as u64);
+        Some((first_bytes, snapshot))
     }
 
     /// Transition type from a Delay to a Thunk with the introduction of a new file
     /// Preview both files and add them to the contents of the new Thunk
-    fn transition<T: VFS>(&mut self, vfs: &T, new_id: ID, new_path: &Path) {
+    /// (or to a SizeOnly without reading them if `ctx.size_only`)
+    fn transition<T: VFS>(&mut self, ctx: &mut Context<T, H>, new_id: ID, new_path: &Arc<Path>) {
         // convert from a Delay to a Thunk
         // panics if new belongs in Delay.dups
         // panics if `self` is of type Thunk
@@ -110,21 +635,30 @@ impl<H: FileHash> FirstKBytesProxy<H> {
             _ => unreachable!(),
         };
         assert!(new_id != del_id);
+        // too large to hash: group by size without reading either file
+        if ctx.size_only {
+            let mut dups = del_dups;
+            dups.push(new_path);
+            *self = FirstKBytesProxy::SizeOnly { dups };
+            return;
+        }
         // Initialize new type's variables
         let mut thunk: HashMap<<H as FileHash>::Output, _> = HashMap::new();
         let mut shortcut: HashMap<_, <H as FileHash>::Output> = HashMap::new();
 
         // get first bytes of both files
-        let new_file = vfs.get_file(new_path)
-            .expect("Failed to get file from path");
-        let old_file = vfs.get_file(&del_dups.0[0])
-            .expect("Failed to get file from path");
-        let new_first_bytes: <H as FileHash>::Output = new_file
-            .get_first_bytes::<H>()
-            .expect("Failed to hash first bytes");
-        let old_first_bytes: <H as FileHash>::Output = old_file
-            .get_first_bytes::<H>()
-            .expect("Failed to hash first bytes");
+        // an unreadable new file is dropped; an unreadable old file is replaced by the new one
+        let (new_first_bytes, new_snapshot) = match Self::preview(ctx, new_path) {
+            Some(preview) => preview,
+            None => return,
+        };
+        let (old_first_bytes, old_snapshot) = match Self::preview(ctx, del_dups.get_path()) {
+            Some(preview) => preview,
+            None => {
+                *self = FirstKBytesProxy::new(new_id, new_path);
+                return;
+            }
+        };
 
         // and add them to the map's shortcut.
         shortcut.insert(new_id, new_first_bytes.clone());
@@ -133,19 +667,82 @@ impl<H: FileHash> FirstKBytesProxy<H> {
         // hashmap.
         let new_dups = Duplicates::from(new_path);
         if new_first_bytes == old_first_bytes {
-            let mut hp = HashProxy::new(del_id, del_dups);
-            hp.insert(vfs, new_id, new_dups);
+            ctx.comparing(&old_first_bytes);
+            let mut hp = LastKBytesProxy::new(ctx, del_id, del_dups, old_snapshot);
+            hp.insert(ctx, new_id, new_dups, new_snapshot);
             thunk.insert(old_first_bytes, hp);
         } else {
-            thunk.insert(new_first_bytes, HashProxy::new(new_id, new_dups));
-            thunk.insert(old_first_bytes, HashProxy::new(del_id, del_dups));
+            let new_hp = LastKBytesProxy::new(ctx, new_id, new_dups, new_snapshot);
+            thunk.insert(new_first_bytes, new_hp);
+            let old_hp = LastKBytesProxy::new(ctx, del_id, del_dups, old_snapshot);
+            thunk.insert(old_first_bytes, old_hp);
         }
         // replace pointer from delay a pointer to thunk.
         *self = FirstKBytesProxy::Thunk { thunk, shortcut };
+        ctx.stats.contested += 1;
+    }
+
+    /// Sort in the files whose hashes were queued on `ctx.pool`, which must have closed
+    pub fn resolve<T: VFS>(&mut self, ctx: &mut Context<T, H>) {
+        if let FirstKBytesProxy::Thunk { ref mut thunk, .. } = *self {
+            for (first_bytes, hp) in thunk.iter_mut() {
+                ctx.comparing(first_bytes);
+                hp.resolve(ctx);
+            }
+        }
+    }
+
+    /// Take `path`, a file with identifier `id`, back out of the proxy, returning whether
+    /// it was here; `last` says no other path of that file is left in the catalog
+    /// Anything left empty is dropped, so the caller should drop the proxy once
+    /// `num_files()` is 0; hashes must not be queued (see `resolve`)
+    pub fn remove(&mut self, id: ID, path: &Path, last: bool) -> bool {
+        match *self {
+            FirstKBytesProxy::Delay { id: id2, ref mut dups } => id == id2 && dups.remove(path),
+            FirstKBytesProxy::SizeOnly { ref mut dups } => dups.remove(path),
+            FirstKBytesProxy::Thunk {
+                ref mut thunk,
+                ref mut shortcut,
+            } => {
+                let first_bytes = match shortcut.get(&id) {
+                    Some(fb) => fb.clone(),
+                    None => return false,
+                };
+                if last {
+                    shortcut.remove(&id);
+                }
+                let (removed, empty) = match thunk.get_mut(&first_bytes) {
+                    Some(hp) => (hp.remove(id, path, last), hp.num_files() == 0),
+                    None => return false,
+                };
+                if empty {
+                    thunk.remove(&first_bytes);
+                }
+                removed
+            }
+        }
+    }
+
+    /// The hashes computed for the file with identifier `id`, if it's here
+    pub(super) fn digests(&self, id: ID) -> Recorded<<H as FileHash>::Output> {
+        match *self {
+            FirstKBytesProxy::Thunk {
+                ref thunk,
+                ref shortcut,
+            } => match shortcut.get(&id) {
+                Some(first_bytes) => Recorded {
+                    first_bytes: Some(first_bytes.clone()),
+                    hash: thunk.get(first_bytes).and_then(|hp| hp.hash_of(id)),
+                },
+                None => Recorded::default(),
+            },
+            _ => Recorded::default(),
+        }
     }
 
     /// Add a new path to the proxy
-    pub fn insert<T: VFS>(&mut self, vfs: &T, id: ID, path: &Path) {
+    /// Promotions to comparing first bytes or hashing are counted in `ctx.stats`
+    pub fn insert<T: VFS>(&mut self, ctx: &mut Context<T, H>, id: ID, path: &Arc<Path>) {
         match *self {
             // If a hard link and self is a Delay, insert a hard link to what's
             // already stored in Delay
@@ -159,30 +756,306 @@ impl<H: FileHash> FirstKBytesProxy<H> {
             }
             // If self is a thunk get first bytes and add to shortcut.
             // If a match for a proxy, add
-            // to the proxy; otherwise create a new hashproxy.
+            // to the proxy; otherwise create a new proxy.
             FirstKBytesProxy::Thunk {
                 ref mut thunk,
                 ref mut shortcut,
             } => {
-                let file = vfs.get_file(path).expect("Failed to get file");
-                let first_bytes: <H as FileHash>::Output = file.get_first_bytes::<H>()
-                    .expect("Failed to hash first bytes");
+                // a hard link to a file already here has the same contents: don't read it
+                if let Some(fb) = shortcut.get(&id) {
+                    if let Some(hp) = thunk.get_mut(fb) {
+                        ctx.comparing(fb);
+                        hp.insert(ctx, id, Duplicates::from(path), None);
+                        return;
+                    }
+                }
+                let (first_bytes, snapshot) = match Self::preview(ctx, path) {
+                    Some(preview) => preview,
+                    None => return,
+                };
                 shortcut.insert(id, first_bytes.clone());
                 match thunk.entry(first_bytes) {
-                    // call `insert` on the underlying HashProxy
+                    // call `insert` on the underlying LastKBytesProxy
                     Entry::Occupied(mut occ_entry) => {
-                        occ_entry.get_mut().insert(vfs, id, Duplicates::from(path))
+                        ctx.comparing(occ_entry.key());
+                        occ_entry
+                            .get_mut()
+                            .insert(ctx, id, Duplicates::from(path), snapshot)
                     }
-                    // not there: create a new HashProxy
+                    // not there: create a new LastKBytesProxy
                     Entry::Vacant(vac_entry) => {
-                        let hp = HashProxy::new(id, Duplicates::from(path));
+                        let hp = LastKBytesProxy::new(ctx, id, Duplicates::from(path), snapshot);
                         vac_entry.insert(hp);
                     }
                 }
             }
+            // If matched by size alone, just add the path
+            FirstKBytesProxy::SizeOnly { ref mut dups } => dups.push(path),
             // If we are a delay and need to insert a path that is not a hardlink,
             // transition to a thunk
-            FirstKBytesProxy::Delay { .. } => self.transition(vfs, id, path),
+            FirstKBytesProxy::Delay { .. } => self.transition(ctx, id, path),
+        }
+    }
+}
+
+// Begin LastKBytesProxy
+
+/// Proxy of last bytes: files whose first bytes match are grouped by their last K bytes
+/// before being hashed whole, which tells apart large files that share a header
+/// Until two distinct files have been added, neither is read
+pub enum LastKBytesProxy<H: FileHash> {
+    // the stage is off, or the files are too small for it: go straight to hashing
+    Off(HashProxy<H>),
+    // only one unique file has been added
+    Delay {
+        /// The unique identifier for the paths
+        id: ID,
+        /// A collection of hard links
+        dups: Duplicates,
+        /// The metadata of the paths when their first bytes were read (if it needs re-checking)
+        snapshot: Option<Snapshot>,
+    },
+    // see `FirstKBytesProxy` for more documentation
+    Thunk {
+        /// Identify a `HashProxy` by the hash of the last K bytes
+        thunk: HashMap<<H as FileHash>::Output, HashProxy<H>>,
+        /// Map the unique identifier to a last k bytes hash to enable registering links later
+        shortcut: HashMap<ID, <H as FileHash>::Output>,
+    },
+}
+
+// closely parallels FirstKBytesProxy's documentation
+impl<H: FileHash> LastKBytesProxy<H> {
+    // start with one file, only comparing last bytes if `ctx` calls for it
+    fn new<T: VFS>(
+        ctx: &Context<T, H>,
+        id: ID,
+        dups: Duplicates,
+        snapshot: Option<Snapshot>,
+    ) -> Self {
+        if ctx.compare_last_bytes() {
+            LastKBytesProxy::Delay { id, dups, snapshot }
+        } else {
+            LastKBytesProxy::Off(HashProxy::new(id, dups, snapshot))
+        }
+    }
+
+    // count every path under this node
+    fn num_files(&self) -> usize {
+        match *self {
+            LastKBytesProxy::Off(ref hp) => hp.num_files(),
+            LastKBytesProxy::Delay { ref dups, .. } => dups.len(),
+            LastKBytesProxy::Thunk { ref thunk, .. } => {
+                thunk.values().map(HashProxy::num_files).sum()
+            }
+        }
+    }
+
+    // every group of identical files under this node
+    fn get_repeats(&self) -> Vec<Duplicates> {
+        match *self {
+            LastKBytesProxy::Off(ref hp) => hp.get_repeats(),
+            LastKBytesProxy::Delay { ref dups, .. } => if dups.len() >= 2 {
+                vec![dups.clone().with_distinct_files(1)]
+            } else {
+                vec![]
+            },
+            LastKBytesProxy::Thunk { ref thunk, .. } => {
+                thunk.values().flat_map(HashProxy::get_repeats).collect()
+            }
+        }
+    }
+
+    // the same, moving the groups out
+    fn into_repeats(self) -> Vec<Duplicates> {
+        match self {
+            LastKBytesProxy::Off(hp) => hp.into_repeats(),
+            LastKBytesProxy::Delay { dups, .. } => if dups.len() >= 2 {
+                vec![dups.with_distinct_files(1)]
+            } else {
+                vec![]
+            },
+            LastKBytesProxy::Thunk { thunk, .. } => {
+                thunk.into_values().flat_map(HashProxy::into_repeats).collect()
+            }
+        }
+    }
+
+    // get one path for each distinct content under this node
+    fn get_distinct(&self) -> Vec<PathBuf> {
+        match *self {
+            LastKBytesProxy::Off(ref hp) => hp.get_distinct(),
+            LastKBytesProxy::Delay { ref dups, .. } => vec![dups.get_path().to_path_buf()],
+            LastKBytesProxy::Thunk { ref thunk, .. } => {
+                thunk.values().flat_map(HashProxy::get_distinct).collect()
+            }
+        }
+    }
+
+    // the hash of the file with this identifier, if it was hashed
+    fn hash_of(&self, id: ID) -> Option<<H as FileHash>::Output> {
+        match *self {
+            LastKBytesProxy::Off(ref hp) => hp.hash_of(id),
+            LastKBytesProxy::Delay { .. } => None,
+            LastKBytesProxy::Thunk {
+                ref thunk,
+                ref shortcut,
+            } => shortcut
+                .get(&id)
+                .and_then(|lb| thunk.get(lb))
+                .and_then(|hp| hp.hash_of(id)),
+        }
+    }
+
+    // take `path` back out (see `FirstKBytesProxy::remove`)
+    fn remove(&mut self, id: ID, path: &Path, last: bool) -> bool {
+        match *self {
+            LastKBytesProxy::Off(ref mut hp) => hp.remove(id, path, last),
+            LastKBytesProxy::Delay {
+                id: id2,
+                ref mut dups,
+                ..
+            } => id == id2 && dups.remove(path),
+            LastKBytesProxy::Thunk {
+                ref mut thunk,
+                ref mut shortcut,
+            } => {
+                let last_bytes = match shortcut.get(&id) {
+                    Some(lb) => lb.clone(),
+                    None => return false,
+                };
+                if last {
+                    shortcut.remove(&id);
+                }
+                let (removed, empty) = match thunk.get_mut(&last_bytes) {
+                    Some(hp) => (hp.remove(id, path, last), hp.num_files() == 0),
+                    None => return false,
+                };
+                if empty {
+                    thunk.remove(&last_bytes);
+                }
+                removed
+            }
+        }
+    }
+
+    // sort in the files whose hashes were queued (see `FirstKBytesProxy::resolve`)
+    fn resolve<T: VFS>(&mut self, ctx: &mut Context<T, H>) {
+        match *self {
+            LastKBytesProxy::Off(ref mut hp) => hp.resolve(ctx),
+            LastKBytesProxy::Thunk { ref mut thunk, .. } => {
+                for hp in thunk.values_mut() {
+                    hp.resolve(ctx);
+                }
+            }
+            LastKBytesProxy::Delay { .. } => {}
+        }
+    }
+
+    // Hash the last K bytes of a file
+    fn last_bytes<T: VFS>(ctx: &mut Context<T, H>, path: &Path) -> Option<<H as FileHash>::Output> {
+        let last_bytes = read_contents(ctx, path, File::get_last_bytes::<H>)?;
+        ctx.stats.last_bytes_read += 1;
+        ctx.stats.bytes_hashed += LAST_K_BYTES as u64;
+        Some(last_bytes)
+    }
+
+    // handle the conversion from Delay to Thunk, reading the last bytes of both files
+    fn transition<T: VFS>(
+        &mut self,
+        ctx: &mut Context<T, H>,
+        new_id: ID,
+        new_dups: Duplicates,
+        new_snapshot: Option<Snapshot>,
+    ) {
+        let (del_id, del_dups, del_snapshot) = match *self {
+            LastKBytesProxy::Delay {
+                id,
+                ref dups,
+                snapshot,
+            } => {
+                assert!(id != new_id);
+                (id, dups.clone(), snapshot)
+            }
+            _ => unreachable!(),
+        };
+        let mut thunk = HashMap::new();
+        let mut shortcut = HashMap::new();
+
+        // an unreadable new file is dropped; an unreadable old file is replaced by the new one
+        let new_last_bytes = match Self::last_bytes(ctx, new_dups.get_path()) {
+            Some(lb) => lb,
+            None => return,
+        };
+        let old_last_bytes = match Self::last_bytes(ctx, del_dups.get_path()) {
+            Some(lb) => lb,
+            None => {
+                *self = LastKBytesProxy::Delay {
+                    id: new_id,
+                    dups: new_dups,
+                    snapshot: new_snapshot,
+                };
+                return;
+            }
+        };
+
+        shortcut.insert(new_id, new_last_bytes.clone());
+        shortcut.insert(del_id, old_last_bytes.clone());
+        if new_last_bytes == old_last_bytes {
+            let mut hp = HashProxy::new(del_id, del_dups, del_snapshot);
+            hp.insert(ctx, new_id, new_dups, new_snapshot);
+            thunk.insert(old_last_bytes, hp);
+        } else {
+            thunk.insert(new_last_bytes, HashProxy::new(new_id, new_dups, new_snapshot));
+            thunk.insert(old_last_bytes, HashProxy::new(del_id, del_dups, del_snapshot));
+        }
+        *self = LastKBytesProxy::Thunk { thunk, shortcut };
+    }
+
+    // insert Duplicate into the data structure
+    fn insert<T: VFS>(
+        &mut self,
+        ctx: &mut Context<T, H>,
+        id: ID,
+        dups: Duplicates,
+        snapshot: Option<Snapshot>,
+    ) {
+        match *self {
+            LastKBytesProxy::Off(ref mut hp) => hp.insert(ctx, id, dups, snapshot),
+            // if its just a hard link and we are in Delay: just append it
+            LastKBytesProxy::Delay {
+                id: id2,
+                dups: ref mut dups2,
+                ..
+            } if id == id2 =>
+            {
+                dups2.append(dups);
+            }
+            LastKBytesProxy::Thunk {
+                ref mut thunk,
+                ref mut shortcut,
+            } => {
+                // a hard link to a file already here has the same contents: don't read it
+                if let Some(hp) = shortcut.get(&id).and_then(|lb| thunk.get_mut(lb)) {
+                    hp.insert(ctx, id, dups, snapshot);
+                    return;
+                }
+                let last_bytes = match Self::last_bytes(ctx, dups.get_path()) {
+                    Some(lb) => lb,
+                    None => return,
+                };
+                shortcut.insert(id, last_bytes.clone());
+                match thunk.entry(last_bytes) {
+                    Entry::Occupied(mut occ_entry) => {
+                        occ_entry.get_mut().insert(ctx, id, dups, snapshot)
+                    }
+                    Entry::Vacant(vac_entry) => {
+                        vac_entry.insert(HashProxy::new(id, dups, snapshot));
+                    }
+                }
+            }
+            // a new non-link file while in Delay: transition to Thunk
+            LastKBytesProxy::Delay { .. } => self.transition(ctx, id, dups, snapshot),
         }
     }
 }
@@ -198,13 +1071,24 @@ pub enum HashProxy<H: FileHash> {
         id: ID,
         /// A collection of duplicate paths
         dups: Duplicates,
+        /// The metadata of the paths when their first bytes were read (if it needs re-checking)
+        snapshot: Option<Snapshot>,
     },
     // need to map `Hash`es to a set of `Duplicates`
     Thunk {
-        /// Identify a set of duplicates by the hash of its complete contents
-        thunk: HashMap<<H as FileHash>::Output, Duplicates>,
-        /// Map the unique identifier to a file's hash to enable registering links later
-        shortcut: HashMap<ID, <H as FileHash>::Output>,
+        /// Identify sets of duplicates by the hash of their complete contents
+        /// (more than one only if files with different contents collided)
+        thunk: HashMap<<H as FileHash>::Output, Vec<HashGroup>>,
+        /// Map the unique identifier to a file's hash and group to enable registering links
+        shortcut: HashMap<ID, (<H as FileHash>::Output, usize)>,
+    },
+    // files inserted while a `HashPool` was hashing them, in order
+    // once the hashes are in they are inserted into `base` as usual (see `resolve`)
+    Queued {
+        /// The state before the first queued insert
+        base: Box<HashProxy<H>>,
+        /// Each queued insert's identifier, paths, and snapshot
+        log: Vec<(ID, Duplicates, Option<Snapshot>)>,
     },
     // see `FirstKBytesProxy` for more documentation
     // major difference is that `Duplicates` can contain non-hardlinks
@@ -214,27 +1098,45 @@ pub enum HashProxy<H: FileHash> {
 // closely parallels FirstKBytesProxy's documentation
 impl<H: FileHash> HashProxy<H> {
     //Construct a new hashprxy. As only 1 object, will be of the Delay type.
-    fn new(id: ID, dups: Duplicates) -> Self {
-        HashProxy::Delay { id, dups }
+    fn new(id: ID, dups: Duplicates, snapshot: Option<Snapshot>) -> Self {
+        HashProxy::Delay { id, dups, snapshot }
+    }
+
+    // count every path under this node
+    fn num_files(&self) -> usize {
+        match *self {
+            HashProxy::Delay { ref dups, .. } => dups.len(),
+            HashProxy::Thunk { ref thunk, .. } => {
+                thunk.values().flatten().map(|g| g.dups.len()).sum()
+            }
+            HashProxy::Queued { ref base, ref log } => {
+                base.num_files() + log.iter().map(|e| e.1.len()).sum::<usize>()
+            }
+        }
     }
 
     // get all repeats under this node and return as a set of sets of duplicates.
     /// Check all Duplicates for files associated with multiple Paths
     fn get_repeats(&self) -> Vec<Duplicates> {
         match *self {
-            HashProxy::Delay { ref dups, .. } => if dups.0.len() >= 2 {
-                vec![dups.clone()]
+            HashProxy::Delay { ref dups, .. } => if dups.len() >= 2 {
+                vec![dups.clone().with_distinct_files(1)]
             } else {
                 vec![]
             },
             HashProxy::Thunk { ref thunk, .. } => {
                 thunk
                     .iter()
-                    .filter_map(|(_hash, repeats)| {
-                        if repeats.0.len() >= 2 {
+                    .flat_map(|(hash, groups)| groups.iter().map(move |group| (hash, group)))
+                    .filter_map(|(hash, group)| {
+                        if group.dups.len() >= 2 {
                             // if there are 2 or more elements
                             // (including 2 links to 1 file)
-                            Some(repeats.clone())
+                            let repeats = group.dups.clone().with_distinct_files(group.files);
+                            Some(match H::digest(hash) {
+                                Some(digest) => repeats.with_hash(digest),
+                                None => repeats,
+                            })
                         } else {
                             // exactly one representation on the hard drive
                             None
@@ -242,54 +1144,205 @@ impl<H: FileHash> HashProxy<H> {
                     })
                     .collect()
             }
+            HashProxy::Queued { .. } => panic!("{}", UNRESOLVED),
+        }
+    }
+    // the same as `get_repeats`, moving the groups out rather than cloning them
+    fn into_repeats(self) -> Vec<Duplicates> {
+        match self {
+            HashProxy::Delay { dups, .. } => if dups.len() >= 2 {
+                vec![dups.with_distinct_files(1)]
+            } else {
+                vec![]
+            },
+            HashProxy::Thunk { thunk, .. } => thunk
+                .into_iter()
+                .flat_map(|(hash, groups)| groups.into_iter().map(move |g| (hash.clone(), g)))
+                .filter(|(_, group)| group.dups.len() >= 2)
+                .map(|(hash, group)| {
+                    let repeats = group.dups.with_distinct_files(group.files);
+                    match H::digest(&hash) {
+                        Some(digest) => repeats.with_hash(digest),
+                        None => repeats,
+                    }
+                })
+                .collect(),
+            HashProxy::Queued { .. } => panic!("{}", UNRESOLVED),
+        }
+    }
+    // get one path for each distinct content under this node
+    fn get_distinct(&self) -> Vec<PathBuf> {
+        match *self {
+            HashProxy::Delay { ref dups, .. } => vec![dups.get_path().to_path_buf()],
+            HashProxy::Thunk { ref thunk, .. } => thunk
+                .values()
+                .flatten()
+                .filter(|g| !g.dups.is_empty())
+                .map(|g| g.dups.get_path().to_path_buf())
+                .collect(),
+            HashProxy::Queued { .. } => panic!("{}", UNRESOLVED),
+        }
+    }
+    // whether a file with this identifier is here (i.e. a hard link to it needs no hashing)
+    fn has(&self, id: ID) -> bool {
+        match *self {
+            HashProxy::Delay { id: id2, .. } => id == id2,
+            HashProxy::Thunk { ref shortcut, .. } => shortcut.contains_key(&id),
+            HashProxy::Queued { ref base, ref log } => {
+                base.has(id) || log.iter().any(|e| e.0 == id)
+            }
+        }
+    }
+    // take `path` back out, dropping `id` from the shortcut if it was the `last` path of
+    // its file and any group left empty (see `FirstKBytesProxy::remove`)
+    fn remove(&mut self, id: ID, path: &Path, last: bool) -> bool {
+        match *self {
+            HashProxy::Delay {
+                id: id2,
+                ref mut dups,
+                ..
+            } => id == id2 && dups.remove(path),
+            HashProxy::Thunk {
+                ref mut thunk,
+                ref mut shortcut,
+            } => {
+                let (hash, i) = match shortcut.get(&id) {
+                    Some(entry) => entry.clone(),
+                    None => return false,
+                };
+                let groups = match thunk.get_mut(&hash) {
+                    Some(groups) => groups,
+                    None => return false,
+                };
+                if last {
+                    shortcut.remove(&id);
+                    groups[i].files -= 1;
+                }
+                // an emptied group keeps its place, as the shortcut indexes the others
+                let removed = groups[i].dups.remove(path);
+                let empty = groups.iter().all(|g| g.dups.is_empty());
+                if empty {
+                    thunk.remove(&hash);
+                }
+                removed
+            }
+            HashProxy::Queued { .. } => panic!("{}", UNRESOLVED),
+        }
+    }
+    // the hash of the file with this identifier, if it was hashed
+    fn hash_of(&self, id: ID) -> Option<<H as FileHash>::Output> {
+        match *self {
+            HashProxy::Delay { .. } => None,
+            HashProxy::Thunk { ref shortcut, .. } => shortcut.get(&id).map(|e| e.0.clone()),
+            // files still queued haven't been hashed yet
+            HashProxy::Queued { ref base, .. } => base.hash_of(id),
+        }
+    }
+    // the hash of a file: its first bytes' if it's small, or from a saved catalog or the
+    // pool if it was hashed there
+    fn full_hash<T: VFS>(
+        ctx: &mut Context<T, H>,
+        path: &Path,
+        snapshot: Option<Snapshot>,
+    ) -> Option<<H as FileHash>::Output> {
+        // a small file was hashed whole along with its first bytes
+        if let Some(ref hash) = ctx.first_bytes {
+            let outcome = changed(ctx.vfs, path, snapshot).unwrap_or(Hashed::Done(hash.clone()));
+            return accept(ctx.errors, path, &outcome);
+        }
+        if let Some(hash) = ctx.recorded.get(path).and_then(|r| r.hash.clone()) {
+            return Some(hash);
+        }
+        let hash = match ctx.pool.as_ref().and_then(|pool| pool.get(path)) {
+            Some(outcome) => accept(ctx.errors, path, outcome),
+            None => {
+                let outcome = match ctx.progress {
+                    Some(ref mut report) => {
+                        let mut progress = |hashed, len| report(path, hashed, len);
+                        hash_file::<T, H>(ctx.vfs, path, snapshot, Some(&mut progress))
+                    }
+                    None => hash_file::<T, H>(ctx.vfs, path, snapshot, None),
+                };
+                accept(ctx.errors, path, &outcome)
+            }
+        };
+        if hash.is_some() {
+            ctx.stats.full_hashes += 1;
+            ctx.stats.bytes_hashed += ctx.size;
         }
+        hash
     }
     // private helper fuction which handles the conversion from Delay to HashProxy::Thunk
-    fn transition<T: VFS>(&mut self, vfs: &T, new_id: ID, new_dups: Duplicates) {
+    fn transition<T: VFS>(
+        &mut self,
+        ctx: &mut Context<T, H>,
+        new_id: ID,
+        new_dups: Duplicates,
+        new_snapshot: Option<Snapshot>,
+    ) {
         // convert Delay to Thunk
-        let (del_id, del_dups) = match *self {
-            HashProxy::Delay { id, ref mut dups } => {
+        let (del_id, del_dups, del_snapshot) = match *self {
+            HashProxy::Delay {
+                id,
+                ref mut dups,
+                snapshot,
+            } => {
                 assert!(id != new_id);
-                (id, dups.clone())
+                (id, dups.clone(), snapshot)
             }
             _ => unreachable!(),
         };
         //Set up variables for thunk state
-        let mut thunk: HashMap<<H as FileHash>::Output, Duplicates> = HashMap::new();
+        let mut thunk: HashMap<<H as FileHash>::Output, Vec<HashGroup>> = HashMap::new();
         let mut shortcut = HashMap::new();
 
-        // get hashes
-        let new_file = vfs.get_file(new_dups.get_path())
-            .expect("Failed to get file");
-        let old_file = vfs.get_file(del_dups.get_path())
-            .expect("Failed to get file");
-        let new_hash: <H as FileHash>::Output =
-            new_file.get_hash::<H>().expect("Failed to hash file");
-        let old_hash: <H as FileHash>::Output =
-            old_file.get_hash::<H>().expect("Failed to hash file");
-
-        // insert into shortcut
-        shortcut.insert(new_id, new_hash.clone());
-        shortcut.insert(del_id, old_hash.clone());
-
-        // thunk: HashMap < Hash, Duplicates >
-        thunk.insert(new_hash, new_dups);
-        thunk
-            .entry(old_hash)
-            .or_insert_with(|| Duplicates(vec![]))
-            .append(del_dups);
+        // get hashes, dropping whichever file can't be read or has changed
+        let new_hash = match Self::full_hash(ctx, new_dups.get_path(), new_snapshot) {
+            Some(hash) => hash,
+            None => return,
+        };
+        let old_hash = match Self::full_hash(ctx, del_dups.get_path(), del_snapshot) {
+            Some(hash) => hash,
+            None => {
+                *self = HashProxy::new(new_id, new_dups, new_snapshot);
+                return;
+            }
+        };
+
+        // the file already here comes first; the new one joins it unless the hashes collided
+        shortcut.insert(del_id, (old_hash.clone(), 0));
+        thunk.insert(old_hash, vec![HashGroup::new(del_dups, None)]);
+        let groups = thunk.entry(new_hash.clone()).or_insert_with(Vec::new);
+        if let Some(i) = sort_in(ctx, groups, new_dups) {
+            shortcut.insert(new_id, (new_hash, i));
+        }
 
         // set our pointer to the new thunk state.
         *self = HashProxy::Thunk { thunk, shortcut };
+        ctx.stats.hashed += 1;
     }
 
     // insert Duplicate into the data structure
-    fn insert<T: VFS>(&mut self, vfs: &T, id: ID, dups: Duplicates) {
+    fn insert<T: VFS>(
+        &mut self,
+        ctx: &mut Context<T, H>,
+        id: ID,
+        dups: Duplicates,
+        snapshot: Option<Snapshot>,
+    ) {
+        // while a pool is taking work, hand it the file and sort it in later
+        // (unless it's small, so there's nothing left to hash)
+        if let Some(ref mut pool) = ctx.pool {
+            if pool.is_open() && ctx.first_bytes.is_none() {
+                return self.queue(pool, id, dups, snapshot);
+            }
+        }
         match *self {
             // if its just a hard link and we are in Delay: just append it
             HashProxy::Delay {
                 id: id2,
                 dups: ref mut dups2,
+                ..
             } if id == id2 =>
             {
                 dups2.append(dups);
@@ -299,28 +1352,99 @@ impl<H: FileHash> HashProxy<H> {
                 ref mut thunk,
                 ref mut shortcut,
             } => {
-                let file = vfs.get_file(dups.get_path())
-                    .expect("Failed to get file from path");
-                let hash: <H as FileHash>::Output =
-                    file.get_hash::<H>().expect("Failed to hash file");
-                shortcut.insert(id, hash.clone());
-                match thunk.entry(hash) {
-                    Entry::Occupied(mut occ_entry) => {
-                        // if files are completely identical
-                        // add to the repeat hashtable (the val of the thunk)
-                        // either create it or append to its ID's existing entry
-                        let repeats = occ_entry.get_mut();
-                        repeats.append(dups);
-                    } //  Otherwise just add it to a new hashmap.
-                    Entry::Vacant(vacant_entry) => {
-                        vacant_entry.insert(dups);
+                // a hard link to a file already here joins its group without being hashed
+                if let Some(&(ref hash, i)) = shortcut.get(&id) {
+                    if let Some(groups) = thunk.get_mut(hash) {
+                        groups[i].dups.append(dups);
+                        return;
                     }
                 }
+                let hash = match Self::full_hash(ctx, dups.get_path(), snapshot) {
+                    Some(hash) => hash,
+                    None => return,
+                };
+                // join the files with the same contents, or start a new group
+                let groups = thunk.entry(hash.clone()).or_insert_with(Vec::new);
+                if let Some(i) = sort_in(ctx, groups, dups) {
+                    shortcut.insert(id, (hash, i));
+                }
             }
             // if a new non-link file is added while self is a delay stage: transition to Thunk
             HashProxy::Delay { .. } => {
-                self.transition(vfs, id, dups);
+                self.transition(ctx, id, dups, snapshot);
+            }
+            // the pool has closed, so the hashes are in
+            HashProxy::Queued { .. } => panic!("{}", UNRESOLVED),
+        }
+    }
+
+    // start hashing the files `insert` would need hashed, and log the insert for `resolve`
+    fn queue(
+        &mut self,
+        pool: &mut HashPool<H>,
+        id: ID,
+        dups: Duplicates,
+        snapshot: Option<Snapshot>,
+    ) {
+        match *self {
+            // a hard link is appended without hashing anything, as in `insert`
+            HashProxy::Delay {
+                id: id2,
+                dups: ref mut dups2,
+                ..
+            } if id == id2 =>
+            {
+                dups2.append(dups);
+            }
+            HashProxy::Queued { ref mut log, ref base } => {
+                // a hard link to a queued file is sorted in with it, so needs no hash
+                if !base.has(id) && log.iter().all(|e| e.0 != id) {
+                    pool.submit(dups.get_path(), snapshot);
+                }
+                log.push((id, dups, snapshot));
+            }
+            // start logging, on top of the current state
+            HashProxy::Delay { .. } | HashProxy::Thunk { .. } => {
+                // the file already here will need hashing too
+                if let HashProxy::Delay {
+                    dups: ref del_dups,
+                    snapshot: del_snapshot,
+                    ..
+                } = *self
+                {
+                    pool.submit(del_dups.get_path(), del_snapshot);
+                }
+                pool.submit(dups.get_path(), snapshot);
+                let base = Box::new(mem::replace(self, HashProxy::empty()));
+                *self = HashProxy::Queued {
+                    base,
+                    log: vec![(id, dups, snapshot)],
+                };
             }
         }
     }
+
+    // insert the queued files now that the pool has hashed them (and has closed)
+    fn resolve<T: VFS>(&mut self, ctx: &mut Context<T, H>) {
+        match mem::replace(self, HashProxy::empty()) {
+            HashProxy::Queued { base, log } => {
+                *self = *base;
+                for (id, dups, snapshot) in log {
+                    self.insert(ctx, id, dups, snapshot);
+                }
+            }
+            settled => *self = settled,
+        }
+    }
+
+    // an empty Thunk, which doesn't allocate (for moving out of `self`)
+    fn empty() -> Self {
+        HashProxy::Thunk {
+            thunk: HashMap::new(),
+            shortcut: HashMap::new(),
+        }
+    }
 }
+
+// a `HashProxy` was read while its pool was still hashing
+const UNRESOLVED: &str = "hashes still queued: call `FileCataloger::finalize` first";
@@ -0,0 +1,172 @@
+//! Save a catalog to disk and load it back, so the files needn't be scanned again
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde_json;
+
+use helpers::{from_hex, to_hex, ID};
+use hash::FileHash;
+use vfs::VFS;
+use super::{FileCataloger, Located};
+use super::proxy::Recorded;
+
+// bumped whenever the layout of a saved catalog changes
+const VERSION: u32 = 1;
+
+// a saved catalog: every path inserted, sorted
+#[derive(Serialize, Deserialize)]
+struct SavedCatalog {
+    version: u32,
+    entries: Vec<SavedEntry>,
+}
+
+// everything the catalog knew about one path
+#[derive(Serialize, Deserialize)]
+struct SavedEntry {
+    path: PathBuf,
+    size: u64,
+    dev: u64,
+    inode: u64,
+    // seconds and nanoseconds since the epoch
+    modified: Option<(u64, u32)>,
+    // digests in hex, if they were computed
+    first_bytes: Option<String>,
+    hash: Option<String>,
+}
+
+impl SavedEntry {
+    fn new<H: FileHash>(
+        path: PathBuf,
+        located: &Located,
+        recorded: Recorded<<H as FileHash>::Output>,
+    ) -> Self {
+        let hex = |output: <H as FileHash>::Output| H::digest(&output).map(|d| to_hex(&d));
+        SavedEntry {
+            path,
+            size: located.size,
+            dev: located.id.dev,
+            inode: located.id.inode,
+            modified: located
+                .modified
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| (d.as_secs(), d.subsec_nanos())),
+            first_bytes: recorded.first_bytes.and_then(&hex),
+            hash: recorded.hash.and_then(&hex),
+        }
+    }
+
+    // where the path was filed and what was known of its contents
+    // digests of another kind of hash are dropped (so the file is read again if need be)
+    fn into_parts<H: FileHash>(self) -> Parts<H> {
+        let output = |hex: Option<String>| {
+            hex.and_then(|hex| from_hex(&hex))
+                .and_then(|digest| H::from_digest(&digest))
+        };
+        let located = Located {
+            size: self.size,
+            id: ID {
+                dev: self.dev,
+                inode: self.inode,
+            },
+            modified: self.modified
+                .map(|(secs, nanos)| UNIX_EPOCH + Duration::new(secs, nanos)),
+        };
+        let recorded = Recorded {
+            first_bytes: output(self.first_bytes),
+            hash: output(self.hash),
+        };
+        (self.path, located, recorded)
+    }
+}
+
+impl<T: VFS, H: FileHash> FileCataloger<T, H> {
+    /// Write every path in the catalog, with its size, identifier, modification time, and
+    /// whatever hashes were computed, to `writer` (as versioned JSON)
+    /// With `hash_threads`, call `finalize` first or files still being hashed are saved
+    /// without their hashes
+    pub fn save<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut entries: Vec<SavedEntry> = self.located
+            .iter()
+            .map(|(path, located)| {
+                let recorded = self.known(path, located);
+                SavedEntry::new::<H>(path.to_path_buf(), located, recorded)
+            })
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let saved = SavedCatalog {
+            version: VERSION,
+            entries,
+        };
+        serde_json::to_writer(writer, &saved).map_err(io::Error::from)
+    }
+
+    // whatever hashes of the file at `path` (filed as `located`) were computed or loaded
+    pub(super) fn known(
+        &self,
+        path: &Path,
+        located: &Located,
+    ) -> Recorded<<H as FileHash>::Output> {
+        let mut recorded = match self.catalog.get(&located.size) {
+            Some(fkbp) => fkbp.digests(located.id),
+            None => Recorded::default(),
+        };
+        // a path loaded without being compared to anything keeps what was loaded
+        if let Some(loaded) = self.recorded.get(path) {
+            if recorded.first_bytes.is_none() {
+                recorded = loaded.clone();
+            }
+        }
+        recorded
+    }
+
+    /// Rebuild a catalog of the files on `vfs` from what `save` wrote to `reader`,
+    /// without reading any of them
+    /// Nothing is trusted blindly: before a loaded path is reported with its duplicates,
+    /// its size and modification time are checked, and it's left out if they changed
+    pub fn load<R: Read>(vfs: T, reader: R) -> io::Result<Self> {
+        let mut fc = FileCataloger::new(vfs);
+        for (path, located, recorded) in read_saved::<H, R>(reader)? {
+            fc.recorded.insert(path.clone(), recorded);
+            fc.unverified.insert(path.clone());
+            fc.file(&path, located);
+        }
+        Ok(fc)
+    }
+
+    /// Rebuild a catalog from what `save` wrote to `reader`, trusting it completely:
+    /// nothing on `vfs` is looked at, so the duplicates can be analyzed offline
+    /// (unlike `load`, which leaves out files that changed since)
+    pub fn import<R: Read>(vfs: T, reader: R) -> io::Result<Self> {
+        let mut fc = FileCataloger::load(vfs, reader)?;
+        fc.unverified.clear();
+        Ok(fc)
+    }
+
+    /// Reuse the hashes in what `save` wrote to `reader` (e.g. on the previous run):
+    /// a file inserted with the same size, modification time, and identifier as it was
+    /// saved with isn't read again; changed and new files are read as usual
+    pub fn incremental<R: Read>(mut self, reader: R) -> io::Result<Self> {
+        for (path, located, recorded) in read_saved::<H, R>(reader)? {
+            // without a modification time there's no telling if the file changed
+            if located.modified.is_some() {
+                self.cache.insert(path, (located, recorded));
+            }
+        }
+        Ok(self)
+    }
+}
+
+// a saved path, where it was filed, and what was known of its contents
+type Parts<H> = (PathBuf, Located, Recorded<<H as FileHash>::Output>);
+
+// every entry of a saved catalog
+fn read_saved<H: FileHash, R: Read>(reader: R) -> io::Result<Vec<Parts<H>>> {
+    let saved: SavedCatalog = serde_json::from_reader(reader)?;
+    if saved.version != VERSION {
+        let msg = format!("unsupported saved catalog version {}", saved.version);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+    Ok(saved.entries.into_iter().map(SavedEntry::into_parts::<H>).collect())
+}
@@ -1,11 +1,22 @@
 #[cfg(test)]
 mod test {
 
-    use vfs::{TestFile, TestFileSystem, TestMD};
-    use catalog::FileCataloger;
-    use hash::{Md5Sum, Sha3Sum};
+    use vfs::{OnError, Operation, TestFile, TestFileSystem, TestMD, TreeSpec, VFS};
+    use catalog::{filter_min_copies, split_by_owner, Failure, FileCataloger, LastLogged,
+                  LogInterval, Verify};
+    use catalog::histogram::SizeHistogram;
+    use catalog::proxy::Duplicates;
+    use hash::{FileHash, HashKind, HashOutput, Md5Sum, Sha3Sum, WithLength};
+    use walker::DirWalker;
+    use helpers::{to_hex, FIRST_K_BYTES, HASH_CHUNK_SIZE};
 
-    use std::path::PathBuf;
+    use std::io;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::{Duration, Instant, UNIX_EPOCH};
     use std::collections::HashSet;
 
     #[test]
@@ -40,7 +51,7 @@ mod test {
 
         let mut fc: FileCataloger<_, Sha3Sum> = FileCataloger::new(fs);
         for file in &files {
-            fc.insert(file);
+            fc.insert(file).unwrap();
         }
 
         let repeats = fc.get_repeats();
@@ -78,7 +89,7 @@ mod test {
 
         let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
         for file in &files {
-            fc.insert(file);
+            fc.insert(file).unwrap();
         }
 
         let repeats = fc.get_repeats();
@@ -117,7 +128,7 @@ mod test {
 
         let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
         for file in &files {
-            fc.insert(file);
+            fc.insert(file).unwrap();
         }
 
         let repeats = fc.get_repeats();
@@ -155,17 +166,150 @@ mod test {
 
         let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
         for file in &files {
-            fc.insert(file);
+            fc.insert(file).unwrap();
         }
 
         let repeats = fc.get_repeats();
         assert_eq!(1, repeats.len());
-        let dups = &repeats[0].0;
+        let dups = repeats[0].clone().into_paths();
         assert_eq!(3, dups.len());
         assert!(dups.contains(&PathBuf::from("/a")));
         assert!(dups.contains(&PathBuf::from("/b")));
     }
 
+    #[test]
+    fn collisions_same_size() {
+        // same-size files with different contents are near-misses, not duplicates
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.add(
+                TestFile::new("/a")
+                    .with_contents(String::from("AAAA"))
+                    .with_metadata(TestMD::new())
+                    .with_inode(1),
+            );
+            fs.add(
+                TestFile::new("/b")
+                    .with_contents(String::from("BBBB"))
+                    .with_metadata(TestMD::new())
+                    .with_inode(2),
+            );
+            fs.add(
+                TestFile::new("/c")
+                    .with_contents(String::from("CCCCCC"))
+                    .with_metadata(TestMD::new())
+                    .with_inode(3),
+            );
+        }
+
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        for file in &["/a", "/b", "/c"] {
+            fc.insert(&PathBuf::from(file)).unwrap();
+        }
+
+        assert!(fc.get_repeats().is_empty());
+        let collisions = fc.get_collisions();
+        assert_eq!(1, collisions.len());
+        assert_eq!(4, collisions[0].size);
+        assert_eq!(2, collisions[0].paths.len());
+        assert!(collisions[0].paths.contains(&PathBuf::from("/a")));
+        assert!(collisions[0].paths.contains(&PathBuf::from("/b")));
+    }
+
+    #[test]
+    fn stats_promotions() {
+        // buckets are only promoted when a second distinct file needs comparing
+        let fs = TestFileSystem::new();
+        let files = [
+            ("/a", "AAAA", 1), // size 4: alone for now
+            ("/b", "AAAA", 1), // hard link to /a: no promotion
+            ("/c", "BBBB", 2), // size 4 contested, first bytes differ
+            ("/d", "AAAA", 3), // matches /a's first bytes: hashed
+            ("/e", "AAAA", 4), // joins the hashed group: no promotion
+            ("/f", "CCCCCC", 5), // size 6: alone
+            ("/g", "DDDDDD", 6), // size 6 contested, first bytes differ
+        ];
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            for &(path, contents, inode) in &files {
+                fs.add(
+                    TestFile::new(path)
+                        .with_contents(String::from(contents))
+                        .with_metadata(TestMD::new())
+                        .with_inode(inode),
+                );
+            }
+        }
+
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        // (buckets, contested, hashed) after each insert
+        let expected = [
+            (1, 0, 0),
+            (1, 0, 0),
+            (1, 1, 0),
+            (1, 1, 1),
+            (1, 1, 1),
+            (2, 1, 1),
+            (2, 2, 1),
+        ];
+        for (&(path, _, _), &(buckets, contested, hashed)) in files.iter().zip(&expected) {
+            fc.insert(&PathBuf::from(path)).unwrap();
+            let stats = fc.stats();
+            assert_eq!(
+                (buckets, contested, hashed),
+                (stats.buckets, stats.contested, stats.hashed)
+            );
+        }
+        assert_eq!("2 buckets, 2 contested, 1 fully hashed", fc.stats().to_string());
+    }
+
+    #[test]
+    fn hash_size_limit() {
+        // buckets over the limit are matched by size alone and never read
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            let small = [("/a", "AAAA", 1), ("/b", "AAAA", 2), ("/c", "BBBB", 3)];
+            for &(path, contents, inode) in &small {
+                fs.add(
+                    TestFile::new(path)
+                        .with_contents(String::from(contents))
+                        .with_metadata(TestMD::new())
+                        .with_inode(inode),
+                );
+            }
+            // large files with unreadable contents: reading them would drop them
+            for &(path, inode) in &[("/x", 4), ("/y", 5)] {
+                let md = TestMD::new().with_len(1 << 30);
+                fs.add(TestFile::new(path).with_metadata(md).with_inode(inode));
+            }
+        }
+
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs).hash_size_limit(1 << 20);
+        for path in &["/a", "/x", "/b", "/y", "/c"] {
+            fc.insert(&PathBuf::from(path)).unwrap();
+        }
+
+        // only the small bucket was compared, and only it produces actionable duplicates
+        let stats = fc.stats();
+        assert_eq!(1, stats.contested);
+        assert_eq!(1, stats.hashed);
+        let repeats = fc.get_repeats();
+        assert_eq!(1, repeats.len());
+        let group: HashSet<_> = repeats[0].iter().map(|p| p.to_path_buf()).collect();
+        let expected: HashSet<_> = ["/a", "/b"].iter().map(PathBuf::from).collect();
+        assert_eq!(expected, group);
+        let size_only = fc.get_size_only();
+        assert_eq!(1, size_only.len());
+        let expected = vec![PathBuf::from("/x"), PathBuf::from("/y")];
+        assert_eq!(expected, size_only[0].clone().into_paths());
+        assert!(fc.get_collisions().iter().all(|c| c.size == 4));
+    }
+
     #[test]
     fn dup_test_hard_links() {
         // hard links to the same file should be flagged as duplicates
@@ -192,15 +336,1708 @@ mod test {
 
         let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
         for file in &files {
-            fc.insert(file);
+            fc.insert(file).unwrap();
         }
 
         let repeats = fc.get_repeats();
         assert_eq!(1, repeats.len());
-        let dup = &repeats[0].0;
+        let dup = repeats[0].clone().into_paths();
         assert_eq!(2, dup.len());
         assert!(dup.contains(&PathBuf::from("/a")));
         assert!(dup.contains(&PathBuf::from("/b")));
     }
 
+    #[test]
+    fn hard_links_never_read() {
+        // a path to a file already in the catalog joins it without being read, whether the
+        // file is alone in its bucket or has been hashed already
+        // the links' contents differ here only to show they weren't read
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1
+             file /b 'ZZZZ' inode=1
+             file /c 'AAAA' inode=2
+             file /d 'ZZZZ' inode=1",
+        ).unwrap();
+        // only the reads of the comparison itself, not of checking matches (see `Verify`)
+        let mut fc: FileCataloger<_, Md5Sum> =
+            FileCataloger::new(fs.clone()).verify(Verify::Off);
+        fc.insert(&PathBuf::from("/a")).unwrap();
+        fc.insert(&PathBuf::from("/b")).unwrap();
+        assert_eq!(1, fc.get_repeats().len());
+        assert_eq!(0, fs.borrow().num_reads());
+
+        // a different file of the same size has to be read, along with one path of the first
+        fc.insert(&PathBuf::from("/c")).unwrap();
+        assert_eq!(2, fs.borrow().num_reads());
+        fc.insert(&PathBuf::from("/d")).unwrap();
+        assert_eq!(2, fs.borrow().num_reads());
+
+        let repeats = fc.get_repeats();
+        assert_eq!(1, repeats.len());
+        let mut paths = repeats[0].clone().into_paths();
+        paths.sort();
+        let expected: Vec<PathBuf> = ["/a", "/b", "/c", "/d"].iter().map(PathBuf::from).collect();
+        assert_eq!(expected, paths);
+        assert_eq!(2, repeats[0].distinct_files());
+    }
+
+    #[test]
+    fn dup_unreadable_contents() {
+        // a file with metadata but no readable contents can't be compared
+        // it should be left out of every group without stopping the run
+        let orders = [["/a", "/b", "/c"], ["/c", "/a", "/b"], ["/a", "/c", "/b"]];
+        for order in &orders {
+            let fs = TestFileSystem::new();
+            {
+                let mut fs = fs.borrow_mut();
+                fs.create_dir("/");
+                fs.add(
+                    TestFile::new("/a")
+                        .with_contents(String::from("AAAA"))
+                        .with_metadata(TestMD::new())
+                        .with_inode(1),
+                );
+                fs.add(
+                    TestFile::new("/b")
+                        .with_contents(String::from("AAAA"))
+                        .with_metadata(TestMD::new())
+                        .with_inode(2),
+                );
+                fs.add(TestFile::new("/c").with_metadata(TestMD::new()).with_inode(3));
+            }
+
+            let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+            for file in order {
+                fc.insert(&PathBuf::from(file)).unwrap();
+            }
+
+            let repeats = fc.get_repeats();
+            assert_eq!(1, repeats.len());
+            let dups = repeats[0].clone().into_paths();
+            assert_eq!(2, dups.len());
+            assert!(!dups.contains(&PathBuf::from("/c")));
+        }
+    }
+
+    #[test]
+    fn dup_hash_failure() {
+        // a file that becomes unreadable between its first bytes and its full hash
+        // is dropped while the rest of its group is still found
+        let fs = TestFileSystem::new();
+        let start = "A".repeat(4096);
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            for (i, name) in ["/a", "/c", "/d"].iter().enumerate() {
+                fs.add(
+                    TestFile::new(name)
+                        .with_contents(format!("{}_same", start))
+                        .with_metadata(TestMD::new())
+                        .with_inode(i as u64 + 1),
+                );
+            }
+            fs.add(
+                TestFile::new("/b")
+                    .with_contents(format!("{}_diff", "B".repeat(4096)))
+                    .with_metadata(TestMD::new())
+                    .with_inode(4),
+            );
+        }
+
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs.clone());
+        // /a and /b differ in their first bytes so /a's contents haven't been hashed yet
+        fc.insert(&PathBuf::from("/a")).unwrap();
+        fc.insert(&PathBuf::from("/b")).unwrap();
+        fs.borrow_mut()
+            .fail_next(Operation::ReadContents, "/a", io::ErrorKind::PermissionDenied);
+        // /c matches /a's first bytes, so /a gets hashed (and fails)
+        fc.insert(&PathBuf::from("/c")).unwrap();
+        fc.insert(&PathBuf::from("/d")).unwrap();
+
+        let repeats = fc.get_repeats();
+        assert_eq!(1, repeats.len());
+        let dups = repeats[0].clone().into_paths();
+        assert_eq!(2, dups.len());
+        assert!(dups.contains(&PathBuf::from("/c")));
+        assert!(dups.contains(&PathBuf::from("/d")));
+    }
+
+    #[test]
+    fn dup_rehash_on_mismatch() {
+        // a file modified after its first bytes were read but before its full hash
+        // is dropped with `rehash_on_mismatch` and kept without it
+        for &guard in &[true, false] {
+            let fs = TestFileSystem::new();
+            let same = String::from("AAAA");
+            let md = TestMD::new().with_mod_time(UNIX_EPOCH);
+            {
+                let mut fs = fs.borrow_mut();
+                fs.create_dir("/");
+                for (i, name) in ["/a", "/c", "/d"].iter().enumerate() {
+                    fs.add(
+                        TestFile::new(name)
+                            .with_contents(same.clone())
+                            .with_metadata(md)
+                            .with_inode(i as u64 + 1),
+                    );
+                }
+                fs.add(
+                    TestFile::new("/b")
+                        .with_contents(String::from("BBBB"))
+                        .with_metadata(md)
+                        .with_inode(4),
+                );
+            }
+
+            let mut fc: FileCataloger<_, Md5Sum> =
+                FileCataloger::new(fs.clone()).rehash_on_mismatch(guard);
+            // /a's first bytes are read (it differs from /b) but it isn't hashed yet
+            fc.insert(&PathBuf::from("/a")).unwrap();
+            fc.insert(&PathBuf::from("/b")).unwrap();
+            // touch /a
+            fs.borrow_mut().add(
+                TestFile::new("/a")
+                    .with_contents(same.clone())
+                    .with_metadata(md.with_mod_time(UNIX_EPOCH + Duration::from_secs(60)))
+                    .with_inode(1),
+            );
+            // /c matches /a's first bytes, so /a gets hashed
+            fc.insert(&PathBuf::from("/c")).unwrap();
+            fc.insert(&PathBuf::from("/d")).unwrap();
+
+            let repeats = fc.get_repeats();
+            assert_eq!(1, repeats.len());
+            let dups = repeats[0].clone().into_paths();
+            assert_eq!(!guard, dups.contains(&PathBuf::from("/a")));
+            assert!(dups.contains(&PathBuf::from("/c")));
+            assert!(dups.contains(&PathBuf::from("/d")));
+        }
+    }
+
+    #[test]
+    fn histogram_bucket_counts() {
+        // files should be counted by exact size and by power-of-two range
+        let fs = TestFileSystem::new();
+        let sizes = [0, 1, 2, 3, 3, 3, 5, 6, 6, 100];
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            for (i, &n) in sizes.iter().enumerate() {
+                // make every file unique so the catalog has to look at them
+                let mut contents = format!("{}", i);
+                contents.truncate(n);
+                while contents.len() < n {
+                    contents.push('x');
+                }
+                fs.add(
+                    TestFile::new(format!("/{}", i))
+                        .with_contents(contents)
+                        .with_metadata(TestMD::new())
+                        .with_inode(i as u64 + 1),
+                );
+            }
+        }
+
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        for i in 0..sizes.len() {
+            fc.insert(&PathBuf::from(format!("/{}", i))).unwrap();
+        }
+
+        let hist = fc.get_histogram(2);
+        assert_eq!(10, hist.num_files);
+        assert_eq!(7, hist.num_buckets);
+        // 0 | 1 | 2,3,3,3 | 5,6,6 | 100
+        assert_eq!(Some(&1), hist.ranges.get(&SizeHistogram::range_of(0)));
+        assert_eq!(Some(&1), hist.ranges.get(&SizeHistogram::range_of(1)));
+        assert_eq!(Some(&4), hist.ranges.get(&SizeHistogram::range_of(2)));
+        assert_eq!(Some(&3), hist.ranges.get(&SizeHistogram::range_of(4)));
+        assert_eq!(Some(&1), hist.ranges.get(&SizeHistogram::range_of(100)));
+        assert_eq!(5, hist.ranges.len());
+        assert_eq!(vec![(3, 3), (6, 2)], hist.largest);
+    }
+
+    #[test]
+    fn dup_generated_tree() {
+        // the catalog should find exactly the groups the generator planted
+        let spec = TreeSpec {
+            fan_out: 4,
+            depth: 2,
+            files_per_dir: 20,
+            min_size: 1,
+            max_size: 5000,
+            dup_ratio: 0.25,
+        };
+        for seed in 0..4 {
+            let tree = TestFileSystem::generate(seed, &spec);
+            let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(tree.fs.clone());
+            for file in &tree.files {
+                fc.insert(file).unwrap();
+            }
+            assert!(tree.duplicate_groups > 0);
+            assert_eq!(tree.duplicate_groups, fc.get_repeats().len());
+        }
+    }
+
+    #[test]
+    fn paths_allocated_once() {
+        // the catalog keeps one copy of each path, however many groups it hands out
+        let spec = TreeSpec {
+            fan_out: 4,
+            depth: 2,
+            files_per_dir: 20,
+            min_size: 1,
+            max_size: 5000,
+            dup_ratio: 0.25,
+        };
+        let tree = TestFileSystem::generate(7, &spec);
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(tree.fs.clone());
+        fc.insert_all(&tree.files);
+        assert_eq!(tree.files.len(), fc.paths.len());
+
+        // every path of every group reported, twice over, shares the catalog's copy
+        let reports = [fc.get_repeats(), fc.get_repeats()];
+        let handles: Vec<&Arc<Path>> = reports.iter().flatten().flat_map(|d| d.iter()).collect();
+        let allocations: HashSet<*const Path> = handles.iter().map(|p| Arc::as_ptr(p)).collect();
+        let distinct: HashSet<&Path> = handles.iter().map(|p| &***p).collect();
+        assert!(handles.len() >= 2 * distinct.len());
+        assert_eq!(distinct.len(), allocations.len());
+
+        // a path taken out is no longer held
+        let gone = handles[0].to_path_buf();
+        assert!(fc.remove(&gone));
+        assert_eq!(tree.files.len() - 1, fc.paths.len());
+    }
+
+    #[test]
+    fn dup_stress_100k() {
+        // catalog ~100k mock files
+        let spec = TreeSpec {
+            fan_out: 10,
+            depth: 3,
+            files_per_dir: 90,
+            min_size: 16,
+            max_size: 512,
+            dup_ratio: 0.2,
+        };
+        let tree = TestFileSystem::generate(42, &spec);
+        assert_eq!(99_990, tree.files.len());
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(tree.fs.clone());
+        for file in &tree.files {
+            fc.insert(file).unwrap();
+        }
+        let repeats = fc.get_repeats();
+        assert_eq!(tree.duplicate_groups, repeats.len());
+        let total: usize = repeats.iter().map(|d| d.len()).sum();
+        assert!(total > tree.duplicate_groups);
+    }
+
+    #[test]
+    fn filter_min_copies_groups() {
+        // only groups with enough members survive the filter
+        let fs = TestFileSystem::new();
+        let mut paths = vec![];
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            // groups of 2, 3, and 6 identical files
+            for (group, &count) in [2, 3, 6].iter().enumerate() {
+                for i in 0..count {
+                    let path = format!("/g{}_{}", group, i);
+                    fs.add(
+                        TestFile::new(&path)
+                            .with_contents(format!("group {}", group))
+                            .with_metadata(TestMD::new())
+                            .with_inode((paths.len() + 1) as u64),
+                    );
+                    paths.push(PathBuf::from(path));
+                }
+            }
+        }
+
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        for path in &paths {
+            fc.insert(path).unwrap();
+        }
+        let repeats = fc.get_repeats();
+        assert_eq!(3, repeats.len());
+
+        let repeats = filter_min_copies(repeats, 5);
+        assert_eq!(1, repeats.len());
+        assert_eq!(6, repeats[0].len());
+        assert!(repeats[0].clone().into_paths().contains(&PathBuf::from("/g2_0")));
+    }
+
+    #[test]
+    fn split_by_owner_groups() {
+        // identical files owned by different users are never grouped together
+        let fs = TestFileSystem::new();
+        let owners = [("/a", 1000), ("/b", 1001), ("/c", 1000), ("/d", 1001), ("/e", 0)];
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            for (i, &(path, uid)) in owners.iter().enumerate() {
+                fs.add(
+                    TestFile::new(path)
+                        .with_contents(String::from("same"))
+                        .with_metadata(TestMD::new().with_uid(uid))
+                        .with_inode(i as u64 + 1),
+                );
+            }
+        }
+
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs.clone());
+        for &(path, _) in &owners {
+            fc.insert(&PathBuf::from(path)).unwrap();
+        }
+        let repeats = fc.get_repeats();
+        assert_eq!(1, repeats.len());
+        assert_eq!(5, repeats[0].len());
+
+        // root's lone copy has nothing left to be a duplicate of
+        let groups: HashSet<_> = split_by_owner(&fs, repeats)
+            .into_iter()
+            .map(|d| {
+                let mut paths = d.into_paths();
+                paths.sort();
+                paths
+            })
+            .collect();
+        let expected: HashSet<_> = [["/a", "/c"], ["/b", "/d"]]
+            .iter()
+            .map(|g| g.iter().map(PathBuf::from).collect::<Vec<_>>())
+            .collect();
+        assert_eq!(expected, groups);
+    }
+
+    #[test]
+    fn single_file_nothing_to_compare() {
+        // one file can't have duplicates, so nothing is read or hashed
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1",
+        ).unwrap();
+        fs.borrow_mut().fail_always(Operation::ReadContents, "/a", io::ErrorKind::Other);
+
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        assert_eq!(0, fc.num_inserted());
+        assert!(fc.get_repeats().is_empty());
+        fc.insert(&PathBuf::from("/a")).unwrap();
+        assert_eq!(1, fc.num_inserted());
+        assert!(fc.get_repeats().is_empty());
+        assert!(fc.get_collisions().is_empty());
+        let stats = fc.stats();
+        assert_eq!((1, 0, 0), (stats.buckets, stats.contested, stats.hashed));
+    }
+
+    #[test]
+    fn case_insensitive_not_own_duplicate() {
+        // a file reached again under a different case (here through a link) is the same
+        // file, not a copy of itself; only the real copy is reported
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.set_case_insensitive(true);
+            fs.create_dir("/");
+            fs.add(
+                TestFile::new("/Foo.txt")
+                    .with_contents(String::from("same"))
+                    .with_metadata(TestMD::new())
+                    .with_inode(1),
+            );
+            fs.add(
+                TestFile::new("/other.txt")
+                    .with_contents(String::from("diff"))
+                    .with_metadata(TestMD::new())
+                    .with_inode(2),
+            );
+            fs.create_symlink("/link", "/FOO.TXT");
+        }
+        let files = DirWalker::new(fs.clone(), &["/", "/foo.txt"]).traverse_ordered();
+        assert_eq!(vec![PathBuf::from("/Foo.txt"), PathBuf::from("/other.txt")], files);
+
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs.clone());
+        for file in &files {
+            fc.insert(file).unwrap();
+        }
+        assert!(fc.get_repeats().is_empty());
+
+        // a real copy is still found, listed once under its stored case
+        fs.borrow_mut().add(
+            TestFile::new("/Copy.txt")
+                .with_contents(String::from("same"))
+                .with_metadata(TestMD::new())
+                .with_inode(3),
+        );
+        let files = DirWalker::new(fs.clone(), &["/"]).traverse_ordered();
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        for file in &files {
+            fc.insert(file).unwrap();
+        }
+        let repeats = fc.get_repeats();
+        assert_eq!(1, repeats.len());
+        let mut paths = repeats[0].clone().into_paths();
+        paths.sort();
+        let expected: Vec<_> = ["/Copy.txt", "/Foo.txt"].iter().map(PathBuf::from).collect();
+        assert_eq!(expected, paths);
+    }
+
+    #[test]
+    fn insert_failure_keeps_catalog() {
+        // a file that vanished or can't be looked up is reported, not fatal
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1
+             file /b 'AAAA' inode=2
+             file /c 'AAAA' inode=3",
+        ).unwrap();
+        fs.borrow_mut().fail_always(Operation::ReadMetadata, "/c", io::ErrorKind::PermissionDenied);
+
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        fc.insert(&PathBuf::from("/a")).unwrap();
+        let err = fc.insert(&PathBuf::from("/gone")).unwrap_err();
+        assert_eq!(PathBuf::from("/gone"), err.path());
+        assert_eq!(io::ErrorKind::NotFound, err.cause().unwrap().kind());
+        let err = fc.insert(&PathBuf::from("/c")).unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.cause().unwrap().kind());
+        assert_eq!(Operation::ReadMetadata, err.cause().unwrap().op());
+        fc.insert(&PathBuf::from("/b")).unwrap();
+
+        assert_eq!(2, fc.num_inserted());
+        let repeats = fc.get_repeats();
+        assert_eq!(1, repeats.len());
+        let mut paths = repeats[0].clone().into_paths();
+        paths.sort();
+        assert_eq!(vec![PathBuf::from("/a"), PathBuf::from("/b")], paths);
+    }
+
+    #[test]
+    fn duplicates_api() {
+        // a group is used through its methods rather than its field
+        let paths: Vec<_> = ["/a", "/b/c", "/d"].iter().map(PathBuf::from).collect();
+        let dups = Duplicates::new(paths.clone());
+        assert_eq!(3, dups.len());
+        assert!(!dups.is_empty());
+        let shared: Vec<&Path> = dups.paths().iter().map(|p| &**p).collect();
+        assert_eq!(paths.iter().map(PathBuf::as_path).collect::<Vec<_>>(), shared);
+        assert_eq!(dups.paths().iter().collect::<Vec<_>>(), dups.iter().collect::<Vec<_>>());
+        assert_eq!("[/a, /b/c, /d]", format!("{:?}", dups));
+        assert_eq!(paths, dups.into_paths());
+
+        let empty = Duplicates::new(vec![]);
+        assert_eq!(0, empty.len());
+        assert!(empty.is_empty());
+        assert_eq!(None, empty.iter().next());
+
+        // sizes are unknown unless recorded, and every path is taken to be its own file
+        let dups = Duplicates::new(paths.clone());
+        assert_eq!((None, None, 3), (dups.size(), dups.hash(), dups.distinct_files()));
+        assert_eq!((0, 0), (dups.total_bytes(), dups.redundant_bytes()));
+        let dups = dups.with_size(10).with_hash(vec![0xab]);
+        assert_eq!((Some(10), Some(&[0xab][..])), (dups.size(), dups.hash()));
+        assert_eq!((30, 20), (dups.total_bytes(), dups.redundant_bytes()));
+        let dups = dups.with_distinct_files(2);
+        assert_eq!((20, 10), (dups.total_bytes(), dups.redundant_bytes()));
+    }
+
+    #[test]
+    fn repeats_carry_size_and_hash() {
+        // groups from the catalog know their size and hash, and count hard links once
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            let files = [("/a", 1), ("/b", 1), ("/c", 2), ("/d", 3)];
+            for &(path, inode) in &files {
+                fs.add(
+                    TestFile::new(path)
+                        .with_contents(String::from("AAAA"))
+                        .with_metadata(TestMD::new())
+                        .with_inode(inode),
+                );
+            }
+        }
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        for path in &["/a", "/b", "/c", "/d"] {
+            fc.insert(&PathBuf::from(path)).unwrap();
+        }
+        let repeats = fc.get_repeats();
+        assert_eq!(1, repeats.len());
+        let dups = &repeats[0];
+        assert_eq!(4, dups.len());
+        assert_eq!(3, dups.distinct_files());
+        assert_eq!(Some(4), dups.size());
+        assert_eq!(Some(&Md5Sum::hash(b"AAAA")[..]), dups.hash());
+        assert_eq!(12, dups.total_bytes());
+        assert_eq!(8, dups.redundant_bytes());
+
+        // a group of hard links alone frees nothing
+        let links = Duplicates::new(vec![PathBuf::from("/x"), PathBuf::from("/y")])
+            .with_size(4)
+            .with_distinct_files(1);
+        assert_eq!((4, 0), (links.total_bytes(), links.redundant_bytes()));
+    }
+
+    #[test]
+    fn get_repeats_visits_contested_only() {
+        // only sizes shared by several files are searched for duplicates
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            for i in 0..100 {
+                fs.add(
+                    TestFile::new(format!("/unique{}", i))
+                        .with_contents("u".repeat(i + 1))
+                        .with_metadata(TestMD::new())
+                        .with_inode(i as u64 + 1),
+                );
+            }
+            // longer than any unique file
+            let others = [
+                ("/d1", 'd', 200),
+                ("/d2", 'd', 200),
+                ("/x1", 'x', 300),
+                ("/x2", 'y', 300),
+            ];
+            for (i, &(path, c, len)) in others.iter().enumerate() {
+                fs.add(
+                    TestFile::new(path)
+                        .with_contents(c.to_string().repeat(len))
+                        .with_metadata(TestMD::new())
+                        .with_inode(i as u64 + 101),
+                );
+            }
+        }
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        for i in 0..100 {
+            fc.insert(&PathBuf::from(format!("/unique{}", i))).unwrap();
+        }
+        assert!(fc.contested.is_empty());
+        for path in &["/d1", "/d2", "/x1", "/x2"] {
+            fc.insert(&PathBuf::from(path)).unwrap();
+        }
+
+        // a same-size pair with different contents is visited but yields nothing
+        let mut contested: Vec<_> = fc.contested.iter().cloned().collect();
+        contested.sort();
+        assert_eq!(vec![200, 300], contested);
+        let repeats = fc.get_repeats();
+        assert_eq!(1, repeats.len());
+        // nothing a full sweep would find is missed
+        let swept: usize = fc.catalog.values().map(|p| p.get_repeats().len()).sum();
+        assert_eq!(swept, repeats.len());
+    }
+
+    #[test]
+    fn insert_all_report() {
+        // failures are collected, not fatal; progress is reported and can cancel
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1
+             file /b 'AAAA' inode=2
+             file /c 'CC' inode=3",
+        ).unwrap();
+        fs.borrow_mut().fail_always(Operation::ReadMetadata, "/c", io::ErrorKind::PermissionDenied);
+        let paths: Vec<_> = ["/a", "/gone", "/b", "/c"].iter().map(PathBuf::from).collect();
+
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs.clone());
+        let report = fc.insert_all(paths.clone());
+        assert_eq!((2, 2, false), (report.inserted, report.skipped(), report.cancelled));
+        let failed: Vec<_> = report.errors.iter().map(|e| e.path().to_path_buf()).collect();
+        assert_eq!(vec![PathBuf::from("/gone"), PathBuf::from("/c")], failed);
+        assert_eq!(1, fc.get_repeats().len());
+
+        // cancel once two paths have been handled
+        let cancel = AtomicBool::new(false);
+        let mut seen = vec![];
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        let report = fc.insert_all_with(
+            &paths,
+            |r| {
+                seen.push((r.inserted, r.skipped()));
+                if seen.len() == 2 {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+            },
+            &cancel,
+        );
+        assert_eq!(vec![(1, 0), (1, 1)], seen);
+        assert_eq!((1, 1, true), (report.inserted, report.skipped(), report.cancelled));
+        assert_eq!(1, fc.num_inserted());
+    }
+
+    #[test]
+    fn insert_all_on_error() {
+        // by default a file that can't be inserted is left out; with `Abort` nothing after it is
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1
+             file /b 'AAAA' inode=2
+             file /c 'AAAA' inode=3",
+        ).unwrap();
+        fs.borrow_mut().fail_always(Operation::ReadMetadata, "/b", io::ErrorKind::PermissionDenied);
+        for &policy in &[OnError::Skip, OnError::Warn] {
+            let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs.clone()).on_error(policy);
+            let report = fc.insert_all(["/a", "/b", "/c"].iter());
+            assert_eq!((2, 1, false), (report.inserted, report.skipped(), report.aborted));
+        }
+
+        // the error that stopped it is kept even past `max_errors`
+        let mut fc: FileCataloger<_, Md5Sum> =
+            FileCataloger::new(fs).on_error(OnError::Abort).max_errors(0);
+        let report = fc.insert_all(["/a", "/b", "/c"].iter());
+        assert_eq!((1, 1, true), (report.inserted, report.skipped(), report.aborted));
+        assert_eq!(1, report.errors.len());
+        assert_eq!(Path::new("/b"), report.errors[0].path());
+        assert_eq!(1, fc.num_inserted());
+    }
+
+    #[test]
+    fn insert_all_errors_by_kind() {
+        // files left out while being compared are reported along with those that couldn't
+        // be inserted, counted by kind, and only listed up to the cap
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1 mtime=0
+             file /b 'BBBB' inode=2
+             file /c 'AAAA' inode=3
+             file /d 'DDDDD' inode=4
+             file /e 'DDDDD' inode=5
+             file /f 'FFFFFF' inode=6
+             file /g 'FFFFFF' inode=7",
+        ).unwrap();
+        // /e can't be opened, and /g is gone by the time its first bytes are needed
+        fs.borrow_mut().fail_always(Operation::OpenFile, "/e", io::ErrorKind::PermissionDenied);
+        fs.borrow_mut().fail_next(Operation::OpenFile, "/g", io::ErrorKind::NotFound);
+
+        let mut fc: FileCataloger<_, Md5Sum> =
+            FileCataloger::new(fs.clone()).rehash_on_mismatch(true).max_errors(2);
+        let report = fc.insert_all(["/a", "/b", "/gone", "/d", "/e", "/f", "/g"].iter());
+        assert_eq!((6, 3), (report.inserted, report.skipped()));
+        let failures = report.failures;
+        assert_eq!((2, 1, 0), (failures.vanished, failures.unreadable, failures.changed));
+        let listed: Vec<_> = report.errors.iter().map(|e| (e.path(), e.failure())).collect();
+        assert_eq!(
+            vec![(Path::new("/gone"), Failure::Vanished), (Path::new("/e"), Failure::Unreadable)],
+            listed
+        );
+        assert_eq!("1 file unreadable, 2 vanished during scan", report.failures.to_string());
+
+        // /a is touched after its first bytes were read, so it can't be hashed
+        fs.borrow_mut().add(
+            TestFile::new("/a")
+                .with_contents(String::from("AAAA"))
+                .with_metadata(TestMD::new().with_mod_time(UNIX_EPOCH + Duration::from_secs(60)))
+                .with_inode(1),
+        );
+        let report = fc.insert_all(Some("/c"));
+        assert_eq!(1, report.failures.changed);
+        assert_eq!(Failure::Changed, report.errors[0].failure());
+        assert!(report.errors[0].cause().is_none());
+        assert!(fc.take_errors().is_empty());
+    }
+
+    #[test]
+    fn hard_links_not_read() {
+        // a hard link joins its file's group without its contents being read
+        // (the links here have no contents, so reading them would drop them)
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            let files = [("/a", "AAAA", 1), ("/c", "AAAA", 2), ("/d", "BBBB", 3)];
+            for &(path, contents, inode) in &files {
+                fs.add(
+                    TestFile::new(path)
+                        .with_contents(String::from(contents))
+                        .with_metadata(TestMD::new())
+                        .with_inode(inode),
+                );
+            }
+            for &(path, inode) in &[("/b", 1), ("/e", 3)] {
+                let md = TestMD::new().with_len(4);
+                fs.add(TestFile::new(path).with_metadata(md).with_inode(inode));
+            }
+        }
+
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        for path in &["/a", "/c", "/d", "/b", "/e"] {
+            fc.insert(&PathBuf::from(path)).unwrap();
+        }
+        let mut repeats: Vec<Vec<PathBuf>> = fc.get_repeats()
+            .into_iter()
+            .map(|dups| {
+                let mut paths = dups.into_paths();
+                paths.sort();
+                paths
+            })
+            .collect();
+        repeats.sort();
+        let expected: Vec<Vec<PathBuf>> = vec![
+            ["/a", "/b", "/c"].iter().map(PathBuf::from).collect(),
+            ["/d", "/e"].iter().map(PathBuf::from).collect(),
+        ];
+        assert_eq!(expected, repeats);
+        assert_eq!(1, fc.stats().hashed);
+    }
+
+    #[test]
+    fn remove_paths() {
+        // taking one of a pair out breaks up the group; unknown paths aren't there to remove
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            let files = [("/a", "AAAA", 1), ("/b", "AAAA", 2), ("/c", "CCCC", 3)];
+            for &(path, contents, inode) in &files {
+                fs.add(
+                    TestFile::new(path)
+                        .with_contents(String::from(contents))
+                        .with_metadata(TestMD::new())
+                        .with_inode(inode),
+                );
+            }
+            // two links to one file, alone at its size
+            for path in &["/d", "/e"] {
+                fs.add(
+                    TestFile::new(path)
+                        .with_contents(String::from("DD"))
+                        .with_metadata(TestMD::new())
+                        .with_inode(4),
+                );
+            }
+        }
+
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        for path in &["/a", "/b", "/c", "/d", "/e"] {
+            fc.insert(&PathBuf::from(path)).unwrap();
+        }
+        assert_eq!(2, fc.get_repeats().len());
+
+        assert!(fc.remove(&PathBuf::from("/b")));
+        assert!(fc.remove(&PathBuf::from("/e")));
+        assert!(fc.get_repeats().is_empty());
+        assert_eq!(3, fc.num_inserted());
+        // the files left still differ
+        assert_eq!(1, fc.get_collisions().len());
+
+        assert!(!fc.remove(&PathBuf::from("/b")));
+        assert!(!fc.remove(&PathBuf::from("/nonexistent")));
+
+        // a removed path can come back
+        fc.insert(&PathBuf::from("/b")).unwrap();
+        let repeats = fc.get_repeats();
+        assert_eq!(1, repeats.len());
+        assert_eq!(2, repeats[0].distinct_files());
+    }
+
+    // the groups found, as sorted lists of sorted paths
+    fn sorted_repeats<T: VFS, H: FileHash>(fc: &FileCataloger<T, H>) -> Vec<Vec<PathBuf>> {
+        let mut repeats: Vec<Vec<PathBuf>> = fc.get_repeats()
+            .into_iter()
+            .map(|dups| {
+                let mut paths = dups.into_paths();
+                paths.sort();
+                paths
+            })
+            .collect();
+        repeats.sort();
+        repeats
+    }
+
+    #[test]
+    fn save_and_load() {
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1 mtime=100
+             file /b 'AAAA' inode=2 mtime=100
+             file /c 'AAAB' inode=3 mtime=100
+             file /d 'XYZ' inode=4 mtime=100
+             file /e 'XYZ' inode=4 mtime=100
+             file /f 'lonely' inode=5 mtime=100",
+        ).unwrap();
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        for path in &["/a", "/b", "/c", "/d", "/e", "/f"] {
+            fc.insert(&PathBuf::from(path)).unwrap();
+        }
+        let mut saved = vec![];
+        fc.save(&mut saved).unwrap();
+
+        // the same files, but unreadable: loading mustn't need their contents
+        let unreadable = "dir /
+             file /a size=4 inode=1 mtime=100
+             file /b size=4 inode=2 mtime=100
+             file /c size=4 inode=3 mtime=100
+             file /d size=3 inode=4 mtime=100
+             file /e size=3 inode=4 mtime=100
+             file /f size=6 inode=5 mtime=100";
+        let fs = TestFileSystem::from_spec(unreadable).unwrap();
+        let loaded: FileCataloger<_, Md5Sum> = FileCataloger::load(fs, &saved[..]).unwrap();
+        assert_eq!(sorted_repeats(&fc), sorted_repeats(&loaded));
+        assert_eq!(2, sorted_repeats(&loaded).len());
+        assert_eq!(fc.get_collisions().len(), loaded.get_collisions().len());
+        assert_eq!(fc.num_inserted(), loaded.num_inserted());
+
+        // and it saves the same way again
+        let mut resaved = vec![];
+        loaded.save(&mut resaved).unwrap();
+        assert_eq!(saved, resaved);
+
+        // a different version isn't read
+        let old = String::from_utf8(saved).unwrap().replace("\"version\":1", "\"version\":0");
+        let fs = TestFileSystem::new();
+        let res: io::Result<FileCataloger<_, Md5Sum>> = FileCataloger::load(fs, old.as_bytes());
+        assert_eq!(io::ErrorKind::InvalidData, res.err().unwrap().kind());
+    }
+
+    #[test]
+    fn load_stale_entry() {
+        // a file that changed size after the catalog was saved isn't reported
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1 mtime=100
+             file /b 'AAAA' inode=2 mtime=100
+             file /c 'AAAA' inode=3 mtime=100",
+        ).unwrap();
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        for path in &["/a", "/b", "/c"] {
+            fc.insert(&PathBuf::from(path)).unwrap();
+        }
+        let mut saved = vec![];
+        fc.save(&mut saved).unwrap();
+
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1 mtime=100
+             file /b 'AAAA' inode=2 mtime=100
+             file /c 'AAAAAA' inode=3 mtime=100",
+        ).unwrap();
+        let loaded: FileCataloger<_, Md5Sum> = FileCataloger::load(fs.clone(), &saved[..]).unwrap();
+        let expected: Vec<Vec<PathBuf>> = vec![vec![PathBuf::from("/a"), PathBuf::from("/b")]];
+        assert_eq!(expected, sorted_repeats(&loaded));
+        assert_eq!(2, loaded.get_repeats()[0].distinct_files());
+
+        // once the pair is down to one, there's nothing to report
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1 mtime=100
+             file /b 'AAAA' inode=2 mtime=200
+             file /c 'AAAAAA' inode=3 mtime=100",
+        ).unwrap();
+        let loaded: FileCataloger<_, Md5Sum> = FileCataloger::load(fs, &saved[..]).unwrap();
+        assert!(loaded.get_repeats().is_empty());
+    }
+
+    #[test]
+    fn reinsert_loaded_paths() {
+        // inserting a loaded path again files it afresh, in place of what was loaded
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1 mtime=100
+             file /b 'AAAA' inode=2 mtime=100",
+        ).unwrap();
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs.clone());
+        fc.insert_all(["/a", "/b"].iter());
+        let mut saved = vec![];
+        fc.save(&mut saved).unwrap();
+
+        let mut loaded: FileCataloger<_, Md5Sum> = FileCataloger::load(fs, &saved[..]).unwrap();
+        loaded.insert_all(["/a", "/b"].iter());
+        assert_eq!(2, loaded.num_inserted());
+        let expected: Vec<Vec<PathBuf>> = vec![vec![PathBuf::from("/a"), PathBuf::from("/b")]];
+        assert_eq!(expected, sorted_repeats(&loaded));
+        assert_eq!(2, loaded.get_repeats()[0].distinct_files());
+    }
+
+    #[test]
+    fn into_repeats_moves_groups_out() {
+        let spec = "dir /
+             file /a 'AAAA' inode=1
+             file /b 'AAAA' inode=2
+             file /c 'AAAA' inode=2
+             file /d 'AAAB' inode=3
+             file /e 'AAAB' inode=4
+             file /f 'lonely' inode=5
+             file /g '' inode=6
+             file /h '' inode=7";
+        let catalog = || {
+            let fs = TestFileSystem::from_spec(spec).unwrap();
+            let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs).dedupe_empty(true);
+            for path in &["/a", "/b", "/c", "/d", "/e", "/f", "/g", "/h"] {
+                fc.insert(Path::new(path)).unwrap();
+            }
+            fc
+        };
+        // everything a group says about itself, in order
+        let described = |repeats: Vec<Duplicates>| {
+            let mut all: Vec<_> = repeats
+                .into_iter()
+                .map(|dups| {
+                    let stamps: Vec<_> = dups.iter().map(|p| dups.stamp_of(p)).collect();
+                    let hash = dups.hash().map(to_hex);
+                    (dups.size(), hash, dups.distinct_files(), stamps, dups.into_paths())
+                })
+                .collect();
+            all.sort_by(|a, b| a.4.cmp(&b.4));
+            all
+        };
+        let fc = catalog();
+        let cloned = fc.get_repeats();
+        assert_eq!(3, cloned.len());
+        // while the catalog is around, it holds the paths too
+        assert!(cloned.iter().all(|dups| dups.iter().all(|p| Arc::strong_count(p) > 2)));
+        assert_eq!(described(cloned), described(catalog().into_repeats()));
+
+        // once it's consumed, each path is only held by its group (and that group's stamps)
+        let moved = catalog().into_repeats();
+        assert!(moved.iter().all(|dups| dups.iter().all(|p| Arc::strong_count(p) == 2)));
+    }
+
+    #[test]
+    fn merge_snapshots() {
+        // two machines, whose files happen to share inode numbers
+        let home = "dir /
+             dir /home
+             file /home/a 'AAAA' inode=1 mtime=100
+             file /home/b 'AAAA' inode=2 mtime=100
+             file /home/c 'WXYZ' inode=3 mtime=100
+             file /home/d 'lonely' inode=4 mtime=100";
+        let backup = "dir /
+             dir /backup
+             file /backup/a 'AAAA' inode=1 mtime=100
+             file /backup/c 'WXYZ' inode=2 mtime=100
+             file /backup/d 'lonely' inode=3 mtime=100
+             file /backup/e 'unique!' inode=4 mtime=100";
+        let scan = |spec: &str, paths: &[&str]| {
+            let fs = TestFileSystem::from_spec(spec).unwrap();
+            // a hash that's trusted, so the backup's files needn't be read to confirm it
+            let mut fc: FileCataloger<_, Sha3Sum> = FileCataloger::new(fs);
+            for path in paths {
+                fc.insert(Path::new(path)).unwrap();
+            }
+            fc
+        };
+        let mut fc = scan(home, &["/home/a", "/home/b", "/home/c", "/home/d"]);
+        let other = scan(backup, &["/backup/a", "/backup/c", "/backup/d", "/backup/e"]);
+        assert_eq!(4, fc.merge(&other));
+        assert_eq!(8, fc.num_inserted());
+
+        // the lonely files were never read, and the backup's can't be read here
+        let paths = |v: &[&str]| v.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let expected = vec![
+            paths(&["/backup/a", "/home/a", "/home/b"]),
+            paths(&["/backup/c", "/home/c"]),
+        ];
+        assert_eq!(expected, sorted_repeats(&fc));
+        assert!(fc.is_merged(Path::new("/backup/a")));
+        assert!(!fc.is_merged(Path::new("/home/a")));
+        for dups in fc.get_repeats() {
+            // equal inode numbers on different machines aren't hard links
+            assert_eq!(dups.len(), dups.distinct_files());
+            assert!(fc.is_cross_snapshot(&dups));
+        }
+        assert_eq!(1, fc.take_errors().len());
+
+        // through a filesystem holding both, files unique to either scan are read
+        let both = format!("{}\n{}", home, backup.replacen("dir /", "", 1));
+        let mut fc = scan(&both, &["/home/a", "/home/b", "/home/c", "/home/d"]);
+        fc.merge(&other);
+        assert_eq!(3, sorted_repeats(&fc).len());
+        assert!(sorted_repeats(&fc).contains(&paths(&["/backup/d", "/home/d"])));
+
+        // merging a scan into itself adds nothing
+        let copy = scan(home, &["/home/a", "/home/b"]);
+        assert_eq!(0, fc.merge(&copy));
+        assert_eq!(3, fc.get_repeats().len());
+    }
+
+    #[test]
+    fn largest_duplicate() {
+        // the biggest files here are unique, so the largest duplicated size wins
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /huge 'HHHHHHHHHH'
+             file /huge2 'GGGGGGGGGG'
+             file /big1 'BBBBBB'
+             file /big2 'BBBBBB'
+             file /mid1 'MMMM'
+             file /mid2 'MMMM'
+             file /mid3 'MMMM'
+             file /small1 'SS'
+             file /small2 'SS'",
+        ).unwrap();
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        assert!(fc.largest_duplicate().is_none());
+        for path in &["/huge", "/huge2", "/small1", "/big1", "/mid1", "/small2", "/big2"] {
+            fc.insert(&PathBuf::from(path)).unwrap();
+        }
+        for path in &["/mid2", "/mid3"] {
+            fc.insert(&PathBuf::from(path)).unwrap();
+        }
+
+        let (size, dups) = fc.largest_duplicate().unwrap();
+        assert_eq!(6, size);
+        let mut paths = dups.into_paths();
+        paths.sort();
+        assert_eq!(vec![PathBuf::from("/big1"), PathBuf::from("/big2")], paths);
+
+        let sizes: Vec<_> = fc.largest_duplicates(2).iter().map(|d| d.size()).collect();
+        assert_eq!(vec![Some(6), Some(4)], sizes);
+        assert_eq!(3, fc.largest_duplicates(10).len());
+    }
+
+    // how many times `Counting` has started hashing something
+    static HASHES_STARTED: AtomicUsize = AtomicUsize::new(0);
+
+    // MD5, counting every file (or first bytes) hashed
+    #[derive(Debug)]
+    struct Counting;
+
+    impl FileHash for Counting {
+        type Output = <Md5Sum as FileHash>::Output;
+        type State = <Md5Sum as FileHash>::State;
+
+        fn start() -> Self::State {
+            HASHES_STARTED.fetch_add(1, Ordering::SeqCst);
+            Md5Sum::start()
+        }
+        fn update(state: &mut Self::State, bytes: &[u8]) {
+            Md5Sum::update(state, bytes)
+        }
+        fn finish(state: Self::State) -> Self::Output {
+            Md5Sum::finish(state)
+        }
+        fn digest(output: &Self::Output) -> Option<Vec<u8>> {
+            Md5Sum::digest(output)
+        }
+        fn from_digest(digest: &[u8]) -> Option<Self::Output> {
+            Md5Sum::from_digest(digest)
+        }
+    }
+
+    #[test]
+    fn incremental_rescan() {
+        // only the file that changed since the last run is read again
+        let paths: Vec<PathBuf> = ["/a", "/b", "/c", "/d"].iter().map(PathBuf::from).collect();
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1 mtime=100
+             file /b 'AAAA' inode=2 mtime=100
+             file /c 'AAAB' inode=3 mtime=100
+             file /d 'AAAC' inode=4 mtime=100",
+        ).unwrap();
+        let mut fc: FileCataloger<_, Counting> = FileCataloger::new(fs);
+        fc.insert_all(&paths);
+        let mut saved = vec![];
+        fc.save(&mut saved).unwrap();
+
+        // /c was rewritten, and now matches /d
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1 mtime=100
+             file /b 'AAAA' inode=2 mtime=100
+             file /c 'AAAC' inode=3 mtime=200
+             file /d 'AAAC' inode=4 mtime=100",
+        ).unwrap();
+        HASHES_STARTED.store(0, Ordering::SeqCst);
+        let mut fc: FileCataloger<_, Counting> = FileCataloger::new(fs.clone())
+            .incremental(&saved[..])
+            .unwrap();
+        fc.insert_all(&paths);
+        // just the first bytes of /c, which are all of it
+        assert_eq!(1, HASHES_STARTED.load(Ordering::SeqCst));
+        let expected: Vec<Vec<PathBuf>> = vec![
+            vec![PathBuf::from("/a"), PathBuf::from("/b")],
+            vec![PathBuf::from("/c"), PathBuf::from("/d")],
+        ];
+        assert_eq!(expected, sorted_repeats(&fc));
+
+        // a full rescan reads every file
+        HASHES_STARTED.store(0, Ordering::SeqCst);
+        let mut fc: FileCataloger<_, Counting> = FileCataloger::new(fs);
+        fc.insert_all(&paths);
+        assert_eq!(4, HASHES_STARTED.load(Ordering::SeqCst));
+        assert_eq!(expected, sorted_repeats(&fc));
+    }
+
+    #[test]
+    fn small_files_read_once() {
+        // a file that fits in its first bytes is hashed whole from them, not read again
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            for &(path, inode) in &[("/small1", 1), ("/small2", 2)] {
+                fs.add(
+                    TestFile::new(path)
+                        .with_contents(String::from("AAAA"))
+                        .with_metadata(TestMD::new().with_len(4))
+                        .with_inode(inode),
+                );
+            }
+            let len = FIRST_K_BYTES as u64 + 1;
+            for &(path, inode) in &[("/big1", 3), ("/big2", 4)] {
+                fs.add(
+                    TestFile::new(path)
+                        .with_pattern_contents(len, b"xyz")
+                        .with_metadata(TestMD::new().with_len(len))
+                        .with_inode(inode),
+                );
+            }
+        }
+
+        // only the reads of the comparison itself, not of checking matches (see `Verify`)
+        let mut fc: FileCataloger<_, Md5Sum> =
+            FileCataloger::new(fs.clone()).verify(Verify::Off);
+        for path in &["/small1", "/small2"] {
+            fc.insert(&PathBuf::from(path)).unwrap();
+        }
+        assert_eq!(2, fs.borrow().num_reads());
+        for path in &["/big1", "/big2"] {
+            fc.insert(&PathBuf::from(path)).unwrap();
+        }
+        assert_eq!(6, fs.borrow().num_reads());
+
+        let repeats = fc.get_repeats();
+        assert_eq!(2, repeats.len());
+        let small = repeats.iter().find(|d| d.size() == Some(4)).unwrap();
+        assert_eq!(Some(&Md5Sum::hash(b"AAAA")[..]), small.hash());
+    }
+
+    #[test]
+    fn stats_work_counters() {
+        // a file is only hashed whole if its size and first bytes both collide
+        let len = FIRST_K_BYTES as u64 * 2;
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            let files = [("/a", b"ab", 1), ("/b", b"ab", 2), ("/c", b"cd", 3)];
+            for &(path, pattern, inode) in &files {
+                fs.add(
+                    TestFile::new(path)
+                        .with_pattern_contents(len, pattern)
+                        .with_metadata(TestMD::new().with_len(len))
+                        .with_inode(inode),
+                );
+            }
+            fs.add(
+                TestFile::new("/lonely")
+                    .with_contents(String::from("x"))
+                    .with_metadata(TestMD::new().with_len(1))
+                    .with_inode(4),
+            );
+        }
+
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        for path in &["/a", "/b", "/c", "/lonely"] {
+            fc.insert(&PathBuf::from(path)).unwrap();
+        }
+        let stats = fc.stats();
+        assert_eq!(4, stats.files);
+        assert_eq!(2, stats.buckets);
+        assert_eq!(1, stats.contested);
+        assert_eq!(3, stats.first_bytes_read);
+        assert_eq!(2, stats.full_hashes);
+        assert_eq!(3 * FIRST_K_BYTES as u64 + 2 * len, stats.bytes_hashed);
+    }
+
+    #[test]
+    fn last_bytes_prefilter() {
+        // multi-megabyte files that only differ in their last byte
+        let len = 8 * 1024 * 1024;
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            for &(path, last, inode) in &[("/a", 1, 1), ("/b", 2, 2), ("/c", 3, 3)] {
+                fs.add(
+                    TestFile::new(path)
+                        .with_generated_contents(len, move |i| if i + 1 == len { last } else { 0 })
+                        .with_metadata(TestMD::new().with_len(len))
+                        .with_inode(inode),
+                );
+            }
+        }
+        let paths = ["/a", "/b", "/c"];
+
+        // compared by their last bytes, none of them is hashed whole
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs.clone()).last_bytes(true);
+        for path in &paths {
+            fc.insert(&PathBuf::from(path)).unwrap();
+        }
+        assert!(fc.get_repeats().is_empty());
+        assert_eq!(3, fc.get_collisions()[0].paths.len());
+        let stats = fc.stats();
+        assert_eq!(3, stats.last_bytes_read);
+        assert_eq!(0, stats.full_hashes);
+        assert_eq!(0, stats.hashed);
+
+        // without the stage, all of them are
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
+        for path in &paths {
+            fc.insert(&PathBuf::from(path)).unwrap();
+        }
+        assert!(fc.get_repeats().is_empty());
+        let stats = fc.stats();
+        assert_eq!(0, stats.last_bytes_read);
+        assert_eq!(3, stats.full_hashes);
+    }
+
+    #[test]
+    fn on_duplicate_streams_groups() {
+        // a group is reported when it's confirmed, then again (whole) each time it grows
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'same' inode=1
+             file /b 'same' inode=2
+             file /c 'diff' inode=3
+             file /d 'same' inode=4
+             file /e 'solo!' inode=5",
+        ).unwrap();
+        let seen = Rc::new(RefCell::new(vec![]));
+        let log = seen.clone();
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs)
+            .on_duplicate(move |dups| log.borrow_mut().push(dups.clone()));
+
+        fc.insert(&PathBuf::from("/a")).unwrap();
+        fc.insert(&PathBuf::from("/e")).unwrap();
+        assert!(seen.borrow().is_empty());
+        fc.insert(&PathBuf::from("/b")).unwrap();
+        assert_eq!(1, seen.borrow().len());
+        // a file of the same size with other contents doesn't touch the group
+        fc.insert(&PathBuf::from("/c")).unwrap();
+        assert_eq!(1, seen.borrow().len());
+        fc.insert(&PathBuf::from("/d")).unwrap();
+
+        let seen = seen.borrow();
+        assert_eq!(2, seen.len());
+        let mut first = seen[0].clone();
+        first.sort();
+        assert_eq!(vec![PathBuf::from("/a"), PathBuf::from("/b")], first.clone().into_paths());
+        assert_eq!(3, seen[1].len());
+        assert_eq!(Some(4), seen[1].size());
+        assert!(seen[1].hash().is_some());
+        // the last report of a group is the group the scan ends up with
+        let repeats = fc.get_repeats();
+        assert_eq!(1, repeats.len());
+        assert_eq!(repeats[0].paths(), seen[1].paths());
+    }
+
+    #[test]
+    fn hash_progress_reported() {
+        // every file hashed whole reports its progress, ending with its full length
+        let len = HASH_CHUNK_SIZE as u64 * 3;
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            for &(path, inode) in &[("/a", 1), ("/b", 2)] {
+                fs.add(
+                    TestFile::new(path)
+                        .with_pattern_contents(len, b"big")
+                        .with_metadata(TestMD::new().with_len(len))
+                        .with_inode(inode),
+                );
+            }
+        }
+        let seen = Rc::new(RefCell::new(vec![]));
+        let log = seen.clone();
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs)
+            .hash_progress(move |path, hashed, total| {
+                log.borrow_mut().push((path.to_path_buf(), hashed, total))
+            });
+        fc.insert(&PathBuf::from("/a")).unwrap();
+        fc.insert(&PathBuf::from("/b")).unwrap();
+        assert_eq!(1, fc.get_repeats().len());
+
+        let seen = seen.borrow();
+        assert_eq!(6, seen.len());
+        for path in &["/a", "/b"] {
+            let reports: Vec<_> = seen.iter().filter(|r| r.0 == PathBuf::from(path)).collect();
+            assert_eq!(3, reports.len());
+            assert_eq!((len, len), (reports[2].1, reports[2].2));
+        }
+    }
+
+    #[test]
+    fn empty_files_policy() {
+        // empty files are listed apart by default, and grouped like any others if asked
+        // either way they're never read
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /e1 '' inode=1
+             file /e2 '' inode=2
+             file /e3 '' inode=3
+             file /a 'AAAA' inode=4
+             file /b 'AAAA' inode=5",
+        ).unwrap();
+        let paths: Vec<PathBuf> = ["/e1", "/e2", "/e3", "/a", "/b"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let empty = vec![PathBuf::from("/e1"), PathBuf::from("/e2"), PathBuf::from("/e3")];
+
+        // only the reads of the comparison itself, not of checking matches (see `Verify`)
+        let mut fc: FileCataloger<_, Md5Sum> =
+            FileCataloger::new(fs.clone()).verify(Verify::Off);
+        fc.insert_all(&paths);
+        let repeats = fc.get_repeats();
+        assert_eq!(1, repeats.len());
+        assert_eq!(Some(4), repeats[0].size());
+        assert_eq!(empty, fc.get_empty());
+        assert_eq!(2, fs.borrow().num_reads());
+
+        let mut fc: FileCataloger<_, Md5Sum> =
+            FileCataloger::new(fs.clone()).dedupe_empty(true).verify(Verify::Off);
+        fc.insert_all(&paths);
+        let mut repeats = fc.get_repeats();
+        repeats.sort_by_key(|dups| dups.size());
+        assert_eq!(2, repeats.len());
+        repeats[0].sort();
+        assert_eq!(empty, repeats[0].clone().into_paths());
+        assert!(fc.get_empty().is_empty());
+        assert_eq!(4, fs.borrow().num_reads());
+    }
+
+    #[test]
+    fn progress_logged_at_interval() {
+        // a line is due every `files` paths, or once `time` has passed, on a made-up clock
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'same'
+             file /b 'same'",
+        ).unwrap();
+        let interval = LogInterval {
+            files: 3,
+            time: Duration::from_secs(60),
+        };
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs).log_progress(Some(interval));
+        fc.insert_all(&["/a", "/b"]);
+        let start = Instant::now();
+        let mut last = LastLogged {
+            done: 0,
+            at: start,
+        };
+        let due: Vec<usize> = (1..10)
+            .filter(|&done| fc.progress_line(done, &mut last, start).is_some())
+            .collect();
+        assert_eq!(vec![3, 6, 9], due);
+
+        // a slow file is logged as soon as a minute has passed, however few came before
+        let line = fc.progress_line(10, &mut last, start + Duration::from_secs(59));
+        assert!(line.is_none());
+        let line = fc.progress_line(10, &mut last, start + Duration::from_secs(60));
+        assert_eq!(
+            Some("Cataloged 10 files so far: 1 groups of duplicates, 8 B hashed"),
+            line.as_deref()
+        );
+        // and the count starts again from there
+        assert!(fc.progress_line(12, &mut last, start + Duration::from_secs(61)).is_none());
+        assert!(fc.progress_line(13, &mut last, start + Duration::from_secs(61)).is_some());
+
+        // never, if turned off
+        let fc = fc.log_progress(None);
+        assert!(fc.progress_line(100, &mut last, start + Duration::from_secs(600)).is_none());
+    }
+
+    #[test]
+    fn duplicates_of_one_path() {
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA'
+             file /b 'AAAA'
+             file /c 'AAAB'
+             file /d 'CC'",
+        ).unwrap();
+        // only the reads of the comparison itself, not of checking matches (see `Verify`)
+        let mut fc: FileCataloger<_, Md5Sum> =
+            FileCataloger::new(fs.clone()).hash_size_limit(3).verify(Verify::Off);
+        fc.insert_all(&["/a", "/b", "/c", "/d"]);
+        assert_eq!(1, fc.get_size_only().len());
+        assert_eq!(0, fs.borrow().num_reads());
+
+        // files matched by size alone are compared the first time one is asked about
+        let dups = fc.duplicates_of(Path::new("/b")).unwrap();
+        let mut paths = dups.clone().into_paths();
+        paths.sort();
+        assert_eq!(vec![PathBuf::from("/a"), PathBuf::from("/b")], paths);
+        assert!(dups.hash().is_some());
+        assert_eq!(3, fs.borrow().num_reads());
+
+        // and only then
+        assert!(fc.duplicates_of(Path::new("/c")).is_none());
+        assert!(fc.duplicates_of(Path::new("/a")).is_some());
+        assert_eq!(3, fs.borrow().num_reads());
+        assert!(fc.get_size_only().is_empty());
+        assert_eq!(1, fc.get_repeats().len());
+
+        // a file alone in its size, or not in the catalog at all, has none
+        assert!(fc.duplicates_of(Path::new("/d")).is_none());
+        assert!(fc.duplicates_of(Path::new("/e")).is_none());
+        assert_eq!(3, fs.borrow().num_reads());
+    }
+
+    #[test]
+    fn group_paths_sorted() {
+        // however the files were inserted, each group lists its paths in the same order
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             dir /a
+             file /a/b 'AAAA'
+             file /a-b 'AAAA'
+             file /c 'AAAA'
+             file /x 'BB' inode=9
+             file /w 'BB' inode=9",
+        ).unwrap();
+        let files = ["/c", "/a/b", "/x", "/a-b", "/w"];
+        let catalog = |order: &[&str]| {
+            let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs.clone());
+            fc.insert_all(order);
+            let mut groups: Vec<Vec<PathBuf>> = fc.get_repeats()
+                .into_iter()
+                .map(Duplicates::into_paths)
+                .collect();
+            groups.sort();
+            groups
+        };
+        let groups = catalog(&files);
+        let expected = vec![
+            vec![PathBuf::from("/a-b"), PathBuf::from("/a/b"), PathBuf::from("/c")],
+            vec![PathBuf::from("/w"), PathBuf::from("/x")],
+        ];
+        assert_eq!(expected, groups);
+        let reversed: Vec<&str> = files.iter().rev().cloned().collect();
+        assert_eq!(groups, catalog(&reversed));
+    }
+
+    // a hash under which every file collides with every other
+    #[derive(Debug)]
+    struct Constant;
+
+    impl FileHash for Constant {
+        type Output = u8;
+        type State = ();
+
+        fn start() {}
+        fn update(_state: &mut (), _bytes: &[u8]) {}
+        fn finish(_state: ()) -> u8 {
+            0
+        }
+        fn is_weak() -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn collisions_kept_apart() {
+        // files whose hashes match but whose contents don't are never grouped,
+        // as far as they're checked, but are still grouped with their own copies
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1
+             file /c 'CCCC' inode=2
+             file /b 'AAAA' inode=3
+             file /d 'CCCC' inode=7",
+        ).unwrap();
+        // /y and /w share the first bytes of /x and /z, but not the last
+        let len = FIRST_K_BYTES as u64 + 4;
+        let tails = [("/x", 4, b'X'), ("/y", 5, b'Y'), ("/z", 6, b'X'), ("/w", 8, b'Y')];
+        for &(name, inode, tail) in &tails {
+            let file = TestFile::new(name)
+                .with_generated_contents(len, move |i| if i < len - 1 { b'.' } else { tail })
+                .with_metadata(TestMD::new())
+                .with_inode(inode);
+            fs.borrow_mut().add(file);
+        }
+        let paths = ["/a", "/c", "/b", "/d", "/x", "/y", "/z", "/w"];
+        let groups = |verify: Option<Verify>| {
+            let mut fc: FileCataloger<_, Constant> = FileCataloger::new(fs.clone());
+            if let Some(verify) = verify {
+                fc = fc.verify(verify);
+            }
+            fc.insert_all(paths.iter());
+            let mut groups: Vec<Vec<PathBuf>> = fc.get_repeats()
+                .into_iter()
+                .map(|dups| dups.into_paths())
+                .collect();
+            groups.sort();
+            groups
+        };
+        let group = |names: &[&str]| names.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        let all = vec![group(&["/a", "/b", "/c", "/d"]), group(&["/w", "/x", "/y", "/z"])];
+        assert_eq!(all, groups(Some(Verify::Off)));
+        // a weak hash has the first bytes of matches checked by default
+        let prefix = vec![
+            group(&["/a", "/b"]),
+            group(&["/c", "/d"]),
+            group(&["/w", "/x", "/y", "/z"]),
+        ];
+        assert_eq!(prefix, groups(None));
+        assert_eq!(prefix, groups(Some(Verify::Prefix)));
+        let full = vec![
+            group(&["/a", "/b"]),
+            group(&["/c", "/d"]),
+            group(&["/w", "/y"]),
+            group(&["/x", "/z"]),
+        ];
+        assert_eq!(full, groups(Some(Verify::Full)));
+
+        // each group's first bytes are read once, and each newcomer's once
+        let mut fc: FileCataloger<_, Constant> = FileCataloger::new(fs.clone());
+        fc.insert_all(paths.iter());
+        assert_eq!(4 + 4, fc.stats().verify_reads);
+    }
+
+    #[test]
+    fn with_length_never_collides_across_sizes() {
+        // contents whose hashes collide still differ once their lengths are part of them
+        type Sized = WithLength<Constant>;
+        assert_eq!(Constant::hash(b"AAAA"), Constant::hash(b"AAAAA"));
+        assert_ne!(Sized::hash(b"AAAA"), Sized::hash(b"AAAAA"));
+        assert_ne!(Sized::hash(b""), Sized::hash(b"A"));
+        assert_eq!(Sized::hash(b"AAAA"), Sized::hash(b"BBBB"));
+
+        // fed in chunks or all at once, and through a digest and back, it's the same
+        let mut state = WithLength::<Md5Sum>::start();
+        WithLength::<Md5Sum>::update(&mut state, b"ab");
+        WithLength::<Md5Sum>::update(&mut state, b"c");
+        let output = WithLength::<Md5Sum>::finish(state);
+        assert_eq!((3, Md5Sum::hash(b"abc")), output);
+        let digest = WithLength::<Md5Sum>::digest(&output).unwrap();
+        assert_eq!(8 + 16, digest.len());
+        assert_eq!(Some(output), WithLength::<Md5Sum>::from_digest(&digest));
+        assert!(WithLength::<Md5Sum>::from_digest(&digest[..20]).is_none());
+
+        // and catalogs group files the same way with it
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA' inode=1
+             file /b 'AAAA' inode=2
+             file /c 'AAAB' inode=3
+             file /d 'XYZ' inode=4
+             file /e 'XYZ' inode=5",
+        ).unwrap();
+        let paths = ["/a", "/b", "/c", "/d", "/e"];
+        let mut plain: FileCataloger<_, Md5Sum> = FileCataloger::new(fs.clone());
+        plain.insert_all(paths.iter());
+        let mut sized: FileCataloger<_, WithLength<Md5Sum>> = FileCataloger::new(fs);
+        sized.insert_all(paths.iter());
+        assert_eq!(sorted_repeats(&plain), sorted_repeats(&sized));
+        assert_eq!(2, sorted_repeats(&sized).len());
+    }
+
+    #[test]
+    fn hash_kind_digests() {
+        // each algorithm chosen at runtime gives its known digest
+        let hex = |kind: HashKind| {
+            let mut state = kind.begin();
+            HashKind::update(&mut state, b"ab");
+            HashKind::update(&mut state, b"c");
+            to_hex(&HashKind::digest(&HashKind::finish(state)).unwrap())
+        };
+        assert_eq!("900150983cd24fb0d6963f7d28e17f72", hex(HashKind::Md5));
+        assert_eq!(
+            "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532",
+            hex(HashKind::Sha3)
+        );
+
+        // hashing as a `FileHash` goes by the algorithm selected on this thread
+        assert_eq!(HashOutput::Md5(Md5Sum::hash(b"abc")), HashKind::hash(b"abc"));
+        HashKind::Sha3.select();
+        assert_eq!(HashOutput::Sha3(Sha3Sum::hash(b"abc")), HashKind::hash(b"abc"));
+        assert!(HashKind::from_digest(&Md5Sum::hash(b"abc")).is_none());
+        assert_eq!("sha3".parse(), Ok(HashKind::Sha3));
+
+        // and so does a catalog of them
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA'
+             file /b 'AAAA'",
+        ).unwrap();
+        let mut fc: FileCataloger<_, HashKind> = FileCataloger::new(fs);
+        fc.insert_all(&["/a", "/b"]);
+        assert_eq!(Some(&Sha3Sum::hash(b"AAAA")[..]), fc.get_repeats()[0].hash());
+        HashKind::Md5.select();
+
+        // an algorithm selected for a while is deselected after
+        let sha3 = HashKind::Sha3.select_while(|| HashKind::hash(b"abc"));
+        assert_eq!(HashOutput::Sha3(Sha3Sum::hash(b"abc")), sha3);
+        assert_eq!(HashKind::Md5, HashKind::selected());
+        assert_eq!(Md5Sum::is_weak(), HashKind::is_weak());
+    }
+
+    #[test]
+    fn symlinks_cataloged_as_target() {
+        // a link is never a duplicate of its own target
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             dir /d
+             file /d/t 'same'
+             file /u 'same'
+             link /l -> /d/t
+             link /m -> /d/../d/t
+             link /broken -> /nowhere",
+        ).unwrap();
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs.clone());
+        fc.insert(Path::new("/d/t")).unwrap();
+        fc.insert(Path::new("/l")).unwrap();
+        assert_eq!(1, fc.num_inserted());
+        assert!(fc.get_repeats().is_empty());
+
+        // inserted first, the link files its target (once)
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs.clone());
+        fc.insert(Path::new("/m")).unwrap();
+        fc.insert(Path::new("/l")).unwrap();
+        fc.insert(Path::new("/d/t")).unwrap();
+        fc.insert(Path::new("/u")).unwrap();
+        let expected = vec![vec![PathBuf::from("/d/t"), PathBuf::from("/u")]];
+        assert_eq!(expected, sorted_repeats(&fc));
+        assert_eq!(2, fc.num_inserted());
+
+        // a dangling link is left out, like any file that can't be looked up
+        assert!(fc.insert(Path::new("/broken")).is_err());
+        assert_eq!(2, fc.num_inserted());
+
+        // unless links are kept as files of their own
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs).keep_symlinks(true);
+        fc.insert(Path::new("/d/t")).unwrap();
+        fc.insert(Path::new("/l")).unwrap();
+        let expected = vec![vec![PathBuf::from("/d/t"), PathBuf::from("/l")]];
+        assert_eq!(expected, sorted_repeats(&fc));
+    }
+
+    #[test]
+    fn large_bucket_grouping() {
+        // hundreds of same-size files are told apart by their first bytes in one lookup each,
+        // and those sharing them by their whole contents, as when they're few
+        let len = 2 * FIRST_K_BYTES as u64;
+        let fs = TestFileSystem::new();
+        fs.borrow_mut().create_dir("/");
+        let mut expected: Vec<Vec<PathBuf>> = vec![vec![]; 60];
+        for i in 0..600u64 {
+            let (head, tail) = ((i % 20) as u8, (i / 20 % 3) as u8);
+            let path = PathBuf::from(format!("/f{}", i));
+            let file = TestFile::new(&path)
+                .with_generated_contents(len, move |off| if off == len - 1 { tail } else { head })
+                .with_metadata(TestMD::new().with_len(len))
+                .with_inode(i + 1);
+            fs.borrow_mut().add(file);
+            expected[head as usize * 3 + tail as usize].push(path);
+        }
+        let paths: Vec<PathBuf> = (0..600).map(|i| PathBuf::from(format!("/f{}", i))).collect();
+
+        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs.clone()).verify(Verify::Off);
+        fc.insert_all(&paths);
+        for group in &mut expected {
+            group.sort();
+        }
+        expected.sort();
+        assert_eq!(expected, sorted_repeats(&fc));
+        // each file's first bytes are read once, and then the whole of it (as every file
+        // shares its first bytes with others)
+        assert_eq!(600 * 2, fs.borrow().num_reads());
+    }
 }
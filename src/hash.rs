@@ -3,8 +3,11 @@
 use md5;
 use tiny_keccak;
 
-use std::fmt::Debug;
+use std::cell::Cell;
+use std::fmt::{self, Debug, Display};
 use std::hash;
+use std::marker::PhantomData;
+use std::str::FromStr;
 
 // The standard library has a similar trait: std::hash::Hash
 // However, Rust does not allow you to implement a trait from an external crate
@@ -12,14 +15,47 @@ use std::hash;
 // So to implement this we use our own FileHash trait
 
 /// Easy way to hash byte arrays
-pub trait FileHash: Debug {
+// must be 'static (state and all) to be fed through a boxed file (see `vfs::DynHasher`)
+pub trait FileHash: Debug + 'static {
     // Output must be Debug for the whole type to be
     // must be Eq and Hash to be a key for a hash table
     // must be Clone because hashes sometimes must be stored redundantly
     /// Output type of hashing (different algorithms returns differently sized outputs)
     type Output: Debug + Clone + Eq + hash::Hash; //+ ::std::ops::Index<usize>;
+    /// Running state of a hash fed a chunk at a time (so big files needn't fit in memory)
+    type State: 'static;
+    /// Begin hashing
+    fn start() -> Self::State;
+    /// Hash the next chunk of bytes
+    fn update(state: &mut Self::State, bytes: &[u8]);
+    /// Finish hashing and return the result
+    fn finish(state: Self::State) -> Self::Output;
     /// Hash an array of bytes and return the result
-    fn hash(bytes: &[u8]) -> Self::Output;
+    fn hash(bytes: &[u8]) -> Self::Output {
+        let mut state = Self::start();
+        Self::update(&mut state, bytes);
+        Self::finish(state)
+    }
+    /// The bytes of a digest, to show to the user (e.g. in hex)
+    /// None for outputs that aren't digests of the contents
+    fn digest(_output: &Self::Output) -> Option<Vec<u8>> {
+        None
+    }
+    /// Turn the bytes of a digest back into an output (the inverse of `digest`)
+    /// None if they aren't a digest of this kind (e.g. the wrong length)
+    fn from_digest(_digest: &[u8]) -> Option<Self::Output> {
+        None
+    }
+    /// Whether different contents with the same hash can be made on purpose (e.g. MD5),
+    /// so files with matching hashes are worth a second look (see `catalog::Verify`)
+    fn is_weak() -> bool {
+        false
+    }
+    /// Set up a new thread (e.g. a hash worker) to hash the way the current one does
+    /// Called on the current thread; the returned function is called on the new one
+    fn carry_to_thread() -> Box<dyn FnOnce() + Send> {
+        Box::new(|| ())
+    }
 }
 
 /// Generate 128-bit MD5 digest
@@ -34,9 +70,31 @@ pub struct Sha3Sum;
 // returns a 128-bit hash
 impl FileHash for Md5Sum {
     type Output = [u8; 16];
+    type State = md5::Context;
 
-    fn hash(bytes: &[u8]) -> Self::Output {
-        *md5::compute(bytes)
+    fn start() -> Self::State {
+        md5::Context::new()
+    }
+    fn update(state: &mut Self::State, bytes: &[u8]) {
+        state.consume(bytes);
+    }
+    fn finish(state: Self::State) -> Self::Output {
+        *state.compute()
+    }
+    fn digest(output: &Self::Output) -> Option<Vec<u8>> {
+        Some(output.to_vec())
+    }
+    // collisions can be computed in seconds
+    fn is_weak() -> bool {
+        true
+    }
+    fn from_digest(digest: &[u8]) -> Option<Self::Output> {
+        let mut output = [0; 16];
+        if digest.len() != output.len() {
+            return None;
+        }
+        output.copy_from_slice(digest);
+        Some(output)
     }
 }
 
@@ -44,12 +102,222 @@ impl FileHash for Md5Sum {
 // returns a 256-bit hash
 impl FileHash for Sha3Sum {
     type Output = [u8; 32];
+    type State = tiny_keccak::Keccak;
 
-    fn hash(bytes: &[u8]) -> Self::Output {
-        let mut sha = tiny_keccak::Keccak::new_sha3_256();
+    fn start() -> Self::State {
+        tiny_keccak::Keccak::new_sha3_256()
+    }
+    fn update(state: &mut Self::State, bytes: &[u8]) {
+        state.update(bytes);
+    }
+    fn finish(state: Self::State) -> Self::Output {
         let mut arr = [0u8; 32];
-        sha.update(bytes);
-        sha.finalize(&mut arr);
+        state.finalize(&mut arr);
         arr
     }
+    fn digest(output: &Self::Output) -> Option<Vec<u8>> {
+        Some(output.to_vec())
+    }
+    fn from_digest(digest: &[u8]) -> Option<Self::Output> {
+        let mut output = [0; 32];
+        if digest.len() != output.len() {
+            return None;
+        }
+        output.copy_from_slice(digest);
+        Some(output)
+    }
+}
+
+/// Another hash with the number of bytes hashed made part of its output, so an output is
+/// unique to a (length, contents) pair: files of different sizes never match, even in
+/// catalogs saved or merged by runs that bucketed them differently (see `FileCataloger::save`)
+#[derive(Debug)]
+pub struct WithLength<H: FileHash>(PhantomData<H>);
+
+// the length sits beside the inner hash rather than being fed to it,
+// so even colliding contents of different lengths are told apart
+impl<H: FileHash> FileHash for WithLength<H> {
+    type Output = (u64, H::Output);
+    type State = (u64, H::State);
+
+    fn start() -> Self::State {
+        (0, H::start())
+    }
+    fn update(state: &mut Self::State, bytes: &[u8]) {
+        state.0 += bytes.len() as u64;
+        H::update(&mut state.1, bytes);
+    }
+    fn finish(state: Self::State) -> Self::Output {
+        (state.0, H::finish(state.1))
+    }
+    // the length as 8 big-endian bytes, then the inner digest
+    fn digest(output: &Self::Output) -> Option<Vec<u8>> {
+        let mut digest = output.0.to_be_bytes().to_vec();
+        digest.extend(H::digest(&output.1)?);
+        Some(digest)
+    }
+    fn from_digest(digest: &[u8]) -> Option<Self::Output> {
+        if digest.len() < 8 {
+            return None;
+        }
+        let (len, inner) = digest.split_at(8);
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(len);
+        Some((u64::from_be_bytes(bytes), H::from_digest(inner)?))
+    }
+    fn is_weak() -> bool {
+        H::is_weak()
+    }
+    fn carry_to_thread() -> Box<dyn FnOnce() + Send> {
+        H::carry_to_thread()
+    }
+}
+
+/// A hash algorithm chosen at runtime (e.g. from the command line)
+/// As a `FileHash` it hashes with the algorithm `select`ed on the current thread,
+/// so one `FileCataloger<_, HashKind>` serves every algorithm
+/// (`scan` selects the one asked for with `select_while`, so it's only selected while it runs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashKind {
+    /// MD5 (see `Md5Sum`)
+    Md5,
+    /// SHA-3 (see `Sha3Sum`)
+    Sha3,
+}
+
+/// The digest of a `HashKind`, tagged with the algorithm that produced it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HashOutput {
+    Md5(<Md5Sum as FileHash>::Output),
+    Sha3(<Sha3Sum as FileHash>::Output),
+}
+
+/// The running state of a `HashKind`
+pub enum HashState {
+    Md5(<Md5Sum as FileHash>::State),
+    Sha3(<Sha3Sum as FileHash>::State),
+}
+
+thread_local! {
+    // the algorithm `HashKind` hashes with on this thread
+    static SELECTED: Cell<HashKind> = const { Cell::new(HashKind::Md5) };
+}
+
+impl HashKind {
+    /// Hash with this algorithm on the current thread from now on
+    /// (and on the threads its `FileCataloger`s start)
+    pub fn select(self) {
+        SELECTED.with(|kind| kind.set(self));
+    }
+    /// The algorithm selected on the current thread (MD5 unless another was)
+    pub fn selected() -> Self {
+        SELECTED.with(Cell::get)
+    }
+    /// Hash with this algorithm on the current thread while `f` runs, then go back to
+    /// the one selected before (even if `f` panics)
+    pub fn select_while<R, F: FnOnce() -> R>(self, f: F) -> R {
+        // reselects what was selected when it's dropped
+        struct Restore(HashKind);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                self.0.select();
+            }
+        }
+        let _restore = Restore(HashKind::selected());
+        self.select();
+        f()
+    }
+    /// Begin hashing with this algorithm, whichever is selected
+    pub fn begin(self) -> HashState {
+        match self {
+            HashKind::Md5 => HashState::Md5(Md5Sum::start()),
+            HashKind::Sha3 => HashState::Sha3(Sha3Sum::start()),
+        }
+    }
+}
+
+impl Display for HashKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HashKind::Md5 => write!(f, "MD5"),
+            HashKind::Sha3 => write!(f, "SHA-3"),
+        }
+    }
+}
+
+// parse the name of an algorithm as given on the command line
+impl FromStr for HashKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "md5" => Ok(HashKind::Md5),
+            "sha3" | "sha-3" => Ok(HashKind::Sha3),
+            _ => Err(format!("unknown hash `{}` (expected md5 or sha3)", s)),
+        }
+    }
+}
+
+// HashKind dispatches to the algorithm selected when hashing started
+impl FileHash for HashKind {
+    type Output = HashOutput;
+    type State = HashState;
+
+    fn start() -> Self::State {
+        HashKind::selected().begin()
+    }
+    fn update(state: &mut Self::State, bytes: &[u8]) {
+        match *state {
+            HashState::Md5(ref mut s) => Md5Sum::update(s, bytes),
+            HashState::Sha3(ref mut s) => Sha3Sum::update(s, bytes),
+        }
+    }
+    fn finish(state: Self::State) -> Self::Output {
+        match state {
+            HashState::Md5(s) => HashOutput::Md5(Md5Sum::finish(s)),
+            HashState::Sha3(s) => HashOutput::Sha3(Sha3Sum::finish(s)),
+        }
+    }
+    fn digest(output: &Self::Output) -> Option<Vec<u8>> {
+        match *output {
+            HashOutput::Md5(ref o) => Md5Sum::digest(o),
+            HashOutput::Sha3(ref o) => Sha3Sum::digest(o),
+        }
+    }
+    // only digests of the selected algorithm, like the hashes it would produce
+    fn from_digest(digest: &[u8]) -> Option<Self::Output> {
+        match HashKind::selected() {
+            HashKind::Md5 => Md5Sum::from_digest(digest).map(HashOutput::Md5),
+            HashKind::Sha3 => Sha3Sum::from_digest(digest).map(HashOutput::Sha3),
+        }
+    }
+    fn is_weak() -> bool {
+        match HashKind::selected() {
+            HashKind::Md5 => Md5Sum::is_weak(),
+            HashKind::Sha3 => Sha3Sum::is_weak(),
+        }
+    }
+    fn carry_to_thread() -> Box<dyn FnOnce() + Send> {
+        let kind = HashKind::selected();
+        Box::new(move || kind.select())
+    }
+}
+
+// a "hash" that returns its input, for getting raw bytes out of any `File`
+#[derive(Debug)]
+pub(crate) struct Identity;
+
+impl FileHash for Identity {
+    type Output = Vec<u8>;
+    type State = Vec<u8>;
+
+    fn start() -> Self::State {
+        vec![]
+    }
+    fn update(state: &mut Self::State, bytes: &[u8]) {
+        state.extend_from_slice(bytes);
+    }
+    fn finish(state: Self::State) -> Self::Output {
+        state
+    }
 }
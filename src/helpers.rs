@@ -1,16 +1,41 @@
+use std::{io, str};
+use std::borrow::Cow;
+use std::path::Path;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+
+use vfs::MetaData;
+
 /// The number of bytes that will be read and hashed for a file
 /// for the `FirstKBytesProxy` pass
 pub const FIRST_K_BYTES: usize = 4096;
 
+/// The number of bytes at the end of a file hashed for the optional `LastKBytesProxy` pass
+pub const LAST_K_BYTES: usize = 4096;
+
+/// The number of bytes read at a time when hashing a whole file
+pub const HASH_CHUNK_SIZE: usize = 64 * 1024;
+
 /// Uniquely identify a file by its device id and inode
 #[derive(Clone, Copy, Hash, PartialEq, Eq)]
 pub struct ID {
-    /// Device ID (unique to Linux)
+    /// Device ID (the volume serial number on Windows)
     pub dev: u64,
-    /// Filesystem Inode
+    /// Filesystem Inode (the file index on Windows)
     pub inode: u64,
 }
 
+impl ID {
+    /// Identify the file described by `md`
+    /// Every `ID` of a real file should be built here so all platforms agree
+    pub fn of<M: MetaData>(md: &M) -> io::Result<Self> {
+        Ok(ID {
+            dev: md.get_device()?.0,
+            inode: md.get_inode().0,
+        })
+    }
+}
+
 /// Represent the first K bytes of a file
 
 pub fn prettify_bytes(b: u64) -> String {
@@ -23,13 +48,50 @@ pub fn prettify_bytes(b: u64) -> String {
     } else if b < 1024 * 1024 * 1024 * 1024 {
         format!("{} GB", b / 1024 / 1024 / 1024)
     } else {
-        // insert commas
-        let mut s = format!("{} B", b);
-        let mut i = s.len() as i64 - 2 - 3;
-        while i > 0 {
-            s.insert(i as usize, ',');
-            i -= 3;
-        }
-        s
+        format!("{} B", with_commas(b))
+    }
+}
+
+/// Format a number with commas between groups of thousands, e.g. `1,204`
+pub fn with_commas(n: u64) -> String {
+    let mut s = n.to_string();
+    let mut i = s.len() as i64 - 3;
+    while i > 0 {
+        s.insert(i as usize, ',');
+        i -= 3;
+    }
+    s
+}
+
+/// Write bytes (e.g. a digest) as lowercase hex, the way `md5sum` and friends do
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Read bytes back from hex written by `to_hex`, or None if it isn't hex
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair.len() {
+            2 => str::from_utf8(pair).ok().and_then(|b| u8::from_str_radix(b, 16).ok()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The bytes of a path exactly as the OS stores them, for machine-readable output
+/// Unix paths needn't be UTF-8, so they are passed through untouched
+#[cfg(unix)]
+pub fn path_bytes<'a>(p: &'a Path) -> Cow<'a, [u8]> {
+    Cow::Borrowed(p.as_os_str().as_bytes())
+}
+
+/// The bytes of a path exactly as the OS stores them, for machine-readable output
+/// Elsewhere paths are converted to UTF-8 (lossily, if they aren't valid Unicode)
+#[cfg(not(unix))]
+pub fn path_bytes<'a>(p: &'a Path) -> Cow<'a, [u8]> {
+    match p.to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
     }
 }
@@ -0,0 +1,198 @@
+//! The machine-readable report written by `smllr --json`, and the schema it follows
+
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde_json;
+
+use helpers::to_hex;
+use summary::ScanSummary;
+use {Duplicates, Report};
+
+/// Version of the report's layout, bumped whenever its shape changes
+/// Parsers should check it before reading anything else (see `SCHEMA`)
+pub const VERSION: u32 = 3;
+
+/// A JSON Schema documenting the report `write` produces
+pub const SCHEMA: &str = r#"{
+  "$schema": "http://json-schema.org/draft-07/schema#",
+  "title": "smllr report",
+  "description": "Duplicates found by `smllr --json`; the layout only changes with `version`",
+  "type": "object",
+  "required": [
+    "version", "files", "skipped", "omitted", "omitted_bytes", "unlinkable", "summary", "groups"
+  ],
+  "properties": {
+    "version": {
+      "description": "Version of this layout",
+      "const": 3
+    },
+    "files": {
+      "description": "Number of files the walk turned up",
+      "type": "integer",
+      "minimum": 0
+    },
+    "skipped": {
+      "description": "Number of files that couldn't be read",
+      "type": "integer",
+      "minimum": 0
+    },
+    "omitted": {
+      "description": "Number of groups left out by --min-copies or --min-waste",
+      "type": "integer",
+      "minimum": 0
+    },
+    "omitted_bytes": {
+      "description": "Bytes the redundant copies in the omitted groups take up",
+      "type": "integer",
+      "minimum": 0
+    },
+    "unlinkable": {
+      "description": "Files whose duplicates are all on other devices (with --link)",
+      "type": "array",
+      "items": { "type": "string" }
+    },
+    "summary": {
+      "description": "What the run covered; sizes are space on disk where it's known",
+      "type": "object",
+      "required": ["dirs", "files", "bytes", "skipped", "groups", "duplicates", "reclaimable"],
+      "properties": {
+        "dirs": {
+          "description": "Number of directories walked",
+          "type": "integer",
+          "minimum": 0
+        },
+        "files": {
+          "description": "Number of files cataloged",
+          "type": "integer",
+          "minimum": 0
+        },
+        "bytes": {
+          "description": "Space the files the walk turned up occupy",
+          "type": "integer",
+          "minimum": 0
+        },
+        "skipped": {
+          "description": "Number of files that couldn't be cataloged",
+          "type": "integer",
+          "minimum": 0
+        },
+        "groups": {
+          "description": "Number of groups reported",
+          "type": "integer",
+          "minimum": 0
+        },
+        "duplicates": {
+          "description": "Number of paths in those groups",
+          "type": "integer",
+          "minimum": 0
+        },
+        "reclaimable": {
+          "description": "Space keeping only one file of each group would free",
+          "type": "integer",
+          "minimum": 0
+        }
+      }
+    },
+    "groups": {
+      "description": "Groups of identical files, most wasteful first unless --top was given",
+      "type": "array",
+      "items": {
+        "type": "object",
+        "required": ["size", "hash", "wasted", "paths", "devices"],
+        "properties": {
+          "size": {
+            "description": "Length of each file in bytes",
+            "type": ["integer", "null"],
+            "minimum": 0
+          },
+          "hash": {
+            "description": "Hex digest of the contents (MD5, or SHA-3 with --hash sha3)",
+            "type": ["string", "null"]
+          },
+          "wasted": {
+            "description": "Bytes taken up by all but one of the distinct files",
+            "type": "integer",
+            "minimum": 0
+          },
+          "paths": {
+            "description": "Every path in the group (hard links to one file each count)",
+            "type": "array",
+            "items": { "type": "string" },
+            "minItems": 2
+          },
+          "devices": {
+            "description": "The device ID of each path, in the same order",
+            "type": "array",
+            "items": { "type": ["integer", "null"], "minimum": 0 }
+          }
+        }
+      }
+    }
+  }
+}
+"#;
+
+// the whole report, as described by `SCHEMA`
+#[derive(Serialize)]
+struct JsonReport {
+    version: u32,
+    files: usize,
+    skipped: usize,
+    omitted: usize,
+    omitted_bytes: u64,
+    unlinkable: Vec<String>,
+    summary: ScanSummary,
+    groups: Vec<JsonGroup>,
+}
+
+// one group of duplicates
+#[derive(Serialize)]
+struct JsonGroup {
+    size: Option<u64>,
+    hash: Option<String>,
+    wasted: u64,
+    // lossily converted, as JSON strings must be UTF-8
+    paths: Vec<String>,
+    devices: Vec<Option<u64>>,
+}
+
+impl JsonGroup {
+    // `dups`, its paths on the devices `report` found them on
+    fn new(dups: &Duplicates, report: &Report) -> Self {
+        JsonGroup {
+            size: dups.size(),
+            hash: dups.hash().map(to_hex),
+            wasted: dups.redundant_bytes(),
+            paths: dups.iter().map(|path| lossy(path)).collect(),
+            devices: dups.iter()
+                .map(|path| report.devices.get(&**path).cloned())
+                .collect(),
+        }
+    }
+}
+
+// a path as a JSON string
+fn lossy(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Write `report` to `writer` as a single JSON object following `SCHEMA`,
+/// with its groups in the order they're in
+pub fn write<W: Write>(report: &Report, writer: W) -> io::Result<()> {
+    let json = JsonReport {
+        version: VERSION,
+        files: report.files,
+        skipped: report.skipped,
+        omitted: report.omitted,
+        omitted_bytes: report.omitted_bytes,
+        unlinkable: report.unlinkable.iter().map(|path| lossy(path)).collect(),
+        summary: report.summary(),
+        groups: report
+            .repeats
+            .iter()
+            .map(|dups| JsonGroup::new(dups, report))
+            .collect(),
+    };
+    serde_json::to_writer_pretty(writer, &json).map_err(io::Error::from)
+}
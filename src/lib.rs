@@ -0,0 +1,643 @@
+//! Identify and handle duplicate files in a fast and extensible way
+//!
+//! `deduplicate` runs the whole pipeline (walk the paths, then catalog the files)
+//! over any `VFS` and returns the groups of identical files.
+//! The `smllr` binary is a command line wrapper around `scan`.
+
+#[macro_use]
+extern crate log;
+extern crate md5;
+extern crate regex;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate tiny_keccak;
+#[cfg(unix)]
+extern crate libc;
+#[cfg(windows)]
+extern crate winapi;
+
+// import from standard library
+use std::{fs, io};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+// import from our own modules
+
+mod helpers;
+
+pub mod walker;
+use walker::{DirWalker, TraversalOrder, WalkStats};
+
+pub mod vfs;
+pub use vfs::{DynVfs, OnError, RealFileSystem, SendVfs, TestFileSystem, VFS};
+use vfs::TextNormalizedVfs;
+
+pub mod catalog;
+pub use catalog::proxy::Duplicates;
+use catalog::{filter_min_copies, filter_min_waste, filter_symlinked, largest_first, sort_groups,
+              split_by_device, split_by_owner, CatalogError, CatalogStats, Collision, ErrorTally,
+              FileCataloger, Verify, ID, MAX_ERRORS};
+use catalog::histogram::SizeHistogram;
+
+pub mod actor;
+use actor::Savings;
+
+pub mod hash;
+use hash::{FileHash, HashKind};
+
+pub mod json;
+
+pub mod summary;
+use summary::ScanSummary;
+
+/// What to scan and how to compare files, for `scan` and `deduplicate`
+/// Chainable setters customize it, e.g. `Options::new(&["/home"]).paranoid(true)`
+#[derive(Debug, Clone)]
+pub struct Options {
+    paths: Vec<PathBuf>,
+    skip_paths: Vec<OsString>,
+    skip_dir_names: Vec<OsString>,
+    skip_patterns: Vec<String>,
+    skip_mounts: Vec<OsString>,
+    hash: HashKind,
+    verify: bool,
+    rehash_on_mismatch: bool,
+    hash_size_limit: Option<u64>,
+    last_bytes: bool,
+    dedupe_empty: bool,
+    text_limit: Option<u64>,
+    min_copies: usize,
+    min_waste: u64,
+    same_owner: bool,
+    same_device: bool,
+    symlinked_only: bool,
+    dir_symlinks_only: bool,
+    dedupignore: bool,
+    stable: bool,
+    top: Option<usize>,
+    histogram: Option<usize>,
+    order: TraversalOrder,
+    export: Option<PathBuf>,
+    import: Option<PathBuf>,
+    max_errors: usize,
+    max_runtime: Option<Duration>,
+    sample: Option<(f64, u64)>,
+    on_error: OnError,
+    io_threads: usize,
+    hash_threads: usize,
+}
+
+impl Options {
+    /// Look for duplicates beneath each of `paths`
+    pub fn new<P: AsRef<Path>>(paths: &[P]) -> Self {
+        Options {
+            paths: paths.iter().map(|p| p.as_ref().to_path_buf()).collect(),
+            skip_paths: vec![],
+            skip_dir_names: vec![],
+            skip_patterns: vec![],
+            skip_mounts: vec![],
+            hash: HashKind::Md5,
+            verify: false,
+            rehash_on_mismatch: false,
+            hash_size_limit: None,
+            last_bytes: false,
+            dedupe_empty: false,
+            text_limit: None,
+            min_copies: 2,
+            min_waste: 0,
+            same_owner: false,
+            same_device: false,
+            symlinked_only: false,
+            dir_symlinks_only: false,
+            dedupignore: false,
+            stable: false,
+            top: None,
+            histogram: None,
+            order: TraversalOrder::DepthFirst,
+            export: None,
+            import: None,
+            max_errors: MAX_ERRORS,
+            max_runtime: None,
+            sample: None,
+            on_error: OnError::default(),
+            io_threads: 1,
+            hash_threads: 1,
+        }
+    }
+    /// Omit these files or folders
+    pub fn skip_paths<S: AsRef<OsStr>>(mut self, paths: &[S]) -> Self {
+        self.skip_paths = paths.iter().map(|p| p.as_ref().to_owned()).collect();
+        self
+    }
+    /// Omit directories with these names wherever they appear (e.g. `.git`)
+    pub fn skip_dir_names<S: AsRef<OsStr>>(mut self, names: &[S]) -> Self {
+        self.skip_dir_names = names.iter().map(|n| n.as_ref().to_owned()).collect();
+        self
+    }
+    /// Omit files whose paths match any of these regexes
+    pub fn skip_patterns<S: AsRef<str>>(mut self, patterns: &[S]) -> Self {
+        self.skip_patterns = patterns.iter().map(|p| p.as_ref().to_owned()).collect();
+        self
+    }
+    /// Omit other filesystems mounted beneath these paths (see `DirWalker::skip_mounts`)
+    pub fn skip_mounts<S: AsRef<OsStr>>(mut self, prefixes: &[S]) -> Self {
+        self.skip_mounts = prefixes.iter().map(|p| p.as_ref().to_owned()).collect();
+        self
+    }
+    /// Use SHA-3 to hash files instead of MD5
+    pub fn paranoid(mut self, on: bool) -> Self {
+        self.hash = if on { HashKind::Sha3 } else { HashKind::Md5 };
+        self
+    }
+    /// Hash files with `kind` (MD5 by default)
+    pub fn hash(mut self, kind: HashKind) -> Self {
+        self.hash = kind;
+        self
+    }
+    /// Compare files byte by byte before grouping them, rather than trusting their hashes
+    /// (by default only MD5 matches are checked, by their first bytes; see `Verify`)
+    pub fn verify(mut self, on: bool) -> Self {
+        self.verify = on;
+        self
+    }
+    /// See `FileCataloger::rehash_on_mismatch`
+    pub fn rehash_on_mismatch(mut self, on: bool) -> Self {
+        self.rehash_on_mismatch = on;
+        self
+    }
+    /// See `FileCataloger::hash_size_limit`
+    pub fn hash_size_limit(mut self, limit: u64) -> Self {
+        self.hash_size_limit = Some(limit);
+        self
+    }
+    /// See `FileCataloger::last_bytes`
+    pub fn last_bytes(mut self, on: bool) -> Self {
+        self.last_bytes = on;
+        self
+    }
+    /// See `FileCataloger::dedupe_empty`
+    pub fn dedupe_empty(mut self, on: bool) -> Self {
+        self.dedupe_empty = on;
+        self
+    }
+    /// Compare files of at most `limit` bytes as text, ignoring trailing whitespace and
+    /// CRLF line endings (see `TextNormalizedVfs`)
+    /// Files grouped this way may differ byte for byte, so they should only be reported:
+    /// acting on them would delete or link files that aren't copies of each other
+    pub fn text_normalize(mut self, limit: u64) -> Self {
+        self.text_limit = Some(limit);
+        self
+    }
+    /// Only report groups with at least `n` paths
+    pub fn min_copies(mut self, n: usize) -> Self {
+        self.min_copies = n;
+        self
+    }
+    /// Only report groups whose redundant copies waste at least `bytes`
+    pub fn min_waste(mut self, bytes: u64) -> Self {
+        self.min_waste = bytes;
+        self
+    }
+    /// Visit directories in this order, which decides the order of paths within each group
+    pub fn traversal_order(mut self, order: TraversalOrder) -> Self {
+        self.order = order;
+        self
+    }
+    /// Only group files with the same owner, so files of different users are never merged
+    pub fn same_owner(mut self, on: bool) -> Self {
+        self.same_owner = on;
+        self
+    }
+    /// Only group files on the same device, which are the only ones that can be hard linked
+    /// The files left alone on their device are listed by `Report::unlinkable`
+    pub fn same_device(mut self, on: bool) -> Self {
+        self.same_device = on;
+        self
+    }
+    /// Walk symlinks to files as files of their own, and only report groups containing one
+    /// (an audit of links whose contents duplicate other files)
+    pub fn symlinked_only(mut self, on: bool) -> Self {
+        self.symlinked_only = on;
+        self
+    }
+    /// See `DirWalker::dir_symlinks_only`
+    pub fn dir_symlinks_only(mut self, on: bool) -> Self {
+        self.dir_symlinks_only = on;
+        self
+    }
+    /// Only look at a share `rate` (from 0 to 1) of the files, picked by `seed`, for a
+    /// quick estimate on a huge tree; the groups reported are among the sampled files
+    /// (see `DirWalker::sample`)
+    pub fn sample(mut self, rate: f64, seed: u64) -> Self {
+        self.sample = Some((rate, seed));
+        self
+    }
+    /// Deal with files that can't be walked or read as `policy` says: leave them out
+    /// silently, with a warning (the default), or stop `scan` with an error at the first
+    pub fn on_error(mut self, policy: OnError) -> Self {
+        self.on_error = policy;
+        self
+    }
+    /// With `scan_threaded`, add up the sizes of the files found on `n` threads while the
+    /// walk goes on (see `DirWalker::io_threads`); by default it's done one at a time
+    /// Only that total is affected: files are still listed and cataloged on one thread
+    pub fn io_threads(mut self, n: usize) -> Self {
+        self.io_threads = n;
+        self
+    }
+    /// With `scan_threaded`, hash whole files on `n` threads while the others are sorted
+    /// (see `FileCataloger::hash_threads`); by default they're hashed one at a time
+    pub fn hash_threads(mut self, n: usize) -> Self {
+        self.hash_threads = n;
+        self
+    }
+    /// Skip whatever `.dedupignore` files list (see `DirWalker::ignore_files`)
+    pub fn dedupignore(mut self, on: bool) -> Self {
+        self.dedupignore = on;
+        self
+    }
+    /// Report groups, their paths, and collisions in sorted order, so scanning the same
+    /// files always gives identical results (instead of paths within a group following
+    /// the traversal order)
+    pub fn stable(mut self, on: bool) -> Self {
+        self.stable = on;
+        self
+    }
+    /// Only report the `k` groups with the largest files, largest first
+    pub fn top(mut self, k: usize) -> Self {
+        self.top = Some(k);
+        self
+    }
+    /// Also summarize file sizes, listing the `top` most common sizes
+    pub fn histogram(mut self, top: usize) -> Self {
+        self.histogram = Some(top);
+        self
+    }
+    /// Save the catalog to the file at `path` once every file is in it
+    /// (see `FileCataloger::save`), to be analyzed later with `import`
+    pub fn export<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.export = Some(path.as_ref().to_path_buf());
+        self
+    }
+    /// Keep at most `n` of the errors files run into in `Report::errors`; the rest are only
+    /// counted (see `FileCataloger::max_errors`)
+    pub fn max_errors(mut self, n: usize) -> Self {
+        self.max_errors = n;
+        self
+    }
+    /// Stop walking and reading files once `budget` has passed since `scan` started,
+    /// finishing what's in flight and reporting what was found by then
+    /// The walk gets at most half of it, so the files it turns up have time to be read
+    /// The results are partial (see `Report::partial`): files never reached can't be in them
+    pub fn max_runtime(mut self, budget: Duration) -> Self {
+        self.max_runtime = Some(budget);
+        self
+    }
+    /// Start from the catalog `export`ed to the file at `path` instead of walking and
+    /// reading the paths, trusting it without looking at the files again
+    /// (see `FileCataloger::import`)
+    pub fn import<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.import = Some(path.as_ref().to_path_buf());
+        self
+    }
+}
+
+/// Everything `scan` found
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Number of files the walk turned up
+    pub files: usize,
+    /// Whether the scan ran out of time before covering every file
+    /// (see `Options::max_runtime`), so some duplicates may be missing
+    pub partial: bool,
+    /// Number of those that couldn't be cataloged
+    pub skipped: usize,
+    /// Why they couldn't be, for up to `Options::max_errors` of them
+    pub errors: Vec<CatalogError>,
+    /// How many couldn't be for each kind of problem
+    pub failures: ErrorTally,
+    /// Groups of identical files
+    pub repeats: Vec<Duplicates>,
+    /// The space keeping only one file of each group in `repeats` would free
+    pub reclaimable: Savings,
+    /// Number of groups left out for having too few copies or wasting too little space
+    /// (see `Options::min_copies` and `Options::min_waste`)
+    pub omitted: usize,
+    /// The space the omitted groups' redundant copies waste
+    pub omitted_bytes: u64,
+    /// Sizes shared by files with different contents
+    pub collisions: Vec<Collision>,
+    /// Groups of files matched by size alone (see `Options::hash_size_limit`)
+    pub size_only: Vec<Duplicates>,
+    /// Empty files, which aren't reported as duplicates unless `Options::dedupe_empty`
+    pub empty: Vec<PathBuf>,
+    /// Files with duplicates only on other devices (see `Options::same_device`)
+    pub unlinkable: Vec<PathBuf>,
+    /// The device each path in `repeats` and `unlinkable` is on
+    pub devices: HashMap<PathBuf, u64>,
+    /// Files lying in a skipped folder, found through a symlink from outside it
+    /// They shouldn't be kept in place of their duplicates (see `selector::AvoidSelect`)
+    pub excluded: Vec<PathBuf>,
+    /// What the walk covered (nothing if the catalog was imported)
+    pub walk: WalkStats,
+    /// How far the files got along the lazy comparison path
+    pub stats: CatalogStats,
+    /// How the files are distributed by size (if `Options::histogram` was set)
+    pub histogram: Option<SizeHistogram>,
+}
+
+impl Report {
+    /// How much was scanned and how much of it is duplicated, in a few numbers
+    pub fn summary(&self) -> ScanSummary {
+        ScanSummary::new(&self.walk, &self.stats, self)
+    }
+}
+
+// the worker threads a scan starts, which only filesystems that can be sent to them allow
+trait Workers<T: VFS> {
+    // set up `walker` to look up files on them
+    fn walker(&self, walker: DirWalker<T>) -> DirWalker<T>;
+    // set up `fc` to hash files on them
+    fn cataloger<H: FileHash>(&self, fc: FileCataloger<T, H>) -> FileCataloger<T, H>
+    where
+        H::Output: Send + 'static;
+}
+
+// no worker threads: everything happens on the calling thread
+struct Serial;
+
+impl<T: VFS> Workers<T> for Serial {
+    fn walker(&self, walker: DirWalker<T>) -> DirWalker<T> {
+        walker
+    }
+    fn cataloger<H: FileHash>(&self, fc: FileCataloger<T, H>) -> FileCataloger<T, H>
+    where
+        H::Output: Send + 'static,
+    {
+        fc
+    }
+}
+
+// as many worker threads as `Options::io_threads` and `Options::hash_threads` ask for
+struct Threads {
+    io: usize,
+    hash: usize,
+}
+
+impl<T: VFS + Send + 'static> Workers<T> for Threads {
+    fn walker(&self, walker: DirWalker<T>) -> DirWalker<T> {
+        walker.io_threads(self.io)
+    }
+    fn cataloger<H: FileHash>(&self, fc: FileCataloger<T, H>) -> FileCataloger<T, H>
+    where
+        H::Output: Send + 'static,
+    {
+        fc.hash_threads(self.hash)
+    }
+}
+
+/// Walk `opts`'s paths on `vfs` and catalog every file found
+/// Fails if one of the paths doesn't exist or the catalog can't be imported or exported;
+/// problems with individual files are counted in `Report::failures`
+pub fn scan<V>(opts: &Options, vfs: V) -> io::Result<Report>
+where
+    V: VFS,
+    V::FileIter: 'static,
+{
+    scan_with(opts, vfs, &Serial)
+}
+
+/// The same as `scan`, but looking up and hashing files on as many threads as
+/// `Options::io_threads` and `Options::hash_threads` ask for, each sized on its own
+/// The filesystem is shared with those threads (e.g. `RealFileSystem` or a `Box<dyn SendVfs>`)
+pub fn scan_threaded<V>(opts: &Options, vfs: V) -> io::Result<Report>
+where
+    V: VFS + Send + 'static,
+    V::FileIter: 'static,
+{
+    let threads = Threads {
+        io: opts.io_threads,
+        hash: opts.hash_threads,
+    };
+    scan_with(opts, vfs, &threads)
+}
+
+// walk and catalog, starting the worker threads `workers` allows
+fn scan_with<V, W>(opts: &Options, vfs: V, workers: &W) -> io::Result<Report>
+where
+    V: VFS,
+    V::FileIter: 'static,
+    W: Workers<V> + Workers<TextNormalizedVfs<V>>,
+{
+    let start = Instant::now();
+    let deadline = opts.max_runtime.map(|budget| start + budget);
+    if opts.import.is_some() {
+        // everything there is to know is in the imported catalog
+        return catalog_with_hash(opts, vfs, &[], deadline, workers);
+    }
+    for path in &opts.paths {
+        vfs.get_metadata(path)?;
+    }
+    let walker = DirWalker::new(vfs.clone(), &opts.paths)
+        .blacklist_folders(opts.skip_paths.iter().map(OsString::as_os_str).collect())
+        .blacklist_dir_names(opts.skip_dir_names.iter().map(OsString::as_os_str).collect())
+        .blacklist_patterns(opts.skip_patterns.iter().map(String::as_str).collect())
+        .skip_mounts(opts.skip_mounts.iter().map(OsString::as_os_str).collect())
+        .traversal_order(opts.order)
+        .keep_symlinks(opts.symlinked_only)
+        .dir_symlinks_only(opts.dir_symlinks_only)
+        .ignore_files(opts.dedupignore)
+        .on_error(opts.on_error);
+    let mut walker = workers.walker(walker);
+    let walk_deadline = opts.max_runtime.map(|budget| start + budget / 2);
+    if let Some(at) = walk_deadline {
+        walker = walker.deadline(at);
+    }
+    if let Some((rate, seed)) = opts.sample {
+        walker = walker.sample(rate, seed);
+    }
+    let (files, excluded, walk) = walker.traverse_tagged()?;
+    let walked_all = !out_of_time(walk_deadline);
+    let mut report = match opts.text_limit {
+        Some(limit) => {
+            let vfs = TextNormalizedVfs::new(vfs, limit);
+            catalog_with_hash(opts, vfs, &files, deadline, workers)
+        }
+        None => catalog_with_hash(opts, vfs, &files, deadline, workers),
+    }?;
+    report.partial |= !walked_all;
+    report.walk = walk;
+    report.excluded = excluded.into_iter().collect();
+    report.excluded.sort();
+    Ok(report)
+}
+
+// hash with the algorithm the user asked for
+fn catalog_with_hash<V: VFS, W: Workers<V>>(
+    opts: &Options,
+    vfs: V,
+    files: &[PathBuf],
+    deadline: Option<Instant>,
+    workers: &W,
+) -> io::Result<Report> {
+    info!("Using {}", opts.hash);
+    opts.hash.select_while(|| catalog::<V, HashKind, W>(opts, vfs, files, deadline, workers))
+}
+
+// whether `deadline` has passed
+fn out_of_time(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|at| Instant::now() >= at)
+}
+
+// duplicates are identified as files are inserted one at a time
+fn catalog<V, H, W>(
+    opts: &Options,
+    vfs: V,
+    files: &[PathBuf],
+    deadline: Option<Instant>,
+    workers: &W,
+) -> io::Result<Report>
+where
+    V: VFS,
+    H: FileHash,
+    H::Output: Send + 'static,
+    W: Workers<V>,
+{
+    let fc: FileCataloger<V, H> = match opts.import {
+        Some(ref path) => FileCataloger::import(vfs.clone(), fs::File::open(path)?)?,
+        None => FileCataloger::new(vfs.clone()),
+    };
+    let imported = fc.num_inserted();
+    let mut fc = fc
+        .rehash_on_mismatch(opts.rehash_on_mismatch)
+        .last_bytes(opts.last_bytes)
+        .dedupe_empty(opts.dedupe_empty)
+        .keep_symlinks(opts.symlinked_only)
+        .max_errors(opts.max_errors)
+        .on_error(opts.on_error);
+    if let Some(limit) = opts.hash_size_limit {
+        fc = fc.hash_size_limit(limit);
+    }
+    if opts.verify {
+        fc = fc.verify(Verify::Full);
+    }
+    let mut fc = workers.cataloger(fc);
+    // a file can vanish between the walk and now: leave it out and carry on
+    // once out of time, the files already being hashed are finished and the rest left out
+    let cancel = AtomicBool::new(out_of_time(deadline));
+    let check_time = |_: &_| if out_of_time(deadline) {
+        cancel.store(true, Ordering::Relaxed);
+    };
+    let mut inserted = fc.insert_all_with(files, check_time, &cancel);
+    if inserted.aborted {
+        return Err(inserted.errors.pop().expect("aborted without an error").into());
+    }
+    if inserted.cancelled {
+        warn!("Out of time; only {} of {} files were cataloged", inserted.inserted, files.len());
+    }
+    if let Some(ref path) = opts.export {
+        fc.save(io::BufWriter::new(fs::File::create(path)?))?;
+    }
+    // everything else asked of the catalog, before its groups are moved out of it
+    let mut collisions = fc.get_collisions();
+    let size_only = fc.get_size_only();
+    let empty = fc.get_empty();
+    let stats = fc.stats();
+    let histogram = opts.histogram.map(|top| fc.get_histogram(top));
+    let mut repeats = fc.into_repeats();
+    if opts.same_owner {
+        repeats = split_by_owner(&vfs, repeats);
+    }
+    let mut unlinkable = vec![];
+    if opts.same_device {
+        let (split, alone) = split_by_device(&vfs, repeats);
+        repeats = split;
+        unlinkable = alone;
+        unlinkable.sort();
+    }
+    if opts.symlinked_only {
+        repeats = filter_symlinked(&vfs, repeats);
+    }
+    // groups too small to care about are only counted
+    let found = repeats.len();
+    let found_bytes: u64 = repeats.iter().map(Duplicates::redundant_bytes).sum();
+    let mut repeats = filter_min_waste(filter_min_copies(repeats, opts.min_copies), opts.min_waste);
+    let omitted = found - repeats.len();
+    let omitted_bytes = found_bytes - repeats.iter().map(Duplicates::redundant_bytes).sum::<u64>();
+    if opts.stable {
+        repeats = sort_groups(repeats);
+        for c in &mut collisions {
+            c.paths.sort();
+        }
+    }
+    if let Some(k) = opts.top {
+        repeats = largest_first(repeats, k);
+    }
+    let reclaimable = repeats.iter().fold(Savings::default(), |mut total, dups| {
+        total += reclaimable(&vfs, dups);
+        total
+    });
+    // as cataloged, or for paths no longer in a group, as they are now
+    let devices = repeats
+        .iter()
+        .flat_map(|dups| {
+            dups.iter()
+                .filter_map(move |path| dups.stamp_of(path).map(|s| (path.to_path_buf(), s.id.dev)))
+        })
+        .chain(unlinkable.iter().filter_map(|path| {
+            let md = vfs.get_metadata(path).ok()?;
+            ID::of(&md).ok().map(|id| (path.clone(), id.dev))
+        }))
+        .collect();
+    Ok(Report {
+        files: imported + files.len(),
+        partial: inserted.cancelled,
+        skipped: inserted.skipped(),
+        errors: inserted.errors,
+        failures: inserted.failures,
+        repeats,
+        reclaimable,
+        omitted,
+        omitted_bytes,
+        collisions,
+        size_only,
+        empty,
+        unlinkable,
+        devices,
+        excluded: vec![],
+        walk: WalkStats::default(),
+        stats,
+        histogram,
+    })
+}
+
+// the space keeping only one of `dups`'s files would free, going by the first one's size
+// on disk (or its length, if it can't be looked up)
+fn reclaimable<V: VFS>(vfs: &V, dups: &Duplicates) -> Savings {
+    let one = match dups.iter().next().map(|path| vfs.get_metadata(path)) {
+        Some(Ok(md)) => Savings::of(&md),
+        _ => Savings {
+            logical: dups.size().unwrap_or(0),
+            allocated: dups.size().unwrap_or(0),
+        },
+    };
+    let copies = dups.distinct_files().saturating_sub(1) as u64;
+    Savings {
+        logical: one.logical * copies,
+        allocated: one.allocated * copies,
+    }
+}
+
+/// Find every group of identical files beneath `opts`'s paths on `vfs`
+pub fn deduplicate<V>(opts: Options, vfs: V) -> io::Result<Vec<Duplicates>>
+where
+    V: VFS,
+    V::FileIter: 'static,
+{
+    scan(&opts, vfs).map(|report| report.repeats)
+}
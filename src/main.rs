@@ -1,40 +1,74 @@
-//! Identify and handle duplicate files in a fast and extensible way
+//! Command line interface for finding and handling duplicate files
 
 extern crate clap;
 extern crate env_logger;
 #[macro_use]
 extern crate log;
-extern crate md5;
-extern crate regex;
-extern crate tiny_keccak;
+extern crate smllr;
 
 // import from external libraries
 use clap::{App, Arg};
 
 // import from standard library
+use std::{env, fs, io, process, thread};
+use std::io::Write;
 use std::path::Path;
+use std::time::Duration;
 use std::ffi::OsStr;
 
-// import from our own modules
+// import from our own library
+use smllr::{json, OnError, Options, RealFileSystem, SendVfs};
+use smllr::vfs::{DeviceId, ReadOnlyVfs, RetryPolicy, RetryVfs};
+use smllr::actor::{FileActor, FileDeleter, FileLinker, FilePrinter, FileReflinker, Savings};
+use smllr::actor::tree::{self, Action};
+use smllr::walker::TraversalOrder;
+use smllr::catalog::most_wasteful_first;
+use smllr::actor::selector::{AvoidSelect, ChainSelect, DateSelect, DeviceSelect, PathSelect,
+                             PrefixSelect, Selector};
 
-mod helpers;
-use helpers::prettify_bytes;
+// the filesystem everything runs on, picked at startup (and shared with worker threads)
+type Fs = Box<dyn SendVfs>;
 
-pub mod walker;
-use walker::DirWalker;
+/// Build the selector described by a single `--keep` strategy
+fn parse_keep(s: &str, fs: Fs) -> Result<Box<dyn Selector<Fs>>, String> {
+    let mut selector: Box<dyn Selector<Fs>> = match s {
+        "path-len" | "deepest" => Box::new(PathSelect::new(fs)),
+        "newest-file" | "oldest-file" => Box::new(DateSelect::new(fs)),
+        _ if s.starts_with("under:") => {
+            // paths from the walker are canonical, so the prefix must be too
+            let dir = env::current_dir()
+                .map_err(|e| e.to_string())?
+                .join(&s["under:".len()..]);
+            let dir = fs.canonicalize(&dir).map_err(|e| e.to_string())?;
+            Box::new(PrefixSelect::new(fs, dir))
+        }
+        _ => return Err(format!("Unknown strategy `{}`", s)),
+    };
+    if s == "deepest" || s == "oldest-file" {
+        selector.reverse();
+    }
+    Ok(selector)
+}
 
-pub mod vfs;
-pub use vfs::{RealFileSystem, TestFileSystem};
+// write a line of progress or results to `$out`
+// (stdout, unless `--print0` needs it for the list of duplicates)
+macro_rules! status {
+    ($out:expr) => {
+        writeln!($out).expect("Failed to write status")
+    };
+    ($out:expr, $($arg:tt)*) => {
+        writeln!($out, $($arg)*).expect("Failed to write status")
+    };
+}
 
-pub mod catalog;
-use catalog::FileCataloger;
+/// The number of largest size buckets listed by `--histogram`
+const HISTOGRAM_TOP: usize = 10;
 
-pub mod actor;
-use actor::{FileActor, FileDeleter, FileLinker, FilePrinter};
-use actor::selector::{DateSelect, PathSelect, Selector};
+/// How long `--io-retries` waits before the first retry unless told otherwise
+const IO_RETRY_DELAY_MS: u64 = 100;
 
-pub mod hash;
-use hash::{Md5Sum, Sha3Sum};
+/// How many threads `--io-threads` adds up file sizes on unless told otherwise
+const IO_THREADS: usize = 4;
 
 fn main() {
     // build arg parser
@@ -44,7 +78,7 @@ fn main() {
              .help("List of files or directories to deduplicate")
              .multiple(true)
              .takes_value(true)
-             .required(true)
+             .required_unless_one(&["json-schema", "import"])
              )
         // paths to skip (`--skip /tmp --skip /usr`)
         .arg(Arg::with_name("bad_paths")
@@ -54,6 +88,22 @@ fn main() {
              .multiple(true)
              .takes_value(true)
              )
+        // directory names to skip anywhere (`--skip-dir-name .git`)
+        .arg(Arg::with_name("bad_dir_names")
+             .long("skip-dir-name")
+             .help("A directory name to omit wherever it appears (e.g. `node_modules`)")
+             .multiple(true)
+             .number_of_values(1)
+             .takes_value(true)
+             )
+        // mounts to skip (`--skip-mount /net`)
+        .arg(Arg::with_name("skip_mounts")
+             .long("skip-mount")
+             .help("Skip other filesystems mounted at or beneath this path (e.g. network shares)")
+             .multiple(true)
+             .number_of_values(1)
+             .takes_value(true)
+             )
         // regex to skip / include
         .arg(Arg::with_name("bad_regex")
              .short("o")
@@ -62,13 +112,184 @@ fn main() {
              .multiple(true)
              .takes_value(true)
              )
+        // traversal order
+        .arg(Arg::with_name("depth-first")
+             .long("depth-first")
+             .conflicts_with("breadth-first")
+             .help("Finish each directory before moving on to the next (default)")
+             )
+        .arg(Arg::with_name("breadth-first")
+             .long("breadth-first")
+             .help("Visit shallower files before deeper ones")
+             )
         // paranoid flag
         .arg(Arg::with_name("paranoid")
              .short("p")
              .long("paranoid")
-             .help("Use SHA-3 to hash files instead of MD5")
+             .help("Use SHA-3 to hash files instead of MD5 (same as --hash sha3)")
+             )
+        .arg(Arg::with_name("hash")
+             .long("hash")
+             .takes_value(true)
+             .possible_values(&["md5", "sha3"])
+             .conflicts_with("paranoid")
+             .help("Algorithm to hash files with (default md5)")
+             )
+        .arg(Arg::with_name("verify")
+             .long("verify")
+             .help("Compare files byte by byte before calling them duplicates, in case their \
+                    hashes collide (by default only the first bytes of MD5 matches are)")
+             )
+        // print size statistics
+        .arg(Arg::with_name("histogram")
+             .long("histogram")
+             .help("Print how the scanned files are distributed by size")
+             )
+        // guard against files changing mid-scan
+        .arg(Arg::with_name("rehash-on-mismatch")
+             .long("rehash-on-mismatch")
+             .help("Skip files whose size or mtime changes while they are being compared")
+             )
+        // cope with flaky (e.g. network) filesystems
+        .arg(Arg::with_name("io-retries")
+             .long("io-retries")
+             .takes_value(true)
+             .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+             .help("Retry reads that time out or are interrupted this many times (default 0)")
+             )
+        .arg(Arg::with_name("io-retry-delay")
+             .long("io-retry-delay")
+             .takes_value(true)
+             .requires("io-retries")
+             .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+             .help("Milliseconds to wait before the first retry, doubling each time (default 100)")
+             )
+        .arg(Arg::with_name("io-threads")
+             .long("io-threads")
+             .takes_value(true)
+             .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+             .help("Add up the sizes of the files found on this many threads while walking \
+                    (default 4; 1 for one at a time); only the total reported is sped up")
+             )
+        .arg(Arg::with_name("hash-threads")
+             .long("hash-threads")
+             .takes_value(true)
+             .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+             .help("Hash this many files at once (default the number of CPUs; 1 for one at \
+                    a time)")
+             )
+        // don't read huge files
+        .arg(Arg::with_name("hash-size-limit")
+             .long("hash-size-limit")
+             .takes_value(true)
+             .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+             .help("Never read files larger than this many bytes; \
+                    list same-size ones as probable duplicates instead")
+             )
+        // which parts of same-size files to compare before hashing them whole
+        .arg(Arg::with_name("prefilter")
+             .long("prefilter")
+             .takes_value(true)
+             .possible_values(&["first", "both"])
+             .help("Compare the first bytes of same-size files before hashing them (default), \
+                    or both their first and last bytes")
+             )
+        .arg(Arg::with_name("dedupe-empty")
+             .long("dedupe-empty")
+             .help("Treat empty files as duplicates of each other (by default they're only listed)")
+             )
+        // files found this way may differ byte for byte, so they're only ever reported
+        .arg(Arg::with_name("text-normalize")
+             .long("text-normalize")
+             .takes_value(true)
+             .conflicts_with("delete")
+             .conflicts_with("link")
+             .conflicts_with("reflink")
+             .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+             .help("Compare files up to this many bytes as text, ignoring trailing whitespace \
+                    and CRLF line endings (files found this way may differ byte for byte, \
+                    so they can only be printed)")
+             )
+        // print near-misses
+        .arg(Arg::with_name("report-collisions")
+             .long("report-collisions")
+             .help("Also list files that share a size but have different contents")
+             )
+        // filter results
+        .arg(Arg::with_name("same-owner")
+             .long("same-owner")
+             .alias("no-cross-user")
+             .help("Only treat files with the same owner as duplicates of each other")
+             )
+        .arg(Arg::with_name("symlinked-only")
+             .long("symlinked-only")
+             .conflicts_with("delete")
+             .conflicts_with("link")
+             .conflicts_with("reflink")
+             .help("Audit symlinks: only report duplicates where a symlink matches another file")
+             )
+        .arg(Arg::with_name("follow-dir-symlinks")
+             .long("follow-dir-symlinks")
+             .conflicts_with("symlinked-only")
+             .help("Follow symlinks to directories, but skip symlinks to files")
+             )
+        .arg(Arg::with_name("top")
+             .long("top")
+             .takes_value(true)
+             .conflicts_with("delete")
+             .conflicts_with("link")
+             .conflicts_with("reflink")
+             .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+             .help("Only print the K groups of duplicates with the largest files")
+             )
+        .arg(Arg::with_name("stable")
+             .long("stable")
+             .alias("stable-output")
+             .help("Sort the output so the same files always give identical results")
+             )
+        .arg(Arg::with_name("quiet-no-duplicates")
+             .long("quiet-no-duplicates")
+             .help("Print nothing at all unless duplicates are found (e.g. for cron jobs)")
+             )
+        .arg(Arg::with_name("dedupignore")
+             .long("dedupignore")
+             .help("Skip paths matching the globs in each directory's .dedupignore file")
+             )
+        // a rough idea of a tree far too big to scan whole
+        .arg(Arg::with_name("sample-rate")
+             .long("sample-rate")
+             .takes_value(true)
+             .validator(|s| match s.parse::<f64>() {
+                 Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(()),
+                 Ok(_) => Err(String::from("must be between 0.0 and 1.0")),
+                 Err(e) => Err(e.to_string()),
+             })
+             .conflicts_with("import")
+             .help("Only look at this share of the files (0.0 to 1.0), for a quick estimate; \
+                    the same files are picked on every run with the same --seed")
+             )
+        .arg(Arg::with_name("seed")
+             .long("seed")
+             .takes_value(true)
+             .requires("sample-rate")
+             .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+             .help("Pick a different sample for --sample-rate (0 by default)")
+             )
+        .arg(Arg::with_name("min-copies")
+             .long("min-copies")
+             .alias("min-group-size")
+             .alias("min-group")
+             .takes_value(true)
+             .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+             .help("Only report duplicates with at least this many copies")
              )
         // determine selector
+        .arg(Arg::with_name("min-waste")
+             .long("min-waste")
+             .takes_value(true)
+             .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+             .help("Only report duplicates whose extra copies waste at least this many bytes")
+             )
         .arg(Arg::with_name("path-len")
              .long("path-len")
              .conflicts_with("newest-file")
@@ -78,6 +299,35 @@ fn main() {
              .long("newest-file")
              .help("Preserve the file that was modified most recently")
              )
+        .arg(Arg::with_name("keep")
+             .long("keep")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .conflicts_with("path-len")
+             .conflicts_with("newest-file")
+             .validator(|s| parse_keep(&s, Box::new(RealFileSystem)).map(|_| ()))
+             .help("Strategy for choosing the file to preserve; repeat to break ties in order \
+                    (path-len, deepest, newest-file, oldest-file, or under:DIR)")
+             )
+        .arg(Arg::with_name("prefer-device")
+             .long("prefer-device")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+             .help("Preserve the file on this device (e.g. an SSD) before anything else; \
+                    repeat to rank devices in order of preference")
+             )
+        .arg(Arg::with_name("keep-copies")
+             .long("keep-copies")
+             .takes_value(true)
+             .validator(|s| match s.parse::<usize>() {
+                 Ok(n) if n >= 1 => Ok(()),
+                 _ => Err(String::from("must be a number of at least 1")),
+             })
+             .help("Number of files to preserve in each set of duplicates (default 1)")
+             )
         .arg(Arg::with_name("invert-selector")
              .long("invert-selector")
              .help("Invert the selector criterion (e.g. preserve the deepest file)")
@@ -87,24 +337,139 @@ fn main() {
              .long("print")
              .conflicts_with("delete")
              .conflicts_with("link")
+             .conflicts_with("reflink")
              .help("Print duplicate files (default)")
              )
         .arg(Arg::with_name("delete")
              .conflicts_with("link")
+             .conflicts_with("reflink")
              .long("delete")
              .help("Delete duplicate files")
              )
         .arg(Arg::with_name("link")
              .long("link")
+             .conflicts_with("reflink")
              .help("Replace duplicate files with hard links")
              )
+        .arg(Arg::with_name("reflink")
+             .long("reflink")
+             .help("Make duplicate files copy-on-write clones of the kept one, sharing its \
+                    storage but keeping their own metadata (Btrfs, XFS, APFS)")
+             )
+        .arg(Arg::with_name("ignore-permissions-diff")
+             .long("ignore-permissions-diff")
+             .requires("link")
+             .help("Also link duplicates whose permissions differ from the kept file's \
+                    (they take on its permissions); otherwise they're skipped with a warning")
+             )
+        .arg(Arg::with_name("no-revalidate")
+             .long("no-revalidate")
+             .help("With --delete, --link or --reflink, don't look files up again before \
+                    acting on them to leave out those that changed since they were scanned")
+             )
+        .arg(Arg::with_name("print0")
+             .long("print0")
+             .conflicts_with("delete")
+             .conflicts_with("link")
+             .conflicts_with("reflink")
+             .help("Print only the duplicates, each followed by a NUL byte (for `xargs -0`)")
+             )
+        .arg(Arg::with_name("show-hash")
+             .long("show-hash")
+             .conflicts_with("print0")
+             .conflicts_with("delete")
+             .conflicts_with("link")
+             .conflicts_with("reflink")
+             .help("Start each group with the hex hash of its contents (see --hash)")
+             )
+        .arg(Arg::with_name("verbose")
+             .long("verbose")
+             .short("v")
+             .conflicts_with("print0")
+             .help("Note next to each printed file how many hard links it already has, \
+                    and list every file that couldn't be read")
+             )
+        // where to print results
+        .arg(Arg::with_name("json")
+             .long("json")
+             .conflicts_with("delete")
+             .conflicts_with("link")
+             .conflicts_with("reflink")
+             .conflicts_with("print0")
+             .help("Write the duplicates as versioned JSON instead (see --json-schema)")
+             )
+        .arg(Arg::with_name("preview-tree")
+             .long("preview-tree")
+             .alias("tree")
+             .conflicts_with("json")
+             .conflicts_with("print0")
+             .help("Draw the files that would be affected as a tree, marking each K (kept), \
+                    D (deleted), or L (linked, with --link), without acting on any")
+             )
+        .arg(Arg::with_name("json-schema")
+             .long("json-schema")
+             .help("Print the JSON Schema that --json output follows, and exit")
+             )
+        .arg(Arg::with_name("relative-to")
+             .long("relative-to")
+             .takes_value(true)
+             .conflicts_with("json")
+             .help("Print paths beneath this directory relative to it (others in full)")
+             )
+        .arg(Arg::with_name("output")
+             .long("output")
+             .takes_value(true)
+             .help("Write the list of duplicates to a file instead of stdout")
+             )
+        // analyze a scan later without reading the files again
+        .arg(Arg::with_name("export")
+             .long("export")
+             .takes_value(true)
+             .help("Save the catalog of the scanned files to a file, for --import")
+             )
+        .arg(Arg::with_name("max-errors")
+             .long("max-errors")
+             .takes_value(true)
+             .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+             .help("List at most this many of the files that couldn't be read with -v \
+                    (the rest are only counted)")
+             )
+        .arg(Arg::with_name("on-error")
+             .long("on-error")
+             .takes_value(true)
+             .possible_values(&["skip", "warn", "abort"])
+             .help("What to do about files or directories that can't be read, deleted or \
+                    linked: leave them out quietly, leave them out with a warning (default), \
+                    or stop at the first one and exit with an error")
+             )
+        // give up on huge trees rather than run for hours
+        .arg(Arg::with_name("max-runtime")
+             .long("max-runtime")
+             .takes_value(true)
+             .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+             .help("Stop scanning after this many seconds and report what was found by then; \
+                    the results are partial, as files never reached can't be in them")
+             )
+        .arg(Arg::with_name("import")
+             .long("import")
+             .takes_value(true)
+             .conflicts_with("paths")
+             .help("Report (or act on) the duplicates in a catalog saved with --export \
+                    instead of scanning; the files are only touched to act on them")
+             )
         .get_matches();
 
+    if matches.is_present("json-schema") {
+        print!("{}", json::SCHEMA);
+        return;
+    }
+
     // decide which files are fair game
+    // (none if the files come from an imported catalog)
     let dirs: Vec<&OsStr> = matches
         .values_of_os("paths")
-        .expect("Failed to get `paths` from the command line arguments")
-        .collect();
+        .map(|paths| paths.collect())
+        .unwrap_or_default();
     // if the user supplied blacklisted paths, collect them
     let dirs_n: Vec<&OsStr> = if matches.is_present("bad_paths") {
         matches
@@ -114,6 +479,24 @@ fn main() {
     } else {
         vec![]
     };
+    // if the user supplied blacklisted directory names, collect them
+    let names_n: Vec<&OsStr> = if matches.is_present("bad_dir_names") {
+        matches
+            .values_of_os("bad_dir_names")
+            .expect("Failed to get `bad_dir_names`")
+            .collect()
+    } else {
+        vec![]
+    };
+    // if the user supplied mount prefixes to skip, collect them
+    let mounts_n: Vec<&OsStr> = if matches.is_present("skip_mounts") {
+        matches
+            .values_of_os("skip_mounts")
+            .expect("Failed to get `skip_mounts`")
+            .collect()
+    } else {
+        vec![]
+    };
     // if the user supplied blacklisted file regexes, collect them
     let pats_n: Vec<_> = if matches.is_present("bad_regex") {
         matches
@@ -129,30 +512,182 @@ fn main() {
     // e.g. `RUST_LOG=debug ./smllr ... 2> /tmp/smllr_log`
     env_logger::init().expect("Failed to initialize logging");
 
-    // create and customize a DirWalker over the real filesystem
-    // collect all relevant files
-    let fs = RealFileSystem;
-    let paths: Vec<&Path> = dirs.iter().map(Path::new).collect();
-    let dw = DirWalker::new(fs, &paths)
-        .blacklist_folders(dirs_n)
-        .blacklist_patterns(pats_n);
-    let files = dw.traverse_all();
-    println!("Traversing {} files...", files.len());
-
-    // catalog all files from the DirWalker
-    // duplicates are identified as files are inserted one at a time
-    // can't combine code because Sha3Sum and Md5Sum might be different sizes
-    let repeats = if matches.is_present("paranoid") {
-        info!("Using SHA-3");
-        let mut fc: FileCataloger<_, Sha3Sum> = FileCataloger::new(fs);
-        files.iter().for_each(|f| fc.insert(f));
-        fc.get_repeats()
+    // with `--print0`, stdout is only the list of duplicates; everything else goes to stderr
+    let print0 = matches.is_present("print0");
+    let verbose = matches.is_present("verbose");
+    let relative_to = matches.value_of_os("relative-to");
+    let show_hash = matches.is_present("show-hash");
+    let mut status: Box<dyn Write> = if print0 {
+        Box::new(io::stderr())
     } else {
-        info!("Using MD5");
-        let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(fs);
-        files.iter().for_each(|f| fc.insert(f));
-        fc.get_repeats()
+        Box::new(io::stdout())
+    };
+
+    // open the output file (if any) before doing any work so a bad path fails fast
+    let output: Option<Box<dyn Write>> = matches.value_of_os("output").map(|path| {
+        let file = fs::File::create(path).expect("Failed to create output file");
+        Box::new(io::BufWriter::new(file)) as Box<dyn Write>
+    });
+
+    // describe the scan: which files are fair game and how to compare them
+    // chosen at runtime, so everything below works with whichever filesystem it is given
+    // printing must never change anything, so make sure it can't
+    let retries = matches
+        .value_of("io-retries")
+        .map(|n| n.parse().expect("Invalid `--io-retries`"))
+        .unwrap_or(0);
+    let delay = matches
+        .value_of("io-retry-delay")
+        .map(|ms| ms.parse().expect("Invalid `--io-retry-delay`"))
+        .unwrap_or(IO_RETRY_DELAY_MS);
+    let policy = RetryPolicy::new(retries, Duration::from_millis(delay));
+    let real = RetryVfs::new(RealFileSystem, policy);
+    let modifies = ["link", "delete", "reflink"].iter().any(|a| matches.is_present(a));
+    let fs: Fs = if modifies {
+        Box::new(real)
+    } else {
+        Box::new(ReadOnlyVfs::new(real))
+    };
+    let paths: Vec<&Path> = dirs.iter().map(Path::new).collect();
+    let mut opts = Options::new(&paths)
+        .skip_paths(&dirs_n)
+        .skip_dir_names(&names_n)
+        .skip_mounts(&mounts_n)
+        .skip_patterns(&pats_n)
+        .paranoid(matches.is_present("paranoid"))
+        .verify(matches.is_present("verify"))
+        .rehash_on_mismatch(matches.is_present("rehash-on-mismatch"))
+        .same_owner(matches.is_present("same-owner"))
+        // files can only be linked to (or cloned from) others on the same device
+        .same_device(matches.is_present("link") || matches.is_present("reflink"))
+        .symlinked_only(matches.is_present("symlinked-only"))
+        .dir_symlinks_only(matches.is_present("follow-dir-symlinks"))
+        .dedupignore(matches.is_present("dedupignore"))
+        .stable(matches.is_present("stable"))
+        .last_bytes(matches.value_of("prefilter") == Some("both"))
+        .dedupe_empty(matches.is_present("dedupe-empty"));
+    if matches.is_present("breadth-first") {
+        opts = opts.traversal_order(TraversalOrder::BreadthFirst);
+    }
+    if let Some(path) = matches.value_of_os("export") {
+        opts = opts.export(path);
+    }
+    if let Some(path) = matches.value_of_os("import") {
+        opts = opts.import(path);
+    }
+    if let Some(n) = matches.value_of("max-errors") {
+        opts = opts.max_errors(n.parse().expect("Invalid `--max-errors`"));
+    }
+    // walking waits on the disk, hashing on the CPU, so each gets its own threads
+    let io_threads = matches
+        .value_of("io-threads")
+        .map(|n| n.parse().expect("Invalid `--io-threads`"))
+        .unwrap_or(IO_THREADS);
+    let hash_threads = matches
+        .value_of("hash-threads")
+        .map(|n| n.parse().expect("Invalid `--hash-threads`"))
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    opts = opts.io_threads(io_threads).hash_threads(hash_threads);
+    let on_error: OnError = match matches.value_of("on-error") {
+        Some(policy) => policy.parse().expect("Invalid `--on-error`"),
+        None => OnError::default(),
     };
+    opts = opts.on_error(on_error);
+    if let Some(rate) = matches.value_of("sample-rate") {
+        let rate = rate.parse().expect("Invalid `--sample-rate`");
+        let seed = match matches.value_of("seed") {
+            Some(seed) => seed.parse().expect("Invalid `--seed`"),
+            None => 0,
+        };
+        opts = opts.sample(rate, seed);
+    }
+    if let Some(secs) = matches.value_of("max-runtime") {
+        let secs = secs.parse().expect("Invalid `--max-runtime`");
+        opts = opts.max_runtime(Duration::from_secs(secs));
+    }
+    if let Some(kind) = matches.value_of("hash") {
+        opts = opts.hash(kind.parse().expect("Invalid `--hash`"));
+    }
+    if let Some(n) = matches.value_of("hash-size-limit") {
+        opts = opts.hash_size_limit(n.parse().expect("Invalid `--hash-size-limit`"));
+    }
+    if let Some(n) = matches.value_of("text-normalize") {
+        opts = opts.text_normalize(n.parse().expect("Invalid `--text-normalize`"));
+    }
+    // only report groups with enough copies (if the user asked)
+    if let Some(n) = matches.value_of("min-copies") {
+        opts = opts.min_copies(n.parse().expect("Invalid `--min-copies`"));
+    }
+    if let Some(n) = matches.value_of("min-waste") {
+        opts = opts.min_waste(n.parse().expect("Invalid `--min-waste`"));
+    }
+    if let Some(k) = matches.value_of("top") {
+        opts = opts.top(k.parse().expect("Invalid `--top`"));
+    }
+    if matches.is_present("histogram") {
+        opts = opts.histogram(HISTOGRAM_TOP);
+    }
+    // refuse to start linking on a filesystem that can't hold hard links (e.g. FAT)
+    if matches.is_present("link") {
+        for path in &paths {
+            let ok = RealFileSystem.supports_hard_links(path)
+                .expect("Failed to check for hard link support");
+            if !ok {
+                eprintln!("{:?} is on a filesystem without hard links; try --delete", path);
+                process::exit(1);
+            }
+        }
+    }
+
+    // walk and catalog all files; duplicates are identified as files are inserted
+    let mut report = smllr::scan_threaded(&opts, fs.clone()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    // the biggest wins come first (unless `--top` already ordered them by file size)
+    if !matches.is_present("top") {
+        report.repeats = most_wasteful_first(report.repeats);
+    }
+    // nothing to act on: stay silent if asked to
+    if matches.is_present("quiet-no-duplicates") && report.repeats.is_empty() {
+        return;
+    }
+    // the whole report is the JSON, with nothing around it
+    if matches.is_present("json") {
+        let out = output.unwrap_or_else(|| Box::new(io::stdout()));
+        json::write(&report, out).expect("Failed to write output");
+        return;
+    }
+    let summary = report.summary();
+    status!(status, "Traversing {} files...", report.files);
+    if report.partial {
+        status!(status, "Ran out of time (see --max-runtime); these results are partial");
+    }
+    if matches.is_present("sample-rate") {
+        status!(status, "Only a sample of the files was scanned (see --sample-rate)");
+    }
+    if report.skipped > 0 {
+        status!(status, "Skipped {}", report.failures);
+        if verbose {
+            for e in &report.errors {
+                status!(status, "\t{}", e);
+            }
+            let unlisted = report.skipped - report.errors.len();
+            if unlisted > 0 {
+                status!(status, "\t... and {} more (see --max-errors)", unlisted);
+            }
+        }
+    }
+    if let Some(ref hist) = report.histogram {
+        write!(status, "{}", hist).expect("Failed to write status");
+    }
+    status!(status, "{}", report.stats);
+    let report_collisions = matches.is_present("report-collisions");
+    let (repeats, collisions, size_only) = (report.repeats, report.collisions, report.size_only);
+    let empty = report.empty;
+    let unlinkable = report.unlinkable;
+    // files only reachable through a skipped folder are never the ones kept
+    let avoid = AvoidSelect::new(fs.clone(), report.excluded.iter().cloned());
 
     // use a Box to put the Selector and Actor on the heap as trait objects
     // different selectors or actors are different sizes (e.g. test_fs contains
@@ -164,41 +699,201 @@ fn main() {
     // this works because we impl'd these traits for Box<T>
 
     // select which of the duplicates are "true" and act on the others
-    let mut selector: Box<Selector<RealFileSystem>> = {
-        // `--newest-file` or `--path-len` (default)
-        if matches.is_present("newest-file") {
-            Box::new(DateSelect::new(fs))
+    let mut selector: Box<dyn Selector<Fs>> = {
+        // `--keep ...`, `--newest-file`, or `--path-len` (default)
+        if let Some(strategies) = matches.values_of("keep") {
+            let links = strategies
+                .map(|s| parse_keep(s, fs.clone()).expect("Invalid `--keep` strategy"))
+                .collect();
+            Box::new(ChainSelect::new(links))
+        } else if matches.is_present("newest-file") {
+            Box::new(DateSelect::new(fs.clone()))
         } else {
-            Box::new(PathSelect::new(fs))
+            Box::new(PathSelect::new(fs.clone()))
         }
     };
     // invert selector if necessary (e.g. use longest path instead of shortest)
     if matches.is_present("invert-selector") {
         selector.reverse();
     }
+    // the preferred device comes first, whichever way the rest is inverted
+    if let Some(ids) = matches.values_of("prefer-device") {
+        let devices = ids.map(|id| DeviceId(id.parse().expect("Invalid `--prefer-device`")))
+            .collect();
+        let links = vec![
+            Box::new(DeviceSelect::new(fs.clone(), devices)) as Box<dyn Selector<Fs>>,
+            selector,
+        ];
+        selector = Box::new(ChainSelect::new(links));
+    }
+    if !report.excluded.is_empty() {
+        let links = vec![
+            Box::new(AvoidSelect::new(fs.clone(), report.excluded)) as Box<dyn Selector<Fs>>,
+            selector,
+        ];
+        selector = Box::new(ChainSelect::new(links));
+    }
     let selector = selector; // remove mutability
 
+    // how many of the top-ranked files in each group to preserve
+    let keep = matches
+        .value_of("keep-copies")
+        .map(|n| n.parse().expect("Invalid `--keep-copies`"))
+        .unwrap_or(1);
+
+    // only show what would be done
+    if matches.is_present("preview-tree") {
+        let action = if matches.is_present("link") {
+            Action::Link
+        } else if matches.is_present("reflink") {
+            Action::Reflink
+        } else {
+            Action::Delete
+        };
+        let groups: Vec<_> = repeats.into_iter().filter(|dups| !avoid.avoids_all(dups)).collect();
+        let mut out = output.unwrap_or_else(|| Box::new(io::stdout()));
+        write!(out, "{}", tree::preview(&groups, &selector, keep, action))
+            .expect("Failed to write output");
+        status!(status, "{}", summary);
+        return;
+    }
+
     // determine what action should be taken on non-selected files
-    let mut actor: Box<FileActor<RealFileSystem, Box<Selector<RealFileSystem>>>> = {
-        // `--link`, `--delete`, or `--print` (default)
+    let mut actor: Box<dyn FileActor<Fs, Box<dyn Selector<Fs>>>> = {
+        // `--link`, `--reflink`, `--delete`, or `--print` (default)
         if matches.is_present("link") {
-            Box::new(FileLinker::new(fs, selector))
+            let linker = FileLinker::new(fs, selector)
+                .keep_copies(keep)
+                .ignore_permissions(matches.is_present("ignore-permissions-diff"))
+                .revalidate(!matches.is_present("no-revalidate"))
+                .on_error(on_error);
+            Box::new(linker)
+        } else if matches.is_present("reflink") {
+            let reflinker = FileReflinker::new(fs, selector)
+                .keep_copies(keep)
+                .revalidate(!matches.is_present("no-revalidate"))
+                .on_error(on_error);
+            Box::new(reflinker)
         } else if matches.is_present("delete") {
-            Box::new(FileDeleter::new(fs, selector))
+            let deleter = FileDeleter::new(fs, selector)
+                .keep_copies(keep)
+                .revalidate(!matches.is_present("no-revalidate"))
+                .on_error(on_error);
+            Box::new(deleter)
+        } else if let Some(out) = output {
+            let mut printer = FilePrinter::with_output(fs, selector, out)
+                .keep_copies(keep)
+                .print0(print0)
+                .verbose(verbose)
+                .show_hash(show_hash)
+                .numbered(true)
+                .on_error(on_error);
+            if let Some(base) = relative_to {
+                printer = printer.relative_to(base);
+            }
+            Box::new(printer)
         } else {
-            Box::new(FilePrinter::new(fs, selector))
+            let mut printer = FilePrinter::new(fs, selector)
+                .keep_copies(keep)
+                .print0(print0)
+                .verbose(verbose)
+                .show_hash(show_hash)
+                .numbered(true)
+                .on_error(on_error);
+            if let Some(base) = relative_to {
+                printer = printer.relative_to(base);
+            }
+            Box::new(printer)
         }
     };
 
     // act on all sets of duplicates
-    if repeats.is_empty() {
-        println!("No duplicates found");
+    if report.files < 2 {
+        status!(status, "Nothing to compare");
+    } else if repeats.is_empty() {
+        status!(status, "No duplicates found");
     } else {
-        println!("Acting on {} sets of duplicates...", repeats.len());
-        let mut saved_bytes = 0;
+        status!(status, "Acting on {} sets of duplicates...", repeats.len());
+        let mut saved_bytes = Savings::default();
         for dups in repeats {
+            if avoid.avoids_all(&dups) {
+                warn!(
+                    "Not acting on {:?} and its duplicates: all of them are in skipped folders",
+                    dups.paths()[0]
+                );
+                continue;
+            }
             saved_bytes += actor.act(dups);
+            // `--on-error abort`: leave the remaining groups as they are
+            if actor.aborted() {
+                status!(status, "Idenfied {}", saved_bytes);
+                eprintln!("Stopped at the first error (see --on-error)");
+                process::exit(1);
+            }
+        }
+        status!(status, "Idenfied {}", saved_bytes);
+    }
+    // groups filtered out by `--min-copies` or `--min-waste` were left alone
+    if report.omitted > 0 {
+        status!(
+            status,
+            "Omitted {} smaller sets of duplicates ({} redundant bytes)",
+            report.omitted,
+            report.omitted_bytes
+        );
+    }
+
+    // files matched by size alone are listed separately; none of them were acted on
+    if !size_only.is_empty() {
+        status!(status);
+        status!(
+            status,
+            "{} sets of probable duplicates (size only, contents not compared):",
+            size_only.len()
+        );
+        for dups in &size_only {
+            for path in dups.paths() {
+                status!(status, "\t{}", path.display());
+            }
+            status!(status);
         }
-        println!("Idenfied {}", prettify_bytes(saved_bytes));
     }
+
+    // empty files are listed separately unless `--dedupe-empty`; none of them were acted on
+    if empty.len() >= 2 {
+        status!(status);
+        status!(status, "{} empty files (not acted on; see --dedupe-empty):", empty.len());
+        for path in &empty {
+            status!(status, "\t{}", path.display());
+        }
+    }
+
+    // copies whose duplicates are all on other devices can't be linked to them
+    if !unlinkable.is_empty() {
+        status!(status);
+        status!(
+            status,
+            "{} files with duplicates only on other devices (not linkable):",
+            unlinkable.len()
+        );
+        for path in &unlinkable {
+            status!(status, "\t{}", path.display());
+        }
+    }
+
+    // near-misses are listed separately; none of them were acted on
+    if report_collisions {
+        status!(status);
+        status!(
+            status,
+            "{} sizes shared by files with different contents (not duplicates):",
+            collisions.len()
+        );
+        for collision in &collisions {
+            write!(status, "{}", collision).expect("Failed to write status");
+        }
+    }
+
+    status!(status);
+    status!(status, "{}", summary);
 }
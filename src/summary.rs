@@ -0,0 +1,59 @@
+//! A few lines on everything a run covered, e.g.
+//! "Scanned 1,204,113 files, 3.4 TB; 18,002 files in 6,120 duplicate groups; 92 GB reclaimable"
+
+use std::fmt;
+
+use walker::WalkStats;
+use catalog::CatalogStats;
+use helpers::{prettify_bytes, with_commas};
+use Report;
+
+/// How much a scan covered and how much of it is duplicated (see `Report::summary`)
+/// Sizes are the space files occupy on disk where that's known, rather than their lengths
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ScanSummary {
+    /// Number of directories walked
+    pub dirs: usize,
+    /// Number of files cataloged (including any imported)
+    pub files: usize,
+    /// Space the files the walk turned up occupy
+    pub bytes: u64,
+    /// Number of files that couldn't be cataloged
+    pub skipped: usize,
+    /// Number of groups of duplicates reported
+    pub groups: usize,
+    /// Number of paths in those groups
+    pub duplicates: usize,
+    /// Space keeping only one file of each group would free
+    pub reclaimable: u64,
+}
+
+impl ScanSummary {
+    /// Put together what the walk covered, what the catalog held, and what `report` found
+    pub fn new(walk: &WalkStats, catalog: &CatalogStats, report: &Report) -> Self {
+        ScanSummary {
+            dirs: walk.dirs,
+            files: catalog.files,
+            bytes: walk.allocated,
+            skipped: report.skipped,
+            groups: report.repeats.len(),
+            duplicates: report.repeats.iter().map(|dups| dups.len()).sum(),
+            reclaimable: report.reclaimable.allocated,
+        }
+    }
+}
+
+// e.g. "Scanned 1,204 files, 3 MB; 12 files in 5 duplicate groups; 1 MB reclaimable"
+impl fmt::Display for ScanSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Scanned {} files, {}; {} files in {} duplicate groups; {} reclaimable",
+            with_commas(self.files as u64),
+            prettify_bytes(self.bytes),
+            with_commas(self.duplicates as u64),
+            with_commas(self.groups as u64),
+            prettify_bytes(self.reclaimable)
+        )
+    }
+}
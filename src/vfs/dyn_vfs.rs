@@ -0,0 +1,403 @@
+//! Use any `VFS` as a trait object, so the filesystem can be chosen at runtime
+
+use std::{io, time};
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+
+use vfs::{DeviceId, File, FileType, Inode, MetaData, Result, VFS};
+use hash::{FileHash, Identity};
+
+/// The entries of a directory, as returned by `DynVfs::list_dir`
+pub type DynDirIter = Box<dyn Iterator<Item = Result<Box<dyn DynFile>>>>;
+
+/// An object-safe version of `VFS`: paths are `&Path` and files and metadata are boxed
+/// Every `VFS` is a `DynVfs`, and `Box<dyn DynVfs>` is a `VFS`, so a boxed filesystem
+/// can be handed to the walker, catalog, and actors like any other
+pub trait DynVfs: Debug {
+    /// See `VFS::list_dir`
+    fn list_dir(&self, p: &Path) -> Result<DynDirIter>;
+    /// See `VFS::get_metadata`
+    fn get_metadata(&self, p: &Path) -> Result<Box<dyn MetaData>>;
+    /// See `VFS::get_symlink_metadata`
+    fn get_symlink_metadata(&self, p: &Path) -> Result<Box<dyn MetaData>>;
+    /// See `VFS::read_link`
+    fn read_link(&self, p: &Path) -> Result<PathBuf>;
+    /// See `VFS::canonicalize`
+    fn canonicalize(&self, p: &Path) -> Result<PathBuf>;
+    /// See `VFS::get_file`
+    fn get_file(&self, p: &Path) -> Result<Box<dyn DynFile>>;
+    /// Feed the bytes `VFS::hash_path` would hash to `hasher`, and hand it back
+    fn hash_path_chunks(&self, p: &Path, hasher: Box<dyn DynHasher>)
+        -> Result<Box<dyn DynHasher>>;
+    /// See `VFS::rm_file`
+    fn rm_file(&mut self, p: &Path) -> Result<()>;
+    /// See `VFS::make_hard_link`
+    fn make_hard_link(&mut self, src: &Path, dst: &Path) -> Result<()>;
+    /// See `VFS::reflink`
+    fn reflink(&mut self, src: &Path, dst: &Path) -> Result<()>;
+    /// See `VFS::rename`
+    fn rename(&mut self, from: &Path, to: &Path) -> Result<()>;
+    /// See `VFS::free_space`
+    fn free_space(&self, p: &Path) -> Result<u64>;
+    /// Copy the filesystem into a new box (`VFS` requires `Clone`)
+    fn box_clone(&self) -> Box<dyn DynVfs>;
+}
+
+/// A hash in progress, of any algorithm, for `DynFile::hash_chunks` to feed
+pub trait DynHasher {
+    /// Start over (e.g. when reading the file is retried from its first byte)
+    fn restart(&mut self);
+    /// Hash the next chunk of bytes
+    fn update(&mut self, bytes: &[u8]);
+    /// The hasher as `Any`, to take its state back out once the file has been fed to it
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+// the running state of hash `H`
+struct Hasher<H: FileHash>(H::State);
+
+impl<H: FileHash> DynHasher for Hasher<H> {
+    fn restart(&mut self) {
+        self.0 = H::start();
+    }
+    fn update(&mut self, bytes: &[u8]) {
+        H::update(&mut self.0, bytes);
+    }
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+thread_local! {
+    // the hashers `hash_chunks` is feeding on this thread, innermost last
+    // each is taken out while it's in use, as it may feed another (a box of a box)
+    static FEEDING: RefCell<Vec<Option<Box<dyn DynHasher>>>> = const { RefCell::new(vec![]) };
+}
+
+// use the hasher `hash_chunks` is feeding at `depth`, with none of the others borrowed
+fn with_hasher<F: FnOnce(&mut dyn DynHasher)>(depth: usize, f: F) {
+    let mut hasher = FEEDING
+        .with(|feeding| feeding.borrow_mut()[depth].take())
+        .expect("Hasher fed while in use");
+    f(&mut *hasher);
+    FEEDING.with(|feeding| feeding.borrow_mut()[depth] = Some(hasher));
+}
+
+// a "hash" that passes the bytes of any `File` on to the innermost hasher not in use,
+// so files are streamed through `hash_chunks` with the chunking their `File::get_hash` does
+#[derive(Debug)]
+struct Forward;
+
+impl FileHash for Forward {
+    type Output = ();
+    // the depth of the hasher being fed
+    type State = usize;
+
+    fn start() -> Self::State {
+        let depth = FEEDING
+            .with(|feeding| feeding.borrow().iter().rposition(Option::is_some))
+            .expect("Nothing to feed");
+        with_hasher(depth, |hasher| hasher.restart());
+        depth
+    }
+    fn update(state: &mut Self::State, bytes: &[u8]) {
+        with_hasher(*state, |hasher| hasher.update(bytes));
+    }
+    fn finish(_state: Self::State) -> Self::Output {}
+}
+
+/// An object-safe version of `File`
+/// Hashing is generic, so files feed their bytes to a boxed hasher (see `hash_chunks`)
+/// and `Box<dyn DynFile>` hashes them without holding more than a chunk at a time
+pub trait DynFile: Debug {
+    /// See `File::get_inode`
+    fn get_inode(&self) -> Result<Inode>;
+    /// See `File::get_path`
+    fn get_path(&self) -> PathBuf;
+    /// See `File::get_type`
+    fn get_type(&self) -> Result<FileType>;
+    /// See `File::get_metadata`
+    fn get_metadata(&self) -> Result<Box<dyn MetaData>>;
+    /// Read the bytes `File::get_first_bytes` would hash
+    fn read_first_bytes(&self) -> Result<Vec<u8>>;
+    /// Read the bytes `File::get_last_bytes` would hash
+    fn read_last_bytes(&self) -> Result<Vec<u8>>;
+    /// Feed the bytes `File::get_hash` would hash to `hasher` a chunk at a time, calling
+    /// `progress` like `File::get_hash_with_progress` if given, and hand `hasher` back
+    fn hash_chunks(
+        &self,
+        hasher: Box<dyn DynHasher>,
+        progress: Option<&mut dyn FnMut(u64, u64)>,
+    ) -> Result<Box<dyn DynHasher>>;
+}
+
+// box every file and its metadata
+impl<F> DynFile for F
+where
+    F: File + 'static,
+    F::MD: 'static,
+{
+    fn get_inode(&self) -> Result<Inode> {
+        File::get_inode(self)
+    }
+    fn get_path(&self) -> PathBuf {
+        File::get_path(self)
+    }
+    fn get_type(&self) -> Result<FileType> {
+        File::get_type(self)
+    }
+    fn get_metadata(&self) -> Result<Box<dyn MetaData>> {
+        let md = File::get_metadata(self)?;
+        Ok(Box::new(md))
+    }
+    fn read_first_bytes(&self) -> Result<Vec<u8>> {
+        self.get_first_bytes::<Identity>()
+    }
+    fn read_last_bytes(&self) -> Result<Vec<u8>> {
+        self.get_last_bytes::<Identity>()
+    }
+    fn hash_chunks(
+        &self,
+        hasher: Box<dyn DynHasher>,
+        progress: Option<&mut dyn FnMut(u64, u64)>,
+    ) -> Result<Box<dyn DynHasher>> {
+        feed(hasher, || match progress {
+            Some(progress) => self.get_hash_with_progress::<Forward>(progress),
+            None => self.get_hash::<Forward>(),
+        })
+    }
+}
+
+// make `hasher` the one `Forward` feeds while `hash` runs, and hand it back
+fn feed<F: FnOnce() -> Result<()>>(
+    hasher: Box<dyn DynHasher>,
+    hash: F,
+) -> Result<Box<dyn DynHasher>> {
+    FEEDING.with(|feeding| feeding.borrow_mut().push(Some(hasher)));
+    let hashed = hash();
+    let hasher = FEEDING
+        .with(|feeding| feeding.borrow_mut().pop())
+        .and_then(|hasher| hasher)
+        .expect("Hasher lost while feeding it");
+    hashed.map(|()| hasher)
+}
+
+// feed something to a new hash `H` with `feed_to`, and finish it
+fn hash_boxed<H, F>(feed_to: F) -> Result<<H as FileHash>::Output>
+where
+    H: FileHash,
+    F: FnOnce(Box<dyn DynHasher>) -> Result<Box<dyn DynHasher>>,
+{
+    let hasher = feed_to(Box::new(Hasher::<H>(H::start())))?;
+    let hasher = hasher
+        .into_any()
+        .downcast::<Hasher<H>>()
+        .expect("Hasher changed type while feeding it");
+    Ok(H::finish(hasher.0))
+}
+
+impl File for Box<dyn DynFile> {
+    type MD = Box<dyn MetaData>;
+
+    fn get_inode(&self) -> Result<Inode> {
+        (**self).get_inode()
+    }
+    fn get_path(&self) -> PathBuf {
+        (**self).get_path()
+    }
+    fn get_type(&self) -> Result<FileType> {
+        (**self).get_type()
+    }
+    fn get_metadata(&self) -> Result<Self::MD> {
+        (**self).get_metadata()
+    }
+    fn get_first_bytes<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        (**self).read_first_bytes().map(|bytes| H::hash(&bytes))
+    }
+    fn get_last_bytes<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        (**self).read_last_bytes().map(|bytes| H::hash(&bytes))
+    }
+    fn get_hash<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        hash_boxed::<H, _>(|hasher| (**self).hash_chunks(hasher, None))
+    }
+    fn get_hash_with_progress<H: FileHash>(
+        &self,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<<H as FileHash>::Output> {
+        hash_boxed::<H, _>(|hasher| (**self).hash_chunks(hasher, Some(progress)))
+    }
+}
+
+impl MetaData for Box<dyn MetaData> {
+    fn get_len(&self) -> u64 {
+        (**self).get_len()
+    }
+    fn get_allocated_size(&self) -> u64 {
+        (**self).get_allocated_size()
+    }
+    fn get_mod_time(&self) -> io::Result<time::SystemTime> {
+        (**self).get_mod_time()
+    }
+    fn get_type(&self) -> FileType {
+        (**self).get_type()
+    }
+    fn get_inode(&self) -> Inode {
+        (**self).get_inode()
+    }
+    fn get_device(&self) -> io::Result<DeviceId> {
+        (**self).get_device()
+    }
+    fn get_nlink(&self) -> u64 {
+        (**self).get_nlink()
+    }
+    fn get_uid(&self) -> u32 {
+        (**self).get_uid()
+    }
+    fn get_mode(&self) -> u32 {
+        (**self).get_mode()
+    }
+}
+
+// box every filesystem's files and metadata
+impl<V> DynVfs for V
+where
+    V: VFS + 'static,
+    V::FileIter: 'static,
+    <V::FileIter as File>::MD: 'static,
+{
+    fn list_dir(&self, p: &Path) -> Result<DynDirIter> {
+        let iter = VFS::list_dir(self, p)?;
+        Ok(Box::new(iter.map(|f| f.map(|f| Box::new(f) as Box<dyn DynFile>))))
+    }
+    fn get_metadata(&self, p: &Path) -> Result<Box<dyn MetaData>> {
+        let md = VFS::get_metadata(self, p)?;
+        Ok(Box::new(md))
+    }
+    fn get_symlink_metadata(&self, p: &Path) -> Result<Box<dyn MetaData>> {
+        let md = VFS::get_symlink_metadata(self, p)?;
+        Ok(Box::new(md))
+    }
+    fn read_link(&self, p: &Path) -> Result<PathBuf> {
+        VFS::read_link(self, p)
+    }
+    fn canonicalize(&self, p: &Path) -> Result<PathBuf> {
+        VFS::canonicalize(self, p)
+    }
+    fn get_file(&self, p: &Path) -> Result<Box<dyn DynFile>> {
+        let file = VFS::get_file(self, p)?;
+        Ok(Box::new(file))
+    }
+    fn hash_path_chunks(
+        &self,
+        p: &Path,
+        hasher: Box<dyn DynHasher>,
+    ) -> Result<Box<dyn DynHasher>> {
+        feed(hasher, || VFS::hash_path::<Forward>(self, p))
+    }
+    fn rm_file(&mut self, p: &Path) -> Result<()> {
+        VFS::rm_file(self, &p)
+    }
+    fn make_hard_link(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        VFS::make_hard_link(self, src, dst)
+    }
+    fn reflink(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        VFS::reflink(self, src, dst)
+    }
+    fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+        VFS::rename(self, from, to)
+    }
+    fn free_space(&self, p: &Path) -> Result<u64> {
+        VFS::free_space(self, p)
+    }
+    fn box_clone(&self) -> Box<dyn DynVfs> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn DynVfs> {
+    fn clone(&self) -> Self {
+        (**self).box_clone()
+    }
+}
+
+/// A `DynVfs` that can be sent to other threads, so a filesystem chosen at runtime can
+/// still be read on worker threads (see `FileCataloger::hash_threads`, `DirWalker::io_threads`)
+pub trait SendVfs: DynVfs + Send {
+    /// Copy the filesystem into a new box that can be sent too
+    fn box_clone_send(&self) -> Box<dyn SendVfs>;
+}
+
+// box every filesystem that can be sent
+impl<V> SendVfs for V
+where
+    V: VFS + Send + 'static,
+    V::FileIter: 'static,
+    <V::FileIter as File>::MD: 'static,
+{
+    fn box_clone_send(&self) -> Box<dyn SendVfs> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn SendVfs> {
+    fn clone(&self) -> Self {
+        (**self).box_clone_send()
+    }
+}
+
+// forward every `VFS` method of a boxed filesystem to the `DynVfs` inside it
+macro_rules! boxed_vfs {
+    ($dyn:ty) => {
+        impl VFS for Box<$dyn> {
+            type FileIter = Box<dyn DynFile>;
+
+            fn list_dir<P: AsRef<Path>>(
+                &self,
+                p: P,
+            ) -> Result<Box<dyn Iterator<Item = Result<Self::FileIter>>>> {
+                (**self).list_dir(p.as_ref())
+            }
+            fn get_metadata<P: AsRef<Path>>(&self, p: P) -> Result<<Self::FileIter as File>::MD> {
+                (**self).get_metadata(p.as_ref())
+            }
+            fn get_symlink_metadata<P: AsRef<Path>>(
+                &self,
+                p: P,
+            ) -> Result<<Self::FileIter as File>::MD> {
+                (**self).get_symlink_metadata(p.as_ref())
+            }
+            fn read_link<P: AsRef<Path>>(&self, p: P) -> Result<PathBuf> {
+                (**self).read_link(p.as_ref())
+            }
+            fn canonicalize(&self, p: &Path) -> Result<PathBuf> {
+                (**self).canonicalize(p)
+            }
+            fn get_file(&self, p: &Path) -> Result<Self::FileIter> {
+                (**self).get_file(p)
+            }
+            fn hash_path<H: FileHash>(&self, p: &Path) -> Result<<H as FileHash>::Output> {
+                hash_boxed::<H, _>(|hasher| (**self).hash_path_chunks(p, hasher))
+            }
+            fn rm_file<P: AsRef<Path>>(&mut self, p: &P) -> Result<()> {
+                (**self).rm_file(p.as_ref())
+            }
+            fn make_hard_link(&mut self, src: &Path, dst: &Path) -> Result<()> {
+                (**self).make_hard_link(src, dst)
+            }
+            fn reflink(&mut self, src: &Path, dst: &Path) -> Result<()> {
+                (**self).reflink(src, dst)
+            }
+            fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+                (**self).rename(from, to)
+            }
+            fn free_space(&self, p: &Path) -> Result<u64> {
+                (**self).free_space(p)
+            }
+        }
+    };
+}
+
+boxed_vfs!(dyn DynVfs);
+boxed_vfs!(dyn SendVfs);
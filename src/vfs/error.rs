@@ -0,0 +1,163 @@
+//! Errors from the VFS layer, annotated with the operation and path involved
+
+use std::{error, fmt, io, result};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Result type for operations on a `VFS` or `File`
+pub type Result<T> = result::Result<T, VfsError>;
+
+/// The kind of filesystem operation that failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    ListDir,
+    ReadMetadata,
+    ReadLink,
+    OpenFile,
+    ReadContents,
+    Delete,
+    HardLink,
+    Reflink,
+    Rename,
+    FreeSpace,
+    Canonicalize,
+}
+
+// phrase each operation so it reads as "failed to <op> <path>"
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            Operation::ListDir => "list directory",
+            Operation::ReadMetadata => "read metadata for",
+            Operation::ReadLink => "resolve symlink",
+            Operation::OpenFile => "open",
+            Operation::ReadContents => "read contents of",
+            Operation::Delete => "delete",
+            Operation::HardLink => "create hard link at",
+            Operation::Reflink => "clone a file onto",
+            Operation::Rename => "rename a file onto",
+            Operation::FreeSpace => "check free space for",
+            Operation::Canonicalize => "find the canonical path of",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// An `io::Error` along with which operation failed on which path
+pub struct VfsError {
+    op: Operation,
+    path: PathBuf,
+    cause: io::Error,
+}
+
+impl VfsError {
+    /// Wrap `cause`, recording that `op` failed on `path`
+    pub fn new<P: AsRef<Path>>(op: Operation, path: P, cause: io::Error) -> Self {
+        VfsError {
+            op,
+            path: path.as_ref().to_path_buf(),
+            cause,
+        }
+    }
+    /// Build a closure suitable for `map_err` on an `io::Result`
+    pub fn wrap<P: AsRef<Path>>(op: Operation, path: P) -> impl FnOnce(io::Error) -> Self {
+        move |cause| VfsError::new(op, path, cause)
+    }
+    /// The operation that failed
+    pub fn op(&self) -> Operation {
+        self.op
+    }
+    /// The path the operation failed on
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// The underlying I/O error
+    pub fn cause(&self) -> &io::Error {
+        &self.cause
+    }
+    /// Shortcut for the kind of the underlying I/O error
+    pub fn kind(&self) -> io::ErrorKind {
+        self.cause.kind()
+    }
+}
+
+// e.g. "failed to read metadata for /data/x.bin: Permission denied"
+impl fmt::Display for VfsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "failed to {} {}: {}",
+            self.op,
+            self.path.display(),
+            self.cause
+        )
+    }
+}
+
+// Debug is what `expect()` prints, so make it just as readable
+impl fmt::Debug for VfsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+// lose the structure but keep the kind and the message
+impl From<VfsError> for io::Error {
+    fn from(e: VfsError) -> io::Error {
+        io::Error::new(e.kind(), e)
+    }
+}
+
+impl error::Error for VfsError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.cause)
+    }
+}
+
+/// What to do when an operation on one file fails, wherever that happens: walking,
+/// reading files to catalog them, or acting on duplicates (`--on-error`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// Leave the file out without saying so (beyond debug logging)
+    Skip,
+    /// Log a warning, leave the file out, and carry on (the default)
+    // warn and carry on unless told otherwise
+    #[default]
+    Warn,
+    /// Stop at the first failure
+    Abort,
+}
+
+impl OnError {
+    /// Log `e` the way the policy asks, and tell whether to carry on past it
+    pub fn carry_on<E: fmt::Display>(self, e: &E) -> bool {
+        match self {
+            OnError::Skip => {
+                debug!("{}", e);
+                true
+            }
+            OnError::Warn => {
+                warn!("{}", e);
+                true
+            }
+            OnError::Abort => {
+                error!("{}", e);
+                false
+            }
+        }
+    }
+}
+
+// parse the --on-error value
+impl FromStr for OnError {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(OnError::Skip),
+            "warn" => Ok(OnError::Warn),
+            "abort" => Ok(OnError::Abort),
+            _ => Err(format!("unknown error policy `{}` (expected skip, warn, or abort)", s)),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Build large, reproducible mock filesystems for benchmarks and stress tests
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use vfs::{TestFile, TestFileSystem, TestMD};
+
+/// Shape of a tree built by `TestFileSystem::generate`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeSpec {
+    /// Number of subdirectories in each directory
+    pub fan_out: usize,
+    /// Number of levels of subdirectories beneath the root
+    pub depth: usize,
+    /// Number of files in each directory (including the root)
+    pub files_per_dir: usize,
+    /// Smallest file size in bytes
+    pub min_size: usize,
+    /// Largest file size in bytes
+    pub max_size: usize,
+    /// Probability that a file is a copy of an earlier file (0.0 to 1.0)
+    pub dup_ratio: f64,
+}
+
+/// A generated filesystem along with what a catalog should find in it
+#[derive(Debug)]
+pub struct GeneratedTree {
+    /// The mock filesystem, rooted at "/"
+    pub fs: Rc<RefCell<TestFileSystem>>,
+    /// Every regular file that was created
+    pub files: Vec<PathBuf>,
+    /// The number of distinct contents that appear in more than one file
+    pub duplicate_groups: usize,
+}
+
+// xorshift64*: small, fast, and good enough to scatter test data
+// deterministic given the seed, which is the point
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // the state must never be zero
+        Rng(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+    // uniform-ish integer in `[low, high]`
+    fn range(&mut self, low: usize, high: usize) -> usize {
+        low + (self.next() % (high - low + 1) as u64) as usize
+    }
+    // true with probability `p`
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+impl TestFileSystem {
+    /// Deterministically build a pseudo-random tree described by `spec`
+    /// The same seed and spec always produce the same tree
+    pub fn generate(seed: u64, spec: &TreeSpec) -> GeneratedTree {
+        assert!(spec.min_size <= spec.max_size);
+        let mut rng = Rng::new(seed);
+        let fs = TestFileSystem::new();
+        let mut files = vec![];
+        // contents of every original file and how many copies it has
+        let mut originals: Vec<(String, usize)> = vec![];
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            // breadth-first: each level is the subdirectories of the last
+            let mut level = vec![PathBuf::from("/")];
+            for depth in 0..spec.depth + 1 {
+                let mut next_level = vec![];
+                for dir in &level {
+                    for i in 0..spec.files_per_dir {
+                        let path = dir.join(format!("f{}", i));
+                        let contents = if !originals.is_empty() && rng.chance(spec.dup_ratio) {
+                            let n = rng.range(0, originals.len() - 1);
+                            originals[n].1 += 1;
+                            originals[n].0.clone()
+                        } else {
+                            let c = Self::unique_contents(&mut rng, originals.len(), spec);
+                            originals.push((c.clone(), 0));
+                            c
+                        };
+                        fs.add_generated(&path, contents);
+                        files.push(path);
+                    }
+                    if depth < spec.depth {
+                        for i in 0..spec.fan_out {
+                            let sub = dir.join(format!("d{}", i));
+                            fs.create_dir(&sub);
+                            next_level.push(sub);
+                        }
+                    }
+                }
+                level = next_level;
+            }
+        }
+        let duplicate_groups = originals.iter().filter(|&&(_, n)| n > 0).count();
+        GeneratedTree {
+            fs,
+            files,
+            duplicate_groups,
+        }
+    }
+
+    // contents that differ from every other original: they start with its index
+    fn unique_contents(rng: &mut Rng, index: usize, spec: &TreeSpec) -> String {
+        let mut contents = format!("{:x}:", index);
+        let size = rng.range(spec.min_size, spec.max_size);
+        while contents.len() < size {
+            contents.push((b'a' + (rng.next() % 26) as u8) as char);
+        }
+        contents
+    }
+
+    // register a regular file with a fresh inode
+    fn add_generated(&mut self, path: &Path, contents: String) {
+        let inode = self.num_elements() as u64;
+        let md = TestMD::new().with_mod_time(UNIX_EPOCH);
+        let name = path.to_str().expect("generated paths are unicode");
+        self.add(
+            TestFile::new(name)
+                .with_contents(contents)
+                .with_metadata(md)
+                .with_inode(inode),
+        );
+    }
+}
@@ -4,11 +4,37 @@ use std::{fs, io, time};
 use std::fmt::Debug;
 use std::path::{Path, PathBuf};
 
+mod error;
+pub use self::error::{OnError, Operation, Result, VfsError};
+
 mod real_fs;
 pub use self::real_fs::RealFileSystem;
 
 mod test_fs;
-pub use self::test_fs::{TestFile, TestFileSystem, TestMD};
+pub use self::test_fs::{FsOp, TestFile, TestFileSystem, TestMD};
+
+mod overlay;
+pub use self::overlay::{OverlayFile, OverlayFileSystem};
+
+mod read_only;
+pub use self::read_only::ReadOnlyVfs;
+
+mod retry;
+pub use self::retry::{is_transient, RetryFile, RetryPolicy, RetryVfs};
+
+mod text;
+pub use self::text::{TextFile, TextMetaData, TextNormalizedVfs};
+
+mod generate;
+pub use self::generate::{GeneratedTree, TreeSpec};
+
+mod spec;
+pub use self::spec::SpecError;
+
+mod dyn_vfs;
+pub use self::dyn_vfs::{DynDirIter, DynFile, DynHasher, DynVfs, SendVfs};
+
+mod test; // include unit tests
 
 use hash::FileHash;
 
@@ -26,54 +52,134 @@ pub trait VFS: Clone + Debug {
     fn list_dir<P: AsRef<Path>>(
         &self,
         p: P,
-    ) -> io::Result<Box<Iterator<Item = io::Result<Self::FileIter>>>>;
+    ) -> Result<Box<Iterator<Item = Result<Self::FileIter>>>>;
 
     /// Get the metadata of a file (follows symlinks)
-    fn get_metadata<P: AsRef<Path>>(&self, p: P) -> io::Result<<Self::FileIter as File>::MD>;
+    fn get_metadata<P: AsRef<Path>>(&self, p: P) -> Result<<Self::FileIter as File>::MD>;
 
     /// Get the metadata of a file (doesn't follow symlinks)
-    fn get_symlink_metadata<P: AsRef<Path>>(
-        &self,
-        p: P,
-    ) -> io::Result<<Self::FileIter as File>::MD>;
+    fn get_symlink_metadata<P: AsRef<Path>>(&self, p: P) -> Result<<Self::FileIter as File>::MD>;
 
     /// Resolve a link path to the path of its target
-    fn read_link<P: AsRef<Path>>(&self, p: P) -> io::Result<PathBuf>;
+    fn read_link<P: AsRef<Path>>(&self, p: P) -> Result<PathBuf>;
+
+    /// Get the absolute path of `p` with every symlink, `.`, and `..` resolved
+    fn canonicalize(&self, p: &Path) -> Result<PathBuf>;
 
     // must be of type "File" (not a dir/link/other)
     /// Get a File handle from a path
-    fn get_file(&self, p: &Path) -> io::Result<Self::FileIter>;
+    fn get_file(&self, p: &Path) -> Result<Self::FileIter>;
+
+    /// Hash the whole file at `p` with `H`, as `get_file(p)?.get_hash::<H>()` would
+    /// Filesystems that can open a path directly override this, as looking up its File
+    /// can be slow (the real filesystem lists the whole directory to find it)
+    fn hash_path<H: FileHash>(&self, p: &Path) -> Result<<H as FileHash>::Output> {
+        self.get_file(p)?.get_hash::<H>()
+    }
 
     // must be of type "File" (not a dir/link/other)
     /// Delete a file
-    fn rm_file<P: AsRef<Path>>(&mut self, p: &P) -> io::Result<()>;
+    fn rm_file<P: AsRef<Path>>(&mut self, p: &P) -> Result<()>;
+
+    // same argument order as `std::fs::hard_link`
+    /// Create a hard link at `dst` pointing to the existing file at `src`
+    fn make_hard_link(&mut self, src: &Path, dst: &Path) -> Result<()>;
+
+    // both must be regular files on the same filesystem, and one that supports it
+    //  (e.g. Btrfs or XFS on Linux, APFS on macOS); elsewhere this fails
+    /// Make the existing file at `dst` a copy-on-write clone of the file at `src` (a reflink):
+    /// it shares `src`'s storage but stays a separate file, with its own inode and permissions
+    fn reflink(&mut self, src: &Path, dst: &Path) -> Result<()>;
+
+    // same semantics as `std::fs::rename`: an existing `to` is replaced atomically
+    /// Move the file at `from` to `to`
+    fn rename(&mut self, from: &Path, to: &Path) -> Result<()>;
+
+    /// Get the number of bytes that can still be written to the filesystem holding `p`
+    fn free_space(&self, p: &Path) -> Result<u64>;
+
+    /// Check whether `a` and `b` are on the same filesystem (so they can be hard linked)
+    fn same_filesystem(&self, a: &Path, b: &Path) -> Result<bool> {
+        let device = |p: &Path| -> Result<DeviceId> {
+            let md = self.get_metadata(p)?;
+            md.get_device().map_err(VfsError::wrap(Operation::ReadMetadata, p))
+        };
+        Ok(device(a)? == device(b)?)
+    }
 
-    // create
-    /// Create a haard link at `src` pointing to what's at `dst`
-    fn make_link(&mut self, src: &Path, dst: &Path) -> io::Result<()>;
+    /// Replace `victim` with a hard link to `keeper`
+    /// The link is created at `staging_path(victim)` and then renamed over `victim`,
+    /// so `victim` never stops existing and is left untouched if either step fails
+    fn replace_with_link(&mut self, keeper: &Path, victim: &Path) -> Result<()> {
+        let staged = staging_path(victim);
+        self.make_hard_link(keeper, &staged)?;
+        if let Err(e) = self.rename(&staged, victim) {
+            // don't leave the extra link lying around
+            if let Err(cleanup) = self.rm_file(&staged) {
+                warn!("{}", cleanup);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+/// Where `VFS::replace_with_link` creates the new link before moving it over `victim`
+/// It is in the same directory as `victim` so the rename can't cross devices
+pub fn staging_path(victim: &Path) -> PathBuf {
+    let name = victim.file_name().expect("Can't replace the root").to_string_lossy();
+    victim.with_file_name(format!(".{}.smllr-link", name))
 }
 
 /// Define common interface for a real or mock file
 pub trait File: Debug {
     type MD: MetaData;
-    fn get_inode(&self) -> io::Result<Inode>;
+    fn get_inode(&self) -> Result<Inode>;
     fn get_path(&self) -> PathBuf;
-    fn get_type(&self) -> io::Result<FileType>;
-    fn get_metadata(&self) -> io::Result<Self::MD>;
+    fn get_type(&self) -> Result<FileType>;
+    fn get_metadata(&self) -> Result<Self::MD>;
     /// Read and hash first K bytes of the file
-    fn get_first_bytes<H: FileHash>(&self) -> io::Result<<H as FileHash>::Output>;
+    /// Files shorter than K bytes hash only their contents (no padding)
+    fn get_first_bytes<H: FileHash>(&self) -> Result<<H as FileHash>::Output>;
+    /// Read and hash the last K bytes of the file, seeking past the rest
+    /// Files shorter than K bytes hash all their contents
+    fn get_last_bytes<H: FileHash>(&self) -> Result<<H as FileHash>::Output>;
     /// Hash the contents of the file
-    fn get_hash<H: FileHash>(&self) -> io::Result<<H as FileHash>::Output>;
+    fn get_hash<H: FileHash>(&self) -> Result<<H as FileHash>::Output>;
+    /// Hash the contents of the file like `get_hash`, calling `progress` with the number
+    /// of bytes hashed so far and the length of the file as it goes (e.g. for a progress bar)
+    /// By default it is only called once, when the whole file has been hashed
+    fn get_hash_with_progress<H: FileHash>(
+        &self,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<<H as FileHash>::Output> {
+        let hash = self.get_hash::<H>()?;
+        let len = self.get_metadata()?.get_len();
+        progress(len, len);
+        Ok(hash)
+    }
 }
 
 // it is the subset of the interface of fs::MetaData that we use
 /// Define common interface for real of mock metadata object
 pub trait MetaData: Debug {
     fn get_len(&self) -> u64;
+    /// Space the file occupies on disk (smaller than `get_len` for sparse files)
+    fn get_allocated_size(&self) -> u64;
     fn get_mod_time(&self) -> io::Result<time::SystemTime>;
     fn get_type(&self) -> FileType;
     fn get_inode(&self) -> Inode;
     fn get_device(&self) -> io::Result<DeviceId>;
+    /// Number of paths (hard links) that refer to the file
+    fn get_nlink(&self) -> u64;
+    /// User ID of the file's owner
+    fn get_uid(&self) -> u32;
+    /// Permission bits of the file (e.g. `0o644`)
+    fn get_mode(&self) -> u32;
+    /// Whether the file is already reachable through other hard links too
+    fn is_multiply_linked(&self) -> bool {
+        self.get_nlink() > 1
+    }
 }
 
 
@@ -0,0 +1,279 @@
+//! Wrap another VFS and keep every change in memory instead of applying it
+
+use std::io;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+
+use vfs::{File, FileType, FsOp, Inode, Operation, Result, VfsError, VFS};
+use hash::FileHash;
+
+/// A `VFS` that reads through to another one but records deletes, links, and moves
+/// in memory rather than performing them
+/// Reads see the recorded changes, so multi-step actions behave as they would for real
+/// (except link counts, which are read from the inner filesystem as they are)
+/// Clones share the same overlay (like `Rc<RefCell<TestFileSystem>>`)
+#[derive(Debug, Clone)]
+pub struct OverlayFileSystem<V: VFS> {
+    inner: V,
+    overlay: Rc<RefCell<Overlay>>,
+}
+
+// the changes layered on top of the inner filesystem
+#[derive(Debug, Default)]
+struct Overlay {
+    // paths that no longer exist
+    removed: HashSet<PathBuf>,
+    // paths that now exist, mapped to the inner path holding their contents
+    added: HashMap<PathBuf, PathBuf>,
+    // every successful mutating call, oldest first
+    ops: Vec<FsOp>,
+}
+
+impl Overlay {
+    // find the path in the inner filesystem that `path` refers to (if it still exists)
+    fn resolve(&self, path: &Path) -> Option<PathBuf> {
+        if let Some(real) = self.added.get(path) {
+            Some(real.clone())
+        } else if self.removed.contains(path) {
+            None
+        } else {
+            Some(path.to_path_buf())
+        }
+    }
+    // make `path` stop existing
+    fn remove(&mut self, path: &Path) {
+        self.added.remove(path);
+        self.removed.insert(path.to_path_buf());
+    }
+}
+
+impl<V: VFS> OverlayFileSystem<V> {
+    /// Layer an empty overlay over `inner`
+    pub fn new(inner: V) -> Self {
+        OverlayFileSystem {
+            inner,
+            overlay: Rc::new(RefCell::new(Overlay::default())),
+        }
+    }
+    /// The wrapped filesystem, which is never modified
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+    /// Get every mutating operation performed so far, oldest first
+    pub fn ops(&self) -> Vec<FsOp> {
+        self.overlay.borrow().ops.clone()
+    }
+
+    // find the inner path for `path`, or report that `op` failed because it doesn't exist
+    fn resolve(&self, op: Operation, path: &Path) -> Result<PathBuf> {
+        self.overlay
+            .borrow()
+            .resolve(path)
+            .ok_or_else(|| not_found(op, path))
+    }
+    // like `resolve`, but also make sure the inner file is really there
+    fn existing(&self, op: Operation, path: &Path) -> Result<PathBuf> {
+        let real = self.resolve(op, path)?;
+        match self.inner.get_symlink_metadata(&real) {
+            Ok(_) => Ok(real),
+            Err(_) => Err(not_found(op, path)),
+        }
+    }
+    // make sure nothing exists at `path` yet
+    fn vacant(&self, op: Operation, path: &Path) -> Result<()> {
+        match self.existing(op, path) {
+            Ok(_) => {
+                // EEXIST: refuse to clobber an existing file
+                Err(VfsError::new(op, path, io::Error::from_raw_os_error(17)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// helper: the error for operating on a path that isn't in the overlay
+fn not_found(op: Operation, path: &Path) -> VfsError {
+    let e = io::Error::new(io::ErrorKind::NotFound, "No such file");
+    VfsError::new(op, path, e)
+}
+
+/// A file from the inner filesystem, seen at its path in the overlay
+/// (e.g. a recorded hard link reports the link's path, not the original's)
+#[derive(Debug, Clone)]
+pub struct OverlayFile<F: File> {
+    path: PathBuf,
+    inner: F,
+}
+
+impl<F: File> File for OverlayFile<F> {
+    type MD = F::MD;
+
+    fn get_inode(&self) -> Result<Inode> {
+        self.inner.get_inode()
+    }
+    fn get_path(&self) -> PathBuf {
+        self.path.clone()
+    }
+    fn get_type(&self) -> Result<FileType> {
+        self.inner.get_type()
+    }
+    fn get_metadata(&self) -> Result<Self::MD> {
+        self.inner.get_metadata()
+    }
+    fn get_first_bytes<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        self.inner.get_first_bytes::<H>()
+    }
+    fn get_last_bytes<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        self.inner.get_last_bytes::<H>()
+    }
+    fn get_hash<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        self.inner.get_hash::<H>()
+    }
+    fn get_hash_with_progress<H: FileHash>(
+        &self,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<<H as FileHash>::Output> {
+        self.inner.get_hash_with_progress::<H>(progress)
+    }
+}
+
+impl<V> VFS for OverlayFileSystem<V>
+where
+    V: VFS,
+    V::FileIter: 'static,
+{
+    type FileIter = OverlayFile<V::FileIter>;
+
+    /// List the inner directory, minus removed paths, plus added ones
+    fn list_dir<P: AsRef<Path>>(
+        &self,
+        p: P,
+    ) -> Result<Box<dyn Iterator<Item = Result<Self::FileIter>>>> {
+        let dir = p.as_ref();
+        let overlay = self.overlay.borrow();
+        let mut v = vec![];
+        for entry in self.inner.list_dir(dir)? {
+            match entry {
+                Ok(f) => {
+                    let path = f.get_path();
+                    if !overlay.removed.contains(&path) && !overlay.added.contains_key(&path) {
+                        v.push(Ok(OverlayFile { path, inner: f }));
+                    }
+                }
+                Err(e) => v.push(Err(e)),
+            }
+        }
+        for (path, real) in &overlay.added {
+            if path.parent() == Some(dir) {
+                let file = self.inner.get_file(real).map(|inner| OverlayFile {
+                    path: path.clone(),
+                    inner,
+                });
+                v.push(file);
+            }
+        }
+        Ok(Box::new(v.into_iter()))
+    }
+
+    fn get_metadata<P: AsRef<Path>>(&self, p: P) -> Result<<Self::FileIter as File>::MD> {
+        let real = self.resolve(Operation::ReadMetadata, p.as_ref())?;
+        self.inner.get_metadata(real)
+    }
+
+    fn get_symlink_metadata<P: AsRef<Path>>(
+        &self,
+        p: P,
+    ) -> Result<<Self::FileIter as File>::MD> {
+        let real = self.resolve(Operation::ReadMetadata, p.as_ref())?;
+        self.inner.get_symlink_metadata(real)
+    }
+
+    fn read_link<P: AsRef<Path>>(&self, p: P) -> Result<PathBuf> {
+        let real = self.resolve(Operation::ReadLink, p.as_ref())?;
+        self.inner.read_link(real)
+    }
+
+    /// Resolve `p` on the inner filesystem; recorded files resolve through their directory
+    fn canonicalize(&self, p: &Path) -> Result<PathBuf> {
+        let overlay = self.overlay.borrow();
+        if overlay.added.contains_key(p) {
+            let name = p.file_name().expect("Added the root");
+            let dir = p.parent().expect("Added the root");
+            Ok(self.inner.canonicalize(dir)?.join(name))
+        } else if overlay.removed.contains(p) {
+            Err(not_found(Operation::Canonicalize, p))
+        } else {
+            self.inner.canonicalize(p)
+        }
+    }
+
+    fn get_file(&self, p: &Path) -> Result<Self::FileIter> {
+        let real = self.resolve(Operation::OpenFile, p)?;
+        let inner = self.inner.get_file(&real)?;
+        Ok(OverlayFile {
+            path: p.to_path_buf(),
+            inner,
+        })
+    }
+    fn hash_path<H: FileHash>(&self, p: &Path) -> Result<<H as FileHash>::Output> {
+        let real = self.resolve(Operation::OpenFile, p)?;
+        self.inner.hash_path::<H>(&real)
+    }
+
+    /// Record that `p` was deleted
+    fn rm_file<P: AsRef<Path>>(&mut self, p: &P) -> Result<()> {
+        let path = p.as_ref();
+        self.existing(Operation::Delete, path)?;
+        let mut overlay = self.overlay.borrow_mut();
+        overlay.remove(path);
+        overlay.ops.push(FsOp::Delete(path.to_path_buf()));
+        Ok(())
+    }
+
+    /// Record a hard link at `dst` to the file at `src`
+    fn make_hard_link(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        let real = self.existing(Operation::HardLink, src)?;
+        self.vacant(Operation::HardLink, dst)?;
+        let mut overlay = self.overlay.borrow_mut();
+        overlay.added.insert(dst.to_path_buf(), real);
+        overlay.ops.push(FsOp::HardLink {
+            src: src.to_path_buf(),
+            dst: dst.to_path_buf(),
+        });
+        Ok(())
+    }
+
+    /// Record that the file at `dst` was made a clone of the one at `src`
+    /// Both keep resolving to their own files, whose contents are the same already
+    fn reflink(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        self.existing(Operation::Reflink, src)?;
+        self.existing(Operation::Reflink, dst)?;
+        self.overlay.borrow_mut().ops.push(FsOp::Reflink {
+            src: src.to_path_buf(),
+            dst: dst.to_path_buf(),
+        });
+        Ok(())
+    }
+
+    /// Check free space on the inner filesystem; recorded changes take up no room
+    fn free_space(&self, p: &Path) -> Result<u64> {
+        let real = self.resolve(Operation::FreeSpace, p)?;
+        self.inner.free_space(&real)
+    }
+
+    /// Record that the file at `from` was moved to `to`
+    fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+        let real = self.existing(Operation::Rename, from)?;
+        let mut overlay = self.overlay.borrow_mut();
+        overlay.remove(from);
+        overlay.removed.remove(to);
+        overlay.added.insert(to.to_path_buf(), real);
+        overlay.ops.push(FsOp::Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        });
+        Ok(())
+    }
+}
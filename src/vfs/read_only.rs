@@ -0,0 +1,86 @@
+//! Wrap another VFS and refuse every change to it
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use vfs::{File, Operation, Result, VfsError, VFS};
+use hash::FileHash;
+
+/// A `VFS` that reads through to another one but fails every delete, link, and move
+/// with `PermissionDenied`, so code that must only look (e.g. `FilePrinter`) can't touch files
+#[derive(Debug, Clone)]
+pub struct ReadOnlyVfs<V: VFS> {
+    inner: V,
+}
+
+impl<V: VFS> ReadOnlyVfs<V> {
+    /// Guard `inner` against changes
+    pub fn new(inner: V) -> Self {
+        ReadOnlyVfs { inner }
+    }
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+}
+
+// helper: the error for trying to change a read-only filesystem
+// it is a bug in the caller, so it is logged loudly as well as returned
+fn refuse(op: Operation, path: &Path) -> VfsError {
+    let e = io::Error::new(io::ErrorKind::PermissionDenied, "Filesystem is read-only");
+    let err = VfsError::new(op, path, e);
+    error!("{}", err);
+    err
+}
+
+impl<V: VFS> VFS for ReadOnlyVfs<V> {
+    type FileIter = V::FileIter;
+
+    fn list_dir<P: AsRef<Path>>(
+        &self,
+        p: P,
+    ) -> Result<Box<dyn Iterator<Item = Result<Self::FileIter>>>> {
+        self.inner.list_dir(p)
+    }
+    fn get_metadata<P: AsRef<Path>>(&self, p: P) -> Result<<Self::FileIter as File>::MD> {
+        self.inner.get_metadata(p)
+    }
+    fn get_symlink_metadata<P: AsRef<Path>>(
+        &self,
+        p: P,
+    ) -> Result<<Self::FileIter as File>::MD> {
+        self.inner.get_symlink_metadata(p)
+    }
+    fn read_link<P: AsRef<Path>>(&self, p: P) -> Result<PathBuf> {
+        self.inner.read_link(p)
+    }
+    fn canonicalize(&self, p: &Path) -> Result<PathBuf> {
+        self.inner.canonicalize(p)
+    }
+    fn get_file(&self, p: &Path) -> Result<Self::FileIter> {
+        self.inner.get_file(p)
+    }
+    fn hash_path<H: FileHash>(&self, p: &Path) -> Result<<H as FileHash>::Output> {
+        self.inner.hash_path::<H>(p)
+    }
+    fn free_space(&self, p: &Path) -> Result<u64> {
+        self.inner.free_space(p)
+    }
+
+    /// Refuse to delete `p`
+    fn rm_file<P: AsRef<Path>>(&mut self, p: &P) -> Result<()> {
+        Err(refuse(Operation::Delete, p.as_ref()))
+    }
+    /// Refuse to create a link at `dst`
+    fn make_hard_link(&mut self, _src: &Path, dst: &Path) -> Result<()> {
+        Err(refuse(Operation::HardLink, dst))
+    }
+    /// Refuse to clone anything onto `dst`
+    fn reflink(&mut self, _src: &Path, dst: &Path) -> Result<()> {
+        Err(refuse(Operation::Reflink, dst))
+    }
+    /// Refuse to move anything onto `to`
+    fn rename(&mut self, _from: &Path, to: &Path) -> Result<()> {
+        Err(refuse(Operation::Rename, to))
+    }
+}
@@ -1,23 +1,50 @@
 // shim around real file system so it can be injected into
 // DirWalker.
+// File identity (device/inode) isn't portable, so that part is split into
+// a Unix and a Windows implementation; everything else goes through `std::fs`
 
 use std::path::{Path, PathBuf};
 use std::fs::{self, DirEntry};
-use std::os::unix::fs::{DirEntryExt, MetadataExt}; // need unix
-use std::os::linux::fs::MetadataExt as MetadataExt_linux; // ew
-use std::{io, time};
-use std::io::Read;
+#[cfg(unix)]
+use std::os::unix::fs::{DirEntryExt, MetadataExt};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(unix)]
+use std::{ffi, mem};
+#[cfg(unix)]
+use std::time;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
 
 use vfs::{File, MetaData, VFS};
-use vfs::{DeviceId, FileType, Inode};
-use helpers::FIRST_K_BYTES;
+use vfs::{FileType, Inode, Operation, Result, VfsError};
+#[cfg(unix)]
+use vfs::DeviceId;
+#[cfg(target_os = "macos")]
+use vfs::staging_path;
+use helpers::{FIRST_K_BYTES, HASH_CHUNK_SIZE, LAST_K_BYTES};
 use hash::FileHash;
 
+#[cfg(windows)]
+use self::windows::WinMetadata;
+
+// the metadata type of a real file
+// on Unix `fs::Metadata` already knows the file's device and inode
+#[cfg(unix)]
+type RealMetadata = fs::Metadata;
+#[cfg(windows)]
+type RealMetadata = WinMetadata;
+
 // Wrap our metadata trait around fs::Metadata.
+#[cfg(unix)]
 impl MetaData for fs::Metadata {
     fn get_len(&self) -> u64 {
         self.len()
     }
+    fn get_allocated_size(&self) -> u64 {
+        // st_blocks is always in units of 512 bytes
+        self.blocks() * 512
+    }
     fn get_mod_time(&self) -> io::Result<time::SystemTime> {
         self.modified()
     }
@@ -28,107 +55,426 @@ impl MetaData for fs::Metadata {
         Inode(self.ino())
     }
     fn get_device(&self) -> io::Result<DeviceId> {
-        Ok(DeviceId(self.st_dev()))
+        Ok(DeviceId(self.dev()))
+    }
+    fn get_nlink(&self) -> u64 {
+        self.nlink()
+    }
+    fn get_uid(&self) -> u32 {
+        self.uid()
+    }
+    fn get_mode(&self) -> u32 {
+        // leave out the file type bits
+        self.mode() & 0o7777
+    }
+}
+
+// read the metadata of a real file, following symlinks if `follow` is set
+#[cfg(unix)]
+fn read_metadata(p: &Path, follow: bool) -> io::Result<RealMetadata> {
+    if follow {
+        fs::metadata(p)
+    } else {
+        fs::symlink_metadata(p)
+    }
+}
+#[cfg(windows)]
+fn read_metadata(p: &Path, follow: bool) -> io::Result<RealMetadata> {
+    WinMetadata::read(p, follow)
+}
+
+// the space available to unprivileged users on the filesystem holding `p`
+#[cfg(unix)]
+fn free_space(p: &Path) -> io::Result<u64> {
+    let path = ffi::CString::new(p.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut st: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut st) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(st.f_bavail as u64 * st.f_frsize as u64)
+}
+#[cfg(windows)]
+fn free_space(p: &Path) -> io::Result<u64> {
+    windows::free_space(p)
+}
+
+// share `src`'s blocks with `dst` in place, so `dst` keeps its inode and metadata
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let src = fs::File::open(src)?;
+    let dst = fs::OpenOptions::new().write(true).open(dst)?;
+    if unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+// `clonefile` only creates new files: clone next to `dst`, give the clone `dst`'s
+// permissions, and move it over `dst`
+#[cfg(target_os = "macos")]
+fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    let c_path = |p: &Path| {
+        ffi::CString::new(p.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let staged = staging_path(dst);
+    if unsafe { libc::clonefile(c_path(src)?.as_ptr(), c_path(&staged)?.as_ptr(), 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let swapped = fs::metadata(dst)
+        .and_then(|md| fs::set_permissions(&staged, md.permissions()))
+        .and_then(|_| fs::rename(&staged, dst));
+    if swapped.is_err() {
+        // don't leave the clone lying around
+        let _ = fs::remove_file(&staged);
+    }
+    swapped
+}
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+fn reflink(_src: &Path, _dst: &Path) -> io::Result<()> {
+    let msg = "reflinks aren't supported on this platform";
+    Err(io::Error::new(io::ErrorKind::Unsupported, msg))
+}
+
+// Windows doesn't expose a file's identity through `fs::Metadata` (on stable),
+// so it is read from a handle to the file and stored alongside the metadata
+#[cfg(windows)]
+mod windows {
+    use std::{fs, io, mem, ptr, time};
+    use std::path::Path;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use std::os::windows::ffi::OsStrExt;
+
+    use winapi::um::fileapi::{GetDiskFreeSpaceExW, GetFileInformationByHandle,
+                              GetVolumeInformationByHandleW, BY_HANDLE_FILE_INFORMATION};
+    use winapi::um::winbase::{FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OPEN_REPARSE_POINT};
+    use winapi::um::winnt::{FILE_SUPPORTS_HARD_LINKS, ULARGE_INTEGER};
+
+    use vfs::{DeviceId, FileType, Inode, MetaData};
+
+    /// `fs::Metadata` along with the volume serial number, file index, and link count of the file
+    #[derive(Debug, Clone)]
+    pub struct WinMetadata {
+        md: fs::Metadata,
+        volume: u64,
+        index: u64,
+        links: u64,
+    }
+
+    // open a handle to `p` for querying only (no read access needed)
+    // backup semantics are required to get a handle to a directory
+    fn open(p: &Path, follow: bool) -> io::Result<fs::File> {
+        let mut flags = FILE_FLAG_BACKUP_SEMANTICS;
+        if !follow {
+            flags |= FILE_FLAG_OPEN_REPARSE_POINT;
+        }
+        fs::OpenOptions::new()
+            .access_mode(0)
+            .custom_flags(flags)
+            .open(p)
+    }
+
+    impl WinMetadata {
+        /// Read the metadata and identity of `p`, following symlinks if `follow` is set
+        pub fn read(p: &Path, follow: bool) -> io::Result<Self> {
+            let md = if follow {
+                fs::metadata(p)?
+            } else {
+                fs::symlink_metadata(p)?
+            };
+            let file = open(p, follow)?;
+            let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { mem::zeroed() };
+            if unsafe { GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(WinMetadata {
+                md,
+                volume: u64::from(info.dwVolumeSerialNumber),
+                index: u64::from(info.nFileIndexHigh) << 32 | u64::from(info.nFileIndexLow),
+                links: u64::from(info.nNumberOfLinks),
+            })
+        }
+    }
+
+    impl MetaData for WinMetadata {
+        fn get_len(&self) -> u64 {
+            self.md.len()
+        }
+        fn get_allocated_size(&self) -> u64 {
+            // there's no cheap equivalent of `st_blocks`; assume fully allocated
+            self.md.len()
+        }
+        fn get_mod_time(&self) -> io::Result<time::SystemTime> {
+            self.md.modified()
+        }
+        fn get_type(&self) -> FileType {
+            self.md.file_type().into()
+        }
+        fn get_inode(&self) -> Inode {
+            Inode(self.index)
+        }
+        fn get_device(&self) -> io::Result<DeviceId> {
+            Ok(DeviceId(self.volume))
+        }
+        fn get_nlink(&self) -> u64 {
+            self.links
+        }
+        fn get_uid(&self) -> u32 {
+            // owners are SIDs rather than numbers; treat every file as having the same one
+            0
+        }
+        fn get_mode(&self) -> u32 {
+            // the only permission is the read-only flag
+            if self.md.permissions().readonly() {
+                0o444
+            } else {
+                0o644
+            }
+        }
+    }
+
+    /// The space available to the current user on the volume holding `p`
+    pub fn free_space(p: &Path) -> io::Result<u64> {
+        let wide: Vec<u16> = p.as_os_str().encode_wide().chain(Some(0)).collect();
+        let mut available: ULARGE_INTEGER = unsafe { mem::zeroed() };
+        let ok = unsafe {
+            GetDiskFreeSpaceExW(
+                wide.as_ptr(),
+                &mut available,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { *available.QuadPart() })
+    }
+
+    /// Whether the volume holding `p` can store hard links (NTFS can, FAT can't)
+    pub fn supports_hard_links(p: &Path) -> io::Result<bool> {
+        let file = open(p, true)?;
+        let mut flags = 0;
+        let ok = unsafe {
+            GetVolumeInformationByHandleW(
+                file.as_raw_handle() as _,
+                ptr::null_mut(),
+                0,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut flags,
+                ptr::null_mut(),
+                0,
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(flags & FILE_SUPPORTS_HARD_LINKS != 0)
     }
 }
 
 // wrapping our File interface around the stdd DirEntry.
 impl File for DirEntry {
-    type MD = fs::Metadata;
+    type MD = RealMetadata;
 
     fn get_path(&self) -> PathBuf {
         // warning: heap
         self.path()
     }
-    fn get_inode(&self) -> io::Result<Inode> {
-        // unix only
+    #[cfg(unix)]
+    fn get_inode(&self) -> Result<Inode> {
         Ok(Inode(self.ino()))
     }
-    fn get_type(&self) -> io::Result<FileType> {
+    #[cfg(windows)]
+    fn get_inode(&self) -> Result<Inode> {
+        Ok(self.get_metadata()?.get_inode())
+    }
+    fn get_type(&self) -> Result<FileType> {
         // free/guaranteed on _most_ unixes... not sure when it's not
         // seems to be free on mine
-        let ft = self.file_type()?;
+        let ft = self.file_type()
+            .map_err(VfsError::wrap(Operation::ReadMetadata, self.path()))?;
         Ok(ft.into())
     }
-    fn get_metadata(&self) -> io::Result<fs::Metadata> {
-        self.metadata()
+    fn get_metadata(&self) -> Result<RealMetadata> {
+        // like `DirEntry::metadata`, this doesn't follow symlinks
+        let path = self.path();
+        read_metadata(&path, false).map_err(VfsError::wrap(Operation::ReadMetadata, &path))
     }
-    //fn get_first_bytes(&self) -> io::Result<FirstBytes> { }
-    fn get_first_bytes<H: FileHash>(&self) -> io::Result<<H as FileHash>::Output> {
-        let mut bytes = [0u8; FIRST_K_BYTES];
+    fn get_first_bytes<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        // `read` may stop short, so read until K bytes or EOF, whichever comes first
+        let mut bytes = Vec::with_capacity(FIRST_K_BYTES);
         let path = self.get_path();
-        let mut file = fs::File::open(&path)?;
-        file.read(&mut bytes)?;
+        let file = fs::File::open(&path).map_err(VfsError::wrap(Operation::OpenFile, &path))?;
+        file.take(FIRST_K_BYTES as u64)
+            .read_to_end(&mut bytes)
+            .map_err(VfsError::wrap(Operation::ReadContents, &path))?;
         Ok(H::hash(&bytes))
-        //Ok(FirstBytes(bytes))
     }
-    fn get_hash<H: FileHash>(&self) -> io::Result<<H as FileHash>::Output> {
+    fn get_last_bytes<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        let mut bytes = Vec::with_capacity(LAST_K_BYTES);
         let path = self.get_path();
-        let mut file = fs::File::open(&path)?;
-        let mut v = vec![];
-        file.read_to_end(&mut v)?;
-        Ok(H::hash(&v))
+        let mut file =
+            fs::File::open(&path).map_err(VfsError::wrap(Operation::OpenFile, &path))?;
+        let len = file.metadata()
+            .map_err(VfsError::wrap(Operation::ReadMetadata, &path))?
+            .len();
+        file.seek(SeekFrom::Start(len.saturating_sub(LAST_K_BYTES as u64)))
+            .and_then(|_| file.read_to_end(&mut bytes))
+            .map_err(VfsError::wrap(Operation::ReadContents, &path))?;
+        Ok(H::hash(&bytes))
+    }
+    fn get_hash<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        hash_contents::<H>(&self.get_path(), None)
+    }
+    fn get_hash_with_progress<H: FileHash>(
+        &self,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<<H as FileHash>::Output> {
+        hash_contents::<H>(&self.get_path(), Some(progress))
+    }
+}
+
+// hash the file at `path` a chunk at a time so it never has to fit in memory,
+// passing `progress` the bytes hashed so far and the file's length after each chunk
+fn hash_contents<H: FileHash>(
+    path: &Path,
+    mut progress: Option<&mut dyn FnMut(u64, u64)>,
+) -> Result<<H as FileHash>::Output> {
+    let mut file = fs::File::open(path).map_err(VfsError::wrap(Operation::OpenFile, path))?;
+    // the length is only needed to report progress
+    let len = match progress {
+        Some(_) => file.metadata()
+            .map_err(VfsError::wrap(Operation::ReadMetadata, path))?
+            .len(),
+        None => 0,
+    };
+    let mut state = H::start();
+    let mut buf = vec![0; HASH_CHUNK_SIZE];
+    let mut hashed = 0;
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                H::update(&mut state, &buf[..n]);
+                hashed += n as u64;
+                if let Some(ref mut progress) = progress {
+                    progress(hashed, len);
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(VfsError::new(Operation::ReadContents, path, e)),
+        }
     }
+    Ok(H::finish(state))
 }
 
 /// Empty struct representing the Real Filesystem. Calls go through `std::fs` to access real files
+/// Case sensitivity is whatever the OS and filesystem provide
+/// (`TestFileSystem::set_case_insensitive` mimics the insensitive ones)
 #[derive(Debug, Clone, Copy)]
 pub struct RealFileSystem;
 
+impl RealFileSystem {
+    /// Check whether hard links can be created on the filesystem holding `p`
+    /// Always true on Unix, where a failed link is reported when it is attempted
+    #[cfg(unix)]
+    pub fn supports_hard_links<P: AsRef<Path>>(&self, _p: P) -> Result<bool> {
+        Ok(true)
+    }
+    /// Check whether hard links can be created on the filesystem holding `p`
+    #[cfg(windows)]
+    pub fn supports_hard_links<P: AsRef<Path>>(&self, p: P) -> Result<bool> {
+        windows::supports_hard_links(p.as_ref()).map_err(VfsError::wrap(Operation::ReadMetadata, p))
+    }
+}
+
 impl VFS for RealFileSystem {
     type FileIter = DirEntry;
 
     /// Get an iterator over the contents of directory P
-    fn list_dir<P: AsRef<Path>>(
-        &self,
-        p: P,
-    ) -> io::Result<Box<Iterator<Item = io::Result<DirEntry>>>> {
-        match ::std::fs::read_dir(p) {
-            Ok(rd) => Ok(Box::new(rd)),
-            Err(e) => Err(e),
+    /// Entries are classified without following symlinks (see `DirEntry::file_type`),
+    /// so links (even broken ones) are listed as `FileType::Symlink` for the caller to judge
+    fn list_dir<P: AsRef<Path>>(&self, p: P) -> Result<Box<Iterator<Item = Result<DirEntry>>>> {
+        let dir = p.as_ref().to_path_buf();
+        match ::std::fs::read_dir(&dir) {
+            Ok(rd) => Ok(Box::new(
+                rd.map(move |e| e.map_err(VfsError::wrap(Operation::ListDir, &dir))),
+            )),
+            Err(e) => Err(VfsError::new(Operation::ListDir, p, e)),
         }
     }
 
     /// Look up the metadata for P (follows symlinks)
-    fn get_metadata<P: AsRef<Path>>(&self, p: P) -> io::Result<<Self::FileIter as File>::MD> {
-        fs::metadata(p)
+    fn get_metadata<P: AsRef<Path>>(&self, p: P) -> Result<<Self::FileIter as File>::MD> {
+        read_metadata(p.as_ref(), true).map_err(VfsError::wrap(Operation::ReadMetadata, p))
     }
 
     /// Look up the metadata for symlink P (don't follow symlinks)
-    fn get_symlink_metadata<P: AsRef<Path>>(
-        &self,
-        p: P,
-    ) -> io::Result<<Self::FileIter as File>::MD> {
-        fs::symlink_metadata(p)
+    fn get_symlink_metadata<P: AsRef<Path>>(&self, p: P) -> Result<<Self::FileIter as File>::MD> {
+        read_metadata(p.as_ref(), false).map_err(VfsError::wrap(Operation::ReadMetadata, p))
     }
 
     /// Resolve symlink P to its target path
-    fn read_link<P: AsRef<Path>>(&self, p: P) -> io::Result<PathBuf> {
-        fs::read_link(p)
+    fn read_link<P: AsRef<Path>>(&self, p: P) -> Result<PathBuf> {
+        fs::read_link(&p).map_err(VfsError::wrap(Operation::ReadLink, p))
+    }
+
+    /// Resolve `p` with `std::fs::canonicalize`
+    fn canonicalize(&self, p: &Path) -> Result<PathBuf> {
+        fs::canonicalize(p).map_err(VfsError::wrap(Operation::Canonicalize, p))
     }
 
     /// Look up a File object from its path
-    fn get_file(&self, p: &Path) -> io::Result<Self::FileIter> {
+    fn get_file(&self, p: &Path) -> Result<Self::FileIter> {
         // this is a little hacky for the RealFileSystem
         // the only way to generate a DirEntry is by iterating over a directory
         // so we have to iterate over the parent directory and identify `p`
         let dir = p.parent().expect("Called get_file() on root dir");
         match fs::read_dir(dir)
-            .expect("Couldn't ls file dir")
+            .map_err(VfsError::wrap(Operation::ListDir, dir))?
             .find(|e| e.as_ref().map(|i| i.path() == p).unwrap_or(false))
         {
-            Some(f) => Ok(f.unwrap()),
-            None => Err(io::Error::new(io::ErrorKind::NotFound, "No such file")),
+            Some(f) => f.map_err(VfsError::wrap(Operation::OpenFile, p)),
+            None => Err(VfsError::new(
+                Operation::OpenFile,
+                p,
+                io::Error::new(io::ErrorKind::NotFound, "No such file"),
+            )),
         }
     }
 
+    /// Hash the file at `p` without looking up its `DirEntry`
+    fn hash_path<H: FileHash>(&self, p: &Path) -> Result<<H as FileHash>::Output> {
+        hash_contents::<H>(p, None)
+    }
+
     /// Delete a file on the real system
-    fn rm_file<P: AsRef<Path>>(&mut self, p: &P) -> io::Result<()> {
-        fs::remove_file(p)
+    fn rm_file<P: AsRef<Path>>(&mut self, p: &P) -> Result<()> {
+        fs::remove_file(p).map_err(VfsError::wrap(Operation::Delete, p))
+    }
+
+    /// Create hard link at `dst` pointing to `src`
+    fn make_hard_link(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        fs::hard_link(src, dst).map_err(VfsError::wrap(Operation::HardLink, dst))
+    }
+
+    /// Clone `src` onto `dst` (`FICLONE` on Linux, `clonefile` on macOS)
+    fn reflink(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        reflink(src, dst).map_err(VfsError::wrap(Operation::Reflink, dst))
+    }
+
+    /// Move `from` to `to`, replacing `to` if it exists
+    fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+        fs::rename(from, to).map_err(VfsError::wrap(Operation::Rename, to))
     }
 
-    /// Create hard link from `src` to `dst`
-    fn make_link(&mut self, src: &Path, dst: &Path) -> io::Result<()> {
-        fs::hard_link(dst, src)
+    /// Ask the OS how much space is left (`statvfs` on Unix)
+    fn free_space(&self, p: &Path) -> Result<u64> {
+        free_space(p).map_err(VfsError::wrap(Operation::FreeSpace, p))
     }
 }
@@ -0,0 +1,181 @@
+//! Wrap another VFS and retry reads that fail for transient reasons (e.g. on network mounts)
+
+use std::{io, thread};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use vfs::{File, FileType, Inode, Result, VFS};
+use hash::FileHash;
+
+/// How many times to retry a read that failed transiently, and how long to wait before
+/// the first retry (each later wait is twice as long)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    retries: u32,
+    delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Retry up to `retries` times, first waiting `delay`
+    pub fn new(retries: u32, delay: Duration) -> Self {
+        RetryPolicy { retries, delay }
+    }
+
+    // run `op` until it succeeds, fails for good, or runs out of retries
+    fn run<T, F: FnMut() -> Result<T>>(&self, mut op: F) -> Result<T> {
+        let mut delay = self.delay;
+        let mut attempt = 0;
+        loop {
+            match op() {
+                Err(ref e) if attempt < self.retries && is_transient(e.kind()) => {
+                    debug!("Retrying in {:?}: {}", delay, e);
+                    thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether an error of this kind may go away if the operation is simply tried again
+/// (`NotFound`, `PermissionDenied`, and the like never do)
+pub fn is_transient(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::TimedOut | io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    )
+}
+
+/// A `VFS` that reads through to another one, retrying lookups and reads that fail
+/// transiently according to its `RetryPolicy`
+/// Changes (deletes, links, and moves) are never retried, as they may have half happened
+#[derive(Debug, Clone)]
+pub struct RetryVfs<V: VFS> {
+    inner: V,
+    policy: RetryPolicy,
+}
+
+impl<V: VFS> RetryVfs<V> {
+    /// Retry reads on `inner` according to `policy`
+    pub fn new(inner: V, policy: RetryPolicy) -> Self {
+        RetryVfs { inner, policy }
+    }
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+    // keep retrying reads of a file from the inner filesystem
+    fn wrap(&self, inner: V::FileIter) -> RetryFile<V::FileIter> {
+        RetryFile {
+            inner,
+            policy: self.policy,
+        }
+    }
+}
+
+/// A file from the inner filesystem whose metadata and contents are read with retries
+#[derive(Debug, Clone)]
+pub struct RetryFile<F: File> {
+    inner: F,
+    policy: RetryPolicy,
+}
+
+impl<F: File> File for RetryFile<F> {
+    type MD = F::MD;
+
+    fn get_inode(&self) -> Result<Inode> {
+        self.policy.run(|| self.inner.get_inode())
+    }
+    fn get_path(&self) -> PathBuf {
+        self.inner.get_path()
+    }
+    fn get_type(&self) -> Result<FileType> {
+        self.policy.run(|| self.inner.get_type())
+    }
+    fn get_metadata(&self) -> Result<Self::MD> {
+        self.policy.run(|| self.inner.get_metadata())
+    }
+    fn get_first_bytes<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        self.policy.run(|| self.inner.get_first_bytes::<H>())
+    }
+    fn get_last_bytes<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        self.policy.run(|| self.inner.get_last_bytes::<H>())
+    }
+    fn get_hash<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        self.policy.run(|| self.inner.get_hash::<H>())
+    }
+    // a retry reads the file again from the start, so its progress is only passed on once
+    // it gets past where the failed attempts got to; that way it never goes backwards
+    fn get_hash_with_progress<H: FileHash>(
+        &self,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<<H as FileHash>::Output> {
+        let mut reported = 0;
+        self.policy.run(|| {
+            self.inner.get_hash_with_progress::<H>(&mut |done, total| {
+                if done > reported {
+                    reported = done;
+                    progress(done, total);
+                }
+            })
+        })
+    }
+}
+
+impl<V> VFS for RetryVfs<V>
+where
+    V: VFS,
+    V::FileIter: 'static,
+{
+    type FileIter = RetryFile<V::FileIter>;
+
+    fn list_dir<P: AsRef<Path>>(
+        &self,
+        p: P,
+    ) -> Result<Box<dyn Iterator<Item = Result<Self::FileIter>>>> {
+        let policy = self.policy;
+        let files = self.policy.run(|| self.inner.list_dir(p.as_ref()))?;
+        Ok(Box::new(
+            files.map(move |f| f.map(|inner| RetryFile { inner, policy })),
+        ))
+    }
+    fn get_metadata<P: AsRef<Path>>(&self, p: P) -> Result<<Self::FileIter as File>::MD> {
+        self.policy.run(|| self.inner.get_metadata(p.as_ref()))
+    }
+    fn get_symlink_metadata<P: AsRef<Path>>(
+        &self,
+        p: P,
+    ) -> Result<<Self::FileIter as File>::MD> {
+        self.policy.run(|| self.inner.get_symlink_metadata(p.as_ref()))
+    }
+    fn read_link<P: AsRef<Path>>(&self, p: P) -> Result<PathBuf> {
+        self.policy.run(|| self.inner.read_link(p.as_ref()))
+    }
+    fn canonicalize(&self, p: &Path) -> Result<PathBuf> {
+        self.policy.run(|| self.inner.canonicalize(p))
+    }
+    fn get_file(&self, p: &Path) -> Result<Self::FileIter> {
+        let file = self.policy.run(|| self.inner.get_file(p))?;
+        Ok(self.wrap(file))
+    }
+    fn hash_path<H: FileHash>(&self, p: &Path) -> Result<<H as FileHash>::Output> {
+        self.policy.run(|| self.inner.hash_path::<H>(p))
+    }
+    fn free_space(&self, p: &Path) -> Result<u64> {
+        self.policy.run(|| self.inner.free_space(p))
+    }
+    fn rm_file<P: AsRef<Path>>(&mut self, p: &P) -> Result<()> {
+        self.inner.rm_file(p)
+    }
+    fn make_hard_link(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        self.inner.make_hard_link(src, dst)
+    }
+    fn reflink(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        self.inner.reflink(src, dst)
+    }
+    fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.rename(from, to)
+    }
+}
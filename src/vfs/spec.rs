@@ -0,0 +1,191 @@
+//! Build small mock filesystems from a textual listing instead of builder calls
+
+use std::{error, fmt, result};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::time::{Duration, UNIX_EPOCH};
+
+use helpers::ID;
+use vfs::{TestFile, TestFileSystem, TestMD};
+
+/// A line of a listing passed to `TestFileSystem::from_spec` that couldn't be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecError {
+    /// The line number, starting at 1
+    pub line: usize,
+    /// What was wrong with it
+    pub message: String,
+}
+
+// e.g. "line 3: unknown entry `fil`"
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for SpecError {}
+
+// a piece of a line: a bare word or a 'quoted string'
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+}
+
+impl Token {
+    // quoted strings never match keywords
+    fn word(&self) -> Option<&str> {
+        match *self {
+            Token::Word(ref w) => Some(w),
+            Token::Quoted(_) => None,
+        }
+    }
+}
+
+// split a line into words, keeping 'quoted strings' together
+// quoted strings may contain spaces and the escapes \\, \', \n, and \0
+fn tokenize(line: &str) -> result::Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '\'' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    None => return Err(String::from("unterminated quote")),
+                    Some('\'') => break,
+                    Some('\\') => match chars.next() {
+                        Some('\\') => s.push('\\'),
+                        Some('\'') => s.push('\''),
+                        Some('n') => s.push('\n'),
+                        Some('0') => s.push('\0'),
+                        Some(e) => return Err(format!("unknown escape `\\{}`", e)),
+                        None => return Err(String::from("unterminated quote")),
+                    },
+                    Some(c) => s.push(c),
+                }
+            }
+            tokens.push(Token::Quoted(s));
+        } else {
+            let mut s = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                s.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(s));
+        }
+    }
+    Ok(tokens)
+}
+
+// parse a `key=N` annotation, e.g. `size=4096`
+fn annotation(token: &str) -> result::Result<(&str, u64), String> {
+    let mut parts = token.splitn(2, '=');
+    let key = parts.next().unwrap_or("");
+    let val = parts
+        .next()
+        .ok_or_else(|| format!("expected `key=value`, found `{}`", token))?;
+    let n = val.parse()
+        .map_err(|_| format!("`{}` needs a number, found `{}`", key, val))?;
+    Ok((key, n))
+}
+
+impl TestFileSystem {
+    /// Build a filesystem from a listing with one entry per line:
+    ///
+    /// ```text
+    /// dir /x
+    /// file /x/a 'hello' mtime=100
+    /// file /x/big size=4096 inode=7
+    /// link /x/b -> /x/a
+    /// ```
+    ///
+    /// `file` takes optional quoted contents and `size=`, `mtime=` (seconds since the epoch),
+    /// `inode=`, and `dev=` annotations; files get a fresh inode on device 0 unless given
+    /// Indentation, blank lines, and lines starting with `#` are ignored
+    pub fn from_spec(spec: &str) -> result::Result<Rc<RefCell<Self>>, SpecError> {
+        let fs = TestFileSystem::new();
+        for (i, line) in spec.lines().enumerate() {
+            let err = |message| SpecError {
+                line: i + 1,
+                message,
+            };
+            let tokens = tokenize(line).map_err(&err)?;
+            let words: Vec<_> = tokens.iter().map(Token::word).collect();
+            match words.first() {
+                None => {}
+                Some(&Some(t)) if t.starts_with('#') => {}
+                Some(&Some("dir")) => match words[..] {
+                    [_, Some(path)] => fs.borrow_mut().create_dir(path),
+                    _ => return Err(err(String::from("expected `dir PATH`"))),
+                },
+                Some(&Some("link")) => match words[..] {
+                    [_, Some(path), Some("->"), Some(target)] => {
+                        fs.borrow_mut().create_symlink(path, target)
+                    }
+                    _ => return Err(err(String::from("expected `link PATH -> TARGET`"))),
+                },
+                Some(&Some("file")) => {
+                    let file = parse_file(&fs.borrow(), &tokens).map_err(&err)?;
+                    fs.borrow_mut().add(file);
+                }
+                Some(&Some(t)) => return Err(err(format!("unknown entry `{}`", t))),
+                Some(&None) => return Err(err(String::from("expected an entry, found a quote"))),
+            }
+        }
+        // the listing is the starting state, not something a test did
+        fs.borrow_mut().clear_ops();
+        Ok(fs)
+    }
+}
+
+// build the file described by `file PATH ['CONTENTS'] [key=N]...`
+fn parse_file(fs: &TestFileSystem, tokens: &[Token]) -> result::Result<TestFile, String> {
+    let path = tokens
+        .get(1)
+        .and_then(Token::word)
+        .ok_or_else(|| String::from("expected `file PATH`"))?;
+    let mut contents = None;
+    let mut md = TestMD::new().with_mod_time(UNIX_EPOCH);
+    let mut size = None;
+    let mut inode = fs.num_elements() as u64;
+    let mut dev = 0;
+    for (j, token) in tokens.iter().enumerate().skip(2) {
+        let token = match *token {
+            Token::Quoted(ref c) if j == 2 => {
+                contents = Some(c.clone());
+                continue;
+            }
+            Token::Quoted(_) => {
+                return Err(String::from("contents must come right after the path"));
+            }
+            Token::Word(ref w) => w,
+        };
+        match annotation(token)? {
+            ("size", n) => size = Some(n),
+            ("mtime", n) => md = md.with_mod_time(UNIX_EPOCH + Duration::from_secs(n)),
+            ("inode", n) => inode = n,
+            ("dev", n) => dev = n,
+            (key, _) => return Err(format!("unknown annotation `{}`", key)),
+        }
+    }
+    if let Some(n) = size {
+        md = md.with_len(n);
+    }
+    let md = md.with_id(ID { dev, inode });
+    let file = TestFile::new(path).with_metadata(md).with_inode(inode);
+    match (contents, size) {
+        (Some(ref c), Some(n)) if c.len() as u64 != n => {
+            Err(format!("`size={}` disagrees with {} bytes of contents", n, c.len()))
+        }
+        (Some(c), _) => Ok(file.with_contents(c)),
+        (None, _) => Ok(file),
+    }
+}
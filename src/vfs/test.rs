@@ -0,0 +1,896 @@
+#[cfg(test)]
+mod test {
+
+    use std::{env, fs, io, process};
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, UNIX_EPOCH};
+    use std::collections::HashSet;
+
+    use helpers::{HASH_CHUNK_SIZE, ID};
+    use hash::{FileHash, Md5Sum};
+    use vfs::{is_transient, staging_path, File, FileType, FsOp, GeneratedTree, MetaData, OnError,
+              Operation, OverlayFileSystem, RealFileSystem, RetryPolicy, RetryVfs, SpecError,
+              TestFile, TestFileSystem, TestMD, TextNormalizedVfs, TreeSpec, VfsError, VFS};
+
+    // helper: collect the paths of the direct children of `dir`
+    fn children<V: VFS>(fs: &V, dir: &str) -> HashSet<PathBuf> {
+        fs.list_dir(Path::new(dir))
+            .expect("Failed to list dir")
+            .map(|f| f.expect("Failed to read entry").get_path())
+            .collect()
+    }
+
+    #[test]
+    fn list_dir_root() {
+        // listing the root only yields its direct children
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.create_file("/a");
+            fs.create_dir("/x");
+            fs.create_file("/x/b");
+            fs.create_symlink("/c", "/a");
+            fs.create_symlink("/x/d", "/a");
+        }
+        let found = children(&fs, "/");
+        assert_eq!(3, found.len());
+        assert!(found.contains(Path::new("/a")));
+        assert!(found.contains(Path::new("/x")));
+        assert!(found.contains(Path::new("/c")));
+    }
+
+    #[test]
+    fn list_dir_nested() {
+        // listing a subdirectory yields neither root-level nor grandchild entries
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.create_file("/a");
+            fs.create_symlink("/c", "/a");
+            fs.create_dir("/x");
+            fs.create_file("/x/b");
+            fs.create_symlink("/x/d", "/a");
+            fs.create_dir("/x/y");
+            fs.create_file("/x/y/e");
+        }
+        let found = children(&fs, "/x");
+        assert_eq!(3, found.len());
+        assert!(found.contains(Path::new("/x/b")));
+        assert!(found.contains(Path::new("/x/d")));
+        assert!(found.contains(Path::new("/x/y")));
+
+        let found = children(&fs, "/x/y");
+        assert_eq!(1, found.len());
+        assert!(found.contains(Path::new("/x/y/e")));
+    }
+
+    #[test]
+    fn list_dir_empty() {
+        // an empty directory has no children, even if the root does
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.create_file("/a");
+            fs.create_dir("/x");
+        }
+        assert!(children(&fs, "/x").is_empty());
+    }
+
+    // helper: exercise `make_hard_link` on any VFS
+    // `dir` must contain a file `a` and must not contain `b`
+    fn check_hard_link<V: VFS>(mut fs: V, dir: &Path) {
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        fs.make_hard_link(&a, &b).expect("Failed to create link");
+        let md_a = fs.get_metadata(&a).unwrap();
+        let md_b = fs.get_metadata(&b).unwrap();
+        assert_eq!(md_a.get_inode(), md_b.get_inode());
+        assert_eq!(md_a.get_len(), md_b.get_len());
+        assert_eq!(2, md_a.get_nlink());
+        assert_eq!(2, md_b.get_nlink());
+        // linking over an existing file is an error
+        let err = fs.make_hard_link(&a, &b).unwrap_err();
+        assert_eq!(io::ErrorKind::AlreadyExists, err.kind());
+    }
+
+    #[test]
+    fn hard_link_test_fs() {
+        // the link shares the inode and contents of its source
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.add(
+                TestFile::new("/a")
+                    .with_contents(String::from("AAAA"))
+                    .with_metadata(TestMD::new())
+                    .with_inode(1),
+            );
+        }
+        check_hard_link(fs.clone(), Path::new("/"));
+        assert_eq!(3, fs.borrow().num_elements());
+        assert_eq!(2, fs.borrow().num_inodes());
+        let b = fs.get_file(Path::new("/b")).unwrap();
+        let a = fs.get_file(Path::new("/a")).unwrap();
+        assert_eq!(a.get_path(), PathBuf::from("/a"));
+        assert_eq!(b.get_path(), PathBuf::from("/b"));
+        assert_eq!(
+            a.get_hash::<Md5Sum>().unwrap(),
+            b.get_hash::<Md5Sum>().unwrap()
+        );
+    }
+
+    #[test]
+    fn hard_link_test_fs_across_devices() {
+        // linking into a directory on another device fails with EXDEV
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.add(
+                TestFile::new("/mnt")
+                    .with_kind(FileType::Dir)
+                    .with_metadata(TestMD::new().with_id(ID { inode: 2, dev: 1 })),
+            );
+            fs.add(
+                TestFile::new("/a").with_metadata(TestMD::new().with_id(ID { inode: 3, dev: 0 })),
+            );
+        }
+        let err = fs.clone()
+            .make_hard_link(Path::new("/a"), Path::new("/mnt/b"))
+            .unwrap_err();
+        assert_eq!(Some(18), err.cause().raw_os_error());
+        assert_eq!(3, fs.borrow().num_elements());
+    }
+
+    #[test]
+    fn rename_test_fs() {
+        // moving replaces the destination, symlinks move themselves, devices can't be crossed
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'A' inode=1
+             file /b 'B' inode=2
+             link /l -> /a",
+        ).unwrap();
+        fs.borrow_mut().add(
+            TestFile::new("/mnt")
+                .with_kind(FileType::Dir)
+                .with_metadata(TestMD::new().with_id(ID { inode: 9, dev: 1 })),
+        );
+        let mut vfs = fs.clone();
+
+        vfs.rename(Path::new("/a"), Path::new("/b")).unwrap();
+        assert!(fs.get_file(Path::new("/a")).is_err());
+        let moved = fs.get_file(Path::new("/b")).unwrap();
+        assert_eq!(PathBuf::from("/b"), moved.get_path());
+        assert_eq!(Md5Sum::hash(b"A"), moved.get_hash::<Md5Sum>().unwrap());
+
+        vfs.rename(Path::new("/l"), Path::new("/m")).unwrap();
+        assert_eq!(PathBuf::from("/a"), fs.read_link("/m").unwrap());
+        assert!(fs.read_link("/l").is_err());
+        assert!(fs.get_file(Path::new("/a")).is_err());
+
+        let err = vfs.rename(Path::new("/b"), Path::new("/mnt/b")).unwrap_err();
+        assert_eq!(Some(18), err.cause().raw_os_error());
+        assert!(fs.get_file(Path::new("/b")).is_ok());
+        let err = vfs.rename(Path::new("/nope"), Path::new("/c")).unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+    }
+
+    #[test]
+    fn nlink_test_fs() {
+        // every path to a file sees the same link count as links come and go
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'A' inode=1
+             file /b 'B' inode=2",
+        ).unwrap();
+        let nlink = |p| fs.get_metadata(p).unwrap().get_nlink();
+        assert_eq!((1, 1), (nlink("/a"), nlink("/b")));
+        let mut vfs = fs.clone();
+        vfs.make_hard_link(Path::new("/a"), Path::new("/c")).unwrap();
+        vfs.make_hard_link(Path::new("/c"), Path::new("/d")).unwrap();
+        assert_eq!((3, 3, 3), (nlink("/a"), nlink("/c"), nlink("/d")));
+        vfs.rm_file(&Path::new("/d")).unwrap();
+        assert_eq!((2, 2), (nlink("/a"), nlink("/c")));
+        // moving a link over another file unlinks that file, not the one moved
+        vfs.make_hard_link(Path::new("/b"), Path::new("/e")).unwrap();
+        vfs.rename(Path::new("/c"), Path::new("/e")).unwrap();
+        assert_eq!((2, 2, 1), (nlink("/a"), nlink("/e"), nlink("/b")));
+        // the count can also be set directly
+        let md = TestMD::new().with_nlink(5);
+        assert_eq!(5, md.get_nlink());
+    }
+
+    #[test]
+    fn canonicalize_test_fs() {
+        // symlinks anywhere in the path are followed, and `.` and `..` are resolved
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             dir /x
+             dir /x/y
+             file /x/y/f
+             link /l -> /x
+             link /x/up -> ..
+             link /loop -> /loop2
+             link /loop2 -> /loop
+             link /broken -> /nowhere",
+        ).unwrap();
+        let canon = |p: &str| fs.canonicalize(Path::new(p));
+        assert_eq!(PathBuf::from("/x/y/f"), canon("/l/y/f").unwrap());
+        assert_eq!(PathBuf::from("/x/y"), canon("/x/./y/../y").unwrap());
+        assert_eq!(PathBuf::from("/x/y"), canon("/l/up/l/y").unwrap());
+        assert_eq!(PathBuf::from("/"), canon("/").unwrap());
+        assert_eq!(Some(40), canon("/loop").unwrap_err().cause().raw_os_error());
+        assert_eq!(io::ErrorKind::NotFound, canon("/broken").unwrap_err().kind());
+        assert_eq!(io::ErrorKind::NotFound, canon("/l/z").unwrap_err().kind());
+    }
+
+    #[test]
+    fn case_insensitive_test_fs() {
+        // any case finds the file, but it keeps the case it was created with
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.set_case_insensitive(true);
+            fs.create_dir("/");
+            fs.create_dir("/Dir");
+            fs.add(
+                TestFile::new("/Dir/Foo.txt")
+                    .with_contents(String::from("foo"))
+                    .with_metadata(TestMD::new()),
+            );
+            fs.create_symlink("/link", "/DIR/FOO.TXT");
+        }
+        let file = fs.get_file(Path::new("/dir/foo.TXT")).unwrap();
+        assert_eq!(PathBuf::from("/Dir/Foo.txt"), file.get_path());
+        assert_eq!(FileType::File, fs.get_metadata("/DIR/foo.txt").unwrap().get_type());
+        assert_eq!(children(&fs, "/dir"), children(&fs, "/Dir"));
+        let canon = fs.canonicalize(Path::new("/link")).unwrap();
+        assert_eq!(PathBuf::from("/Dir/Foo.txt"), canon);
+        // the default stays case-sensitive
+        let fs = TestFileSystem::from_spec("dir /\nfile /Foo.txt").unwrap();
+        assert!(fs.get_file(Path::new("/foo.txt")).is_err());
+    }
+
+    #[test]
+    fn hard_link_real_fs() {
+        // same checks against the real filesystem in a scratch directory
+        let dir = env::temp_dir().join(format!("smllr_hard_link_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a"), b"AAAA").unwrap();
+        check_hard_link(RealFileSystem, &dir);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn id_of_metadata() {
+        // `ID::of` is the one place file identity is built from metadata
+        let id = ID { dev: 3, inode: 7 };
+        assert_eq!(id, ID::of(&TestMD::new().with_id(id)).unwrap());
+
+        // on a real filesystem hard links share an ID and distinct files don't
+        let dir = env::temp_dir().join(format!("smllr_id_of_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a"), b"AAAA").unwrap();
+        fs::write(dir.join("c"), b"AAAA").unwrap();
+        fs::hard_link(dir.join("a"), dir.join("b")).unwrap();
+        let id_of = |name| ID::of(&RealFileSystem.get_metadata(dir.join(name)).unwrap()).unwrap();
+        assert_eq!(id_of("a"), id_of("b"));
+        assert!(id_of("a") != id_of("c"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let md = fs::metadata(dir.join("a")).unwrap();
+            assert_eq!(ID { dev: md.dev(), inode: md.ino() }, id_of("a"));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replace_with_link_real_fs() {
+        // the victim becomes a link to the keeper and nothing is left behind
+        let dir = env::temp_dir().join(format!("smllr_replace_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a"), b"AAAA").unwrap();
+        fs::write(dir.join("b"), b"AAAA").unwrap();
+        let mut vfs = RealFileSystem;
+        vfs.replace_with_link(&dir.join("a"), &dir.join("b")).unwrap();
+        let id_of = |name| ID::of(&RealFileSystem.get_metadata(dir.join(name)).unwrap()).unwrap();
+        assert_eq!(id_of("a"), id_of("b"));
+        assert!(!staging_path(&dir.join("b")).exists());
+        // a failed replacement leaves the victim alone
+        assert!(vfs.replace_with_link(&dir.join("x"), &dir.join("b")).is_err());
+        assert_eq!(b"AAAA", &fs::read(dir.join("b")).unwrap()[..]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn free_space_test_fs() {
+        // free space is the device's capacity minus its files (hard links count once)
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a size=60 inode=1
+             file /b size=60 inode=1
+             file /c size=10 inode=2",
+        ).unwrap();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.set_capacity(0, 100);
+            let md = TestMD::new().with_len(500).with_id(ID { dev: 1, inode: 3 });
+            fs.add(TestFile::new("/other").with_metadata(md));
+        }
+        assert_eq!(30, fs.free_space(Path::new("/a")).unwrap());
+        assert_eq!(30, fs.free_space(Path::new("/")).unwrap());
+        // devices without a capacity never fill up
+        assert_eq!(u64::MAX, fs.free_space(Path::new("/other")).unwrap());
+        assert!(fs.same_filesystem(Path::new("/a"), Path::new("/c")).unwrap());
+        assert!(!fs.same_filesystem(Path::new("/a"), Path::new("/other")).unwrap());
+
+        // a device can be overfull: there's simply no room left
+        let md = TestMD::new().with_len(50).with_id(ID { dev: 0, inode: 4 });
+        fs.borrow_mut().add(TestFile::new("/d").with_metadata(md));
+        assert_eq!(0, fs.free_space(Path::new("/a")).unwrap());
+
+        // missing paths and injected faults are errors
+        let err = fs.free_space(Path::new("/missing")).unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+        fs.borrow_mut()
+            .fail_next(Operation::FreeSpace, "/a", io::ErrorKind::PermissionDenied);
+        assert_eq!(Operation::FreeSpace, fs.free_space(Path::new("/a")).unwrap_err().op());
+    }
+
+    #[test]
+    fn free_space_real_fs() {
+        // the real filesystem reports some room in the temp directory
+        let dir = env::temp_dir();
+        assert!(RealFileSystem.free_space(&dir).unwrap() > 0);
+        assert!(RealFileSystem.same_filesystem(&dir, &dir).unwrap());
+        let missing = dir.join(format!("smllr_missing_{}", process::id()));
+        let err = RealFileSystem.free_space(&missing).unwrap_err();
+        assert_eq!(Operation::FreeSpace, err.op());
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+    }
+
+    #[test]
+    fn journal_records_mutations() {
+        // every mutating call is journaled in order until it is cleared
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.create_file("/a");
+            fs.create_symlink("/s", "/a");
+        }
+        assert_eq!(
+            &[FsOp::Symlink {
+                path: PathBuf::from("/s"),
+                target: PathBuf::from("/a"),
+            }][..],
+            fs.borrow().ops()
+        );
+        fs.borrow_mut().clear_ops();
+
+        let mut vfs = fs.clone();
+        vfs.make_hard_link(Path::new("/a"), Path::new("/b")).unwrap();
+        vfs.rm_file(&Path::new("/a")).unwrap();
+        // failed calls are not recorded
+        assert!(vfs.rm_file(&Path::new("/a")).is_err());
+        let expected = [
+            FsOp::HardLink {
+                src: PathBuf::from("/a"),
+                dst: PathBuf::from("/b"),
+            },
+            FsOp::Delete(PathBuf::from("/a")),
+        ];
+        assert_eq!(&expected[..], fs.borrow().ops());
+    }
+
+    #[test]
+    fn overlay_reads_see_changes() {
+        // reads through an overlay reflect its recorded deletes, links, and moves
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.add(TestFile::new("/a").with_metadata(TestMD::new()).with_inode(1));
+            fs.add(TestFile::new("/b").with_metadata(TestMD::new()).with_inode(2));
+            fs.add(TestFile::new("/c").with_metadata(TestMD::new()).with_inode(3));
+        }
+        let mut overlay = OverlayFileSystem::new(fs.clone());
+        overlay.rm_file(&Path::new("/a")).unwrap();
+        overlay.rename(Path::new("/b"), Path::new("/d")).unwrap();
+        overlay.replace_with_link(Path::new("/d"), Path::new("/c")).unwrap();
+
+        let expected: HashSet<_> = ["/c", "/d"].iter().map(PathBuf::from).collect();
+        assert_eq!(expected, children(&overlay, "/"));
+        // /c is now a link to what used to be /b
+        let c = overlay.get_file(Path::new("/c")).unwrap();
+        assert_eq!(Path::new("/c"), c.get_path());
+        assert_eq!(2, c.get_inode().unwrap().0);
+        assert!(overlay.get_metadata("/a").is_err());
+        assert!(overlay.rm_file(&Path::new("/a")).is_err());
+        // linking over an existing file fails
+        assert!(overlay.make_hard_link(Path::new("/c"), Path::new("/d")).is_err());
+
+        // the mock underneath never changed
+        let expected: HashSet<_> = ["/a", "/b", "/c"].iter().map(PathBuf::from).collect();
+        assert_eq!(expected, children(&fs, "/"));
+        assert!(fs.borrow().ops().is_empty());
+        assert_eq!(4, overlay.ops().len());
+    }
+
+    #[test]
+    fn dyn_vfs_boxed() {
+        // a boxed filesystem behaves like the one inside it
+        // (imported here only: its methods would clash with `VFS`'s in the other tests)
+        use vfs::DynVfs;
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'hello' inode=1
+             file /b 'hello' inode=2",
+        ).unwrap();
+        let boxed: Box<dyn DynVfs> = Box::new(fs.clone());
+        let boxes: [Box<dyn DynVfs>; 3] = [
+            Box::new(fs.clone()),
+            Box::new(OverlayFileSystem::new(fs.clone())),
+            // a box of a box streams its files through both
+            Box::new(boxed),
+        ];
+        for vfs in &boxes {
+            let expected: HashSet<_> = ["/a", "/b"].iter().map(PathBuf::from).collect();
+            assert_eq!(expected, children(vfs, "/"));
+            let a = VFS::get_file(vfs, Path::new("/a")).unwrap();
+            let real = VFS::get_file(&fs, Path::new("/a")).unwrap();
+            assert_eq!(real.get_hash::<Md5Sum>().unwrap(), a.get_hash::<Md5Sum>().unwrap());
+            let mut progress = vec![];
+            let hash = a.get_hash_with_progress::<Md5Sum>(&mut |n, len| progress.push((n, len)));
+            assert_eq!(real.get_hash::<Md5Sum>().unwrap(), hash.unwrap());
+            assert_eq!(vec![(5, 5)], progress);
+            let by_path = VFS::hash_path::<Md5Sum>(vfs, Path::new("/a")).unwrap();
+            assert_eq!(real.get_hash::<Md5Sum>().unwrap(), by_path);
+            assert_eq!(
+                real.get_first_bytes::<Md5Sum>().unwrap(),
+                a.get_first_bytes::<Md5Sum>().unwrap()
+            );
+            assert_eq!(5, VFS::get_metadata(vfs, "/b").unwrap().get_len());
+        }
+
+        // clones share the filesystem underneath, and changes go through to it
+        let mut vfs = boxes[0].clone();
+        VFS::rm_file(&mut vfs, &Path::new("/b")).unwrap();
+        assert_eq!(&[FsOp::Delete(PathBuf::from("/b"))][..], fs.borrow().ops());
+        assert!(VFS::get_metadata(&boxes[0], "/b").is_err());
+    }
+
+    #[test]
+    fn error_names_path() {
+        // errors should say what failed and where
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.create_dir("/data");
+            fs.add(TestFile::new("/data/x.bin").with_metadata(TestMD::new()));
+        }
+        let err = fs.get_metadata("/data/y.bin").unwrap_err();
+        assert_eq!(Operation::ReadMetadata, err.op());
+        assert_eq!(Path::new("/data/y.bin"), err.path());
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+        assert_eq!(
+            "failed to read metadata for /data/y.bin: No such file",
+            err.to_string()
+        );
+
+        // reading a file without contents reports that file
+        let file = fs.get_file(Path::new("/data/x.bin")).unwrap();
+        let err = file.get_hash::<Md5Sum>().unwrap_err();
+        assert_eq!(Operation::ReadContents, err.op());
+        assert!(err.to_string().contains("/data/x.bin"));
+        // `expect()` output is just as informative
+        assert!(format!("{:?}", err).contains("/data/x.bin"));
+    }
+
+    #[test]
+    fn fault_injection() {
+        // armed faults fire on the targeted operation and path only
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            fs.add(
+                TestFile::new("/a")
+                    .with_contents(String::from("A"))
+                    .with_metadata(TestMD::new()),
+            );
+            fs.add(
+                TestFile::new("/b")
+                    .with_contents(String::from("B"))
+                    .with_metadata(TestMD::new()),
+            );
+            fs.fail_next(Operation::ReadMetadata, "/a", io::ErrorKind::PermissionDenied);
+            fs.fail_always(Operation::ReadContents, "/b", io::ErrorKind::Other);
+        }
+
+        // `fail_next` fires once
+        let err = fs.get_metadata("/a").unwrap_err();
+        assert_eq!(Operation::ReadMetadata, err.op());
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+        assert!(fs.get_metadata("/a").is_ok());
+        assert!(fs.get_metadata("/b").is_ok());
+
+        // `fail_always` fires every time, including through file handles
+        let file = fs.get_file(Path::new("/b")).unwrap();
+        assert_eq!(io::ErrorKind::Other, file.get_hash::<Md5Sum>().unwrap_err().kind());
+        assert!(file.get_first_bytes::<Md5Sum>().is_err());
+        assert!(file.get_metadata().is_ok());
+        let file = fs.get_file(Path::new("/a")).unwrap();
+        assert!(file.get_hash::<Md5Sum>().is_ok());
+
+        // a failed mutation is neither performed nor recorded
+        let mut fs = fs;
+        fs.borrow_mut()
+            .fail_next(Operation::Delete, "/a", io::ErrorKind::PermissionDenied);
+        assert!(fs.rm_file(&"/a").is_err());
+        assert!(fs.borrow().ops().is_empty());
+        assert!(fs.rm_file(&"/a").is_ok());
+    }
+
+    #[test]
+    fn first_bytes_short_files() {
+        // short files aren't padded, so trailing zeros don't look like missing bytes
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            let files = [("/a", "AAAA"), ("/b", "AAAA\0\0"), ("/c", "\0\0\0"), ("/d", "")];
+            for &(path, contents) in &files {
+                fs.add(
+                    TestFile::new(path)
+                        .with_contents(String::from(contents))
+                        .with_metadata(TestMD::new()),
+                );
+            }
+        }
+        let first = |p| fs.get_file(Path::new(p)).unwrap().get_first_bytes::<Md5Sum>().unwrap();
+        assert!(first("/a") != first("/b"));
+        assert!(first("/c") != first("/d"));
+        let a = fs.get_file(Path::new("/a")).unwrap();
+        assert_eq!(a.get_hash::<Md5Sum>().unwrap(), first("/a"));
+
+        // the real filesystem agrees
+        let dir = env::temp_dir().join(format!("smllr_first_bytes_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a"), b"AAAA").unwrap();
+        fs::write(dir.join("b"), b"AAAA\0\0").unwrap();
+        let real_first = |name| {
+            let file = RealFileSystem.get_file(&dir.join(name)).unwrap();
+            file.get_first_bytes::<Md5Sum>().unwrap()
+        };
+        assert_eq!(first("/a"), real_first("a"));
+        assert_eq!(first("/b"), real_first("b"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn generated_contents() {
+        // generated and repeated contents read the same as the equivalent text
+        let gen = |i: u64| b"abc"[(i % 3) as usize];
+        let text: String = (0..100_000).map(|i| gen(i) as char).collect();
+        let files = [
+            TestFile::new("/a").with_contents(text),
+            TestFile::new("/b").with_generated_contents(100_000, gen),
+            TestFile::new("/c").with_pattern_contents(100_000, b"abc"),
+        ];
+        for f in &files[1..] {
+            assert_eq!(
+                files[0].get_first_bytes::<Md5Sum>().unwrap(),
+                f.get_first_bytes::<Md5Sum>().unwrap()
+            );
+            assert_eq!(files[0].get_hash::<Md5Sum>().unwrap(), f.get_hash::<Md5Sum>().unwrap());
+        }
+        // the metadata reports the virtual length
+        let f = TestFile::new("/d")
+            .with_metadata(TestMD::new())
+            .with_pattern_contents(1 << 40, b"x");
+        assert_eq!(1 << 40, f.get_metadata().unwrap().get_len());
+    }
+
+    #[test]
+    fn text_normalized_vfs() {
+        // small files compare as text; whitespace inside the text still counts
+        let fs = TestFileSystem::new();
+        {
+            let mut fs = fs.borrow_mut();
+            fs.create_dir("/");
+            let texts = [
+                ("/a", "line\r\nmore\n"),
+                ("/b", "line\nmore"),
+                ("/c", "line\nmore \n\n"),
+                ("/d", "line \nmore"),
+            ];
+            for &(path, text) in &texts {
+                fs.add(
+                    TestFile::new(path)
+                        .with_contents(String::from(text))
+                        .with_metadata(TestMD::new()),
+                );
+            }
+            // trailing whitespace spanning two chunks
+            let len = HASH_CHUNK_SIZE as u64 + 10;
+            let cut = HASH_CHUNK_SIZE as u64 - 10;
+            fs.add(
+                TestFile::new("/e")
+                    .with_metadata(TestMD::new())
+                    .with_generated_contents(len, move |i| if i < cut { b'x' } else { b' ' }),
+            );
+        }
+        let text = TextNormalizedVfs::new(fs.clone(), 1 << 20);
+        let len = |p| text.get_metadata(p).unwrap().get_len();
+        let hash = |p| text.get_file(Path::new(p)).unwrap().get_hash::<Md5Sum>().unwrap();
+        let first = |p| text.get_file(Path::new(p)).unwrap().get_first_bytes::<Md5Sum>();
+        assert_eq!((9, 9, 9, 10), (len("/a"), len("/b"), len("/c"), len("/d")));
+        assert_eq!(Md5Sum::hash(b"line\nmore"), hash("/a"));
+        assert_eq!(hash("/a"), hash("/b"));
+        assert_eq!(hash("/a"), hash("/c"));
+        assert_eq!(hash("/a"), text.hash_path::<Md5Sum>(Path::new("/a")).unwrap());
+        assert_eq!(first("/a").unwrap(), first("/c").unwrap());
+        assert!(hash("/a") != hash("/d"));
+        assert_eq!(HASH_CHUNK_SIZE as u64 - 10, len("/e"));
+        // larger files are compared byte for byte
+        let bytes = TextNormalizedVfs::new(fs.clone(), 9);
+        assert_eq!(11, bytes.get_metadata("/a").unwrap().get_len());
+        assert_eq!(9, bytes.get_metadata("/b").unwrap().get_len());
+        let raw = fs.get_file(Path::new("/a")).unwrap().get_hash::<Md5Sum>().unwrap();
+        let a = bytes.get_file(Path::new("/a")).unwrap();
+        assert_eq!(raw, a.get_hash::<Md5Sum>().unwrap());
+    }
+
+    #[test]
+    fn retry_transient_errors() {
+        // flaky reads are retried until they work; real errors are returned at once
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA'",
+        ).unwrap();
+        let a = Path::new("/a");
+        let retry = |n| RetryVfs::new(fs.clone(), RetryPolicy::new(n, Duration::from_millis(1)));
+        let fail = |op, kind| fs.borrow_mut().fail_next(op, a, kind);
+
+        fail(Operation::OpenFile, io::ErrorKind::TimedOut);
+        fail(Operation::OpenFile, io::ErrorKind::Interrupted);
+        let file = retry(2).get_file(a).unwrap();
+        assert_eq!(PathBuf::from("/a"), file.get_path());
+
+        fail(Operation::ReadContents, io::ErrorKind::TimedOut);
+        fail(Operation::ReadContents, io::ErrorKind::TimedOut);
+        assert_eq!(Md5Sum::hash(b"AAAA"), file.get_hash::<Md5Sum>().unwrap());
+
+        // out of retries
+        fail(Operation::ReadMetadata, io::ErrorKind::TimedOut);
+        fail(Operation::ReadMetadata, io::ErrorKind::TimedOut);
+        let err = retry(1).get_metadata(a).unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, err.kind());
+        assert!(retry(1).get_metadata(a).is_ok());
+
+        fail(Operation::OpenFile, io::ErrorKind::PermissionDenied);
+        let err = retry(5).get_file(a).unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+        assert!(retry(0).get_file(a).is_ok());
+        assert!(!is_transient(io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn retry_hash_progress() {
+        // a hash retried after failing partway reports its progress without going backwards
+        let fs = TestFileSystem::new();
+        let chunk = HASH_CHUNK_SIZE as u64;
+        fs.borrow_mut().add(TestFile::new("/a").with_pattern_contents(4 * chunk, b"retry"));
+        fs.borrow_mut().fail_partway("/a", 2 * chunk, io::ErrorKind::TimedOut);
+        let retry = RetryVfs::new(fs.clone(), RetryPolicy::new(1, Duration::from_millis(1)));
+        let file = retry.get_file(Path::new("/a")).unwrap();
+        let mut seen = vec![];
+        let hash = file.get_hash_with_progress::<Md5Sum>(&mut |done, total| {
+            seen.push((done, total));
+        });
+        // read twice, the second time all the way
+        assert_eq!(2, fs.borrow().num_reads());
+        let whole = fs.get_file(Path::new("/a")).unwrap().get_hash::<Md5Sum>().unwrap();
+        assert_eq!(whole, hash.unwrap());
+        let expected: Vec<(u64, u64)> = (1..5).map(|n| (n * chunk, 4 * chunk)).collect();
+        assert_eq!(expected, seen);
+    }
+
+    // a "hash" that records how many bytes it was fed, and the most at once
+    #[derive(Debug)]
+    struct ChunkLog;
+
+    impl FileHash for ChunkLog {
+        type Output = (u64, usize);
+        type State = (u64, usize);
+
+        fn start() -> Self::State {
+            (0, 0)
+        }
+        fn update(state: &mut Self::State, bytes: &[u8]) {
+            state.0 += bytes.len() as u64;
+            state.1 = state.1.max(bytes.len());
+        }
+        fn finish(state: Self::State) -> Self::Output {
+            state
+        }
+    }
+
+    #[test]
+    fn hash_huge_test_file() {
+        // a 1 GiB mock file is hashed a chunk at a time, never all at once
+        let pattern: Vec<u8> = (0..4093).map(|i| (i % 251) as u8).collect();
+        let f = TestFile::new("/huge").with_pattern_contents(1 << 30, &pattern);
+        assert_eq!((1 << 30, HASH_CHUNK_SIZE), f.get_hash::<ChunkLog>().unwrap());
+        assert_eq!((4096, 4096), f.get_first_bytes::<ChunkLog>().unwrap());
+    }
+
+    #[test]
+    fn hash_progress_huge_test_file() {
+        // hashing reports its progress after every chunk, up to the whole file
+        let len = 64 << 20;
+        let f = TestFile::new("/huge").with_pattern_contents(len, b"progress");
+        let mut seen = vec![];
+        let hash = f.get_hash_with_progress::<ChunkLog>(&mut |hashed, total| {
+            seen.push((hashed, total))
+        });
+        assert_eq!((len, HASH_CHUNK_SIZE), hash.unwrap());
+        assert_eq!(len as usize / HASH_CHUNK_SIZE, seen.len());
+        assert!(seen.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(seen.iter().all(|&(_, total)| total == len));
+        assert_eq!(Some(&(len, len)), seen.last());
+    }
+
+    #[test]
+    fn hash_huge_boxed_file() {
+        // files of a boxed filesystem are streamed into the hash the same way
+        use vfs::DynVfs;
+        let fs = TestFileSystem::from_spec("dir /").unwrap();
+        let pattern: Vec<u8> = (0..4093).map(|i| (i % 251) as u8).collect();
+        fs.borrow_mut().add(TestFile::new("/huge").with_pattern_contents(1 << 30, &pattern));
+        let vfs: Box<dyn DynVfs> = Box::new(fs);
+        let f = VFS::get_file(&vfs, Path::new("/huge")).unwrap();
+        assert_eq!((1 << 30, HASH_CHUNK_SIZE), f.get_hash::<ChunkLog>().unwrap());
+
+        let mut seen = 0;
+        let hash = f.get_hash_with_progress::<ChunkLog>(&mut |_, _| seen += 1);
+        assert_eq!((1 << 30, HASH_CHUNK_SIZE), hash.unwrap());
+        assert_eq!((1 << 30) / HASH_CHUNK_SIZE, seen);
+    }
+
+    #[test]
+    fn error_names_path_real_fs() {
+        // real I/O errors carry the path too
+        let path = env::temp_dir().join(format!("smllr_missing_{}", process::id()));
+        let err = RealFileSystem.get_metadata(&path).unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+        let msg = err.to_string();
+        assert!(msg.starts_with("failed to read metadata for "));
+        assert!(msg.contains(path.to_str().unwrap()));
+    }
+
+    #[test]
+    fn generate_is_deterministic() {
+        // the same seed and spec build the same tree; another seed doesn't
+        let spec = TreeSpec {
+            fan_out: 3,
+            depth: 2,
+            files_per_dir: 4,
+            min_size: 8,
+            max_size: 64,
+            dup_ratio: 0.3,
+        };
+        let a = TestFileSystem::generate(7, &spec);
+        let b = TestFileSystem::generate(7, &spec);
+        let c = TestFileSystem::generate(8, &spec);
+
+        // 1 + 3 + 9 directories with 4 files each
+        assert_eq!(52, a.files.len());
+        assert_eq!(a.files, b.files);
+        assert_eq!(a.duplicate_groups, b.duplicate_groups);
+        let contents = |t: &GeneratedTree| -> Vec<_> {
+            t.files
+                .iter()
+                .map(|p| t.fs.get_file(p).unwrap().get_hash::<Md5Sum>().unwrap())
+                .collect()
+        };
+        assert_eq!(contents(&a), contents(&b));
+        assert!(contents(&a) != contents(&c));
+
+        // directories hold files and (above the deepest level) subdirectories
+        assert_eq!(4 + 3, children(&a.fs, "/d1").len());
+        assert_eq!(4, children(&a.fs, "/d1/d2").len());
+    }
+
+    #[test]
+    fn from_spec_entries() {
+        // each kind of entry becomes the matching file, directory, or symlink
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             dir /x
+                 file /x/a 'hello world'
+                 link /x/b -> /x/a
+
+             # comments and blank lines are skipped
+             file /x/c",
+        ).unwrap();
+        assert!(fs.borrow().ops().is_empty());
+        assert_eq!(5, fs.borrow().num_elements());
+        assert_eq!(FileType::Dir, fs.get_metadata("/x").unwrap().get_type());
+        assert_eq!(Path::new("/x/a"), fs.read_link("/x/b").unwrap());
+        let a = fs.get_file(Path::new("/x/a")).unwrap();
+        assert_eq!(11, a.get_metadata().unwrap().get_len());
+        let expected = TestFile::new("/")
+            .with_contents(String::from("hello world"))
+            .get_hash::<Md5Sum>()
+            .unwrap();
+        assert_eq!(expected, a.get_hash::<Md5Sum>().unwrap());
+        // files are distinct unless told otherwise
+        let c = fs.get_file(Path::new("/x/c")).unwrap();
+        assert!(a.get_inode().unwrap() != c.get_inode().unwrap());
+        assert!(c.get_hash::<Md5Sum>().is_err());
+    }
+
+    #[test]
+    fn from_spec_annotations() {
+        // size, mtime, and inode can be set; quotes can hold spaces and escapes
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a size=4096 mtime=100 inode=7
+             file /b 'it\\'s\\n' inode=7
+             file /c 'AB' size=2",
+        ).unwrap();
+        let md = fs.get_metadata("/a").unwrap();
+        assert_eq!(4096, md.get_len());
+        assert_eq!(UNIX_EPOCH + Duration::from_secs(100), md.get_mod_time().unwrap());
+        assert_eq!(7, md.get_inode().0);
+        assert_eq!(7, fs.get_metadata("/b").unwrap().get_inode().0);
+        assert_eq!(5, fs.get_metadata("/b").unwrap().get_len());
+        assert_eq!(UNIX_EPOCH, fs.get_metadata("/b").unwrap().get_mod_time().unwrap());
+        assert_eq!(2, fs.get_metadata("/c").unwrap().get_len());
+    }
+
+    #[test]
+    fn from_spec_errors() {
+        // parse errors name the offending line
+        let err = |spec| TestFileSystem::from_spec(spec).unwrap_err();
+        assert_eq!(
+            SpecError {
+                line: 2,
+                message: String::from("unknown entry `fil`"),
+            },
+            err("dir /\nfil /a")
+        );
+        assert_eq!("line 3: unterminated quote", err("dir /\n\nfile /a 'abc").to_string());
+        assert_eq!(1, err("link /a /b").line);
+        assert_eq!(1, err("file /a size=big").line);
+        assert_eq!(1, err("file /a color=3").line);
+        assert_eq!(1, err("file /a 'abc' size=4").line);
+        assert_eq!(1, err("file /a size=3 'abc'").line);
+        assert_eq!(2, err("dir /\ndir /x /y").line);
+    }
+
+    #[test]
+    fn on_error_policies() {
+        // only `abort` stops at an error; the others differ only in how loudly they log it
+        let e = VfsError::new(Operation::Delete, "/a", io::ErrorKind::PermissionDenied.into());
+        let parsed: Vec<OnError> =
+            ["skip", "warn", "abort"].iter().map(|s| s.parse().unwrap()).collect();
+        assert_eq!(vec![OnError::Skip, OnError::Warn, OnError::Abort], parsed);
+        assert!("strict".parse::<OnError>().is_err());
+        assert_eq!(OnError::Warn, OnError::default());
+        let carry_on: Vec<bool> = parsed.iter().map(|policy| policy.carry_on(&e)).collect();
+        assert_eq!(vec![true, true, false], carry_on);
+    }
+}
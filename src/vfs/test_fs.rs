@@ -1,23 +1,29 @@
 // mock filesystem for testing
 
-use std::io;
+use std::{fmt, io, thread};
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::path::{Path, PathBuf};
-use std::time::{self, SystemTime};
+use std::path::{Component, Path, PathBuf};
+use std::time::{self, Duration, SystemTime};
 use std::collections::{HashMap, HashSet};
 
 use vfs::{DeviceId, File, FileType, Inode, MetaData, VFS};
-use helpers::{FIRST_K_BYTES, ID};
+use vfs::{Operation, Result, VfsError};
+use helpers::{FIRST_K_BYTES, HASH_CHUNK_SIZE, ID, LAST_K_BYTES};
 use hash::FileHash;
 
 /// `TestMD` is the mock metadata struct.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct TestMD {
     len: u64,
+    // `None` means the file is fully allocated (i.e. the same as `len`)
+    allocated: Option<u64>,
     modified: SystemTime,
     kind: FileType,
     id: ID,
+    nlink: u64,
+    uid: u32,
+    mode: u32,
 }
 
 //implementation of the MetaData trait for testMD.
@@ -25,6 +31,9 @@ impl MetaData for TestMD {
     fn get_len(&self) -> u64 {
         self.len
     }
+    fn get_allocated_size(&self) -> u64 {
+        self.allocated.unwrap_or(self.len)
+    }
     fn get_mod_time(&self) -> io::Result<SystemTime> {
         Ok(self.modified)
     }
@@ -37,6 +46,15 @@ impl MetaData for TestMD {
     fn get_device(&self) -> io::Result<DeviceId> {
         Ok(DeviceId(self.id.dev))
     }
+    fn get_nlink(&self) -> u64 {
+        self.nlink
+    }
+    fn get_uid(&self) -> u32 {
+        self.uid
+    }
+    fn get_mode(&self) -> u32 {
+        self.mode
+    }
 }
 
 // TestMD must be easy to make and also customize for unit testing
@@ -46,15 +64,23 @@ impl TestMD {
     pub fn new() -> Self {
         TestMD {
             len: 0,
+            allocated: None,
             modified: SystemTime::now(),
             kind: FileType::File,
             id: ID { dev: 0, inode: 0 },
+            nlink: 1,
+            uid: 0,
+            mode: 0o644,
         }
     }
     pub fn with_len(mut self, n: u64) -> Self {
         self.len = n;
         self
     }
+    pub fn with_allocated_size(mut self, n: u64) -> Self {
+        self.allocated = Some(n);
+        self
+    }
     pub fn with_mod_time(mut self, t: SystemTime) -> Self {
         self.modified = t;
         self
@@ -67,6 +93,85 @@ impl TestMD {
         self.id = id;
         self
     }
+    pub fn with_nlink(mut self, n: u64) -> Self {
+        self.nlink = n;
+        self
+    }
+    pub fn with_uid(mut self, uid: u32) -> Self {
+        self.uid = uid;
+        self
+    }
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+// where the bytes of a `TestFile` come from
+// generated contents are produced as they are read, so a file can be far bigger than memory
+#[derive(Clone)]
+enum Contents {
+    // exactly these bytes
+    Text(String),
+    // `len` bytes where the byte at each offset is `gen(offset)`
+    Generated(u64, Rc<dyn Fn(u64) -> u8>),
+    // `len` bytes of a pattern repeated over and over
+    Pattern(u64, Vec<u8>),
+}
+
+impl Contents {
+    // the number of bytes in the file
+    fn len(&self) -> u64 {
+        match *self {
+            Contents::Text(ref s) => s.len() as u64,
+            Contents::Generated(len, _) | Contents::Pattern(len, _) => len,
+        }
+    }
+    // fill `buf` with the bytes starting at `offset` (which must all exist)
+    fn read_at(&self, offset: u64, buf: &mut [u8]) {
+        match *self {
+            Contents::Text(ref s) => {
+                let start = offset as usize;
+                buf.copy_from_slice(&s.as_bytes()[start..start + buf.len()]);
+            }
+            Contents::Generated(_, ref gen) => for (i, b) in buf.iter_mut().enumerate() {
+                *b = gen(offset + i as u64);
+            },
+            Contents::Pattern(_, ref pattern) => {
+                // copy whole runs of the pattern at a time
+                let mut pos = (offset % pattern.len() as u64) as usize;
+                let mut filled = 0;
+                while filled < buf.len() {
+                    let n = (pattern.len() - pos).min(buf.len() - filled);
+                    buf[filled..filled + n].copy_from_slice(&pattern[pos..pos + n]);
+                    filled += n;
+                    pos = 0;
+                }
+            }
+        }
+    }
+}
+
+// generators can't be compared, so only the same generator is equal to itself
+impl PartialEq for Contents {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Contents::Text(a), Contents::Text(b)) => a == b,
+            (Contents::Generated(n, f), Contents::Generated(m, g)) => n == m && Rc::ptr_eq(f, g),
+            (Contents::Pattern(n, p), Contents::Pattern(m, q)) => n == m && p == q,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for Contents {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Contents::Text(ref s) => write!(f, "Text({:?})", s),
+            Contents::Generated(len, _) => write!(f, "Generated({} bytes)", len),
+            Contents::Pattern(len, ref p) => write!(f, "Pattern({} bytes of {:?})", len, p),
+        }
+    }
 }
 
 /// `TestFile` denotes a mockfile.
@@ -76,28 +181,42 @@ impl TestMD {
 #[derive(Debug, Clone, PartialEq)]
 pub struct TestFile {
     path: PathBuf,
-    contents: Option<String>,
+    contents: Option<Contents>,
     kind: FileType,
     inode: Inode,
     metadata: Option<TestMD>,
+    faults: FaultHook,
 }
 
 // build up a File object for mock testing
 // Chainable setters to easily construct test objects
 // e.g. `TestFile::new().with_kind(FileType::Dir).with_inode(42)`
 impl TestFile {
-    pub fn new(s: &str) -> Self {
+    pub fn new<P: AsRef<Path>>(p: P) -> Self {
         TestFile {
-            path: PathBuf::from(s),
+            path: p.as_ref().to_path_buf(),
             contents: None,
             kind: FileType::File,
             inode: Inode(0),
             metadata: None,
+            faults: FaultHook::default(),
         }
     }
-    pub fn with_contents(mut self, c: String) -> Self {
+    pub fn with_contents(self, c: String) -> Self {
+        self.set_contents(Contents::Text(c))
+    }
+    /// `len` bytes made by `gen(offset)` as they are read, e.g. to mock a huge file
+    pub fn with_generated_contents<F: Fn(u64) -> u8 + 'static>(self, len: u64, gen: F) -> Self {
+        self.set_contents(Contents::Generated(len, Rc::new(gen)))
+    }
+    /// `len` bytes of `pattern` repeated, e.g. to mock a huge file
+    pub fn with_pattern_contents(self, len: u64, pattern: &[u8]) -> Self {
+        assert!(!pattern.is_empty(), "Can't repeat an empty pattern");
+        self.set_contents(Contents::Pattern(len, pattern.to_vec()))
+    }
+    fn set_contents(mut self, c: Contents) -> Self {
         if let Some(ref mut md) = self.metadata {
-            md.len = c.len() as u64;
+            md.len = c.len();
         }
         self.contents = Some(c);
         self
@@ -125,7 +244,7 @@ impl TestFile {
         }
         // fix len discrepancy
         if let Some(ref c) = self.contents {
-            md.len = c.len() as u64;
+            md.len = c.len();
         } else if md.len != 0 {
             // for now do nothing
             // it is okay for `len` to be >0 and `contents` to be empty
@@ -143,6 +262,39 @@ impl TestFile {
     }
 }
 
+// error for reading a TestFile whose contents were never set
+impl TestFile {
+    fn no_contents(&self) -> VfsError {
+        let e = io::Error::new(io::ErrorKind::NotFound, "No contents set");
+        VfsError::new(Operation::ReadContents, &self.path, e)
+    }
+    // hash the bytes from `start` up to `limit` a chunk at a time, like reading a real file
+    // `progress` is passed the bytes hashed so far and the number to hash after each chunk
+    fn hash_chunks<H: FileHash>(
+        &self,
+        start: u64,
+        limit: u64,
+        mut progress: Option<&mut dyn FnMut(u64, u64)>,
+    ) -> Result<<H as FileHash>::Output> {
+        let contents = self.contents.as_ref().ok_or_else(|| self.no_contents())?;
+        let end = contents.len().min(limit);
+        let mut state = H::start();
+        let mut buf = vec![0; HASH_CHUNK_SIZE.min(end.saturating_sub(start) as usize)];
+        let mut offset = start;
+        while offset < end {
+            let n = (end - offset).min(buf.len() as u64) as usize;
+            contents.read_at(offset, &mut buf[..n]);
+            H::update(&mut state, &buf[..n]);
+            offset += n as u64;
+            if let Some(ref mut progress) = progress {
+                progress(offset - start, end - start);
+            }
+            self.faults.check_partway(&self.path, offset)?;
+        }
+        Ok(H::finish(state))
+    }
+}
+
 /// Implementation of the File trait for `TestFile`
 impl File for TestFile {
     type MD = TestMD;
@@ -150,46 +302,160 @@ impl File for TestFile {
     fn get_path(&self) -> PathBuf {
         self.path.clone()
     }
-    fn get_inode(&self) -> io::Result<Inode> {
+    fn get_inode(&self) -> Result<Inode> {
         Ok(self.inode)
     }
-    fn get_type(&self) -> io::Result<FileType> {
+    fn get_type(&self) -> Result<FileType> {
         Ok(self.kind)
     }
-    fn get_metadata(&self) -> io::Result<TestMD> {
-        self.metadata
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "No MD"))
+    fn get_metadata(&self) -> Result<TestMD> {
+        self.faults.check(Operation::ReadMetadata, &self.path)?;
+        self.metadata.ok_or_else(|| {
+            let e = io::Error::new(io::ErrorKind::Other, "No MD");
+            VfsError::new(Operation::ReadMetadata, &self.path, e)
+        })
     }
-    //fn get_first_bytes(&self) -> io::Result<FirstBytes> {
-    fn get_first_bytes<H: FileHash>(&self) -> io::Result<<H as FileHash>::Output> {
+    fn get_first_bytes<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        self.faults.check(Operation::ReadContents, &self.path)?;
         // read the first K bytes of the file
-        // if the file is less than K bytes, the remaining bytes are treated as zeros
-        if let Some(ref cont) = self.contents {
-            let mut bytes = [0u8; FIRST_K_BYTES];
-            for (c, b) in cont.bytes().zip(bytes.iter_mut()) {
-                *b = c;
+        // if the file is less than K bytes, only the bytes it has are hashed
+        self.hash_chunks::<H>(0, FIRST_K_BYTES as u64, None)
+    }
+    fn get_last_bytes<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        self.faults.check(Operation::ReadContents, &self.path)?;
+        let len = self.contents.as_ref().map_or(0, |c| c.len());
+        self.hash_chunks::<H>(len.saturating_sub(LAST_K_BYTES as u64), u64::MAX, None)
+    }
+    fn get_hash<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        self.faults.check(Operation::ReadContents, &self.path)?;
+        self.hash_chunks::<H>(0, u64::MAX, None)
+    }
+    fn get_hash_with_progress<H: FileHash>(
+        &self,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<<H as FileHash>::Output> {
+        self.faults.check(Operation::ReadContents, &self.path)?;
+        self.hash_chunks::<H>(0, u64::MAX, Some(progress))
+    }
+}
+
+// An error armed by `TestFileSystem::fail_next` or `TestFileSystem::fail_always`
+#[derive(Debug)]
+struct Fault {
+    op: Operation,
+    path: PathBuf,
+    kind: io::ErrorKind,
+    // disarm after the first time it fires
+    once: bool,
+    // fire only once this many bytes of the file have been read (see `fail_partway`)
+    after: u64,
+}
+
+// Every armed fault of a `TestFileSystem`, and how many times contents and metadata were read
+// Shared with the `TestFile` handles it gives out, since reading
+// contents or metadata through a handle doesn't go through the filesystem
+#[derive(Debug, Default)]
+struct Faults {
+    armed: Vec<Fault>,
+    reads: usize,
+    metadata_reads: usize,
+}
+
+impl Faults {
+    // fire the fault armed for `op` on `path`, if any
+    fn take(&mut self, op: Operation, path: &Path) -> Result<()> {
+        match op {
+            Operation::ReadContents => self.reads += 1,
+            Operation::ReadMetadata => self.metadata_reads += 1,
+            _ => (),
+        }
+        self.fire(op, path, |after| after == 0)
+    }
+    // fire the fault armed for partway through reading `path`, if `offset` bytes get to it
+    fn take_partway(&mut self, path: &Path, offset: u64) -> Result<()> {
+        self.fire(Operation::ReadContents, path, |after| after > 0 && after <= offset)
+    }
+    fn fire<F: Fn(u64) -> bool>(&mut self, op: Operation, path: &Path, due: F) -> Result<()> {
+        match self.armed.iter().position(|f| f.op == op && f.path == path && due(f.after)) {
+            None => Ok(()),
+            Some(i) => {
+                let kind = self.armed[i].kind;
+                if self.armed[i].once {
+                    self.armed.remove(i);
+                }
+                let e = io::Error::new(kind, "Injected fault");
+                Err(VfsError::new(op, path, e))
             }
-            //Ok(FirstBytes(bytes))
-            Ok(H::hash(&bytes))
-        } else {
-            Err(io::Error::new(io::ErrorKind::NotFound, "No contents set"))
         }
     }
-    fn get_hash<H: FileHash>(&self) -> io::Result<<H as FileHash>::Output> {
-        if let Some(ref cont) = self.contents {
-            Ok(H::hash(cont.as_bytes()))
-        } else {
-            Err(io::Error::new(io::ErrorKind::NotFound, "No contents set"))
+}
+
+// A `TestFile`'s link back to the faults of the filesystem it came from
+// Files built directly by tests have none; it is ignored when comparing files
+#[derive(Clone, Default)]
+struct FaultHook(Option<Rc<RefCell<Faults>>>);
+
+impl FaultHook {
+    fn check(&self, op: Operation, path: &Path) -> Result<()> {
+        match self.0 {
+            Some(ref faults) => faults.borrow_mut().take(op, path),
+            None => Ok(()),
+        }
+    }
+    fn check_partway(&self, path: &Path, offset: u64) -> Result<()> {
+        match self.0 {
+            Some(ref faults) => faults.borrow_mut().take_partway(path, offset),
+            None => Ok(()),
         }
     }
 }
 
+impl PartialEq for FaultHook {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl fmt::Debug for FaultHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FaultHook")
+    }
+}
+
+/// A mutating operation performed on a `TestFileSystem`
+/// Recorded in order so tests can assert exactly what an actor did
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsOp {
+    /// A file was removed
+    Delete(PathBuf),
+    /// A hard link was created at `dst` pointing to `src`
+    HardLink { src: PathBuf, dst: PathBuf },
+    /// A symlink was created at `path` pointing to `target`
+    Symlink { path: PathBuf, target: PathBuf },
+    /// The file at `dst` was made a copy-on-write clone of the one at `src`
+    Reflink { src: PathBuf, dst: PathBuf },
+    /// The file at `from` was moved to `to`
+    Rename { from: PathBuf, to: PathBuf },
+}
+
 /// Denotes a Mock Filesystem used instead of risking
 /// real data or dealing with the actual filesystem
 #[derive(Debug)]
 pub struct TestFileSystem {
     files: HashMap<PathBuf, TestFile>,
     symlinks: HashMap<PathBuf, (TestFile, PathBuf)>,
+    // journal of every successful mutating call
+    ops: Vec<FsOp>,
+    // errors to inject, see `fail_next`
+    faults: Rc<RefCell<Faults>>,
+    // size of each device in bytes, see `set_capacity`
+    capacities: HashMap<u64, u64>,
+    // whether `files` and `symlinks` are keyed by lowercased paths
+    case_insensitive: bool,
+    // how long listing a directory takes, see `set_latency`
+    latency: Option<Duration>,
+    // the file whose storage each reflinked file shares, see `create_reflink`
+    clones: HashMap<ID, ID>,
 }
 
 impl TestFileSystem {
@@ -209,12 +475,16 @@ impl TestFileSystem {
         // Create the metadata for the file
         let md = TestMD {
             len: 0,
+            allocated: None,
             modified: time::UNIX_EPOCH,
             kind,
             id: ID {
                 inode: inode.0,
                 dev: 0,
             },
+            nlink: 1,
+            uid: 0,
+            mode: 0o644,
         };
         // Create the File.
         let tf = TestFile {
@@ -223,9 +493,11 @@ impl TestFileSystem {
             inode,
             contents: None,
             metadata: Some(md),
+            faults: FaultHook::default(),
         };
         // Add the file to the filesystem.
-        self.files.insert(path.to_owned(), tf);
+        let key = self.key(path);
+        self.files.insert(key, tf);
     }
 
     /// constructor: initializes self.
@@ -233,12 +505,123 @@ impl TestFileSystem {
         Rc::new(RefCell::new(TestFileSystem {
             files: HashMap::new(),
             symlinks: HashMap::new(),
+            ops: vec![],
+            faults: Rc::new(RefCell::new(Faults::default())),
+            capacities: HashMap::new(),
+            case_insensitive: false,
+            latency: None,
+            clones: HashMap::new(),
         }))
     }
+    /// make paths that differ only in case refer to the same file (like macOS and Windows)
+    /// Files keep the case they were created with; must be set before anything is added
+    pub fn set_case_insensitive(&mut self, on: bool) {
+        assert_eq!(0, self.num_elements(), "Can't change case sensitivity of a populated fs");
+        self.case_insensitive = on;
+    }
+    // the key `path` is stored under
+    fn key(&self, path: &Path) -> PathBuf {
+        if self.case_insensitive {
+            PathBuf::from(path.to_string_lossy().to_lowercase())
+        } else {
+            path.to_path_buf()
+        }
+    }
+    /// make listing a directory take `d`, like a slow network share
+    pub fn set_latency(&mut self, d: Duration) {
+        self.latency = Some(d);
+    }
     /// get size
     pub fn num_elements(&self) -> usize {
         self.files.len() + self.symlinks.len()
     }
+    /// get every mutating operation performed so far, oldest first
+    pub fn ops(&self) -> &[FsOp] {
+        &self.ops
+    }
+    /// forget all recorded operations (e.g. after setting up a test)
+    pub fn clear_ops(&mut self) {
+        self.ops.clear();
+    }
+    /// make the next `op` on `path` fail with an error of kind `kind`
+    /// Operations reached through a `TestFile` (reading contents or metadata)
+    /// fail if the file was obtained from this filesystem via `get_file`
+    pub fn fail_next<P: AsRef<Path>>(&mut self, op: Operation, path: P, kind: io::ErrorKind) {
+        self.arm(op, path.as_ref(), kind, true);
+    }
+    /// make every `op` on `path` fail with an error of kind `kind`
+    pub fn fail_always<P: AsRef<Path>>(&mut self, op: Operation, path: P, kind: io::ErrorKind) {
+        self.arm(op, path.as_ref(), kind, false);
+    }
+    /// make the next read of the contents of `path` fail with an error of kind `kind`
+    /// once `after` bytes of it have been read, as if the connection dropped partway
+    pub fn fail_partway<P: AsRef<Path>>(&mut self, path: P, after: u64, kind: io::ErrorKind) {
+        self.faults.borrow_mut().armed.push(Fault {
+            op: Operation::ReadContents,
+            path: path.as_ref().to_path_buf(),
+            kind,
+            once: true,
+            after: after.max(1),
+        });
+    }
+    fn arm(&mut self, op: Operation, path: &Path, kind: io::ErrorKind, once: bool) {
+        self.faults.borrow_mut().armed.push(Fault {
+            op,
+            path: path.to_path_buf(),
+            kind,
+            once,
+            after: 0,
+        });
+    }
+    /// how many times file contents (first bytes or all of them) have been read
+    /// through files from `get_file`
+    pub fn num_reads(&self) -> usize {
+        self.faults.borrow().reads
+    }
+    /// how many times metadata has been looked up, through the filesystem or its files
+    pub fn num_metadata_reads(&self) -> usize {
+        self.faults.borrow().metadata_reads
+    }
+    // fire the fault armed for `op` on `path`, if any
+    fn check_fault(&self, op: Operation, path: &Path) -> Result<()> {
+        self.faults.borrow_mut().take(op, path)
+    }
+    /// make device `dev` hold `bytes` bytes; devices without a capacity never fill up
+    /// Free space is the capacity minus the length of every distinct file on the device
+    pub fn set_capacity(&mut self, dev: u64, bytes: u64) {
+        self.capacities.insert(dev, bytes);
+    }
+    // bytes left on device `dev`
+    fn free_bytes(&self, dev: u64) -> u64 {
+        let capacity = match self.capacities.get(&dev) {
+            Some(&c) => c,
+            None => return u64::MAX,
+        };
+        // hard links share their space, and so do reflinks
+        let used: u64 = self.files
+            .values()
+            .filter_map(|f| f.metadata)
+            .filter(|md| md.kind == FileType::File && md.id.dev == dev)
+            .filter(|md| !self.clones.contains_key(&md.id))
+            .map(|md| (md.id, md.len))
+            .collect::<HashMap<_, _>>()
+            .values()
+            .sum();
+        capacity.saturating_sub(used)
+    }
+    // a link to the file `id` was added or removed: update the count every path sees
+    fn count_links(&mut self, id: ID, added: bool) {
+        let mds = self.files.values_mut().filter_map(|f| f.metadata.as_mut());
+        for md in mds.filter(|md| md.kind == FileType::File && md.id == id) {
+            md.nlink = if added { md.nlink + 1 } else { md.nlink.saturating_sub(1) };
+        }
+    }
+    // the path to `file` is gone
+    fn unlinked(&mut self, file: &TestFile) {
+        if let Some(md) = file.metadata {
+            self.count_links(md.id, false);
+        }
+    }
     /// get number of unique inodes
     pub fn num_inodes(&self) -> usize {
         let inodes: HashSet<_> = self.files
@@ -265,14 +648,170 @@ impl TestFileSystem {
             inode: self.get_next_inode(),
             contents: None,
             metadata: None,
+            faults: FaultHook::default(),
         };
         // add the symlink to the filesystem.
         let val = (tf, target.as_ref().to_owned());
-        self.symlinks.insert(path.as_ref().to_owned(), val);
+        let key = self.key(path.as_ref());
+        self.symlinks.insert(key, val);
+        self.ops.push(FsOp::Symlink {
+            path: path.as_ref().to_owned(),
+            target: target.as_ref().to_owned(),
+        });
+    }
+    /// Creates a new hard link at dst pointing to the file at src.
+    /// analogous to `ln src dst`
+    /// The link shares the inode, contents, and metadata of src
+    pub fn create_hardlink<P: AsRef<Path>>(&mut self, src: P, dst: P) -> Result<()> {
+        let (src, dst) = (src.as_ref(), dst.as_ref());
+        let fail = |e| VfsError::new(Operation::HardLink, dst, e);
+        let dst_key = self.key(dst);
+        if self.files.contains_key(&dst_key) || self.symlinks.contains_key(&dst_key) {
+            // EEXIST: refuse to clobber an existing file
+            return Err(fail(io::Error::from_raw_os_error(17)));
+        }
+        let old_file = self.files
+            .get(&self.key(src))
+            .ok_or_else(|| fail(io::Error::new(io::ErrorKind::NotFound, "No src file")))?;
+        let old_md = old_file.get_metadata()?;
+        let old_device = old_md.get_device().map_err(fail)?;
+
+        // verify the new link is going to be on the same device
+        let new_dir = dst.parent()
+            .ok_or_else(|| fail(io::Error::new(io::ErrorKind::Other, "Cannot link to root")))?;
+        let new_device = self.files
+            .get(&self.key(new_dir))
+            .ok_or_else(|| fail(io::Error::new(io::ErrorKind::NotFound, "No dst dir")))?
+            .get_metadata()?
+            .get_device()
+            .map_err(fail)?;
+        if old_device != new_device {
+            // EXDEV: can't make a hard link across devices (on most filesystems)
+            return Err(fail(io::Error::from_raw_os_error(18)));
+        }
+
+        let mut link = old_file.clone();
+        link.path = dst.to_path_buf();
+        self.files.insert(dst_key, link);
+        self.count_links(old_md.id, true);
+        self.ops.push(FsOp::HardLink {
+            src: src.to_path_buf(),
+            dst: dst.to_path_buf(),
+        });
+        Ok(())
+    }
+    /// Makes the existing file dst a copy-on-write clone of src (like `cp --reflink`):
+    /// it takes on src's contents and shares its storage, but keeps its own inode,
+    /// permissions and links. Fails with EXDEV if they're on different devices
+    pub fn create_reflink<P: AsRef<Path>>(&mut self, src: P, dst: P) -> Result<()> {
+        let (src, dst) = (src.as_ref(), dst.as_ref());
+        let fail = |e| VfsError::new(Operation::Reflink, dst, e);
+        let no_file = |what| fail(io::Error::new(io::ErrorKind::NotFound, what));
+        let (contents, src_md) = {
+            let old_file = self.files.get(&self.key(src)).ok_or_else(|| no_file("No src file"))?;
+            (old_file.contents.clone(), old_file.get_metadata()?)
+        };
+        let dst_key = self.key(dst);
+        let dst_md = self.files
+            .get(&dst_key)
+            .ok_or_else(|| no_file("No dst file"))?
+            .get_metadata()?;
+        if src_md.id.dev != dst_md.id.dev {
+            // EXDEV: clones share blocks, so they can't span filesystems
+            return Err(fail(io::Error::from_raw_os_error(18)));
+        }
+        // every link to dst sees the new contents; a clone of a clone shares the original
+        let shared = self.clones.get(&src_md.id).cloned().unwrap_or(src_md.id);
+        for file in self.files.values_mut() {
+            if let Some(ref mut md) = file.metadata {
+                if md.kind == FileType::File && md.id == dst_md.id {
+                    md.len = src_md.len;
+                    md.allocated = src_md.allocated;
+                    file.contents = contents.clone();
+                }
+            }
+        }
+        if shared != dst_md.id {
+            self.clones.insert(dst_md.id, shared);
+        }
+        self.ops.push(FsOp::Reflink {
+            src: src.to_path_buf(),
+            dst: dst.to_path_buf(),
+        });
+        Ok(())
+    }
+    /// Whether the files at `a` and `b` share their storage, as hard links or reflinks
+    pub fn shares_storage<P: AsRef<Path>>(&self, a: P, b: P) -> bool {
+        let storage = |p: &Path| {
+            let id = self.files.get(&self.key(p))?.metadata?.id;
+            Some(self.clones.get(&id).cloned().unwrap_or(id))
+        };
+        match (storage(a.as_ref()), storage(b.as_ref())) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+    /// Moves the file or symlink at from to to, replacing whatever was at to.
+    /// analogous to `mv from to`; a symlink is moved itself, not its target
+    /// Fails with EXDEV if to is in a directory on another device (like rename(2))
+    pub fn rename_file<P: AsRef<Path>>(&mut self, from: P, to: P) -> Result<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let (from_key, to_key) = (self.key(from), self.key(to));
+        let is_link = if self.files.contains_key(&from_key) {
+            false
+        } else if self.symlinks.contains_key(&from_key) {
+            true
+        } else {
+            return Err(not_found(Operation::Rename, from));
+        };
+
+        // files carry their own device; symlinks live on their directory's
+        let old_device = if is_link {
+            from.parent().and_then(|dir| self.device_of(dir))
+        } else {
+            self.device_of(from)
+        };
+        let new_device = to.parent().and_then(|dir| self.device_of(dir));
+        if let (Some(old), Some(new)) = (old_device, new_device) {
+            if old != new {
+                // EXDEV: rename can't move anything across devices
+                let e = io::Error::from_raw_os_error(18);
+                return Err(VfsError::new(Operation::Rename, to, e));
+            }
+        }
+
+        // take whatever is being moved out before clobbering `to`, so moving
+        //  a path onto itself (or onto a different case of itself) keeps it
+        let moved_file = self.files.remove(&from_key);
+        let moved_link = self.symlinks.remove(&from_key);
+        if let Some(replaced) = self.files.remove(&to_key) {
+            self.unlinked(&replaced);
+        }
+        self.symlinks.remove(&to_key);
+        if let Some(mut file) = moved_file {
+            file.path = to.to_path_buf();
+            self.files.insert(to_key, file);
+        } else if let Some((mut link, target)) = moved_link {
+            link.path = to.to_path_buf();
+            self.symlinks.insert(to_key, (link, target));
+        }
+        self.ops.push(FsOp::Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        });
+        Ok(())
+    }
+    // the device a file or directory is on, if it has metadata
+    fn device_of(&self, path: &Path) -> Option<u64> {
+        self.files
+            .get(&self.key(path))
+            .and_then(|f| f.metadata)
+            .map(|md| md.id.dev)
     }
     /// Register a new file
     pub fn add(&mut self, tf: TestFile) {
-        self.files.insert(tf.path.to_owned(), tf);
+        let key = self.key(&tf.path);
+        self.files.insert(key, tf);
     }
 
     // getters for the Mock Filesystem.
@@ -281,28 +820,81 @@ impl TestFileSystem {
     // through so they are invalidated when the parent is.
 
     /// Resolves the path into a TestFile
-    fn lookup<'a>(&'a self, path: &Path) -> io::Result<&'a TestFile> {
-        if let Some(tf) = self.files.get(path) {
+    fn lookup<'a>(&'a self, path: &Path) -> Result<&'a TestFile> {
+        if let Some(tf) = self.files.get(&self.key(path)) {
             Ok(tf)
         } else {
             // traverse the symlink chain
-            let mut cur = self.symlinks.get(path);
+            let mut cur = self.symlinks.get(&self.key(path));
             // `seen` can't be a Hash table because SystemTime isn't Hash
             let mut seen: Vec<&Path> = vec![];
             while let Some(c) = cur {
                 if seen.contains(&c.1.as_path()) {
                     // infinite symlink loop
-                    return Err(io::Error::from_raw_os_error(40));
+                    let e = io::Error::from_raw_os_error(40);
+                    return Err(VfsError::new(Operation::ReadMetadata, path, e));
                 } else {
                     seen.push(&c.1);
-                    cur = self.symlinks.get(&c.1);
+                    cur = self.symlinks.get(&self.key(&c.1));
                 }
             }
-            Err(io::Error::new(io::ErrorKind::NotFound, "No such file"))
+            Err(not_found(Operation::ReadMetadata, path))
         }
     }
 }
 
+// symlinks followed by `TestFileSystem::canonical_path` before giving up (like Linux's limit)
+const MAX_SYMLINK_HOPS: usize = 40;
+
+impl TestFileSystem {
+    // resolve every component of `path`, following symlinks (relative to the root)
+    fn canonical_path(&self, path: &Path) -> Result<PathBuf> {
+        // components still to be resolved, the next one last
+        let mut pending: Vec<Component> = path.components().rev().collect();
+        let mut resolved = PathBuf::from("/");
+        let mut hops = 0;
+        while let Some(component) = pending.pop() {
+            match component {
+                Component::Prefix(_) | Component::CurDir => continue,
+                Component::RootDir => {
+                    resolved = PathBuf::from("/");
+                    continue;
+                }
+                Component::ParentDir => {
+                    resolved.pop();
+                    continue;
+                }
+                Component::Normal(name) => resolved.push(name),
+            }
+            let key = self.key(&resolved);
+            if let Some(target) = self.symlinks.get(&key).map(|link| &link.1) {
+                hops += 1;
+                if hops > MAX_SYMLINK_HOPS {
+                    // ELOOP: too many levels of symbolic links
+                    let e = io::Error::from_raw_os_error(40);
+                    return Err(VfsError::new(Operation::Canonicalize, path, e));
+                }
+                // continue from the link's directory with the target's components
+                resolved.pop();
+                pending.extend(target.components().rev());
+            } else {
+                // use the case the file was created with
+                match self.files.get(&key) {
+                    Some(f) => resolved = f.path.clone(),
+                    None => return Err(not_found(Operation::Canonicalize, path)),
+                }
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+// helper: the error for operating on a path that isn't in the filesystem
+fn not_found<P: AsRef<Path>>(op: Operation, path: P) -> VfsError {
+    let e = io::Error::new(io::ErrorKind::NotFound, "No such file");
+    VfsError::new(op, path, e)
+}
+
 // Implementation of the VFS interface for the whole of the Mock File System.
 impl VFS for Rc<RefCell<TestFileSystem>> {
     type FileIter = TestFile;
@@ -311,21 +903,25 @@ impl VFS for Rc<RefCell<TestFileSystem>> {
     fn list_dir<P: AsRef<Path>>(
         &self,
         p: P,
-    ) -> io::Result<Box<Iterator<Item = io::Result<TestFile>>>> {
+    ) -> Result<Box<Iterator<Item = Result<TestFile>>>> {
         let mut v = vec![];
         let fs = self.borrow();
-        // collect all files which are children of p
-        let is_root = p.as_ref().components().count() == 1;
+        fs.check_fault(Operation::ListDir, p.as_ref())?;
+        if let Some(d) = fs.latency {
+            thread::sleep(d);
+        }
+        let dir = &fs.key(p.as_ref());
+        // collect all files which are direct children of p
+        // the root is not special: the parent of "/a" is Some("/"),
+        // and the parent of "/" itself is None so it is never its own child
         for (path, file) in &fs.files {
-            if path.parent() == Some(p.as_ref()) || (is_root && path.components().count() == 2) {
-                // include a file if it's parent is in `p`
-                // or if `p` is the root and `path` is 1 level down
+            if path.parent() == Some(dir) {
                 v.push(Ok(file.clone()));
             }
         }
-        // collect all symlinks which are children of p
+        // collect all symlinks which are direct children of p
         for (src, &(ref file, ref _dst)) in &fs.symlinks {
-            if src.parent() == Some(p.as_ref()) || p.as_ref().parent().is_none() {
+            if src.parent() == Some(dir) {
                 v.push(Ok(file.clone()));
             }
         }
@@ -355,13 +951,15 @@ impl VFS for Rc<RefCell<TestFileSystem>> {
     /// VFS::get_metadata gets the Metadata of Path
     /// FileType of path cannot be symlink; they are handled diffrently; use
     /// VFS::get_symlink_metadata for symlinks
-    fn get_metadata<P: AsRef<Path>>(&self, path: P) -> io::Result<<Self::FileIter as File>::MD> {
+    fn get_metadata<P: AsRef<Path>>(&self, path: P) -> Result<<Self::FileIter as File>::MD> {
         let fs = self.borrow();
-        match fs.files.get(path.as_ref()) {
+        fs.check_fault(Operation::ReadMetadata, path.as_ref())?;
+        let key = fs.key(path.as_ref());
+        match fs.files.get(&key) {
             Some(f) => f.get_metadata(),
-            None => match fs.symlinks.get(path.as_ref()) {
+            None => match fs.symlinks.get(&key) {
                 Some(&(_, ref p)) => fs.lookup(p).and_then(|f| f.get_metadata()),
-                None => Err(io::Error::new(io::ErrorKind::NotFound, "No such file")),
+                None => Err(not_found(Operation::ReadMetadata, path)),
             },
         }
     }
@@ -370,73 +968,102 @@ impl VFS for Rc<RefCell<TestFileSystem>> {
     fn get_symlink_metadata<P: AsRef<Path>>(
         &self,
         path: P,
-    ) -> io::Result<<Self::FileIter as File>::MD> {
+    ) -> Result<<Self::FileIter as File>::MD> {
         let fs = self.borrow();
-        match fs.files.get(path.as_ref()) {
+        fs.check_fault(Operation::ReadMetadata, path.as_ref())?;
+        let key = fs.key(path.as_ref());
+        match fs.files.get(&key) {
             Some(f) => f.get_metadata(),
-            None => match fs.symlinks.get(path.as_ref()) {
+            None => match fs.symlinks.get(&key) {
                 Some(&(ref f, _)) => f.get_metadata(),
-                None => Err(io::Error::new(io::ErrorKind::NotFound, "No such file")),
+                None => Err(not_found(Operation::ReadMetadata, path)),
             },
         }
     }
 
     /// VFS::read_link(p) resolves symlink at path p to the path its pointing to
     /// or gives an error if the link is broken.
-    fn read_link<P: AsRef<Path>>(&self, path: P) -> io::Result<PathBuf> {
-        match self.borrow().symlinks.get(path.as_ref()) {
+    fn read_link<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        let fs = self.borrow();
+        fs.check_fault(Operation::ReadLink, path.as_ref())?;
+        match fs.symlinks.get(&fs.key(path.as_ref())) {
             Some(&(_, ref p)) => Ok(p.to_owned()),
-            None => Err(io::Error::new(io::ErrorKind::NotFound, "No such file")),
+            None => Err(not_found(Operation::ReadLink, path)),
         }
     }
 
-    fn get_file(&self, p: &Path) -> io::Result<Self::FileIter> {
-        match self.borrow().files.get(p) {
-            Some(f) => Ok(f.to_owned()),
-            None => Err(io::Error::new(io::ErrorKind::NotFound, "No such file")),
-        }
+    // the mock has no working directory, so relative paths start at the root
+    fn canonicalize(&self, p: &Path) -> Result<PathBuf> {
+        let fs = self.borrow();
+        fs.check_fault(Operation::Canonicalize, p)?;
+        fs.canonical_path(p)
     }
 
-    fn rm_file<P: AsRef<Path>>(&mut self, p: &P) -> io::Result<()> {
+    /// Like opening a file, a symlink is followed to the file it points to
+    /// (which keeps the link's path, as a `DirEntry` would)
+    fn get_file(&self, p: &Path) -> Result<Self::FileIter> {
+        let fs = self.borrow();
+        fs.check_fault(Operation::OpenFile, p)?;
+        let key = fs.key(p);
+        let mut f = match fs.files.get(&key) {
+            Some(f) => f.to_owned(),
+            None if fs.symlinks.contains_key(&key) => {
+                let target = fs.canonical_path(p)
+                    .ok()
+                    .and_then(|real| fs.files.get(&fs.key(&real)));
+                match target {
+                    Some(f) => TestFile {
+                        path: p.to_path_buf(),
+                        ..f.to_owned()
+                    },
+                    None => return Err(not_found(Operation::OpenFile, p)),
+                }
+            }
+            None => return Err(not_found(Operation::OpenFile, p)),
+        };
+        f.faults = FaultHook(Some(fs.faults.clone()));
+        Ok(f)
+    }
+
+    fn rm_file<P: AsRef<Path>>(&mut self, p: &P) -> Result<()> {
         let mut fs = self.borrow_mut();
-        match fs.files.remove(p.as_ref()) {
-            Some(_) => Ok(()),
-            None => Err(io::Error::new(io::ErrorKind::Other, "Couldn't delete file")),
+        fs.check_fault(Operation::Delete, p.as_ref())?;
+        let key = fs.key(p.as_ref());
+        match fs.files.remove(&key) {
+            Some(removed) => {
+                fs.unlinked(&removed);
+                fs.ops.push(FsOp::Delete(p.as_ref().to_path_buf()));
+                Ok(())
+            }
+            None => Err(not_found(Operation::Delete, p)),
         }
     }
 
     // create a hard link
-    fn make_link(&mut self, src: &Path, dst: &Path) -> io::Result<()> {
+    fn make_hard_link(&mut self, src: &Path, dst: &Path) -> Result<()> {
         let mut fs = self.borrow_mut();
-        let old_md = fs.files
-            .get(dst)
-            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No dst file"))?
-            .get_metadata()?;
-        // need to know the old inode so the new can have the same
-        let old_inode = old_md.get_inode();
-        let old_device = old_md.get_device()?;
-
-        // verify new file is going to be
-        let new_dir = src.parent().unwrap(); // can't be root
-        let new_device = fs.files
-            .get(new_dir)
-            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No src md"))?
-            .get_metadata()?
-            .get_device()?;
+        fs.check_fault(Operation::HardLink, dst)?;
+        fs.create_hardlink(src, dst)
+    }
 
-        if old_device != new_device {
-            // can't make a hard link across devices (on most filesystems)
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Cannot make hard link across filesystems",
-            ));
-        }
-
-        let name = src.to_str().expect("invalid unicode link name");
-        fs.files.insert(
-            src.to_path_buf(),
-            TestFile::new(name).with_inode(old_inode.0),
-        );
-        Ok(())
+    // share a file's storage with another
+    fn reflink(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        let mut fs = self.borrow_mut();
+        fs.check_fault(Operation::Reflink, dst)?;
+        fs.create_reflink(src, dst)
+    }
+
+    // move a file
+    fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+        let mut fs = self.borrow_mut();
+        fs.check_fault(Operation::Rename, to)?;
+        fs.rename_file(from, to)
+    }
+
+    // see how much room is left on a device
+    fn free_space(&self, p: &Path) -> Result<u64> {
+        self.borrow().check_fault(Operation::FreeSpace, p)?;
+        let dev = self.get_metadata(p)?.id.dev;
+        Ok(self.borrow().free_bytes(dev))
     }
 }
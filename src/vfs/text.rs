@@ -0,0 +1,343 @@
+//! Wrap another VFS so small files are compared as text rather than byte for byte
+
+use std::{io, time};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use vfs::{DeviceId, File, FileType, Inode, MetaData, Result, VFS};
+use hash::FileHash;
+use helpers::FIRST_K_BYTES;
+
+/// A `VFS` that reads through to another one, but presents every file of at most `limit`
+/// bytes as text: trailing whitespace is dropped and CRLF line endings become LF
+/// Sizes and hashes are those of the normalized text, so this is a semantic comparison;
+/// files it calls identical may still differ byte for byte
+#[derive(Debug, Clone)]
+pub struct TextNormalizedVfs<V: VFS> {
+    inner: V,
+    limit: u64,
+}
+
+impl<V: VFS> TextNormalizedVfs<V> {
+    /// Treat files on `inner` of at most `limit` bytes as text
+    pub fn new(inner: V, limit: u64) -> Self {
+        TextNormalizedVfs { inner, limit }
+    }
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+}
+
+/// A file from the inner filesystem, read as text if it is small enough
+#[derive(Debug, Clone)]
+pub struct TextFile<F: File> {
+    inner: F,
+    limit: u64,
+}
+
+/// Metadata from the inner filesystem, with the length of the normalized text
+#[derive(Debug, Clone)]
+pub struct TextMetaData<M: MetaData> {
+    inner: M,
+    len: u64,
+}
+
+// whether a file with metadata `md` is read as text
+fn is_text<M: MetaData>(md: &M, limit: u64) -> bool {
+    md.get_type() == FileType::File && md.get_len() <= limit
+}
+
+impl<F: File> TextFile<F> {
+    // whether this file is read as text
+    fn is_text(&self) -> Result<bool> {
+        Ok(is_text(&self.inner.get_metadata()?, self.limit))
+    }
+    // attach the normalized length to the inner file's metadata `md`
+    fn text_metadata(&self, md: F::MD) -> Result<TextMetaData<F::MD>> {
+        let len = if is_text(&md, self.limit) {
+            self.inner.get_hash::<NormalizedLen>()?
+        } else {
+            md.get_len()
+        };
+        Ok(TextMetaData { inner: md, len })
+    }
+}
+
+impl<F: File> File for TextFile<F> {
+    type MD = TextMetaData<F::MD>;
+
+    fn get_inode(&self) -> Result<Inode> {
+        self.inner.get_inode()
+    }
+    fn get_path(&self) -> PathBuf {
+        self.inner.get_path()
+    }
+    fn get_type(&self) -> Result<FileType> {
+        self.inner.get_type()
+    }
+    fn get_metadata(&self) -> Result<Self::MD> {
+        let md = self.inner.get_metadata()?;
+        self.text_metadata(md)
+    }
+    /// Hash the first bytes of the normalized text (which means reading all of it)
+    fn get_first_bytes<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        if self.is_text()? {
+            self.inner.get_hash::<NormalizedPrefix<H>>()
+        } else {
+            self.inner.get_first_bytes::<H>()
+        }
+    }
+    /// Hash all of the normalized text, whose last bytes can't be found without reading it
+    fn get_last_bytes<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        if self.is_text()? {
+            self.inner.get_hash::<Normalized<H>>()
+        } else {
+            self.inner.get_last_bytes::<H>()
+        }
+    }
+    fn get_hash<H: FileHash>(&self) -> Result<<H as FileHash>::Output> {
+        if self.is_text()? {
+            self.inner.get_hash::<Normalized<H>>()
+        } else {
+            self.inner.get_hash::<H>()
+        }
+    }
+    fn get_hash_with_progress<H: FileHash>(
+        &self,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<<H as FileHash>::Output> {
+        if self.is_text()? {
+            self.inner.get_hash_with_progress::<Normalized<H>>(progress)
+        } else {
+            self.inner.get_hash_with_progress::<H>(progress)
+        }
+    }
+}
+
+impl<M: MetaData> MetaData for TextMetaData<M> {
+    fn get_len(&self) -> u64 {
+        self.len
+    }
+    fn get_allocated_size(&self) -> u64 {
+        self.inner.get_allocated_size()
+    }
+    fn get_mod_time(&self) -> io::Result<time::SystemTime> {
+        self.inner.get_mod_time()
+    }
+    fn get_type(&self) -> FileType {
+        self.inner.get_type()
+    }
+    fn get_inode(&self) -> Inode {
+        self.inner.get_inode()
+    }
+    fn get_device(&self) -> io::Result<DeviceId> {
+        self.inner.get_device()
+    }
+    fn get_nlink(&self) -> u64 {
+        self.inner.get_nlink()
+    }
+    fn get_uid(&self) -> u32 {
+        self.inner.get_uid()
+    }
+    fn get_mode(&self) -> u32 {
+        self.inner.get_mode()
+    }
+}
+
+impl<V> TextNormalizedVfs<V>
+where
+    V: VFS,
+{
+    // wrap a file from the inner filesystem
+    fn wrap(&self, inner: V::FileIter) -> TextFile<V::FileIter> {
+        TextFile {
+            inner,
+            limit: self.limit,
+        }
+    }
+    // attach the normalized length to the metadata `md` of `p`
+    // the text is read straight from `p`, as looking up its `File` may list its directory
+    fn text_metadata(
+        &self,
+        p: &Path,
+        md: <V::FileIter as File>::MD,
+    ) -> Result<TextMetaData<<V::FileIter as File>::MD>> {
+        let len = if is_text(&md, self.limit) {
+            self.inner.hash_path::<NormalizedLen>(p)?
+        } else {
+            md.get_len()
+        };
+        Ok(TextMetaData { inner: md, len })
+    }
+}
+
+impl<V> VFS for TextNormalizedVfs<V>
+where
+    V: VFS,
+    V::FileIter: 'static,
+{
+    type FileIter = TextFile<V::FileIter>;
+
+    fn list_dir<P: AsRef<Path>>(
+        &self,
+        p: P,
+    ) -> Result<Box<dyn Iterator<Item = Result<Self::FileIter>>>> {
+        let limit = self.limit;
+        let files = self.inner.list_dir(p)?;
+        Ok(Box::new(
+            files.map(move |f| f.map(|inner| TextFile { inner, limit })),
+        ))
+    }
+    /// Look up the metadata for `p`; a small file is read to measure its text
+    fn get_metadata<P: AsRef<Path>>(&self, p: P) -> Result<<Self::FileIter as File>::MD> {
+        let md = self.inner.get_metadata(p.as_ref())?;
+        self.text_metadata(p.as_ref(), md)
+    }
+    fn get_symlink_metadata<P: AsRef<Path>>(
+        &self,
+        p: P,
+    ) -> Result<<Self::FileIter as File>::MD> {
+        let md = self.inner.get_symlink_metadata(p.as_ref())?;
+        self.text_metadata(p.as_ref(), md)
+    }
+    fn read_link<P: AsRef<Path>>(&self, p: P) -> Result<PathBuf> {
+        self.inner.read_link(p)
+    }
+    fn canonicalize(&self, p: &Path) -> Result<PathBuf> {
+        self.inner.canonicalize(p)
+    }
+    fn get_file(&self, p: &Path) -> Result<Self::FileIter> {
+        Ok(self.wrap(self.inner.get_file(p)?))
+    }
+    fn hash_path<H: FileHash>(&self, p: &Path) -> Result<<H as FileHash>::Output> {
+        if is_text(&self.inner.get_metadata(p)?, self.limit) {
+            self.inner.hash_path::<Normalized<H>>(p)
+        } else {
+            self.inner.hash_path::<H>(p)
+        }
+    }
+    fn rm_file<P: AsRef<Path>>(&mut self, p: &P) -> Result<()> {
+        self.inner.rm_file(p)
+    }
+    fn make_hard_link(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        self.inner.make_hard_link(src, dst)
+    }
+    fn reflink(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        self.inner.reflink(src, dst)
+    }
+    fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.rename(from, to)
+    }
+    fn free_space(&self, p: &Path) -> Result<u64> {
+        self.inner.free_space(p)
+    }
+}
+
+// normalizes a stream of bytes fed to it a chunk at a time
+// a run of whitespace is held back until more text shows it isn't trailing
+#[derive(Debug, Default)]
+struct Normalizer {
+    pending: Vec<u8>,
+}
+
+impl Normalizer {
+    // pass the next chunk's normalized text to `out` (possibly in pieces)
+    fn feed<F: FnMut(&[u8])>(&mut self, bytes: &[u8], mut out: F) {
+        // start of the text in `bytes` not yet passed on
+        let mut start = 0;
+        for (i, &b) in bytes.iter().enumerate() {
+            if (b as char).is_ascii_whitespace() {
+                if start < i {
+                    out(&bytes[start..i]);
+                }
+                self.pending.push(b);
+                start = i + 1;
+            } else if !self.pending.is_empty() {
+                // the whitespace wasn't trailing after all: keep it, minus CRs before LFs
+                let kept: Vec<u8> = self.pending
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, &c)| c != b'\r' || self.pending.get(j + 1) != Some(&b'\n'))
+                    .map(|(_, &c)| c)
+                    .collect();
+                out(&kept);
+                self.pending.clear();
+            }
+        }
+        if start < bytes.len() {
+            out(&bytes[start..]);
+        }
+    }
+}
+
+// hash the normalized text with `H`
+#[derive(Debug)]
+struct Normalized<H: FileHash>(PhantomData<H>);
+
+impl<H: FileHash> FileHash for Normalized<H> {
+    type Output = H::Output;
+    type State = (Normalizer, H::State);
+
+    fn start() -> Self::State {
+        (Normalizer::default(), H::start())
+    }
+    fn update(state: &mut Self::State, bytes: &[u8]) {
+        let (ref mut normalizer, ref mut inner) = *state;
+        normalizer.feed(bytes, |text| H::update(inner, text));
+    }
+    fn finish(state: Self::State) -> Self::Output {
+        H::finish(state.1)
+    }
+    fn digest(output: &Self::Output) -> Option<Vec<u8>> {
+        H::digest(output)
+    }
+    fn from_digest(digest: &[u8]) -> Option<Self::Output> {
+        H::from_digest(digest)
+    }
+}
+
+// hash the first `FIRST_K_BYTES` of the normalized text with `H`
+#[derive(Debug)]
+struct NormalizedPrefix<H: FileHash>(PhantomData<H>);
+
+impl<H: FileHash> FileHash for NormalizedPrefix<H> {
+    type Output = H::Output;
+    // the normalizer, the hash, and how many more bytes to hash
+    type State = (Normalizer, H::State, usize);
+
+    fn start() -> Self::State {
+        (Normalizer::default(), H::start(), FIRST_K_BYTES)
+    }
+    fn update(state: &mut Self::State, bytes: &[u8]) {
+        let (ref mut normalizer, ref mut inner, ref mut left) = *state;
+        normalizer.feed(bytes, |text| {
+            let text = &text[..text.len().min(*left)];
+            H::update(inner, text);
+            *left -= text.len();
+        });
+    }
+    fn finish(state: Self::State) -> Self::Output {
+        H::finish(state.1)
+    }
+}
+
+// count the bytes of normalized text
+#[derive(Debug)]
+struct NormalizedLen;
+
+impl FileHash for NormalizedLen {
+    type Output = u64;
+    type State = (Normalizer, u64);
+
+    fn start() -> Self::State {
+        (Normalizer::default(), 0)
+    }
+    fn update(state: &mut Self::State, bytes: &[u8]) {
+        let (ref mut normalizer, ref mut len) = *state;
+        normalizer.feed(bytes, |text| *len += text.len() as u64);
+    }
+    fn finish(state: Self::State) -> Self::Output {
+        state.1
+    }
+}
@@ -1,15 +1,172 @@
 //! Traverse a filesystem and identify files of interest
 
 use std::path::{Path, PathBuf};
-use std::{env, io};
-use std::ffi::OsStr;
-use std::collections::HashSet;
+use std::{env, fmt, io};
+use std::ffi::{OsStr, OsString};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Instant;
 use regex::{self, Regex};
 
-use vfs::{File, FileType, MetaData, VFS};
+use vfs::{File, FileType, MetaData, OnError, VfsError, VFS};
+use hash::Identity;
+use helpers::{path_bytes, prettify_bytes, with_commas, ID};
 
 mod test; //include unit tests
 
+mod pool;
+use self::pool::StatPool;
+
+// whether `path` is among the files `DirWalker::sample` keeps at `rate` with `seed`
+// a hash of the path rather than a random number, so the sample is the same every time
+fn sampled(path: &Path, rate: f64, seed: u64) -> bool {
+    // FNV-1a, started from the seed, then mixed (as in SplitMix64) so nearby paths spread out
+    let mut h = 0xcbf2_9ce4_8422_2325 ^ seed;
+    for &b in path_bytes(path).iter() {
+        h ^= u64::from(b);
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^= h >> 31;
+    // the top 53 bits, as a fraction in [0, 1)
+    ((h >> 11) as f64 / (1u64 << 53) as f64) < rate
+}
+
+/// The order in which `DirWalker` visits the contents of directories
+/// Entries of each directory are visited sorted by path, so either order is deterministic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraversalOrder {
+    /// Finish each directory (including its subdirectories) before moving on to the next
+    DepthFirst,
+    /// Visit everything at one depth before anything deeper
+    BreadthFirst,
+}
+
+/// What a `DirWalker` covered (see `DirWalker::walk_stats`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WalkStats {
+    /// Number of directories entered
+    pub dirs: usize,
+    /// Number of files looked at, whether or not they were kept (each only once)
+    pub files: usize,
+    /// Number of those left out by a blacklist or `.dedupignore`, by `sample`, or for not
+    /// being regular files (e.g. sockets, or symlinks to files with `dir_symlinks_only`)
+    pub skipped: usize,
+    /// Total size of the files kept
+    pub bytes: u64,
+    /// Total space the files kept occupy on disk (see `MetaData::get_allocated_size`)
+    pub allocated: u64,
+}
+
+// e.g. "12 directories, 340 files (2 skipped), 1 MB"
+impl fmt::Display for WalkStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} directories, {} files ({} skipped), {}",
+            with_commas(self.dirs as u64),
+            with_commas(self.files as u64),
+            with_commas(self.skipped as u64),
+            prettify_bytes(self.bytes)
+        )
+    }
+}
+
+/// The name of the file listing what to skip in the directory it's in (see
+/// `DirWalker::ignore_files`)
+pub const IGNORE_FILE: &str = ".dedupignore";
+
+// one line of a `.dedupignore`: a glob, as in a `.gitignore`
+#[derive(Debug)]
+struct IgnoreRule {
+    regex: Regex,
+    // a glob with a `/` is matched against the path from the ignore file's directory,
+    //  otherwise against the name alone (at any depth)
+    anchored: bool,
+    // a glob ending in `/` only matches directories
+    dir_only: bool,
+}
+
+impl IgnoreRule {
+    // read a line of a `.dedupignore`, skipping blanks, comments, and bad globs
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let dir_only = line.ends_with('/');
+        let glob = line.trim_end_matches('/');
+        let anchored = glob.contains('/');
+        let glob = glob.trim_start_matches('/');
+        match Regex::new(&format!("^{}$", glob_to_regex(glob))) {
+            Ok(regex) => Some(IgnoreRule {
+                regex,
+                anchored,
+                dir_only,
+            }),
+            Err(e) => {
+                warn!("Ignoring bad pattern {:?} in {}: {}", line, IGNORE_FILE, e);
+                None
+            }
+        }
+    }
+
+    // whether the rule excludes `rel`, a path relative to the ignore file's directory
+    fn matches(&self, rel: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let subject = if self.anchored {
+            rel.to_str()
+        } else {
+            rel.file_name().and_then(OsStr::to_str)
+        };
+        subject.is_some_and(|s| self.regex.is_match(s))
+    }
+}
+
+// translate a glob into a regex: `*` and `?` stay within one directory, `**` doesn't,
+// `[...]` is a set of characters, and everything else is literal
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    // `**/` is any number of directories, including none
+                    chars.next();
+                    re.push_str("(?:.*/)?");
+                } else {
+                    re.push_str(".*");
+                }
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' => {
+                re.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    re.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        re.push('\\');
+                    }
+                    re.push(c);
+                }
+                re.push(']');
+            }
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re
+}
+
 /// Customizable object to traverse a series of directories, efficiently identifying files and
 /// omitting files in certain paths or that match certain patterns
 #[derive(Debug)]
@@ -17,13 +174,48 @@ pub struct DirWalker<T: VFS> {
     // files to include/exclude
     directories: Vec<PathBuf>,
     blacklist_dirs: Vec<PathBuf>,
+    blacklist_dir_names: Vec<OsString>,
     blacklist_patterns: Vec<Regex>,
+    // other filesystems mounted beneath these are skipped (e.g. network shares)
+    skip_mounts: Vec<PathBuf>,
 
     // keep track of the files and folders we've seen
     // `files` will only be files, `folders` will only be directories
     // symlinks will be resolved to their targets or discarded
+    //  (links to files are also kept under their own path if `keep_symlinks` is set)
     files: HashSet<PathBuf>,
     folders: HashSet<PathBuf>,
+    // the directories entered, by identifier, so one reached again under another path
+    //  (e.g. a link to an ancestor that couldn't be canonicalized, or a bind mount)
+    //  isn't walked again, which could otherwise go on forever
+    folder_ids: HashSet<ID>,
+    // `files` in the order they were found
+    found: Vec<PathBuf>,
+    // those of `files` lying in a blacklisted folder (reached through a symlink from outside)
+    excluded: HashSet<PathBuf>,
+    keep_symlinks: bool,
+    // symlinks to files are skipped rather than followed
+    dir_symlinks_only: bool,
+    // read `.dedupignore` files, and skip what they list
+    ignore_files: bool,
+    // the rules of each `.dedupignore` found, by the directory whose subtree they apply to
+    ignores: HashMap<PathBuf, Vec<IgnoreRule>>,
+
+    // paths waiting to be visited (a stack for depth-first, a queue for breadth-first)
+    order: TraversalOrder,
+    pending: VecDeque<(PathBuf, Option<FileType>)>,
+    // nothing more is visited once this passes (see `deadline`)
+    deadline: Option<Instant>,
+    // the share of files kept, and the seed picking which (see `sample`)
+    sample: Option<(f64, u64)>,
+    // what to do about paths that can't be visited, and what stopped the walk, if anything
+    on_error: OnError,
+    error: Option<VfsError>,
+
+    // what has been covered so far
+    stats: WalkStats,
+    // looks up the sizes of the files found on other threads (see `io_threads`)
+    stat_pool: Option<StatPool>,
 
     // file system being traversed
     vfs: T,
@@ -60,17 +252,56 @@ where
         })
     }
 
+    /// Helper function to resolve symlinks, `.`, and `..` in absolute paths
+    /// Paths that can't be resolved (e.g. because they don't exist) are kept as they are
+    fn get_canonical_paths(vfs: &V, abs_paths: Vec<PathBuf>) -> Vec<PathBuf> {
+        abs_paths
+            .into_iter()
+            .map(|path| Self::get_canonical_path(vfs, path))
+            .collect()
+    }
+
+    // resolve one path the same way (e.g. a symlink's target)
+    // on a case-insensitive filesystem this also picks the case the file is stored with
+    fn get_canonical_path(vfs: &V, path: PathBuf) -> PathBuf {
+        match vfs.canonicalize(&path) {
+            Ok(canonical) => canonical,
+            Err(e) => {
+                debug!("Not canonicalizing: {}", e);
+                path
+            }
+        }
+    }
+
     /// Create a new DirWalker from a list of directories
     pub fn new<P: AsRef<Path>>(vfs: V, dirs: &[P]) -> DirWalker<V> {
         let dirs: Vec<&Path> = dirs.iter().map(|p| p.as_ref()).collect();
         let abs_paths = Self::get_abs_paths(&dirs);
+        let canonical_paths = Self::get_canonical_paths(&vfs, abs_paths);
 
         DirWalker {
-            directories: abs_paths,
+            directories: canonical_paths,
             blacklist_dirs: vec![],
+            blacklist_dir_names: vec![],
             blacklist_patterns: vec![],
+            skip_mounts: vec![],
             files: HashSet::new(),
             folders: HashSet::new(),
+            folder_ids: HashSet::new(),
+            found: vec![],
+            excluded: HashSet::new(),
+            keep_symlinks: false,
+            dir_symlinks_only: false,
+            ignore_files: false,
+            ignores: HashMap::new(),
+            order: TraversalOrder::DepthFirst,
+            pending: VecDeque::new(),
+            deadline: None,
+            sample: None,
+            on_error: OnError::default(),
+            error: None,
+            stats: WalkStats::default(),
+            stat_pool: None,
             vfs: vfs,
         }
     }
@@ -79,7 +310,46 @@ where
     pub fn blacklist_folders(mut self, bl: Vec<&OsStr>) -> Self {
         let paths: Vec<_> = bl.into_iter().map(|s| Path::new(s)).collect();
         let abs_paths = Self::get_abs_paths(&paths);
-        self.blacklist_dirs = abs_paths;
+        // must match the canonicalized roots
+        self.blacklist_dirs = Self::get_canonical_paths(&self.vfs, abs_paths);
+        self
+    }
+
+    /// Skip every filesystem mounted at or beneath these paths (e.g. `/net`), while still
+    /// walking the directories there that are on the same filesystem as their parent
+    /// A mount point is a directory on a different device than its parent
+    pub fn skip_mounts(mut self, prefixes: Vec<&OsStr>) -> Self {
+        let paths: Vec<_> = prefixes.into_iter().map(Path::new).collect();
+        let abs_paths = Self::get_abs_paths(&paths);
+        self.skip_mounts = Self::get_canonical_paths(&self.vfs, abs_paths);
+        self
+    }
+
+    /// Also list symlinks to files under their own path, next to their targets
+    pub fn keep_symlinks(mut self, on: bool) -> Self {
+        self.keep_symlinks = on;
+        self
+    }
+
+    /// Follow only symlinks to directories, skipping symlinks to files (e.g. aliases)
+    /// By default both are followed, and their targets walked
+    pub fn dir_symlinks_only(mut self, on: bool) -> Self {
+        self.dir_symlinks_only = on;
+        self
+    }
+
+    /// Skip whatever a directory's `.dedupignore` lists (one glob per line, as in a
+    /// `.gitignore`) anywhere beneath that directory
+    /// Globs without a `/` match names at any depth; negation (`!`) isn't supported
+    pub fn ignore_files(mut self, on: bool) -> Self {
+        self.ignore_files = on;
+        self
+    }
+
+    /// Build up a DirWalker with a list of blacklisted directory names
+    /// (e.g. `.git`), which are skipped wherever they appear in the tree
+    pub fn blacklist_dir_names(mut self, bl: Vec<&OsStr>) -> Self {
+        self.blacklist_dir_names = bl.into_iter().map(OsStr::to_os_string).collect();
         self
     }
 
@@ -92,6 +362,36 @@ where
         self
     }
 
+    /// Build up a DirWalker that visits directories in the given order (depth-first by default)
+    pub fn traversal_order(mut self, order: TraversalOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Build up a DirWalker that stops visiting paths once `at` has passed
+    /// The files found by then are kept, but the walk is incomplete
+    pub fn deadline(mut self, at: Instant) -> Self {
+        self.deadline = Some(at);
+        self
+    }
+
+    /// Build up a DirWalker that keeps each file with probability `rate` (from 0 to 1),
+    /// for a quick estimate of how much of a huge tree is duplicated
+    /// Which files are kept depends only on their paths and `seed`, so it's the same on
+    /// every run with the same seed
+    pub fn sample(mut self, rate: f64, seed: u64) -> Self {
+        assert!((0.0..=1.0).contains(&rate), "Sample rate {} isn't between 0 and 1", rate);
+        self.sample = Some((rate, seed));
+        self
+    }
+
+    /// Build up a DirWalker that deals with paths it can't visit (e.g. directories it
+    /// can't list) as `policy` says; by default each is logged as a warning and left out
+    pub fn on_error(mut self, policy: OnError) -> Self {
+        self.on_error = policy;
+        self
+    }
+
     /// Determine whether a file is in scope (i.e. not seen already or blacklisted)
     fn should_handle_file(&self, path: &Path) -> bool {
         // only handle files that
@@ -101,6 +401,12 @@ where
         if self.files.contains(path) {
             // have traversed this file before
             false
+        } else if self.is_ignored(path, false) {
+            // a `.dedupignore` above it lists it
+            false
+        } else if !self.sample.map_or(true, |(rate, seed)| sampled(path, rate, seed)) {
+            // not in the sample
+            false
         } else if let Some(path_str) = path.to_str() {
             // handle file if all regexes do NOT match
             self.blacklist_patterns.iter().all(|re| {
@@ -116,12 +422,25 @@ where
         }
     }
 
+    // whether `path` lies in a blacklisted folder, or one with a blacklisted name
+    fn in_blacklisted_folder(&self, path: &Path) -> bool {
+        let parent = match path.parent() {
+            Some(p) => p,
+            None => return false,
+        };
+        self.blacklist_dirs.iter().any(|dir| parent.starts_with(dir))
+            || parent.components().any(|c| {
+                self.blacklist_dir_names.iter().any(|n| n.as_os_str() == c.as_os_str())
+            })
+    }
+
     /// Determine whether a folder is in scope(i.e. not seen already or blacklisted)
     fn should_traverse_folder(&self, path: &Path) -> bool {
         // only look into folders that
         //  1) haven't been seen before,
-        //  2) don't match a folder blacklist, and
-        //  3) don't match a regex pattern blacklist
+        //  2) don't match a folder blacklist,
+        //  3) don't have a blacklisted name, and
+        //  4) don't match a regex pattern blacklist
         //      NOTE: again, bad unicode paths will not match any regex
 
         if self.folders.contains(path) {
@@ -130,6 +449,20 @@ where
         } else if self.blacklist_dirs.iter().any(|dir| path.starts_with(dir)) {
             // the directory has been blacklisted
             false
+        } else if path.file_name()
+            .is_some_and(|name| self.blacklist_dir_names.iter().any(|n| n == name))
+        {
+            // the directory's name has been blacklisted
+            false
+        } else if self.is_ignored(path, true) {
+            // a `.dedupignore` above it lists it
+            false
+        } else if self.skip_mounts.iter().any(|m| path.starts_with(m))
+            && self.is_mount_point(path)
+        {
+            // another filesystem is mounted here, where mounts are skipped
+            info!("Skipping mount point {:?}", path);
+            false
         } else if let Some(path_str) = path.to_str() {
             // only traverse if all patterns do NOT match
             self.blacklist_patterns.iter().all(|re| {
@@ -147,36 +480,154 @@ where
         }
     }
 
+    // whether `path` is on a different device than its parent
+    // (if either can't be looked up, it's assumed not to be)
+    fn is_mount_point(&self, path: &Path) -> bool {
+        let device = |p: &Path| {
+            self.vfs
+                .get_metadata(p)
+                .ok()
+                .and_then(|md| md.get_device().ok())
+        };
+        match path.parent() {
+            Some(parent) => match (device(path), device(parent)) {
+                (Some(dev), Some(parent_dev)) => dev != parent_dev,
+                _ => false,
+            },
+            None => false,
+        }
+    }
+
+    // whether a `.dedupignore` in one of the directories above `path` excludes it
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        path.ancestors().skip(1).any(|dir| match self.ignores.get(dir) {
+            Some(rules) => match path.strip_prefix(dir) {
+                Ok(rel) => rules.iter().any(|rule| rule.matches(rel, is_dir)),
+                Err(_) => false,
+            },
+            None => false,
+        })
+    }
+
+    // remember the rules in the `.dedupignore` at `file`, which apply beneath `dir`
+    fn load_ignore_file(&mut self, dir: &Path, file: &Path) {
+        let contents = match self.vfs.get_file(file).and_then(|f| f.get_hash::<Identity>()) {
+            Ok(contents) => contents,
+            Err(e) => {
+                warn!("{}", e);
+                return;
+            }
+        };
+        let rules: Vec<IgnoreRule> = String::from_utf8_lossy(&contents)
+            .lines()
+            .filter_map(IgnoreRule::parse)
+            .collect();
+        if !rules.is_empty() {
+            self.ignores.insert(dir.to_owned(), rules);
+        }
+    }
+
     /// Perform operation on a file: in this case just add it to a hashset
     fn handle_file(&mut self, path: &Path) {
         // do your thing: here just add to a field of filepaths
         trace!("\tHANDLING FILE {:?}", path);
         let was_absent = self.files.insert(path.to_owned());
         assert!(was_absent);
+        self.found.push(path.to_owned());
+        self.stats.files += 1;
+        match self.stat_pool {
+            Some(ref mut pool) => pool.submit(path.to_owned()),
+            None => match self.vfs.get_metadata(path) {
+                Ok(md) => {
+                    self.stats.bytes += md.get_len();
+                    self.stats.allocated += md.get_allocated_size();
+                }
+                Err(e) => debug!("Not counting the size of {:?}: {}", path, e),
+            },
+        }
+        // only a symlink leads here, but it's no reason to keep this copy over another
+        if self.in_blacklisted_folder(path) {
+            debug!("{:?} was reached through a symlink into a blacklisted folder", path);
+            self.excluded.insert(path.to_owned());
+        }
     }
 
-    /// Operate on a folder: iterate through its contents recursively
+    /// Operate on a folder: schedule its contents to be visited
     pub fn traverse_folder(&mut self, path: &Path) {
         // assume should_handle_folder was called
-        // mutually recursive with Self::dispatch_any_file (sorry mom)
-        // a complex directory structure will be mirrored with a complex stack
-        //  note this is only sorta how BS does it. his isn't the call stack
+        // the contents are visited later by Self::traverse_pending, so deep
+        //  directory structures don't become a deep call stack
 
         let was_absent = self.folders.insert(path.to_owned());
         assert!(was_absent);
+        let id = match self.vfs.get_metadata(path) {
+            Ok(md) => ID::of(&md).ok(),
+            Err(e) => {
+                debug!("Not checking if {:?} was walked already: {}", path, e);
+                None
+            }
+        };
+        if id.is_some_and(|id| !self.folder_ids.insert(id)) {
+            info!("{:?} is a directory already walked under another path, skipping", path);
+            return;
+        }
+        self.stats.dirs += 1;
 
         let contents = match self.vfs.list_dir(path) {
             Ok(c) => c,
             Err(e) => {
-                warn!("Failed to list contents of dir {:?}: {}", path, e);
+                self.failed(e);
                 return;
             }
         };
+        let mut entries = vec![];
         for entry in contents {
             match entry {
-                Ok(ref e) => self.dispatch_any_file(&e.get_path(), e.get_type().ok()),
-                Err(e) => warn!("Failed to identify file in dir {:?}: {}", path, e),
+                Ok(ref e) => entries.push((e.get_path(), e.get_type().ok())),
+                Err(e) => self.failed(e),
+            }
+        }
+        // its `.dedupignore` applies to everything beneath it, so is read first
+        if self.ignore_files {
+            let ignore_file = entries
+                .iter()
+                .find(|e| e.1 == Some(FileType::File) && e.0.ends_with(IGNORE_FILE))
+                .map(|e| e.0.clone());
+            if let Some(file) = ignore_file {
+                self.load_ignore_file(path, &file);
+            }
+        }
+        // directories list their contents in no particular order
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        match self.order {
+            // push to the front of the stack, so the first entry is popped first
+            TraversalOrder::DepthFirst => for entry in entries.into_iter().rev() {
+                self.pending.push_front(entry);
+            },
+            TraversalOrder::BreadthFirst => self.pending.extend(entries),
+        }
+    }
+
+    // deal with a path that couldn't be visited as `on_error` says, stopping if need be
+    fn failed(&mut self, e: VfsError) {
+        if self.error.is_none() && !self.on_error.carry_on(&e) {
+            self.error = Some(e);
+        }
+    }
+
+    /// Visit paths until none are left to visit
+    fn traverse_pending(&mut self) {
+        while let Some((path, filetype)) = self.pending.pop_front() {
+            if self.error.is_some() {
+                self.pending.clear();
+                return;
+            }
+            if self.deadline.is_some_and(|at| Instant::now() >= at) {
+                warn!("Out of time; {} paths left unvisited", self.pending.len() + 1);
+                self.pending.clear();
+                return;
             }
+            self.dispatch_any_file(&path, filetype);
         }
     }
 
@@ -188,7 +639,7 @@ where
             None => match self.vfs.get_metadata(path) {
                 Ok(md) => md.get_type(),
                 Err(e) => {
-                    warn!("Couldn't get metadata for {:?}: {}", path, e);
+                    self.failed(e);
                     return;
                 }
             },
@@ -196,25 +647,140 @@ where
         match filetype {
             FileType::File => if self.should_handle_file(path) {
                 self.handle_file(path)
+            } else if !self.files.contains(path) {
+                self.skip_file();
             },
             FileType::Dir => if self.should_traverse_folder(path) {
                 self.traverse_folder(path)
             },
             FileType::Symlink => match self.vfs.read_link(path) {
-                Ok(ref f) => self.dispatch_any_file(f, None),
-                Err(e) => warn!("Couldn't resolve symlink {:?}: {}", path, e),
+                // so a file reached through a link isn't handled twice under two names
+                Ok(f) => {
+                    if self.dir_symlinks_only {
+                        match self.vfs.get_metadata(path) {
+                            Ok(ref md) if md.get_type() == FileType::Dir => (),
+                            Ok(_) => {
+                                debug!("Not following symlink {:?} to a non-directory", path);
+                                self.skip_file();
+                                return;
+                            }
+                            Err(e) => {
+                                self.failed(e);
+                                return;
+                            }
+                        }
+                    }
+                    let keep = self.keep_symlinks && self.should_handle_file(path);
+                    if keep && self.links_to_file(path) {
+                        self.handle_file(path);
+                    }
+                    // relative targets are relative to the link's directory
+                    let target = match path.parent() {
+                        Some(dir) => dir.join(f),
+                        None => f,
+                    };
+                    let target = Self::get_canonical_path(&self.vfs, target);
+                    self.dispatch_any_file(&target, None)
+                }
+                Err(e) => self.failed(e),
             },
-            FileType::Other => debug!("Ignoring unknown file {:?}", path),
+            FileType::Other => {
+                debug!("Ignoring unknown file {:?}", path);
+                self.skip_file();
+            }
         }
     }
 
+    // count a file that was looked at but left out
+    fn skip_file(&mut self) {
+        self.stats.files += 1;
+        self.stats.skipped += 1;
+    }
+
+    // whether the symlink at `path` ends up at a regular file
+    fn links_to_file(&self, path: &Path) -> bool {
+        match self.vfs.get_metadata(path) {
+            Ok(md) => md.get_type() == FileType::File,
+            Err(e) => {
+                warn!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// What has been covered so far: directories entered, files looked at and left out,
+    /// and the total size of the files kept
+    /// With `io_threads`, the sizes are only added up once the walk is done
+    pub fn walk_stats(&self) -> WalkStats {
+        self.stats
+    }
+
+    /// Number of threads looking up the sizes of the files found (see `io_threads`)
+    pub fn num_io_threads(&self) -> usize {
+        self.stat_pool.as_ref().map_or(1, StatPool::threads)
+    }
+
+    /// Collect all specified files in the order they were found, along with what the walk
+    /// covered (see `walk_stats`); this consumes the DirWalker
+    pub fn traverse_with_stats(mut self) -> (Vec<PathBuf>, WalkStats) {
+        self.traverse_roots();
+        (self.found, self.stats)
+    }
+
     /// Collect all specified files into a set; this consumes the DirWalker
     pub fn traverse_all(mut self) -> HashSet<PathBuf> {
+        self.traverse_roots();
+        self.files
+    }
+
+    /// Collect all specified files in the order they were found; this consumes the DirWalker
+    pub fn traverse_ordered(mut self) -> Vec<PathBuf> {
+        self.traverse_roots();
+        self.found
+    }
+
+    /// Collect all specified files in the order they were found, along with those of them
+    /// that lie in a blacklisted folder (only reachable through a symlink from outside it)
+    /// and what the walk covered (see `walk_stats`)
+    /// With `on_error` set to `OnError::Abort`, the walk stops at the first path it can't
+    /// visit and that error is returned instead
+    pub fn traverse_tagged(
+        mut self,
+    ) -> Result<(Vec<PathBuf>, HashSet<PathBuf>, WalkStats), VfsError> {
+        self.traverse_roots();
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok((self.found, self.excluded, self.stats)),
+        }
+    }
+
+    // visit each of the starting directories in turn
+    fn traverse_roots(&mut self) {
         // steal directories (performance hack, ask owen)
         let directories = ::std::mem::replace(&mut self.directories, vec![]);
-        for path in directories {
-            self.dispatch_any_file(&path, None);
+        self.pending.extend(directories.into_iter().map(|path| (path, None)));
+        self.traverse_pending();
+        if let Some(ref mut pool) = self.stat_pool {
+            let (bytes, allocated) = pool.finish();
+            self.stats.bytes += bytes;
+            self.stats.allocated += allocated;
         }
-        self.files
+    }
+}
+
+// looking up files on other threads needs a filesystem that can be sent to them
+impl<V: VFS + Send + 'static> DirWalker<V> {
+    /// Look up the sizes of the files found on `n` worker threads while the walk goes on,
+    /// rather than one at a time; what the walk finds is the same either way
+    /// This only speeds up the total in `walk_stats`: directories are still listed, and
+    /// files looked at to decide whether to keep them, on the calling thread
+    /// `n` below 2 looks them up on the calling thread, as by default
+    pub fn io_threads(mut self, n: usize) -> Self {
+        self.stat_pool = if n >= 2 {
+            Some(StatPool::new(&self.vfs, n))
+        } else {
+            None
+        };
+        self
     }
 }
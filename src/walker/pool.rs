@@ -0,0 +1,116 @@
+//! Worker threads that look up the sizes of the files a walk finds while it goes on
+
+use std::thread::{self, JoinHandle};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use vfs::{MetaData, VFS};
+
+/// A fixed set of threads looking up the length and size on disk of the files submitted
+/// to it, so the walk doesn't wait on one `stat` at a time
+#[derive(Debug)]
+pub struct StatPool {
+    // `None` only while being dropped
+    jobs: Option<Sender<PathBuf>>,
+    // the length and size on disk of each file, or nothing if it couldn't be looked up
+    results: Receiver<Option<(u64, u64)>>,
+    workers: Vec<JoinHandle<()>>,
+    // number of submitted files whose sizes haven't come back
+    outstanding: usize,
+    // the sizes that came back so far
+    bytes: u64,
+    allocated: u64,
+}
+
+impl StatPool {
+    /// Look up files on `vfs` with `threads` workers (at least one)
+    pub fn new<T: VFS + Send + 'static>(vfs: &T, threads: usize) -> Self {
+        let (jobs, queue) = mpsc::channel::<PathBuf>();
+        let (report, results) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let vfs = vfs.clone();
+                let queue = Arc::clone(&queue);
+                let report = report.clone();
+                thread::spawn(move || loop {
+                    // the lock is only held while waiting for the next job
+                    let path = match queue.lock().expect("Stat worker panicked").recv() {
+                        Ok(path) => path,
+                        Err(_) => break,
+                    };
+                    let sizes = match vfs.get_metadata(&path) {
+                        Ok(md) => Some((md.get_len(), md.get_allocated_size())),
+                        Err(e) => {
+                            debug!("Not counting the size of {:?}: {}", path, e);
+                            None
+                        }
+                    };
+                    if report.send(sizes).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+        StatPool {
+            jobs: Some(jobs),
+            results,
+            workers,
+            outstanding: 0,
+            bytes: 0,
+            allocated: 0,
+        }
+    }
+
+    /// Number of worker threads
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Queue the file at `path` to be looked up
+    pub fn submit(&mut self, path: PathBuf) {
+        self.jobs
+            .as_ref()
+            .expect("StatPool is being dropped")
+            .send(path)
+            .expect("Stat workers exited early");
+        self.outstanding += 1;
+        // add up what's back already, so the results don't pile up
+        while let Ok(sizes) = self.results.try_recv() {
+            self.add(sizes);
+        }
+    }
+
+    /// Wait for every submitted file, and return the total length and size on disk of
+    /// those that could be looked up since the last call
+    pub fn finish(&mut self) -> (u64, u64) {
+        while self.outstanding > 0 {
+            let sizes = self.results.recv().expect("Stat workers exited early");
+            self.add(sizes);
+        }
+        let totals = (self.bytes, self.allocated);
+        self.bytes = 0;
+        self.allocated = 0;
+        totals
+    }
+
+    // count the sizes of a file that came back
+    fn add(&mut self, sizes: Option<(u64, u64)>) {
+        self.outstanding -= 1;
+        if let Some((bytes, allocated)) = sizes {
+            self.bytes += bytes;
+            self.allocated += allocated;
+        }
+    }
+}
+
+// hang up on the workers, then wait for them to notice
+impl Drop for StatPool {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
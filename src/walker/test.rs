@@ -1,11 +1,14 @@
 #[cfg(test)]
 mod test {
 
-    use std::path::Path;
-    use std::ffi::OsString;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::ffi::{OsStr, OsString};
+    use std::time::{Duration, Instant};
 
-    use walker::DirWalker;
-    use vfs::TestFileSystem;
+    use walker::{DirWalker, TraversalOrder, WalkStats};
+    use vfs::{FileType, MetaData, OnError, Operation, TestFile, TestFileSystem, TestMD, VFS};
+    use helpers::ID;
 
     #[test]
     fn walker_empty_fs() {
@@ -33,27 +36,57 @@ mod test {
     #[test]
     fn walker_handle_symlinks() {
         // test edge cases for symlinks: test data integrity and reselience
-        let fs = TestFileSystem::new();
-        {
-            let mut fs = fs.borrow_mut();
-            fs.create_dir("/");
-            fs.create_file("/alpha");
-            // only deal with a target once, omit symlinks
-            fs.create_symlink("/beta", "/alpha");
-            fs.create_symlink("/gamma", "/alpha");
-            // ignore bad symlinks
-            fs.create_symlink("/delta", "/_nonexistant");
-            // ignore symlink loops
-            fs.create_symlink("/x", "/xx");
-            fs.create_symlink("/xx", "/x");
-            // including a symlink that points to its parent folder
-            fs.create_symlink("/folder", "/");
-        }
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /alpha
+             # only deal with a target once, omit symlinks
+             link /beta -> /alpha
+             link /gamma -> /alpha
+             # ignore bad symlinks
+             link /delta -> /_nonexistant
+             # ignore symlink loops
+             link /x -> /xx
+             link /xx -> /x
+             # including a symlink that points to its parent folder
+             link /folder -> /",
+        ).unwrap();
         let dw = DirWalker::new(fs, &vec![Path::new("/")]);
         let files = dw.traverse_all();
         assert_eq!(files.len(), 1);
     }
 
+    #[test]
+    fn walker_directory_cycles() {
+        // a link to an ancestor is only walked once, even if it can't be canonicalized
+        let spec = "dir /
+             dir /a
+             file /a/f
+             link /a/link -> /a";
+        for &resolvable in &[true, false] {
+            let fs = TestFileSystem::from_spec(spec).unwrap();
+            if !resolvable {
+                let kind = io::ErrorKind::PermissionDenied;
+                fs.borrow_mut().fail_always(Operation::Canonicalize, "/a/link", kind);
+            }
+            let (files, stats) = DirWalker::new(fs, &[Path::new("/")]).traverse_with_stats();
+            assert_eq!(vec![PathBuf::from("/a/f")], files);
+            assert_eq!(2, stats.dirs);
+        }
+
+        // so is the same directory under two paths (e.g. a bind mount of an ancestor)
+        let fs = TestFileSystem::from_spec(spec).unwrap();
+        let id = ID::of(&fs.get_metadata("/a").unwrap()).unwrap();
+        fs.borrow_mut().add(
+            TestFile::new("/a/mnt")
+                .with_kind(FileType::Dir)
+                .with_metadata(TestMD::new().with_id(id)),
+        );
+        fs.borrow_mut().create_file("/a/mnt/f");
+        let (files, stats) = DirWalker::new(fs, &[Path::new("/")]).traverse_with_stats();
+        assert_eq!(vec![PathBuf::from("/a/f")], files);
+        assert_eq!(2, stats.dirs);
+    }
+
     #[test]
     fn walker_blacklist_regex() {
         // verify files can be blacklisted by a regular expression
@@ -117,4 +150,283 @@ mod test {
         assert!(files.contains(Path::new("/f2/b.txt")));
     }
 
+    #[test]
+    fn walker_blacklist_dir_names() {
+        // verify folders can be blacklisted by name at any depth
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             dir /node_modules
+             dir /src
+             dir /src/node_modules
+             dir /src/node_modules/x
+             file /a.js
+             file /node_modules/b.js
+             file /src/c.js
+             file /src/node_modules/d.js
+             file /src/node_modules/x/e.js
+             # only exact names are skipped
+             dir /src/node_modules2
+             file /src/node_modules2/f.js",
+        ).unwrap();
+        let dw = DirWalker::new(fs, &[Path::new("/")])
+            .blacklist_dir_names(vec![&OsString::from("node_modules")]);
+        let files = dw.traverse_all();
+        assert_eq!(3, files.len());
+        assert!(files.contains(Path::new("/a.js")));
+        assert!(files.contains(Path::new("/src/c.js")));
+        assert!(files.contains(Path::new("/src/node_modules2/f.js")));
+    }
+
+    #[test]
+    fn walker_traversal_order() {
+        // depth-first finishes /a before /b; breadth-first finds shallow files first
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             dir /a
+             dir /a/deep
+             dir /b
+             file /a/deep/x
+             file /a/y
+             file /b/z
+             file /top",
+        ).unwrap();
+        let walk = |order| {
+            DirWalker::new(fs.clone(), &[Path::new("/")])
+                .traversal_order(order)
+                .traverse_ordered()
+        };
+        let dfs = walk(TraversalOrder::DepthFirst);
+        let bfs = walk(TraversalOrder::BreadthFirst);
+        let expected = ["/a/deep/x", "/a/y", "/b/z", "/top"];
+        assert_eq!(expected.iter().map(Path::new).collect::<Vec<_>>(), dfs);
+        let expected = ["/top", "/a/y", "/b/z", "/a/deep/x"];
+        assert_eq!(expected.iter().map(Path::new).collect::<Vec<_>>(), bfs);
+        // the same tree always comes out in the same order
+        assert_eq!(dfs, walk(TraversalOrder::DepthFirst));
+    }
+
+    #[test]
+    fn walker_canonical_roots() {
+        // a root reached through a symlink is walked at its real location
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             dir /data
+             dir /data/skip
+             file /data/a
+             file /data/skip/b
+             link /shortcut -> /data",
+        ).unwrap();
+        let files = DirWalker::new(fs, &[Path::new("/shortcut/")])
+            .blacklist_folders(vec![&OsString::from("/shortcut/skip")])
+            .traverse_ordered();
+        assert_eq!(vec![Path::new("/data/a")], files);
+    }
+
+    #[test]
+    fn walker_dir_symlinks_only() {
+        // a symlink to a directory is walked, a symlink to a file is skipped
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             dir /tree
+             dir /elsewhere
+             file /elsewhere/x
+             file /y
+             link /tree/dirlink -> /elsewhere
+             link /tree/filelink -> /y",
+        ).unwrap();
+        let walk = |only| {
+            DirWalker::new(fs.clone(), &[Path::new("/tree")])
+                .dir_symlinks_only(only)
+                .traverse_ordered()
+        };
+        assert_eq!(vec![Path::new("/elsewhere/x")], walk(true));
+        assert_eq!(vec![Path::new("/elsewhere/x"), Path::new("/y")], walk(false));
+    }
+
+    #[test]
+    fn walker_ignore_files() {
+        // a `.dedupignore` applies to its own directory's subtree, and nowhere else
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             dir /a
+             dir /a/sub
+             dir /a/build
+             dir /b
+             dir /b/sub
+             file /a/.dedupignore '*.tmp\\n# build output\\nbuild/'
+             file /a/x.tmp
+             file /a/keep
+             file /a/sub/y.tmp
+             file /a/build/out
+             file /b/.dedupignore '/sub/*.log'
+             file /b/z.tmp
+             file /b/w.log
+             file /b/sub/v.log
+             file /b/sub/u.tmp",
+        ).unwrap();
+        let walk = |on| {
+            DirWalker::new(fs.clone(), &[Path::new("/")])
+                .ignore_files(on)
+                .traverse_ordered()
+        };
+        let expected: Vec<&Path> = vec![
+            Path::new("/a/.dedupignore"),
+            Path::new("/a/keep"),
+            Path::new("/b/.dedupignore"),
+            Path::new("/b/sub/u.tmp"),
+            Path::new("/b/w.log"),
+            Path::new("/b/z.tmp"),
+        ];
+        assert_eq!(expected, walk(true));
+        assert_eq!(10, walk(false).len());
+    }
+
+    #[test]
+    fn walker_skip_mounts() {
+        // /net/share is another device (a network share), /net/local and /data/bind
+        // are the same filesystem or outside /net, so only the share is skipped
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             dir /net
+             dir /net/local
+             dir /data
+             file /net/local/a
+             file /net/share/b
+             file /data/bind/c
+             file /data/d",
+        ).unwrap();
+        for &(path, dev) in &[("/net/share", 7), ("/data/bind", 5)] {
+            let md = TestMD::new().with_id(ID { dev, inode: 100 + dev });
+            fs.borrow_mut()
+                .add(TestFile::new(path).with_kind(FileType::Dir).with_metadata(md));
+        }
+        let walk = |mounts: Vec<&OsStr>| {
+            DirWalker::new(fs.clone(), &[Path::new("/")])
+                .skip_mounts(mounts)
+                .traverse_ordered()
+        };
+        let expected = vec![
+            Path::new("/data/bind/c"),
+            Path::new("/data/d"),
+            Path::new("/net/local/a"),
+        ];
+        assert_eq!(expected, walk(vec![OsStr::new("/net")]));
+        assert_eq!(4, walk(vec![]).len());
+    }
+
+    #[test]
+    fn walker_stats() {
+        // each file is counted once however it's reached, and skipped files and folders
+        // left out entirely are told apart
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             file /a 'AAAA'
+             file /b.tmp 'BB'
+             dir /d
+             file /d/c 'CCCCCC'
+             link /d/l -> /a
+             dir /skip
+             file /skip/x 'XXXXXXXX'",
+        ).unwrap();
+        fs.borrow_mut().add(TestFile::new("/d/pipe").with_kind(FileType::Other));
+        let (files, stats) = DirWalker::new(fs, &[Path::new("/")])
+            .blacklist_folders(vec![OsStr::new("/skip")])
+            .blacklist_patterns(vec![".*\\.tmp"])
+            .traverse_with_stats();
+        assert_eq!(vec![Path::new("/a"), Path::new("/d/c")], files);
+        let expected = WalkStats {
+            dirs: 2,
+            files: 4,
+            skipped: 2,
+            bytes: 10,
+            allocated: 10,
+        };
+        assert_eq!(expected, stats);
+        assert_eq!("2 directories, 4 files (2 skipped), 10 B", stats.to_string());
+    }
+
+    #[test]
+    fn walker_deadline() {
+        // a slow walk stops once it runs out of time, keeping what it found by then
+        let fs = TestFileSystem::new();
+        fs.borrow_mut().create_dir("/");
+        for i in 0..20 {
+            let dir = format!("/d{:02}", i);
+            fs.borrow_mut().create_dir(&dir);
+            fs.borrow_mut().create_file(format!("{}/f", dir));
+        }
+        fs.borrow_mut().set_latency(Duration::from_millis(20));
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let files = DirWalker::new(fs.clone(), &[Path::new("/")])
+            .deadline(deadline)
+            .traverse_all();
+        assert!(Instant::now() < deadline + Duration::from_millis(200));
+        assert!(files.len() < 20, "found all {} files", files.len());
+        for path in &files {
+            assert_eq!(FileType::File, fs.get_metadata(path).unwrap().get_type());
+        }
+
+        // one already passed stops it before it starts
+        let files = DirWalker::new(fs, &[Path::new("/")])
+            .deadline(Instant::now())
+            .traverse_all();
+        assert!(files.is_empty());
+    }
+
+    #[test]
+    fn walker_sample() {
+        let fs = TestFileSystem::new();
+        fs.borrow_mut().create_dir("/");
+        for i in 0..1000 {
+            fs.borrow_mut().create_file(format!("/f{}", i));
+        }
+        let sample = |rate, seed| {
+            DirWalker::new(fs.clone(), &[Path::new("/")])
+                .sample(rate, seed)
+                .traverse_all()
+        };
+        assert!(sample(0.0, 0).is_empty());
+        assert_eq!(1000, sample(1.0, 0).len());
+
+        // about the share asked for, the same files every time, but others with another seed
+        let half = sample(0.5, 7);
+        assert!(half.len() > 400 && half.len() < 600, "sampled {} files", half.len());
+        assert_eq!(half, sample(0.5, 7));
+        assert_ne!(half, sample(0.5, 8));
+        // a smaller share of the same seed is part of the larger one
+        assert!(sample(0.2, 7).is_subset(&half));
+
+        // what's left out is counted as skipped
+        let (_, stats) = DirWalker::new(fs, &[Path::new("/")])
+            .sample(0.5, 7)
+            .traverse_with_stats();
+        assert_eq!(1000, stats.files);
+        assert_eq!(1000 - half.len(), stats.skipped);
+    }
+
+    #[test]
+    fn walker_on_error() {
+        // an unreadable directory is left out, unless the walk is told to stop there
+        let fs = TestFileSystem::from_spec(
+            "dir /
+             dir /a
+             file /a/f
+             dir /b
+             file /b/f",
+        ).unwrap();
+        fs.borrow_mut().fail_always(Operation::ListDir, "/a", io::ErrorKind::PermissionDenied);
+        let walk = |policy| {
+            DirWalker::new(fs.clone(), &[Path::new("/")])
+                .on_error(policy)
+                .traverse_tagged()
+        };
+        for &policy in &[OnError::Skip, OnError::Warn] {
+            let (files, _, _) = walk(policy).unwrap();
+            assert_eq!(vec![PathBuf::from("/b/f")], files);
+        }
+        let e = walk(OnError::Abort).unwrap_err();
+        assert_eq!(Operation::ListDir, e.op());
+        assert_eq!(Path::new("/a"), e.path());
+        assert_eq!(io::ErrorKind::PermissionDenied, e.kind());
+    }
 }
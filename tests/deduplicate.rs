@@ -0,0 +1,570 @@
+//! Run the library entry points end to end over a mock filesystem
+
+extern crate md5;
+extern crate serde_json;
+extern crate smllr;
+
+use std::io;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::collections::HashSet;
+use std::time::{Duration, UNIX_EPOCH};
+
+use smllr::{deduplicate, scan, Duplicates, OnError, Options, TestFileSystem};
+use smllr::vfs::{FsOp, TestFile, TestMD};
+use smllr::vfs::Operation::ReadMetadata;
+use smllr::actor::{FileActor, FileDeleter, FileLinker};
+use smllr::actor::selector::{AvoidSelect, ChainSelect, PathSelect, Selector};
+
+// the groups as sets of paths, so the order doesn't matter
+fn groups(repeats: &[Duplicates]) -> HashSet<Vec<PathBuf>> {
+    repeats
+        .iter()
+        .map(|dups| {
+            let mut paths = dups.clone().into_paths();
+            paths.sort();
+            paths
+        })
+        .collect()
+}
+
+fn paths(ps: &[&str]) -> Vec<PathBuf> {
+    ps.iter().map(PathBuf::from).collect()
+}
+
+#[test]
+fn deduplicate_mock_tree() {
+    let fs = TestFileSystem::from_spec(
+        "dir /
+         dir /x
+         dir /y
+         dir /y/.git
+         file /a 'hello'
+         file /x/b 'hello'
+         file /y/c 'hello'
+         file /x/d 'other'
+         file /y/e 'other'
+         file /y/f 'unique'
+         file /y/.git/g 'unique'",
+    ).unwrap();
+
+    let found = deduplicate(Options::new(&["/"]), fs.clone()).unwrap();
+    let expected: HashSet<_> = [
+        paths(&["/a", "/x/b", "/y/c"]),
+        paths(&["/x/d", "/y/e"]),
+        paths(&["/y/.git/g", "/y/f"]),
+    ].iter()
+        .cloned()
+        .collect();
+    assert_eq!(expected, groups(&found));
+
+    // options are passed through to the walker and catalog
+    let opts = Options::new(&["/"]).skip_dir_names(&[".git"]).min_copies(3);
+    let found = deduplicate(opts, fs.clone()).unwrap();
+    let expected: HashSet<_> = [paths(&["/a", "/x/b", "/y/c"])].iter().cloned().collect();
+    assert_eq!(expected, groups(&found));
+
+    // nothing was modified along the way
+    assert!(fs.borrow().ops().is_empty());
+}
+
+#[test]
+fn symlinked_only_audit() {
+    // a link to a file outside the tree matches a real file inside it
+    let fs = TestFileSystem::from_spec(
+        "dir /
+         dir /w
+         dir /elsewhere
+         file /elsewhere/t 'same'
+         file /w/other 'same'
+         file /w/x 'dup'
+         file /w/y 'dup'
+         link /w/l -> /elsewhere/t",
+    ).unwrap();
+
+    let found = deduplicate(Options::new(&["/w"]).symlinked_only(true), fs.clone()).unwrap();
+    let expected: HashSet<_> = [paths(&["/elsewhere/t", "/w/l", "/w/other"])]
+        .iter()
+        .cloned()
+        .collect();
+    assert_eq!(expected, groups(&found));
+
+    // without the audit the link is only followed
+    let found = deduplicate(Options::new(&["/w"]), fs).unwrap();
+    let expected: HashSet<_> = [paths(&["/elsewhere/t", "/w/other"]), paths(&["/w/x", "/w/y"])]
+        .iter()
+        .cloned()
+        .collect();
+    assert_eq!(expected, groups(&found));
+}
+
+#[test]
+fn text_normalize_trailing_newline() {
+    let fs = TestFileSystem::from_spec(
+        "dir /
+         file /a 'snippet'
+         file /b 'snippet\\n'",
+    ).unwrap();
+
+    // byte for byte they differ (even in size)
+    assert!(deduplicate(Options::new(&["/"]), fs.clone()).unwrap().is_empty());
+    let found = deduplicate(Options::new(&["/"]).text_normalize(1024), fs).unwrap();
+    let expected: HashSet<_> = [paths(&["/a", "/b"])].iter().cloned().collect();
+    assert_eq!(expected, groups(&found));
+}
+
+#[test]
+fn scan_report() {
+    let fs = TestFileSystem::from_spec(
+        "dir /
+         file /a 'AAAA'
+         file /b 'AAAA'
+         file /c 'BBBB'
+         file /d 'CCCCCC'",
+    ).unwrap();
+
+    let report = scan(&Options::new(&["/"]).histogram(5).paranoid(true), fs).unwrap();
+    assert_eq!(4, report.files);
+    assert_eq!(1, report.repeats.len());
+    assert_eq!(1, report.collisions.len());
+    assert_eq!(4, report.collisions[0].size);
+    assert_eq!(2, report.stats.buckets);
+    assert!(report.histogram.is_some());
+}
+
+#[test]
+fn scan_skips_unreadable_files() {
+    let fs = TestFileSystem::from_spec(
+        "dir /
+         file /a 'AAAA'
+         file /b 'AAAA'
+         file /c 'AAAA'",
+    ).unwrap();
+    fs.borrow_mut().fail_always(ReadMetadata, "/b", io::ErrorKind::NotFound);
+
+    let report = scan(&Options::new(&["/"]), fs.clone()).unwrap();
+    assert_eq!((3, 1), (report.files, report.skipped));
+    let expected: HashSet<_> = [paths(&["/a", "/c"])].iter().cloned().collect();
+    assert_eq!(expected, groups(&report.repeats));
+
+    // unless the scan is told to stop at the first error
+    let err = scan(&Options::new(&["/"]).on_error(OnError::Abort), fs).unwrap_err();
+    assert_eq!(io::ErrorKind::NotFound, err.kind());
+    assert!(err.to_string().contains("/b"));
+}
+
+#[test]
+fn scan_summary() {
+    use smllr::actor::Savings;
+    use smllr::catalog::CatalogStats;
+    use smllr::summary::ScanSummary;
+    use smllr::walker::WalkStats;
+    use smllr::Report;
+
+    // sizes on disk are what's counted, not lengths
+    let walk = WalkStats {
+        dirs: 3,
+        files: 12,
+        skipped: 2,
+        bytes: 5 << 20,
+        allocated: 3 << 20,
+    };
+    let catalog = CatalogStats {
+        files: 1_204,
+        ..CatalogStats::default()
+    };
+    let report = Report {
+        skipped: 1,
+        repeats: vec![
+            Duplicates::new(paths(&["/a", "/b", "/c"])).with_size(1 << 20),
+            Duplicates::new(paths(&["/d", "/e"])).with_size(1 << 20),
+        ],
+        reclaimable: Savings {
+            logical: 3 << 20,
+            allocated: 1 << 20,
+        },
+        ..Report::default()
+    };
+    let summary = ScanSummary::new(&walk, &catalog, &report);
+    let expected = ScanSummary {
+        dirs: 3,
+        files: 1_204,
+        bytes: 3 << 20,
+        skipped: 1,
+        groups: 2,
+        duplicates: 5,
+        reclaimable: 1 << 20,
+    };
+    assert_eq!(expected, summary);
+    assert_eq!(
+        "Scanned 1,204 files, 3 MB; 5 files in 2 duplicate groups; 1 MB reclaimable",
+        summary.to_string()
+    );
+
+    // a real scan counts the same things
+    let fs = TestFileSystem::from_spec(
+        "dir /
+         dir /d
+         file /a 'AAAA'
+         file /d/b 'AAAA'
+         file /c 'BBBB'",
+    ).unwrap();
+    let summary = scan(&Options::new(&["/"]), fs).unwrap().summary();
+    assert_eq!((2, 3, 12), (summary.dirs, summary.files, summary.bytes));
+    assert_eq!((1, 2, 4), (summary.groups, summary.duplicates, summary.reclaimable));
+}
+
+#[test]
+fn scan_out_of_time() {
+    // each directory holds a pair of copies, and listing one is slow
+    let fs = TestFileSystem::new();
+    fs.borrow_mut().create_dir("/");
+    for i in 0..20 {
+        let dir = format!("/d{:02}", i);
+        fs.borrow_mut().create_dir(&dir);
+        for (j, name) in ["a", "b"].iter().enumerate() {
+            let file = TestFile::new(format!("{}/{}", dir, name))
+                .with_contents(dir.clone())
+                .with_metadata(TestMD::new())
+                .with_inode(100 + 2 * i + j as u64);
+            fs.borrow_mut().add(file);
+        }
+    }
+    fs.borrow_mut().set_latency(Duration::from_millis(20));
+
+    // the scan stops early, and what it found is still right
+    let opts = Options::new(&["/"]).max_runtime(Duration::from_millis(100));
+    let report = scan(&opts, fs.clone()).unwrap();
+    assert!(report.partial);
+    assert!(report.files < 40, "found all {} files", report.files);
+    assert!(!report.repeats.is_empty());
+    for group in groups(&report.repeats) {
+        let dir = group[0].parent().unwrap();
+        assert_eq!(vec![dir.join("a"), dir.join("b")], group);
+    }
+
+    let report = scan(&Options::new(&["/"]), fs).unwrap();
+    assert!(!report.partial);
+    assert_eq!((40, 20), (report.files, report.repeats.len()));
+}
+
+#[test]
+fn scan_missing_root() {
+    let fs = TestFileSystem::from_spec("dir /").unwrap();
+    let err = scan(&Options::new(&["/missing"]), fs).unwrap_err();
+    assert_eq!(io::ErrorKind::NotFound, err.kind());
+    assert!(err.to_string().contains("/missing"));
+}
+
+#[test]
+fn show_hash_matches_md5() {
+    use smllr::actor::{FileActor, FilePrinter};
+    use smllr::actor::selector::PathSelect;
+
+    let fs = TestFileSystem::from_spec(
+        "dir /
+         file /a 'hello world\\n'
+         file /b 'hello world\\n'",
+    ).unwrap();
+    let mut repeats = deduplicate(Options::new(&["/"]), fs.clone()).unwrap();
+    assert_eq!(1, repeats.len());
+
+    let selector = PathSelect::new(fs.clone());
+    let mut printer = FilePrinter::with_output(fs, selector, Vec::<u8>::new()).show_hash(true);
+    printer.act(repeats.remove(0));
+    let out = String::from_utf8(printer.get_output().clone()).unwrap();
+    let expected = format!("Hash {:x}", md5::compute(b"hello world\n"));
+    assert_eq!(Some(expected.as_str()), out.lines().next());
+}
+
+#[test]
+fn stable_output_is_identical() {
+    // every scan builds its catalog afresh, so only sorting makes the order repeatable
+    let fs = TestFileSystem::from_spec(
+        "dir /
+         dir /x
+         dir /y
+         file /y/a 'hello'
+         file /x/b 'hello'
+         file /a 'hello'
+         file /y/d 'other'
+         file /x/e 'other'
+         file /x/f 'third'
+         file /y/g 'third'
+         file /x/h 'fourth'
+         file /y/h 'fifth!'",
+    ).unwrap();
+
+    let run = || {
+        let report = scan(&Options::new(&["/"]).stable(true), fs.clone()).unwrap();
+        format!("{:?} {:?}", report.repeats, report.collisions)
+    };
+    let first = run();
+    for _ in 0..10 {
+        assert_eq!(first, run());
+    }
+    let report = scan(&Options::new(&["/"]).stable(true), fs.clone()).unwrap();
+    let expected = vec![
+        paths(&["/a", "/x/b", "/y/a"]),
+        paths(&["/x/e", "/y/d"]),
+        paths(&["/x/f", "/y/g"]),
+    ];
+    let found: Vec<_> = report.repeats.iter().map(|d| d.clone().into_paths()).collect();
+    assert_eq!(expected, found);
+}
+
+#[test]
+fn min_waste_omits_small_groups() {
+    let fs = TestFileSystem::from_spec(
+        "dir /
+         file /a1 'AA'
+         file /a2 'AA'
+         file /b1 'BBBBBBBBBB'
+         file /b2 'BBBBBBBBBB'
+         file /b3 'BBBBBBBBBB'
+         file /c1 'CCCCCC'
+         file /c2 'CCCCCC'",
+    ).unwrap();
+
+    // only /b's extra copies waste at least 10 bytes (/c's 6, /a's 2)
+    let report = scan(&Options::new(&["/"]).min_waste(10), fs.clone()).unwrap();
+    let expected: HashSet<_> = [paths(&["/b1", "/b2", "/b3"])].iter().cloned().collect();
+    assert_eq!(expected, groups(&report.repeats));
+    assert_eq!((2, 2 + 6), (report.omitted, report.omitted_bytes));
+
+    // both filters at once: /c is big enough but too small a group
+    let opts = Options::new(&["/"]).min_waste(5).min_copies(3);
+    let report = scan(&opts, fs.clone()).unwrap();
+    assert_eq!(expected, groups(&report.repeats));
+    assert_eq!((2, 8), (report.omitted, report.omitted_bytes));
+
+    // nothing is omitted by default
+    let report = scan(&Options::new(&["/"]), fs).unwrap();
+    assert_eq!((3, 0, 0), (report.repeats.len(), report.omitted, report.omitted_bytes));
+}
+
+#[test]
+fn most_wasteful_groups_print_first() {
+    use smllr::actor::{FileActor, FilePrinter};
+    use smllr::actor::selector::PathSelect;
+    use smllr::catalog::most_wasteful_first;
+
+    let fs = TestFileSystem::from_spec(
+        "dir /
+         file /a1 'AA'
+         file /a2 'AA'
+         file /b1 'BBBBBBBBBB'
+         file /b2 'BBBBBBBBBB'
+         file /b3 'BBBBBBBBBB'
+         file /c1 'CCCCCC'
+         file /c2 'CCCCCC'",
+    ).unwrap();
+    let repeats = deduplicate(Options::new(&["/"]).stable(true), fs.clone()).unwrap();
+
+    let selector = PathSelect::new(fs.clone());
+    let mut printer = FilePrinter::with_output(fs, selector, Vec::<u8>::new()).numbered(true);
+    for dups in most_wasteful_first(repeats) {
+        printer.act(dups);
+    }
+    let out = String::from_utf8(printer.get_output().clone()).unwrap();
+    let expected = "\
+#1 - 20 B wasted across 3 copies
+/b1 is the true file
+\t/b2 is a duplicate
+\t/b3 is a duplicate
+#2 - 6 B wasted across 2 copies
+/c1 is the true file
+\t/c2 is a duplicate
+#3 - 2 B wasted across 2 copies
+/a1 is the true file
+\t/a2 is a duplicate
+";
+    assert_eq!(expected, out);
+}
+
+#[test]
+fn json_report_is_versioned() {
+    use serde_json::Value;
+    use smllr::json;
+
+    let fs = TestFileSystem::from_spec(
+        "dir /
+         file /a 'AAAA'
+         file /b 'AAAA'
+         file /c 'BBBB'",
+    ).unwrap();
+    let report = scan(&Options::new(&["/"]), fs).unwrap();
+    let mut out = vec![];
+    json::write(&report, &mut out).unwrap();
+    let value: Value = serde_json::from_slice(&out).unwrap();
+
+    assert_eq!(Some(u64::from(json::VERSION)), value["version"].as_u64());
+    // the report has exactly the keys the schema requires, at the top and in each group
+    let schema: Value = serde_json::from_str(json::SCHEMA).unwrap();
+    assert_eq!(Some(u64::from(json::VERSION)), schema["properties"]["version"]["const"].as_u64());
+    let keys = |v: &Value| {
+        let mut keys: Vec<String> = v.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    };
+    let required = |v: &Value| {
+        let mut keys: Vec<String> = v.as_array()
+            .unwrap()
+            .iter()
+            .map(|k| k.as_str().unwrap().to_owned())
+            .collect();
+        keys.sort();
+        keys
+    };
+    assert_eq!(required(&schema["required"]), keys(&value));
+    let group = &value["groups"][0];
+    assert_eq!(required(&schema["properties"]["groups"]["items"]["required"]), keys(group));
+    let summary = &value["summary"];
+    assert_eq!(required(&schema["properties"]["summary"]["required"]), keys(summary));
+    assert_eq!(Some(3), summary["files"].as_u64());
+    assert_eq!(Some(4), summary["reclaimable"].as_u64());
+    assert_eq!(Some(4), group["wasted"].as_u64());
+    assert_eq!(Some(format!("{:x}", md5::compute(b"AAAA")).as_str()), group["hash"].as_str());
+}
+
+#[test]
+fn blacklisted_copy_is_never_kept() {
+    // the shortest path to the contents is in a skipped folder, reached through a link
+    let fs = TestFileSystem::from_spec(
+        "dir /
+         dir /skip
+         dir /w
+         dir /w/deep
+         file /skip/a 'same'
+         file /w/deep/a 'same'
+         file /skip/b 'other'
+         file /skip/c 'other'
+         link /w/l -> /skip/a
+         link /w/m -> /skip/b
+         link /w/n -> /skip/c",
+    ).unwrap();
+    let report = scan(&Options::new(&["/w"]).skip_paths(&["/skip"]), fs.clone()).unwrap();
+    assert_eq!(paths(&["/skip/a", "/skip/b", "/skip/c"]), report.excluded);
+
+    let avoid = AvoidSelect::new(fs.clone(), report.excluded.iter().cloned());
+    let links: Vec<Box<dyn Selector<Rc<RefCell<TestFileSystem>>>>> = vec![
+        Box::new(AvoidSelect::new(fs.clone(), report.excluded.iter().cloned())),
+        Box::new(PathSelect::new(fs.clone())),
+    ];
+    let selector = ChainSelect::new(links);
+    for dups in &report.repeats {
+        if dups.iter().any(|p| p.starts_with("/w")) {
+            assert!(!avoid.avoids_all(dups));
+            assert_eq!(Path::new("/w/deep/a"), selector.select(dups));
+        } else {
+            // no copy outside the skipped folder: the group is left alone
+            assert!(avoid.avoids_all(dups));
+        }
+    }
+    assert_eq!(2, report.repeats.len());
+}
+
+#[test]
+fn linking_splits_groups_by_device() {
+    use smllr::json;
+
+    // only files on the same device can be linked: the rest are reported apart
+    let fs = TestFileSystem::from_spec(
+        "dir /
+         dir /one
+         dir /two
+         file /one/a 'same'
+         file /one/b 'same'
+         file /two/c 'same' dev=2
+         file /one/x 'other'
+         file /two/y 'other' dev=2",
+    ).unwrap();
+    let report = scan(&Options::new(&["/"]), fs.clone()).unwrap();
+    assert_eq!(2, report.repeats.len());
+    assert!(report.unlinkable.is_empty());
+
+    let report = scan(&Options::new(&["/"]).same_device(true), fs.clone()).unwrap();
+    let expected: HashSet<_> = [paths(&["/one/a", "/one/b"])].iter().cloned().collect();
+    assert_eq!(expected, groups(&report.repeats));
+    assert_eq!(paths(&["/one/x", "/two/c", "/two/y"]), report.unlinkable);
+    assert_eq!(Some(&0), report.devices.get(Path::new("/one/a")));
+    assert_eq!(Some(&2), report.devices.get(Path::new("/two/c")));
+
+    // the linker only sees the group it can link, and never touches the other device
+    let mut linker = FileLinker::new(fs.clone(), PathSelect::new(fs.clone()));
+    for dups in report.repeats.iter().cloned() {
+        linker.act(dups);
+    }
+    let ops = fs.borrow().ops().to_vec();
+    assert!(!ops.is_empty());
+    assert!(ops.iter().all(|op| !format!("{:?}", op).contains("/two/")));
+
+    // each member's device is in the JSON too
+    let mut out = vec![];
+    json::write(&report, &mut out).unwrap();
+    let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    let group = &value["groups"][0];
+    assert_eq!(Some(0), group["devices"][0].as_u64());
+    assert_eq!(Some(0), group["devices"][1].as_u64());
+    assert_eq!(Some(3), value["unlinkable"].as_array().map(Vec::len));
+}
+
+#[test]
+fn exported_catalog_imports_offline() {
+    // a scan exported once can be reported again without the files
+    let fs = TestFileSystem::from_spec(
+        "dir /
+         dir /d
+         file /a 'AAAA' mtime=100
+         file /d/b 'AAAA' mtime=100
+         file /c 'AAAB' mtime=100
+         file /x 'XY' inode=7 mtime=100
+         file /d/y 'XY' inode=7 mtime=100",
+    ).unwrap();
+    let path = std::env::temp_dir().join(format!("smllr_export_{}", std::process::id()));
+    let scanned = scan(&Options::new(&["/"]).export(&path), fs).unwrap();
+
+    let empty = TestFileSystem::new();
+    let imported = scan(&Options::new::<&str>(&[]).import(&path), empty.clone());
+    let _ = std::fs::remove_file(&path);
+    let imported = imported.unwrap();
+    assert_eq!(2, imported.repeats.len());
+    assert_eq!(groups(&scanned.repeats), groups(&imported.repeats));
+    assert_eq!(scanned.files, imported.files);
+    assert_eq!(0, empty.borrow().num_reads());
+    assert!(empty.borrow().ops().is_empty());
+}
+
+#[test]
+fn changed_files_left_alone() {
+    // files modified or replaced between the scan and acting on it are not deleted
+    let fs = TestFileSystem::from_spec(
+        "dir /
+         file /a 'same' inode=1 mtime=100
+         file /b 'same' inode=2 mtime=100
+         file /c 'same' inode=3 mtime=100
+         file /d 'pair' inode=4 mtime=100
+         file /e 'pair' inode=5 mtime=100",
+    ).unwrap();
+    let found = deduplicate(Options::new(&["/"]), fs.clone()).unwrap();
+    assert_eq!(2, found.len());
+    {
+        let mut fs = fs.borrow_mut();
+        // /b is touched, and /e is replaced by another file
+        let touched = TestMD::new().with_mod_time(UNIX_EPOCH + Duration::from_secs(200));
+        fs.add(TestFile::new("/b").with_contents(String::from("same")).with_metadata(touched));
+        fs.add(TestFile::new("/e").with_contents(String::from("pair")).with_inode(6));
+    }
+
+    for dups in found.clone() {
+        FileDeleter::new(fs.clone(), PathSelect::new(fs.clone())).act(dups);
+    }
+    // only /c was still as it was scanned, and /d has no duplicate left
+    assert_eq!(vec![FsOp::Delete(PathBuf::from("/c"))], fs.borrow().ops());
+
+    // unless told not to look
+    let abc = found.into_iter().find(|dups| dups.len() == 3).unwrap();
+    FileDeleter::new(fs.clone(), PathSelect::new(fs.clone())).revalidate(false).act(abc);
+    assert!(fs.borrow().ops().contains(&FsOp::Delete(PathBuf::from("/b"))));
+}
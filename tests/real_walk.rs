@@ -0,0 +1,197 @@
+//! Walk a scratch directory on the real filesystem
+
+extern crate smllr;
+
+use std::{env, fs, process};
+use std::process::Command;
+use std::path::PathBuf;
+
+use smllr::{scan, scan_threaded, Options, RealFileSystem};
+use smllr::catalog::{CatalogStats, FileCataloger};
+use smllr::hash::{FileHash, HashKind, Md5Sum, Sha3Sum};
+use smllr::walker::DirWalker;
+
+// a fresh, empty directory unique to this test (canonical, as the walker reports paths)
+#[cfg(unix)]
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("smllr_{}_{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::canonicalize(dir).unwrap()
+}
+
+#[cfg(unix)]
+#[test]
+fn dangling_symlink_keeps_listing() {
+    use std::os::unix::fs::symlink;
+
+    let dir = scratch_dir("dangling");
+    fs::write(dir.join("a"), b"same").unwrap();
+    fs::create_dir(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub/b"), b"same").unwrap();
+    symlink(dir.join("nowhere"), dir.join("broken")).unwrap();
+    // relative links, to a file and back up to the directory itself
+    symlink("a", dir.join("rel")).unwrap();
+    symlink("..", dir.join("sub/up")).unwrap();
+
+    let report = scan(&Options::new(&[&dir]), RealFileSystem);
+    let _ = fs::remove_dir_all(&dir);
+    let report = report.unwrap();
+    assert_eq!(2, report.files);
+    assert_eq!(1, report.repeats.len());
+    let mut found = report.repeats[0].clone().into_paths();
+    found.sort();
+    assert_eq!(vec![dir.join("a"), dir.join("sub/b")], found);
+}
+
+// what cataloging found: the groups of duplicates (sorted), how many distinct files
+// share each size, and the stats
+#[cfg(unix)]
+type Found = (Vec<Vec<PathBuf>>, Vec<(u64, usize)>, CatalogStats);
+
+// catalog `files`, hashing on `threads` workers
+#[cfg(unix)]
+fn catalog_sorted(files: &[PathBuf], threads: usize) -> Found {
+    let mut fc: FileCataloger<_, Md5Sum> = FileCataloger::new(RealFileSystem).hash_threads(threads);
+    assert_eq!(files.len(), fc.insert_all(files).inserted);
+    let mut groups: Vec<Vec<PathBuf>> = fc.get_repeats()
+        .into_iter()
+        .map(|dups| {
+            let mut paths = dups.into_paths();
+            paths.sort();
+            paths
+        })
+        .collect();
+    groups.sort();
+    let collisions = fc.get_collisions()
+        .into_iter()
+        .map(|c| (c.size, c.paths.len()))
+        .collect();
+    (groups, collisions, fc.stats())
+}
+
+#[cfg(unix)]
+#[test]
+fn parallel_hashing_matches_sequential() {
+    let dir = scratch_dir("parallel");
+    // a few sizes, most past the first bytes, and files of each size differing only at the end
+    let sizes = [10, 5000, 9000, 20000];
+    for i in 0..240usize {
+        let sub = dir.join(format!("d{}", i % 7)).join(format!("e{}", i % 3));
+        fs::create_dir_all(&sub).unwrap();
+        let mut contents = vec![b'a' + (i % 4) as u8; sizes[i % 4]];
+        *contents.last_mut().unwrap() = (i % 5) as u8;
+        let path = sub.join(format!("f{}", i));
+        fs::write(&path, contents).unwrap();
+        if i % 17 == 0 {
+            fs::hard_link(&path, sub.join(format!("link{}", i))).unwrap();
+        }
+    }
+
+    let files = DirWalker::new(RealFileSystem, &[&dir]).traverse_ordered();
+    let sequential = catalog_sorted(&files, 1);
+    let parallel = catalog_sorted(&files, 4);
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(sequential, parallel);
+    // every size holds 5 distinct contents, each in many files
+    assert_eq!(20, parallel.0.len());
+    assert_eq!(vec![(10, 5), (5000, 5), (9000, 5), (20000, 5)], parallel.1);
+}
+
+#[cfg(unix)]
+#[test]
+fn quiet_without_duplicates() {
+    let dir = scratch_dir("quiet");
+    fs::write(dir.join("a"), b"one").unwrap();
+    fs::write(dir.join("b"), b"two").unwrap();
+    let run = |dir: &PathBuf| {
+        Command::new(env!("CARGO_BIN_EXE_smllr"))
+            .arg("--quiet-no-duplicates")
+            .arg(dir)
+            .output()
+            .unwrap()
+    };
+
+    let quiet = run(&dir);
+    // duplicates are reported as usual
+    fs::write(dir.join("c"), b"one").unwrap();
+    let found = run(&dir);
+    let _ = fs::remove_dir_all(&dir);
+    assert!(quiet.status.success());
+    assert!(quiet.stdout.is_empty());
+    assert!(found.status.success());
+    assert!(String::from_utf8_lossy(&found.stdout).contains("is a duplicate"));
+}
+
+#[cfg(unix)]
+#[test]
+fn selected_hash_reaches_workers() {
+    // files hashed on worker threads use the algorithm selected where the catalog was made
+    let dir = scratch_dir("hash_kind");
+    let contents = vec![b'z'; 5000];
+    fs::write(dir.join("a"), &contents).unwrap();
+    fs::write(dir.join("b"), &contents).unwrap();
+
+    HashKind::Sha3.select();
+    let mut fc: FileCataloger<_, HashKind> = FileCataloger::new(RealFileSystem).hash_threads(2);
+    fc.insert_all(&[dir.join("a"), dir.join("b")]);
+    fc.finalize();
+    let repeats = fc.get_repeats();
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(1, repeats.len());
+    assert_eq!(Some(&Sha3Sum::hash(&contents)[..]), repeats[0].hash());
+}
+
+#[cfg(unix)]
+#[test]
+fn io_and_hash_threads_sized_independently() {
+    let dir = scratch_dir("threads");
+    for i in 0..60usize {
+        let sub = dir.join(format!("d{}", i % 5));
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(sub.join(format!("f{}", i)), vec![b'a' + (i % 3) as u8; 5000 + i % 2]).unwrap();
+    }
+    let sorted = |report: smllr::Report| {
+        let mut groups: Vec<Vec<PathBuf>> = report.repeats
+            .into_iter()
+            .map(|dups| {
+                let mut paths = dups.into_paths();
+                paths.sort();
+                paths
+            })
+            .collect();
+        groups.sort();
+        (groups, report.walk)
+    };
+
+    let serial = sorted(scan(&Options::new(&[&dir]), RealFileSystem).unwrap());
+    let mut threaded = vec![];
+    for &(io, hash) in &[(1, 4), (4, 1), (3, 2)] {
+        // each pool has as many threads as asked for, whatever the other has
+        let walker = DirWalker::new(RealFileSystem, &[&dir]).io_threads(io);
+        let fc: FileCataloger<_, Md5Sum> = FileCataloger::new(RealFileSystem).hash_threads(hash);
+        assert_eq!((io, hash), (walker.num_io_threads(), fc.num_hash_threads()));
+        let opts = Options::new(&[&dir]).io_threads(io).hash_threads(hash);
+        threaded.push(sorted(scan_threaded(&opts, RealFileSystem).unwrap()));
+    }
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(6, serial.0.len());
+    assert!(threaded.iter().all(|found| *found == serial));
+}
+
+#[cfg(unix)]
+#[test]
+fn text_normalize_real_files() {
+    // small files are measured and hashed as text straight from their paths
+    let dir = scratch_dir("text");
+    fs::write(dir.join("a"), b"hello\r\n").unwrap();
+    fs::write(dir.join("b"), b"hello").unwrap();
+    fs::write(dir.join("c"), b"hello!").unwrap();
+    let report = scan(&Options::new(&[&dir]).text_normalize(1024), RealFileSystem);
+    let _ = fs::remove_dir_all(&dir);
+    let report = report.unwrap();
+    assert_eq!(1, report.repeats.len());
+    let mut found = report.repeats[0].clone().into_paths();
+    found.sort();
+    assert_eq!(vec![dir.join("a"), dir.join("b")], found);
+}